@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use precompute::read::decode_bytes;
+use precompute::TransformConfig;
+
+// Decodes arbitrary bytes and transforms whatever validates. Neither step may
+// panic; malformed input must come back as an error.
+fuzz_target!(|data: &[u8]| {
+    let Ok(pages) = decode_bytes(data, None) else {
+        return;
+    };
+    let pixels: u64 = pages
+        .iter()
+        .map(|page| page.width() as u64 * page.height() as u64)
+        .sum();
+    // Large images are valid but only slow the fuzzer down.
+    if pixels <= 1 << 16 {
+        let _ = TransformConfig::new().run_pages(&pages, &Default::default());
+    }
+});
@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+
+/// Which LUT row each sub-tile of an atlas was transformed with, written
+/// alongside the outputs of [`TransformConfig::run_atlas`](crate::TransformConfig::run_atlas).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AtlasManifest {
+    pub columns: u32,
+    pub rows: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    /// File names of the forward image and the LUT, which has one
+    /// `lut_width`-wide row per tile.
+    pub image: String,
+    pub lut: String,
+    pub lut_width: u32,
+    pub tiles: Vec<AtlasTile>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AtlasTile {
+    pub column: u32,
+    pub row: u32,
+    /// Top-left pixel of the tile in the atlas.
+    pub x: u32,
+    pub y: u32,
+    /// Row of the LUT holding this tile's mapping, `row * columns + column`.
+    pub lut_row: u32,
+}
+
+impl AtlasManifest {
+    /// Lays out a `width x height` atlas as `columns x rows` equal tiles.
+    pub fn new(
+        columns: u32,
+        rows: u32,
+        width: u32,
+        height: u32,
+        lut_width: u32,
+        image: String,
+        lut: String,
+    ) -> Self {
+        Self {
+            columns,
+            rows,
+            tile_width: width / columns,
+            tile_height: height / rows,
+            image,
+            lut,
+            lut_width,
+            tiles: atlas_tiles(columns, rows, width, height),
+        }
+    }
+}
+
+/// The tiles of a `columns x rows` grid over a `width x height` atlas, in LUT row order.
+pub(crate) fn atlas_tiles(columns: u32, rows: u32, width: u32, height: u32) -> Vec<AtlasTile> {
+    let (tile_width, tile_height) = (width / columns, height / rows);
+    (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .map(|(column, row)| AtlasTile {
+            column,
+            row,
+            x: column * tile_width,
+            y: row * tile_height,
+            lut_row: row * columns + column,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GenericImage};
+
+    use super::*;
+    use crate::cancel::CancelToken;
+    use crate::self_test::synthetic_input;
+    use crate::TransformConfig;
+
+    #[test]
+    fn atlas_tiles_are_transformed_independently() {
+        let base = synthetic_input();
+        let tiles = [
+            base.clone(),
+            base.fliph(),
+            base.adjust_contrast(-40.0),
+            base.brighten(60),
+        ];
+        let mut atlas = DynamicImage::new_rgb8(128, 128);
+        for (i, tile) in tiles.iter().enumerate() {
+            atlas
+                .copy_from(tile, (i as u32 % 2) * 64, (i as u32 / 2) * 64)
+                .unwrap();
+        }
+        let config = TransformConfig::default()
+            .deterministic(true)
+            .lut_size(Some(256));
+        let result = config.run_atlas(&atlas, 2, 2, &CancelToken::new()).unwrap();
+        assert_eq!(result.lut_height, 4);
+
+        let manifest = AtlasManifest::new(2, 2, 128, 128, 256, "a.tif".into(), "l.tif".into());
+        for (tile, source) in manifest.tiles.iter().zip(&tiles) {
+            let alone = config.run(source).unwrap();
+            let lut_row = tile.lut_row as usize * 256 * 3;
+            assert_eq!(&result.lut[lut_row..lut_row + 256 * 3], &alone.lut[..]);
+            for y in 0..64 {
+                let start = ((tile.y + y) as usize * 128 + tile.x as usize) * 3;
+                let row = &result.image[start..start + 64 * 3];
+                assert_eq!(row, &alone.image[y as usize * 64 * 3..][..64 * 3]);
+            }
+        }
+    }
+}
@@ -0,0 +1,976 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use precompute::{CancelToken, Cancelled, GaussianResult};
+
+use crate::resume::{outputs_up_to_date, ResumeManifest};
+use crate::{
+    bake, print_channel_stats, write_atlas_manifest, write_img, write_lut, write_preview,
+    write_sidecar, write_split_channels, Args, Diagnostics, NamePart, Output,
+};
+
+/// Bakes `inputs` of `--in-dir` or `--input-list` into `out_dir`.
+pub fn run_batch(
+    args: &Args,
+    inputs: Vec<PathBuf>,
+    out_dir: &Path,
+    cancel: &CancelToken,
+) -> Result<(), anyhow::Error> {
+    if let Some(template) = &args.name_template {
+        if !template.0.contains(&NamePart::Input) {
+            return Err(anyhow::anyhow!(
+                "--name-template needs {{input}} in a batch, otherwise every input writes the same files"
+            ));
+        }
+    }
+    if let Some(pair) = &args.pair_naming {
+        if !pair.img.0.contains(&NamePart::Input) || !pair.lut.0.contains(&NamePart::Input) {
+            return Err(anyhow::anyhow!(
+                "--pair-naming needs {{input}} in both templates in a batch, otherwise every input writes the same files"
+            ));
+        }
+    }
+    let events = args.events.as_ref().map(EventSink::open).transpose()?;
+    let start = Instant::now();
+    let write_concurrency = args.write_concurrency.get();
+
+    // Bounding the channel caps how many baked results wait in memory for a writer.
+    let (tx, rx) =
+        mpsc::sync_channel::<(&Path, String, String, GaussianResult, Duration)>(write_concurrency);
+    let rx = Mutex::new(rx);
+    let bars = !args.quiet
+        && args.events != Some(EventTarget::Stdout)
+        && std::io::IsTerminal::is_terminal(&std::io::stderr());
+    let progress = BatchProgress::new(
+        &inputs,
+        start,
+        args.quiet,
+        args.deterministic_batch_order,
+        events.as_ref(),
+    );
+    let progress = match bars {
+        true => progress.with_bars(ProgressDrawTarget::stderr()),
+        false => progress,
+    };
+    // The bars stand in for each bake's own progress lines, which would
+    // scroll them away.
+    let quiet_args;
+    let bake_args = match bars {
+        true => {
+            quiet_args = Args {
+                quiet: true,
+                ..args.clone()
+            };
+            &quiet_args
+        }
+        false => args,
+    };
+    let resume = args
+        .resume
+        .then(|| ResumeManifest::open(out_dir, args.output_settings_digest()))
+        .transpose()?;
+    let img_encoder = args.img_encoder();
+    let lut_encoder = args.lut_encoder();
+    let output_names = |input_path: &Path| {
+        let img_stem = args.output_stem(input_path, Output::Img)?;
+        let lut_file_name =
+            args.output_file_name(input_path, Output::Lut, lut_encoder.extension())?;
+        Ok::<_, anyhow::Error>((img_stem, lut_file_name))
+    };
+    let resumed = |resume: &ResumeManifest, input_path: &Path, img_stem: &str, lut: &str| {
+        let img_file_name = format!("{}.{}", img_stem, img_encoder.extension());
+        let outputs = args.image_outputs(out_dir, &img_file_name, lut);
+        resume.is_done(input_path, &args.sources(input_path), &outputs)
+    };
+    if let Some(resume) = &resume {
+        let done = inputs
+            .iter()
+            .filter(|input| match output_names(input) {
+                Ok((img_stem, lut_file_name)) => resumed(resume, input, &img_stem, &lut_file_name),
+                Err(_) => false,
+            })
+            .count();
+        if done > 0 && !args.quiet {
+            println!("Resuming, {} of {} inputs already done", done, inputs.len());
+        }
+    }
+    let diagnostics = Diagnostics::new(args.strict);
+    let io_pool = args.io_pool()?;
+    if let Some(events) = &events {
+        events.emit(serde_json::json!({ "event": "batch_started", "files": inputs.len() }));
+    }
+
+    std::thread::scope(|s| {
+        for _ in 0..write_concurrency {
+            s.spawn(|| loop {
+                let Ok((input_path, img_stem, lut_file_name, baked, bake_time)) =
+                    rx.lock().unwrap().recv()
+                else {
+                    break;
+                };
+                let img_file_name = format!("{}.{}", img_stem, img_encoder.extension());
+                let img_path = out_dir.join(&img_file_name);
+                let lut_path = out_dir.join(&lut_file_name);
+                let _write = tracing::info_span!("write", input = ?input_path).entered();
+                progress.phase(input_path, Phase::Write);
+                let written_files = io_pool.install(|| {
+                    let written = if args.split_channels {
+                        write_split_channels(&baked, out_dir, &img_file_name, &lut_file_name, args)
+                    } else {
+                        write_img(&baked, &img_path, img_encoder.as_ref(), args)
+                            .and_then(|_| write_lut(&baked, &lut_path, lut_encoder.as_ref(), args))
+                    };
+                    written
+                        .and_then(|_| {
+                            if !args.preview_output {
+                                return Ok(());
+                            }
+                            let preview_path = out_dir.join(format!("{}-preview.png", img_stem));
+                            write_preview(&baked, args, &preview_path)
+                        })
+                        .and_then(|_| {
+                            let Some((columns, rows)) = args.atlas.or(args.local_tiles) else {
+                                return Ok(());
+                            };
+                            write_atlas_manifest(
+                                &baked,
+                                columns,
+                                rows,
+                                &out_dir.join(format!("{}.atlas.json", img_stem)),
+                                &img_file_name,
+                                &lut_file_name,
+                                args,
+                            )
+                        })
+                        .and_then(|_| {
+                            if !args.sidecar {
+                                return Ok(());
+                            }
+                            write_sidecar(
+                                &baked,
+                                args,
+                                input_path,
+                                out_dir,
+                                &img_stem,
+                                &img_file_name,
+                                &lut_file_name,
+                            )
+                        })
+                        .and_then(|_| match &resume {
+                            Some(resume) => resume.record(input_path, &args.sources(input_path)),
+                            None => Ok(()),
+                        })
+                });
+                let pixels = baked.width as u64 * baked.height as u64;
+                progress.record(match written_files {
+                    Ok(()) => FileOutcome::baked(input_path, pixels, bake_time, None),
+                    Err(e) => FileOutcome::baked(input_path, pixels, bake_time, Some(e)),
+                });
+            });
+        }
+
+        for input_path in &inputs {
+            let (img_stem, lut_file_name) = match output_names(input_path) {
+                Ok(names) => names,
+                Err(e) => {
+                    progress.record(FileOutcome::failed(input_path, e));
+                    continue;
+                }
+            };
+            if let Some(resume) = &resume {
+                if resumed(resume, input_path, &img_stem, &lut_file_name) {
+                    progress.record(FileOutcome::skipped(input_path));
+                    continue;
+                }
+            }
+            if args.skip_if_newer {
+                let img_file_name = format!("{}.{}", img_stem, img_encoder.extension());
+                let outputs = args.image_outputs(out_dir, &img_file_name, &lut_file_name);
+                if outputs_up_to_date(&args.sources(input_path), &outputs) {
+                    progress.record(FileOutcome::skipped(input_path));
+                    continue;
+                }
+            }
+            if let Some(events) = &events {
+                events.emit(serde_json::json!({ "event": "file_started", "input": input_path }));
+            }
+            progress.phase(input_path, Phase::Bake);
+            let bake_start = Instant::now();
+            let (baked, timed_out) = match args.timeout {
+                Some(seconds) => with_timeout(cancel, Duration::from_secs(seconds), |cancel| {
+                    bake(input_path, bake_args, &diagnostics, cancel)
+                }),
+                None => (bake(input_path, bake_args, &diagnostics, cancel), false),
+            };
+            match baked {
+                Ok(baked) => {
+                    if args.channel_stats {
+                        print_channel_stats(
+                            input_path,
+                            &baked.histogram,
+                            Some(&baked.exclusions),
+                            args,
+                        );
+                    }
+                    let bake_time = bake_start.elapsed();
+                    if tx
+                        .send((input_path, img_stem, lut_file_name, baked, bake_time))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+                Err(e) if e.is::<Cancelled>() && timed_out && !cancel.is_cancelled() => {
+                    let e =
+                        anyhow::anyhow!("Timed out after {} s", args.timeout.unwrap_or_default());
+                    progress.record(FileOutcome::failed(input_path, e));
+                }
+                Err(e) if e.is::<Cancelled>() => break,
+                Err(e) => progress.record(FileOutcome::failed(input_path, e)),
+            }
+            if cancel.is_cancelled() {
+                break;
+            }
+        }
+        drop(tx);
+    });
+
+    let mut outcomes = progress.finish();
+    outcomes.sort_by_key(|outcome| outcome.input);
+    let count = |status| outcomes.iter().filter(|o| o.status == status).count();
+    let (written, skipped, failed) = (
+        count(FileStatus::Written),
+        count(FileStatus::Skipped),
+        count(FileStatus::Failed),
+    );
+    let total_time = start.elapsed();
+    if let Some(events) = &events {
+        events.emit(serde_json::json!({
+            "event": "batch_finished",
+            "files": inputs.len(),
+            "written": written,
+            "skipped": skipped,
+            "failed": failed,
+            "cancelled": cancel.is_cancelled(),
+            "seconds": total_time.as_secs_f64(),
+        }));
+    }
+    if args.events != Some(EventTarget::Stdout) {
+        if skipped > 0 {
+            println!(
+                "Wrote {} of {} files to {:?}, {} already up to date",
+                written,
+                inputs.len(),
+                out_dir,
+                skipped
+            );
+        } else {
+            println!(
+                "Wrote {} of {} files to {:?}",
+                written,
+                inputs.len(),
+                out_dir
+            );
+        }
+    }
+    for outcome in &outcomes {
+        if let Some(e) = &outcome.error {
+            eprintln!("Failed {:?}: {}", outcome.input, e);
+        }
+    }
+    if args.summary {
+        print_batch_summary(&outcomes, inputs.len(), total_time);
+    }
+    if let Some(json_path) = &args.summary_json {
+        write_batch_summary(json_path, &outcomes, inputs.len(), total_time)?;
+    }
+
+    if cancel.is_cancelled() {
+        Err(Cancelled.into())
+    } else if failed > 0 {
+        Err(anyhow::anyhow!("{} files failed", failed))
+    } else if let Some(resume) = resume {
+        resume.finish()
+    } else {
+        Ok(())
+    }
+}
+
+/// How often [`with_timeout`] checks whether its parent token was cancelled.
+const TIMEOUT_POLL: Duration = Duration::from_millis(50);
+
+/// Runs `f` with a token that is cancelled when `parent` is or once `limit`
+/// has passed, returning its result and whether the time ran out. The
+/// transform checks its token between phases, between the sorts of each
+/// channel and before each chunk of forward values, so `f` overruns the
+/// limit by at most one sort.
+fn with_timeout<T>(
+    parent: &CancelToken,
+    limit: Duration,
+    f: impl FnOnce(&CancelToken) -> T,
+) -> (T, bool) {
+    let token = CancelToken::new();
+    let deadline = Instant::now() + limit;
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    std::thread::scope(|s| {
+        let watched = token.clone();
+        let watchdog = s.spawn(move || loop {
+            let now = Instant::now();
+            if now >= deadline {
+                watched.cancel();
+                return true;
+            }
+            match done_rx.recv_timeout((deadline - now).min(TIMEOUT_POLL)) {
+                Err(mpsc::RecvTimeoutError::Timeout) if parent.is_cancelled() => {
+                    watched.cancel();
+                    return false;
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                _ => return false,
+            }
+        });
+        let result = f(&token);
+        drop(done_tx);
+        (result, watchdog.join().unwrap())
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FileStatus {
+    Written,
+    Skipped,
+    Failed,
+}
+
+impl FileStatus {
+    fn label(self) -> &'static str {
+        match self {
+            FileStatus::Written => "written",
+            FileStatus::Skipped => "skipped",
+            FileStatus::Failed => "failed",
+        }
+    }
+}
+
+/// What happened to one input of a batch, for the final report.
+struct FileOutcome<'a> {
+    input: &'a Path,
+    status: FileStatus,
+    /// Pixels baked over every page, zero if the input wasn't baked.
+    pixels: u64,
+    bake_time: Duration,
+    error: Option<anyhow::Error>,
+}
+
+impl<'a> FileOutcome<'a> {
+    /// An input that was baked, then written unless `error` is set.
+    fn baked(
+        input: &'a Path,
+        pixels: u64,
+        bake_time: Duration,
+        error: Option<anyhow::Error>,
+    ) -> Self {
+        Self {
+            input,
+            status: match error {
+                Some(_) => FileStatus::Failed,
+                None => FileStatus::Written,
+            },
+            pixels,
+            bake_time,
+            error,
+        }
+    }
+
+    fn skipped(input: &'a Path) -> Self {
+        Self {
+            input,
+            status: FileStatus::Skipped,
+            pixels: 0,
+            bake_time: Duration::ZERO,
+            error: None,
+        }
+    }
+
+    fn failed(input: &'a Path, error: anyhow::Error) -> Self {
+        Self {
+            input,
+            status: FileStatus::Failed,
+            pixels: 0,
+            bake_time: Duration::ZERO,
+            error: Some(error),
+        }
+    }
+}
+
+/// What a batch is doing with an input, as reported by `--events` and the
+/// progress bars.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    /// Reading and transforming it.
+    Bake,
+    /// Writing its outputs.
+    Write,
+}
+
+impl Phase {
+    fn label(self) -> &'static str {
+        match self {
+            Phase::Bake => "bake",
+            Phase::Write => "write",
+        }
+    }
+}
+
+/// Collects the outcomes of a batch, printing each as it comes in with how
+/// far through the batch it is and an estimate of the time left.
+struct BatchProgress<'a> {
+    outcomes: Mutex<Vec<FileOutcome<'a>>>,
+    /// With `--deterministic-batch-order`, the order to print outcomes in.
+    order: Option<InputOrder<'a>>,
+    files: usize,
+    start: Instant,
+    quiet: bool,
+    events: Option<&'a EventSink>,
+    bars: Option<ProgressBars<'a>>,
+}
+
+/// A bar for the whole batch, which the progress lines are printed above,
+/// and a spinner above it for each input being baked or written.
+struct ProgressBars<'a> {
+    multi: MultiProgress,
+    overall: ProgressBar,
+    files: Mutex<HashMap<&'a Path, ProgressBar>>,
+}
+
+impl<'a> ProgressBars<'a> {
+    fn new(files: usize, target: ProgressDrawTarget) -> Self {
+        let multi = MultiProgress::with_draw_target(target);
+        let overall = multi.add(ProgressBar::new(files as u64));
+        overall.set_style(
+            ProgressStyle::with_template("[{bar:40}] {pos}/{len} files, about {eta} left")
+                .unwrap()
+                .progress_chars("=> "),
+        );
+        Self {
+            multi,
+            overall,
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn phase(&self, input: &'a Path, phase: Phase) {
+        let mut files = self.files.lock().unwrap();
+        let bar = files.entry(input).or_insert_with(|| {
+            let bar = self
+                .multi
+                .insert_before(&self.overall, ProgressBar::new_spinner());
+            bar.set_style(ProgressStyle::with_template("{spinner} {msg} ({elapsed})").unwrap());
+            bar.enable_steady_tick(Duration::from_millis(100));
+            bar
+        });
+        bar.set_message(format!("{} {:?}", phase.label(), input));
+    }
+
+    /// Takes down the spinner of an input that's done with.
+    fn done(&self, input: &Path) {
+        if let Some(bar) = self.files.lock().unwrap().remove(input) {
+            bar.finish_and_clear();
+            self.multi.remove(&bar);
+        }
+    }
+}
+
+/// Each input's place in the batch, and the outcomes recorded ahead of an
+/// earlier input's, held back until it comes in.
+struct InputOrder<'a> {
+    positions: HashMap<&'a Path, usize>,
+    pending: Mutex<BTreeMap<usize, FileOutcome<'a>>>,
+}
+
+impl<'a> BatchProgress<'a> {
+    fn new(
+        inputs: &'a [PathBuf],
+        start: Instant,
+        quiet: bool,
+        deterministic: bool,
+        events: Option<&'a EventSink>,
+    ) -> Self {
+        let order = deterministic.then(|| InputOrder {
+            positions: inputs
+                .iter()
+                .enumerate()
+                .map(|(i, input)| (input.as_path(), i))
+                .collect(),
+            pending: Mutex::new(BTreeMap::new()),
+        });
+        Self {
+            outcomes: Mutex::new(Vec::with_capacity(inputs.len())),
+            order,
+            files: inputs.len(),
+            start,
+            quiet,
+            events,
+            bars: None,
+        }
+    }
+
+    /// Shows progress bars on `target` in place of the progress lines' time
+    /// estimates.
+    fn with_bars(mut self, target: ProgressDrawTarget) -> Self {
+        self.bars = Some(ProgressBars::new(self.files, target));
+        self
+    }
+
+    /// Notes that `input` has moved on to `phase`.
+    fn phase(&self, input: &'a Path, phase: Phase) {
+        if let Some(bars) = &self.bars {
+            bars.phase(input, phase);
+        }
+        if let Some(events) = self.events {
+            events.emit(
+                serde_json::json!({ "event": "phase", "input": input, "phase": phase.label() }),
+            );
+        }
+    }
+
+    fn record(&self, outcome: FileOutcome<'a>) {
+        if let Some(bars) = &self.bars {
+            bars.done(outcome.input);
+        }
+        let Some(order) = &self.order else {
+            return self.print(outcome);
+        };
+        let mut pending = order.pending.lock().unwrap();
+        pending.insert(order.positions[outcome.input], outcome);
+        loop {
+            // Outcomes are printed in order, so the next one due is the count so far.
+            let next = self.outcomes.lock().unwrap().len();
+            let Some(outcome) = pending.remove(&next) else {
+                break;
+            };
+            self.print(outcome);
+        }
+    }
+
+    fn print(&self, outcome: FileOutcome<'a>) {
+        let mut outcomes = self.outcomes.lock().unwrap();
+        let done = outcomes.len() + 1;
+        if let Some(bars) = &self.bars {
+            let status = outcome.status.label();
+            let progress = format!("[{}/{}] {} {:?}", done, self.files, status, outcome.input);
+            let _ = bars.multi.println(progress);
+            bars.overall.inc(1);
+        } else if !self.quiet {
+            let status = outcome.status.label();
+            let progress = format!("[{}/{}] {} {:?}", done, self.files, status, outcome.input);
+            match self.files - done {
+                0 => println!("{}", progress),
+                // Inputs are baked one at a time, so the average so far is the best guess.
+                left => println!(
+                    "{}, about {:.0?} left",
+                    progress,
+                    self.start.elapsed().mul_f64(left as f64 / done as f64)
+                ),
+            }
+        }
+        if let Some(events) = self.events {
+            events.emit(match &outcome.error {
+                Some(e) => serde_json::json!({
+                    "event": "error",
+                    "input": outcome.input,
+                    "message": e.to_string(),
+                    "done": done,
+                    "files": self.files,
+                }),
+                None => serde_json::json!({
+                    "event": "file_completed",
+                    "input": outcome.input,
+                    "status": outcome.status.label(),
+                    "pixels": outcome.pixels,
+                    "bake_seconds": outcome.bake_time.as_secs_f64(),
+                    "done": done,
+                    "files": self.files,
+                }),
+            });
+        }
+        outcomes.push(outcome);
+    }
+
+    /// Every outcome recorded, after printing any still held back behind an
+    /// input the batch stopped before reaching.
+    fn finish(self) -> Vec<FileOutcome<'a>> {
+        if let Some(order) = &self.order {
+            for (_, outcome) in std::mem::take(&mut *order.pending.lock().unwrap()) {
+                self.print(outcome);
+            }
+        }
+        if let Some(bars) = &self.bars {
+            bars.overall.finish_and_clear();
+        }
+        self.outcomes.into_inner().unwrap()
+    }
+}
+
+/// Where `--events` go.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EventTarget {
+    Stdout,
+    /// A Unix socket a frontend listens on.
+    Socket(PathBuf),
+}
+
+impl EventTarget {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "stdout" => Ok(EventTarget::Stdout),
+            _ => match value.strip_prefix("socket:") {
+                Some(path) if !path.is_empty() => Ok(EventTarget::Socket(PathBuf::from(path))),
+                _ => Err(format!("expected stdout or socket:PATH, got {:?}", value)),
+            },
+        }
+    }
+}
+
+/// Writes `--events`, one JSON object per line, each with an `event` field:
+/// `batch_started`, then per input `file_started`, `phase` for `bake` and
+/// `write`, and `file_completed` or `error`, and `batch_finished`. Lines are
+/// flushed as they're written, so a frontend sees each event as it happens.
+struct EventSink {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl EventSink {
+    fn open(target: &EventTarget) -> Result<Self, anyhow::Error> {
+        let writer: Box<dyn Write + Send> = match target {
+            EventTarget::Stdout => Box::new(std::io::stdout()),
+            #[cfg(unix)]
+            EventTarget::Socket(path) => {
+                Box::new(std::os::unix::net::UnixStream::connect(path).map_err(|e| {
+                    anyhow::anyhow!("Couldn't connect to --events socket {:?}: {}", path, e)
+                })?)
+            }
+            #[cfg(not(unix))]
+            EventTarget::Socket(_) => {
+                return Err(anyhow::anyhow!(
+                    "--events socket:PATH needs Unix sockets, use --events stdout"
+                ))
+            }
+        };
+        Ok(Self {
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Writes `event` as a line. A frontend that went away doesn't stop the
+    /// batch, so errors are ignored.
+    fn emit(&self, event: serde_json::Value) {
+        let mut writer = self.writer.lock().unwrap();
+        let _ = writeln!(writer, "{}", event).and_then(|_| writer.flush());
+    }
+}
+
+/// Megapixels baked across `outcomes`, and seconds of the whole batch per megapixel.
+fn batch_throughput(outcomes: &[FileOutcome], total_time: Duration) -> (f64, Option<f64>) {
+    let megapixels = outcomes.iter().map(|o| o.pixels).sum::<u64>() as f64 / 1e6;
+    let per_megapixel = (megapixels > 0.0).then(|| total_time.as_secs_f64() / megapixels);
+    (megapixels, per_megapixel)
+}
+
+fn print_batch_summary(outcomes: &[FileOutcome], files: usize, total_time: Duration) {
+    println!("Batch summary:");
+    println!(
+        "{:<40} {:>8} {:>10} {:>8}",
+        "input", "status", "megapixels", "seconds"
+    );
+    for outcome in outcomes {
+        println!(
+            "{:<40} {:>8} {:>10.3} {:>8.2}",
+            outcome.input.display().to_string(),
+            outcome.status.label(),
+            outcome.pixels as f64 / 1e6,
+            outcome.bake_time.as_secs_f64()
+        );
+    }
+    let count = |status| outcomes.iter().filter(|o| o.status == status).count();
+    println!(
+        "{} files: {} written, {} skipped, {} failed, {} not reached",
+        files,
+        count(FileStatus::Written),
+        count(FileStatus::Skipped),
+        count(FileStatus::Failed),
+        files - outcomes.len()
+    );
+    let (megapixels, per_megapixel) = batch_throughput(outcomes, total_time);
+    match per_megapixel {
+        Some(per_megapixel) => println!(
+            "Baked {:.3} megapixels in {:.2?}, {:.3} s per megapixel",
+            megapixels, total_time, per_megapixel
+        ),
+        None => println!("Baked nothing in {:.2?}", total_time),
+    }
+}
+
+fn write_batch_summary(
+    path: &Path,
+    outcomes: &[FileOutcome],
+    files: usize,
+    total_time: Duration,
+) -> Result<(), anyhow::Error> {
+    let count = |status| outcomes.iter().filter(|o| o.status == status).count();
+    let (megapixels, per_megapixel) = batch_throughput(outcomes, total_time);
+    let report = serde_json::json!({
+        "files": files,
+        "written": count(FileStatus::Written),
+        "skipped": count(FileStatus::Skipped),
+        "failed": count(FileStatus::Failed),
+        "megapixels": megapixels,
+        "total_seconds": total_time.as_secs_f64(),
+        "seconds_per_megapixel": per_megapixel,
+        "inputs": outcomes
+            .iter()
+            .map(|outcome| serde_json::json!({
+                "input": outcome.input,
+                "status": outcome.status.label(),
+                "pixels": outcome.pixels,
+                "bake_seconds": outcome.bake_time.as_secs_f64(),
+                "error": outcome.error.as_ref().map(|e| e.to_string()),
+            }))
+            .collect::<Vec<_>>(),
+    });
+    std::fs::write(path, serde_json::to_string_pretty(&report)?)?;
+    Ok(())
+}
+
+/// Leaves the files the batch writes out of `inputs` when `out_dir` is
+/// `in_dir`, so the outputs of one run aren't baked again on the next, and
+/// theirs on the run after. `--allow-overlap` keeps them.
+pub fn exclude_own_outputs(
+    args: &Args,
+    in_dir: &Path,
+    out_dir: &Path,
+    inputs: Vec<PathBuf>,
+) -> Vec<PathBuf> {
+    let canonical = |dir: &Path| {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        dir.canonicalize().ok()
+    };
+    if args.allow_overlap || canonical(in_dir).is_none() || canonical(in_dir) != canonical(out_dir)
+    {
+        return inputs;
+    }
+    let (img_extension, lut_extension) = (
+        args.img_encoder().extension().to_string(),
+        args.lut_encoder().extension().to_string(),
+    );
+    let mut outputs = HashSet::new();
+    for input_path in &inputs {
+        let (Ok(img_stem), Ok(lut_file_name)) = (
+            args.output_stem(input_path, Output::Img),
+            args.output_file_name(input_path, Output::Lut, &lut_extension),
+        ) else {
+            continue;
+        };
+        let img_file_name = format!("{}.{}", img_stem, img_extension);
+        outputs.extend(args.image_outputs(Path::new(""), &img_file_name, &lut_file_name));
+        outputs.insert(PathBuf::from(format!("{}-preview.png", img_stem)));
+    }
+    let (kept, excluded): (Vec<PathBuf>, Vec<PathBuf>) = inputs.into_iter().partition(|input| {
+        input
+            .file_name()
+            .is_none_or(|name| !outputs.contains(Path::new(name)))
+    });
+    if !excluded.is_empty() {
+        eprintln!(
+            "Leaving out {} files of --in-dir that this batch writes, since --out-dir is the same directory; \
+             pass --allow-overlap to bake them too",
+            excluded.len()
+        );
+    }
+    kept
+}
+
+/// The paths listed in the file at `path`, or in stdin if it's `-`, one per
+/// line, in their order. Blank lines are skipped.
+pub fn read_input_list(path: &Path) -> Result<Vec<PathBuf>, anyhow::Error> {
+    if path == Path::new("-") {
+        return parse_input_list(std::io::stdin());
+    }
+    let file = std::fs::File::open(path).map_err(|e| anyhow::anyhow!("{:?}: {}", path, e))?;
+    parse_input_list(file).map_err(|e| anyhow::anyhow!("{:?}: {}", path, e))
+}
+
+/// The paths listed in `reader`, one per line, skipping blank lines.
+fn parse_input_list(reader: impl std::io::Read) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let text = std::io::read_to_string(reader)?;
+    Ok(text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+pub fn discover_inputs(in_dir: &Path) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut inputs = Vec::new();
+    for entry in std::fs::read_dir(in_dir)? {
+        let path = entry?.path();
+        if path.is_file() && image::ImageFormat::from_path(&path).is_ok() {
+            inputs.push(path);
+        }
+    }
+    inputs.sort();
+    Ok(inputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn bars_follow_each_input_until_it_is_recorded() {
+        let inputs = [PathBuf::from("a.png"), PathBuf::from("b.png")];
+        let progress = BatchProgress::new(&inputs, Instant::now(), false, false, None)
+            .with_bars(ProgressDrawTarget::hidden());
+        let bars = progress.bars.as_ref().unwrap();
+        let spinners = || bars.files.lock().unwrap().len();
+
+        progress.phase(&inputs[0], Phase::Bake);
+        progress.phase(&inputs[1], Phase::Bake);
+        progress.phase(&inputs[0], Phase::Write);
+        assert_eq!(spinners(), 2);
+        assert_eq!(
+            bars.files.lock().unwrap()[inputs[0].as_path()].message(),
+            "write \"a.png\""
+        );
+
+        progress.record(FileOutcome::baked(&inputs[0], 1, Duration::ZERO, None));
+        assert_eq!((spinners(), bars.overall.position()), (1, 1));
+        progress.record(FileOutcome::failed(&inputs[1], anyhow::anyhow!("broken")));
+        assert_eq!((spinners(), bars.overall.position()), (0, 2));
+        assert_eq!(progress.finish().len(), 2);
+    }
+
+    #[test]
+    fn timeout_cancels_a_bake_that_runs_too_long() {
+        let input = precompute::self_test::synthetic_input();
+        let config = precompute::TransformConfig::default();
+        // Bakes until cancelled, a transform too slow to ever finish.
+        let endless = |cancel: &CancelToken| loop {
+            if let Err(e) = config.run_with_cancel(&input, cancel) {
+                return e;
+            }
+        };
+        let start = Instant::now();
+        let (err, timed_out) =
+            with_timeout(&CancelToken::new(), Duration::from_millis(200), endless);
+        assert!(err.is::<Cancelled>() && timed_out);
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        // Cancelling the batch isn't a timeout.
+        let parent = CancelToken::new();
+        let (err, timed_out) = std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(100));
+                parent.cancel();
+            });
+            with_timeout(&parent, Duration::from_secs(60), endless)
+        });
+        assert!(err.is::<Cancelled>() && !timed_out);
+
+        let (result, timed_out) =
+            with_timeout(&CancelToken::new(), Duration::from_secs(60), |cancel| {
+                config.run_with_cancel(&input, cancel)
+            });
+        assert!(result.is_ok() && !timed_out);
+    }
+
+    /// A writer the test can read back, shared with an `EventSink`.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn parallel_batches_report_in_input_order() {
+        let inputs: Vec<_> = (0..12)
+            .map(|i| PathBuf::from(format!("{:02}.png", i)))
+            .collect();
+        // Records every input from four threads, each finishing its share in
+        // an order that depends on `seed`, and returns the events written.
+        let events_of = |seed: usize, skip_first: bool| {
+            let captured = Captured::default();
+            let events = EventSink {
+                writer: Mutex::new(Box::new(captured.clone())),
+            };
+            let progress = BatchProgress::new(&inputs, Instant::now(), true, true, Some(&events));
+            std::thread::scope(|s| {
+                for thread in 0..4 {
+                    let (progress, inputs) = (&progress, &inputs);
+                    s.spawn(move || {
+                        let mut mine: Vec<_> = (thread..inputs.len())
+                            .step_by(4)
+                            .filter(|&i| !(skip_first && i == 0))
+                            .collect();
+                        mine.sort_by_key(|&i| (i * 7 + seed * 5 + thread) % 11);
+                        for i in mine {
+                            std::thread::sleep(Duration::from_millis(((i + seed) % 3) as u64));
+                            progress.record(FileOutcome::baked(
+                                &inputs[i],
+                                1,
+                                Duration::ZERO,
+                                None,
+                            ));
+                        }
+                    });
+                }
+            });
+            let outcomes = progress.finish();
+            let order: Vec<_> = outcomes.iter().map(|o| o.input.to_owned()).collect();
+            let text = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+            (order, text)
+        };
+
+        let (order, first) = events_of(0, false);
+        assert_eq!(order, inputs);
+        let (_, second) = events_of(1, false);
+        assert_eq!(first, second);
+        let lines: Vec<_> = first.lines().collect();
+        assert_eq!(lines.len(), inputs.len());
+        for (i, line) in lines.iter().enumerate() {
+            let event: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(event["input"], format!("{:02}.png", i));
+            assert_eq!(event["done"], i + 1);
+        }
+
+        // A batch stopped before its first input still reports the rest, in order.
+        let (order, _) = events_of(2, true);
+        assert_eq!(order, &inputs[1..]);
+    }
+
+    #[test]
+    fn input_lists_keep_their_order_and_skip_blank_lines() {
+        let piped = "b/second.png\n\n  \na/first.png\r\n";
+        assert_eq!(
+            parse_input_list(piped.as_bytes()).unwrap(),
+            [PathBuf::from("b/second.png"), PathBuf::from("a/first.png")]
+        );
+        assert!(parse_input_list(&b"\xff\n"[..]).is_err());
+    }
+}
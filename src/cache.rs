@@ -0,0 +1,194 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::TransformConfig;
+use crate::encode::{write_atomic, WriteOptions};
+use crate::transform::ChannelBake;
+
+/// Identifies the cache file format; bumped whenever its layout changes.
+const MAGIC: &[u8; 8] = b"PCCHAN01";
+
+/// Directory of per-channel bakes written by [`TransformConfig::run_cached`],
+/// one file per channel named by a hash of the channel's source values and of
+/// the parameters that shape it. A re-bake loads the channels whose hash it
+/// finds and only transforms the rest.
+#[derive(Clone, Debug)]
+pub struct ChannelCache {
+    dir: PathBuf,
+}
+
+impl ChannelCache {
+    /// A cache in `dir`, created if it doesn't exist.
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, anyhow::Error> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| anyhow::anyhow!("Couldn't create cache directory {:?}: {}", dir, e))?;
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Hash of everything `channel`'s bake depends on: its unfiltered source
+    /// values, the image's dimensions, the LUT width, and the config with the
+    /// options that only concern other channels left out.
+    pub(crate) fn key(
+        values: &[u8],
+        channel: usize,
+        width: u32,
+        height: u32,
+        lut_width: u32,
+        config: &TransformConfig,
+    ) -> u64 {
+        let mut own = config.clone();
+        own.channels = Default::default();
+        own.lut_channels = None;
+        own.distribution = config.channel_distribution(channel);
+        own.channel_distributions = None;
+        // Applied to the recombined image, so it doesn't change a channel's bake.
+        own.mean_gradient = None;
+        let params = serde_json::to_vec(&own).expect("configs always serialize");
+
+        let mut hash = Fnv1a::new();
+        hash.write(env!("CARGO_PKG_VERSION").as_bytes());
+        hash.write(&params);
+        hash.write(&[config.lut_channel_enabled(channel) as u8, channel as u8]);
+        for n in [width, height, lut_width] {
+            hash.write(&n.to_le_bytes());
+        }
+        hash.write(values);
+        hash.finish()
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.channel", key))
+    }
+
+    /// The bake stored under `key`, or `None` if there is none or it can't be
+    /// read, in which case the channel is simply baked again.
+    pub(crate) fn load(&self, key: u64) -> Option<ChannelBake> {
+        let mut bytes = Vec::new();
+        std::fs::File::open(self.path(key))
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .ok()?;
+        let mut reader = Reader(bytes.strip_prefix(MAGIC)?);
+        let counts = [reader.u64()?, reader.u64()?, reader.u64()?];
+        let [forward, lut, sorted] = counts.map(|n| n as usize);
+        let bake = ChannelBake {
+            forward: reader.f32s(forward)?,
+            lut: reader.bytes(lut)?.to_vec(),
+            lut_f32: reader.f32s(lut)?,
+            sorted: reader.bytes(sorted)?.to_vec(),
+        };
+        reader.0.is_empty().then_some(bake)
+    }
+
+    pub(crate) fn store(&self, key: u64, bake: &ChannelBake) -> Result<(), anyhow::Error> {
+        write_atomic(&self.path(key), &WriteOptions::default(), |file| {
+            let mut writer = std::io::BufWriter::new(file);
+            writer.write_all(MAGIC)?;
+            for n in [bake.forward.len(), bake.lut.len(), bake.sorted.len()] {
+                writer.write_all(&(n as u64).to_le_bytes())?;
+            }
+            for v in &bake.forward {
+                writer.write_all(&v.to_le_bytes())?;
+            }
+            writer.write_all(&bake.lut)?;
+            for v in &bake.lut_f32 {
+                writer.write_all(&v.to_le_bytes())?;
+            }
+            writer.write_all(&bake.sorted)?;
+            writer.flush()?;
+            Ok(())
+        })
+    }
+}
+
+/// 64-bit FNV-1a, which unlike `std`'s hasher is the same in every build.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Reads little-endian values off the front of a cache file's bytes.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        if n > self.0.len() {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+
+    fn f32s(&mut self, n: usize) -> Option<Vec<f32>> {
+        let bytes = self.bytes(n.checked_mul(4)?)?;
+        Some(
+            bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::CancelToken;
+    use crate::distribution::DistributionKind;
+    use crate::self_test::synthetic_input;
+
+    #[test]
+    fn changing_one_channel_only_rebakes_that_channel() {
+        let dir = std::env::temp_dir().join(format!("precompute-cache-{}", std::process::id()));
+        let cache = ChannelCache::new(&dir).unwrap();
+        let pages = [synthetic_input()];
+        let cancel = CancelToken::new();
+        let gaussian = vec![DistributionKind::Gaussian; 3];
+        let mut uniform_blue = gaussian.clone();
+        uniform_blue[2] = DistributionKind::Uniform;
+        let config = TransformConfig::default().deterministic(true);
+        let first_config = config.clone().channel_distributions(Some(gaussian));
+        let second_config = config.channel_distributions(Some(uniform_blue));
+
+        let (first, reused) = first_config.run_cached(&pages, &cache, &cancel).unwrap();
+        assert_eq!(reused, 0);
+        let (again, reused) = first_config.run_cached(&pages, &cache, &cancel).unwrap();
+        assert_eq!(reused, 3);
+        assert_eq!((again.image, again.lut), (first.image.clone(), first.lut));
+
+        let (second, reused) = second_config.run_cached(&pages, &cache, &cancel).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(reused, 2);
+        let expected = second_config.run_pages(&pages, &cancel).unwrap();
+        assert_eq!(second.image, expected.image);
+        assert_eq!(second.lut, expected.lut);
+        let channel = |image: &[f32], c: usize| -> Vec<f32> {
+            image.iter().skip(c).step_by(3).cloned().collect()
+        };
+        assert_eq!(channel(&second.image, 0), channel(&first.image, 0));
+        assert_eq!(channel(&second.image, 1), channel(&first.image, 1));
+        assert_ne!(channel(&second.image, 2), channel(&first.image, 2));
+    }
+}
@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+
+/// Which source channels are transformed. Outputs are always written as RGB,
+/// with channels outside the set stored as zero.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channels {
+    #[default]
+    Rgb,
+    Rg,
+}
+
+/// A single RGB channel.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Debug, PartialEq, Eq, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    R,
+    G,
+    B,
+}
+
+impl Channel {
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl Channels {
+    pub fn count(self) -> usize {
+        match self {
+            Channels::Rgb => 3,
+            Channels::Rg => 2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::self_test::synthetic_input;
+    use crate::TransformConfig;
+
+    #[test]
+    fn rg_transforms_only_red_and_green() {
+        let input = synthetic_input();
+        let config = TransformConfig::default().deterministic(true);
+        let rgb = config.run(&input).unwrap();
+        let rg = config.channels(Channels::Rg).run(&input).unwrap();
+        assert_eq!(rg.histogram.channel_count(), 2);
+        // Still RGB, with blue stored as zero and red and green unchanged.
+        assert_eq!(rg.image.len(), rgb.image.len());
+        assert_eq!(rg.lut.len(), rgb.lut.len());
+        for (a, b) in rg.image.chunks_exact(3).zip(rgb.image.chunks_exact(3)) {
+            assert_eq!((a[0], a[1], a[2]), (b[0], b[1], 0.0));
+        }
+        for (a, b) in rg.lut.chunks_exact(3).zip(rgb.lut.chunks_exact(3)) {
+            assert_eq!((a[0], a[1], a[2]), (b[0], b[1], 0));
+        }
+    }
+}
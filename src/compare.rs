@@ -0,0 +1,142 @@
+use rayon::prelude::*;
+
+/// Subpixels summed per partial sum. Fixed so the mean doesn't depend on how
+/// rayon splits the work.
+const CHUNK_LEN: usize = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Difference {
+    pub max_abs: f64,
+    pub mean_abs: f64,
+    /// Root mean square difference.
+    pub rmse: f64,
+}
+
+/// Per-subpixel absolute difference between two equally sized buffers.
+pub fn difference<T>(a: &[T], b: &[T]) -> Difference
+where
+    T: Copy + Into<f64> + Sync,
+{
+    assert_eq!(a.len(), b.len());
+    let partials: Vec<(f64, f64, f64)> = a
+        .par_chunks(CHUNK_LEN)
+        .zip(b.par_chunks(CHUNK_LEN))
+        .map(|(a, b)| {
+            a.iter()
+                .zip(b)
+                .map(|(a, b)| ((*a).into() - (*b).into()).abs())
+                .fold((0.0_f64, 0.0_f64, 0.0_f64), |(max, sum, sum_sq), d| {
+                    (max.max(d), sum + d, sum_sq + d * d)
+                })
+        })
+        .collect();
+    let (max_abs, sum_abs, sum_sq) = partials
+        .into_iter()
+        .fold((0.0_f64, 0.0_f64, 0.0_f64), |(m1, s1, q1), (m2, s2, q2)| {
+            (m1.max(m2), s1 + s2, q1 + q2)
+        });
+    let mean = |sum: f64| {
+        if a.is_empty() {
+            0.0
+        } else {
+            sum / a.len() as f64
+        }
+    };
+    Difference {
+        max_abs,
+        mean_abs: mean(sum_abs),
+        rmse: mean(sum_sq).sqrt(),
+    }
+}
+
+/// Stops of the heat map [`diff_heat_map`] draws, from no difference to the
+/// full scale, roughly matplotlib's `inferno`: black through purple, red
+/// and orange to pale yellow, getting lighter all the way.
+const HEAT: [[f64; 3]; 5] = [
+    [0.0, 0.0, 4.0],
+    [87.0, 16.0, 110.0],
+    [188.0, 55.0, 84.0],
+    [249.0, 142.0, 9.0],
+    [252.0, 255.0, 164.0],
+];
+
+/// Per-pixel heat map of how far two equally sized interleaved images with
+/// `channels` samples per pixel differ, as 8-bit RGB: each pixel's largest
+/// absolute difference over its channels, as a share of `full_scale`, picks
+/// a color from black for none to pale yellow at `full_scale` or more.
+pub fn diff_heat_map(a: &[f32], b: &[f32], channels: usize, full_scale: f64) -> Vec<u8> {
+    assert_eq!(a.len(), b.len());
+    a.par_chunks(channels)
+        .zip(b.par_chunks(channels))
+        .flat_map_iter(|(a, b)| {
+            let d = a
+                .iter()
+                .zip(b)
+                .map(|(a, b)| (*a as f64 - *b as f64).abs())
+                .fold(0.0, f64::max);
+            let position = (d / full_scale).clamp(0.0, 1.0) * (HEAT.len() - 1) as f64;
+            let stop = (position.floor() as usize).min(HEAT.len() - 2);
+            let t = position - stop as f64;
+            (0..3).map(move |c| (HEAT[stop][c] * (1.0 - t) + HEAT[stop + 1][c] * t).round() as u8)
+        })
+        .collect()
+}
+
+/// 8-bit RGB map of where `errors`, one per pixel, are above `tolerance`:
+/// those pixels are red, and the rest show `background`, one gray level per
+/// pixel, at a quarter of its brightness so the texture stays recognizable
+/// under the hotspots.
+pub fn threshold_map(errors: &[u8], background: &[u8], tolerance: f64) -> Vec<u8> {
+    assert_eq!(errors.len(), background.len());
+    errors
+        .par_iter()
+        .zip(background)
+        .flat_map_iter(|(&error, &gray)| match error as f64 > tolerance {
+            true => [255, 0, 0],
+            false => [gray / 4; 3],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::{encode_image_to_vec, Tiff};
+    use crate::read::read_rgb_f32;
+    use crate::self_test::synthetic_input;
+    use crate::TransformConfig;
+
+    #[test]
+    fn reference_bake_diffs_against_itself_and_a_perturbed_copy() {
+        let baked = TransformConfig::default()
+            .deterministic(true)
+            .run(&synthetic_input())
+            .unwrap();
+        let path =
+            std::env::temp_dir().join(format!("precompute-compare-{}.tif", std::process::id()));
+        std::fs::write(
+            &path,
+            encode_image_to_vec(&baked, &Tiff::default()).unwrap(),
+        )
+        .unwrap();
+        let reference = read_rgb_f32(&path);
+        std::fs::remove_file(&path).unwrap();
+        let reference = reference.unwrap();
+        assert_eq!(
+            (reference.width, reference.height),
+            (baked.width, baked.height)
+        );
+
+        let same = difference(&baked.image, &reference.data);
+        assert_eq!(same.max_abs, 0.0);
+        assert_eq!(same.mean_abs, 0.0);
+
+        let mut perturbed = reference.data;
+        perturbed[7] += 0.25;
+        perturbed[100] -= 0.5;
+        let diff = difference(&baked.image, &perturbed);
+        assert!((diff.max_abs - 0.5).abs() < 1e-6);
+        let mean = 0.75 / perturbed.len() as f64;
+        assert!((diff.mean_abs - mean).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,409 @@
+use std::fmt::Write;
+
+use crate::config::{
+    LutEndpoints, LutInterp, LutRowMode, LutSampling, LutTexelOffset, NanPolicy, PlottingPosition,
+    Prefilter, TransformConfig,
+};
+use crate::distribution::{DistributionKind, ACKLAM_A, ACKLAM_B, ACKLAM_C, ACKLAM_D, P_LOW};
+
+/// A WGSL compute shader that runs `config`'s bake on the GPU, for engines
+/// that would rather transform textures at load time than ship baked ones.
+/// It ranks 8-bit levels as the CPU does, by counting them, and has five
+/// entry points, dispatched in order:
+///
+/// - `clear_counts` and `prefix_sums`: one workgroup per channel;
+/// - `count_levels` and `assign_ranks`: one 16x16 workgroup per 16x16 block of
+///   the source;
+/// - `build_lut`: one 64-wide workgroup per 64 texels of the LUT.
+///
+/// The source is an `rgba8unorm` texture at binding 0, and the passes write
+/// the forward image to an `rgba32float` storage texture at binding 1 and the
+/// LUT to an `rgba8unorm` one at binding 2, whose size picks the LUT's width
+/// and rows; bindings 3 to 5 are storage buffers of 768 `u32`s for the
+/// counts, their prefix sums and the next rank of each level. The target and
+/// its parameters are constants of the shader. Equal subpixels take their
+/// ranks in whatever order the GPU's atomics hand them out, like a bake
+/// without `deterministic`. The quantile function is Acklam's and the CDF
+/// Abramowitz and Stegun's, as with [`QuantilePrecision::Low`](crate::QuantilePrecision::Low),
+/// evaluated in 32-bit floats. Options the shader can't run are rejected.
+pub fn compute_shader(config: &TransformConfig) -> Result<String, anyhow::Error> {
+    config.validate()?;
+    for (unsupported, name) in [
+        (
+            config.channel_distributions.is_some(),
+            "channel_distributions",
+        ),
+        (config.target_cdf.is_some(), "target_cdf"),
+        (config.mean_gradient.is_some(), "mean_gradient"),
+        (config.prefilter != Prefilter::None, "prefilter"),
+        (config.lut_channels.is_some(), "lut_channels"),
+        (config.alpha_cutoff.is_some(), "alpha_cutoff"),
+        (config.nan_policy == NanPolicy::Skip, "nan_policy skip"),
+        (config.lut_interp != LutInterp::Nearest, "lut_interp"),
+        (config.lut_endpoints != LutEndpoints::Clamp, "lut_endpoints"),
+        (config.preserve_extremes, "preserve_extremes"),
+        (config.trim_tails != 0.0, "trim_tails"),
+        (config.clip_limit.is_some(), "clip_limit"),
+        (config.lut_row_mode != LutRowMode::Replicate, "lut_row_mode"),
+        (config.shared_lut, "shared_lut"),
+        (config.deterministic, "deterministic"),
+        (config.histogram_samples.is_some(), "histogram_samples"),
+        (config.profile_guided_lut, "profile_guided_lut"),
+        (config.lut_sampling != LutSampling::Uniform, "lut_sampling"),
+    ] {
+        if unsupported {
+            return Err(anyhow::anyhow!(
+                "{} isn't supported by the compute shader",
+                name
+            ));
+        }
+    }
+
+    let mut shader = String::new();
+    let (scale, offset) = config.forward_encoding();
+    writeln!(
+        shader,
+        "// Generated by precompute: a {:?} target, mean {} std {}.",
+        config.distribution, config.mean, config.std
+    )?;
+    writeln!(
+        shader,
+        "const CHANNELS: u32 = {}u;",
+        config.channels.count()
+    )?;
+    writeln!(shader, "const MEAN: f32 = {};", literal(config.lut_mean()))?;
+    writeln!(shader, "const STD: f32 = {};", literal(config.std))?;
+    writeln!(shader, "const SCALE: f32 = {};", literal(scale))?;
+    writeln!(shader, "const OFFSET: f32 = {};", literal(offset))?;
+    shader.push_str(BINDINGS);
+
+    shader.push_str("\nfn base_inv_cdf(u: f32) -> f32 {\n");
+    match config.distribution {
+        DistributionKind::Gaussian => {
+            let (central, tail) = (
+                format!(
+                    "{} * q / ({} * r + 1.0)",
+                    horner(&ACKLAM_A, "r"),
+                    horner(&ACKLAM_B, "r")
+                ),
+                format!(
+                    "{} / ({} * q + 1.0)",
+                    horner(&ACKLAM_C, "q"),
+                    horner(&ACKLAM_D, "q")
+                ),
+            );
+            writeln!(shader, "    var z: f32;")?;
+            writeln!(
+                shader,
+                "    if (u >= {0} && u <= 1.0 - {0}) {{",
+                literal(P_LOW)
+            )?;
+            writeln!(shader, "        let q = u - 0.5;")?;
+            writeln!(shader, "        let r = q * q;")?;
+            writeln!(shader, "        z = {};", central)?;
+            writeln!(shader, "    }} else {{")?;
+            writeln!(shader, "        let q = sqrt(-2.0 * log(min(u, 1.0 - u)));")?;
+            writeln!(shader, "        z = select(-1.0, 1.0, u < 0.5) * {};", tail)?;
+            writeln!(shader, "    }}")?;
+            writeln!(shader, "    return MEAN + STD * z;")?;
+        }
+        DistributionKind::Uniform => {
+            writeln!(shader, "    let half_width = STD * sqrt(3.0);")?;
+            writeln!(
+                shader,
+                "    return MEAN - half_width + u * 2.0 * half_width;"
+            )?;
+        }
+    }
+    shader.push_str("}\n\nfn base_cdf(x: f32) -> f32 {\n");
+    match config.distribution {
+        DistributionKind::Gaussian => {
+            let erfc = horner(
+                &[
+                    1.061405429,
+                    -1.453152027,
+                    1.421413741,
+                    -0.284496736,
+                    0.254829592,
+                ],
+                "t",
+            );
+            writeln!(shader, "    let z = abs(x - MEAN) / (STD * sqrt(2.0));")?;
+            writeln!(shader, "    let t = 1.0 / (1.0 + 0.3275911 * z);")?;
+            writeln!(shader, "    let tail = 0.5 * t * {} * exp(-z * z);", erfc)?;
+            writeln!(shader, "    return select(tail, 1.0 - tail, x >= MEAN);")?;
+        }
+        DistributionKind::Uniform => {
+            writeln!(shader, "    let half_width = STD * sqrt(3.0);")?;
+            writeln!(
+                shader,
+                "    return clamp((x - MEAN + half_width) / (2.0 * half_width), 0.0, 1.0);"
+            )?;
+        }
+    }
+    shader.push_str("}\n");
+
+    // The target through `median_target`, `post_contrast` and the encoding,
+    // in the order `TransformConfig::channel_target` wraps them.
+    let warp = (config.median_target != 0.5).then(|| config.median_target.ln() / 0.5f64.ln());
+    let contrast = (config.post_contrast != 1.0).then_some(config.post_contrast);
+    if contrast.is_some() {
+        shader.push_str(CONTRAST);
+    }
+    shader.push_str("\nfn target_inv_cdf(u: f32) -> f32 {\n");
+    match warp {
+        Some(exponent) => writeln!(
+            shader,
+            "    var x = base_inv_cdf(pow(u, {}));",
+            literal(exponent)
+        )?,
+        None => writeln!(shader, "    var x = base_inv_cdf(u);")?,
+    }
+    if let Some(strength) = contrast {
+        writeln!(shader, "    x = contrast(x, {});", literal(1.0 / strength))?;
+    }
+    shader.push_str("    return OFFSET + SCALE * x;\n}\n\nfn target_cdf(stored: f32) -> f32 {\n");
+    writeln!(shader, "    var x = (stored - OFFSET) / SCALE;")?;
+    if let Some(strength) = contrast {
+        writeln!(shader, "    x = contrast(x, {});", literal(strength))?;
+    }
+    match warp {
+        Some(exponent) => writeln!(
+            shader,
+            "    return pow(base_cdf(x), {});",
+            literal(1.0 / exponent)
+        )?,
+        None => writeln!(shader, "    return base_cdf(x);")?,
+    }
+    shader.push_str("}\n");
+
+    // `PlottingPosition::quantile` and `position`, and `lut_texel_value`.
+    let (quantile, position) = match config.plotting_position {
+        PlottingPosition::Midpoint => ("(f32(k) + 0.5) / f32(n)", "u * f32(n)"),
+        PlottingPosition::Linear => (
+            "select(f32(k) / f32(n - 1u), 0.5, n == 1u)",
+            "u * f32(n - 1u) + 0.5",
+        ),
+        PlottingPosition::Blom => (
+            "(f32(k) + 0.625) / (f32(n) + 0.25)",
+            "u * (f32(n) + 0.25) - 0.125",
+        ),
+    };
+    let texel_value = match (config.lut_for_linear_filtering, config.lut_texel_offset) {
+        (true, _) => "select((f32(i) + 0.5) / f32(width), f32(i) / f32(width - 1u), width > 1u)",
+        (false, LutTexelOffset::None) => "(f32(i) + 0.5) / f32(width)",
+        (false, LutTexelOffset::Half) => "f32(i) / f32(width)",
+    };
+    writeln!(
+        shader,
+        "\nfn quantile(k: u32, n: u32) -> f32 {{\n    return {};\n}}",
+        quantile
+    )?;
+    writeln!(
+        shader,
+        "\nfn position(u: f32, n: u32) -> f32 {{\n    return {};\n}}",
+        position
+    )?;
+    writeln!(
+        shader,
+        "\nfn texel_value(i: u32, width: u32) -> f32 {{\n    return {};\n}}",
+        texel_value
+    )?;
+    shader.push_str(PASSES);
+    Ok(shader)
+}
+
+/// `x` as a WGSL float literal; Rust always writes a `.` or an exponent.
+pub(crate) fn literal(x: f64) -> String {
+    format!("{:?}", x)
+}
+
+/// The polynomial with `coefficients`, highest power first, in `variable`,
+/// by Horner's rule.
+fn horner(coefficients: &[f64], variable: &str) -> String {
+    coefficients[1..]
+        .iter()
+        .fold(literal(coefficients[0]), |acc, c| {
+            format!("({} * {} + {})", acc, variable, literal(*c))
+        })
+}
+
+const BINDINGS: &str = "
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var forward: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(2) var lut: texture_storage_2d<rgba8unorm, write>;
+// Subpixels at each level of each channel, channel-major.
+@group(0) @binding(3) var<storage, read_write> counts: array<atomic<u32>, 768>;
+// Subpixels below each level, the rank of its first subpixel.
+@group(0) @binding(4) var<storage, read_write> offsets: array<u32, 768>;
+// Ranks each level has handed out so far.
+@group(0) @binding(5) var<storage, read_write> cursors: array<atomic<u32>, 768>;
+
+var<workgroup> scan: array<u32, 256>;
+";
+
+const CONTRAST: &str = "
+fn contrast(x: f32, exponent: f32) -> f32 {
+    let r = 3.0 * STD;
+    let d = (x - MEAN) / r;
+    return MEAN + r * sign(d) * pow(abs(d), exponent);
+}
+";
+
+const PASSES: &str = "
+fn level(x: f32) -> u32 {
+    return u32(round(clamp(x, 0.0, 1.0) * 255.0));
+}
+
+// The level of channel `c` the subpixel of rank `rank` holds: the last one
+// whose first rank is at or below it.
+fn level_of_rank(c: u32, rank: u32) -> u32 {
+    var low = 0u;
+    var high = 255u;
+    while (low < high) {
+        let mid = (low + high + 1u) / 2u;
+        if (offsets[c * 256u + mid] <= rank) {
+            low = mid;
+        } else {
+            high = mid - 1u;
+        }
+    }
+    return low;
+}
+
+@compute @workgroup_size(256)
+fn clear_counts(@builtin(workgroup_id) group: vec3<u32>, @builtin(local_invocation_id) local: vec3<u32>) {
+    atomicStore(&counts[group.x * 256u + local.x], 0u);
+}
+
+@compute @workgroup_size(16, 16)
+fn count_levels(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(source);
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+    let texel = textureLoad(source, vec2<i32>(id.xy), 0);
+    for (var c = 0u; c < CHANNELS; c = c + 1u) {
+        atomicAdd(&counts[c * 256u + level(texel[c])], 1u);
+    }
+}
+
+@compute @workgroup_size(256)
+fn prefix_sums(@builtin(workgroup_id) group: vec3<u32>, @builtin(local_invocation_id) local: vec3<u32>) {
+    let i = group.x * 256u + local.x;
+    let count = atomicLoad(&counts[i]);
+    scan[local.x] = count;
+    workgroupBarrier();
+    for (var stride = 1u; stride < 256u; stride = stride * 2u) {
+        var sum = scan[local.x];
+        if (local.x >= stride) {
+            sum = sum + scan[local.x - stride];
+        }
+        workgroupBarrier();
+        scan[local.x] = sum;
+        workgroupBarrier();
+    }
+    offsets[i] = scan[local.x] - count;
+    atomicStore(&cursors[i], 0u);
+}
+
+@compute @workgroup_size(16, 16)
+fn assign_ranks(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(source);
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+    let n = size.x * size.y;
+    let texel = textureLoad(source, vec2<i32>(id.xy), 0);
+    var value = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    for (var c = 0u; c < CHANNELS; c = c + 1u) {
+        let i = c * 256u + level(texel[c]);
+        let rank = offsets[i] + atomicAdd(&cursors[i], 1u);
+        value[c] = target_inv_cdf(quantile(rank, n));
+    }
+    textureStore(forward, vec2<i32>(id.xy), value);
+}
+
+@compute @workgroup_size(64)
+fn build_lut(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(lut);
+    if (id.x >= size.x) {
+        return;
+    }
+    let source_size = textureDimensions(source);
+    let n = source_size.x * source_size.y;
+    let u = target_cdf(texel_value(id.x, size.x));
+    let rank = min(u32(max(position(u, n), 0.0)), n - 1u);
+    var value = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    for (var c = 0u; c < CHANNELS; c = c + 1u) {
+        value[c] = f32(level_of_rank(c, rank)) / 255.0;
+    }
+    for (var row = 0u; row < size.y; row = row + 1u) {
+        textureStore(lut, vec2<i32>(i32(id.x), i32(row)), value);
+    }
+}
+";
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Parses and validates `source` as naga would for a GPU backend.
+    pub(crate) fn validate_wgsl(source: &str) -> Result<(), String> {
+        let module = naga::front::wgsl::parse_str(source).map_err(|e| e.emit_to_string(source))?;
+        naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::all(),
+        )
+        .validate(&module)
+        .map(|_| ())
+        .map_err(|e| e.emit_to_string(source))
+    }
+
+    #[test]
+    fn every_compute_shader_variant_validates() {
+        let mut validated = 0;
+        for distribution in [DistributionKind::Gaussian, DistributionKind::Uniform] {
+            for plotting_position in [
+                PlottingPosition::Midpoint,
+                PlottingPosition::Linear,
+                PlottingPosition::Blom,
+            ] {
+                for (linear_filtering, offset) in [
+                    (false, LutTexelOffset::None),
+                    (false, LutTexelOffset::Half),
+                    (true, LutTexelOffset::None),
+                ] {
+                    for (median_target, post_contrast) in
+                        [(0.5, 1.0), (0.3, 1.0), (0.5, 1.5), (0.7, 0.8)]
+                    {
+                        for signed_encoding in [false, true] {
+                            let config = TransformConfig::default()
+                                .distribution(distribution)
+                                .plotting_position(plotting_position)
+                                .lut_for_linear_filtering(linear_filtering)
+                                .lut_texel_offset(offset)
+                                .median_target(median_target)
+                                .post_contrast(post_contrast)
+                                .signed_encoding(signed_encoding);
+                            // Linear plotting positions need a bounded target.
+                            let Ok(shader) = compute_shader(&config) else {
+                                assert_eq!(
+                                    (distribution, plotting_position),
+                                    (DistributionKind::Gaussian, PlottingPosition::Linear)
+                                );
+                                continue;
+                            };
+                            if let Err(e) = validate_wgsl(&shader) {
+                                panic!("{:?}:\n{}", config, e);
+                            }
+                            validated += 1;
+                        }
+                    }
+                }
+            }
+        }
+        assert_eq!(validated, 2 * 3 * 3 * 4 * 2 - 3 * 4 * 2);
+        assert!(compute_shader(&TransformConfig::default().shared_lut(true)).is_err());
+    }
+}
@@ -0,0 +1,3466 @@
+use std::borrow::Cow;
+use std::time::Duration;
+
+use image::{DynamicImage, GenericImageView};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::atlas::atlas_tiles;
+use crate::cache::ChannelCache;
+use crate::cancel::CancelToken;
+use crate::channels::{Channel, Channels};
+use crate::compare::difference;
+use crate::distribution::{
+    avx_available, Contrasted, Distribution, DistributionKind, Encoded, FastGaussian, Pivoted,
+    QuantileBackend, QuantilePrecision, RationalGaussian, RefinedGaussian, Tabulated,
+};
+use crate::height::{transform_heights, HeightResult, HeightSample};
+use crate::histogram::{ChannelCorrelation, Histogram};
+use crate::hsv::rgb_to_hsv;
+use crate::inverse::{lut_coordinates, sample_lut, warp_coordinates};
+use crate::read::{non_finite_pixels, stack_pages, validate_image, zero_non_finite};
+use crate::rows::ForwardRows;
+use crate::transform::{
+    build_histogram, build_lut, extract_channels, extract_raw_channels, identity_column,
+    interleave, lut_knots, prefilter_channel, rank, transform_channel, transform_histogram,
+    trimmed_exclusions, Ranks,
+};
+use crate::{GAUSSIAN_AVERAGE, GAUSSIAN_STD};
+
+/// Parameters for a transform. Construct with [`TransformConfig::new`] and
+/// chain the setters, or deserialize one; every field's default matches the CLI's.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[non_exhaustive]
+pub struct TransformConfig {
+    /// Mean of the target distribution. Default `0.5`.
+    pub mean: f64,
+    /// Standard deviation of the target distribution. Default `1/6`, so ±3σ spans `[0, 1]`.
+    pub std: f64,
+    /// Shape of the target distribution. Default Gaussian.
+    pub distribution: DistributionKind,
+    /// Shape of each transformed channel's target distribution instead of
+    /// `distribution`, one per channel of `channels`, for packed textures
+    /// whose channels hold different kinds of data. `mean` and `std` are
+    /// shared. Default `None`, `distribution` for every channel.
+    pub channel_distributions: Option<Vec<DistributionKind>>,
+    /// Tabulated quantile function used instead of `distribution`, `mean`
+    /// and `std`. Default `None`.
+    pub target_cdf: Option<Tabulated>,
+    /// Vary the mean linearly across each page, replacing `mean`. The LUT is
+    /// built for the gradient's midpoint, so consumers subtract
+    /// [`MeanGradient::offset`] from a sample before looking it up. Not
+    /// available with `target_cdf`. Default `None`.
+    pub mean_gradient: Option<MeanGradient>,
+    /// Channels to transform. Default RGB.
+    pub channels: Channels,
+    /// Filter applied to each channel before it's ranked, so the forward
+    /// image and LUT describe the filtered texture. Default none.
+    pub prefilter: Prefilter,
+    /// Standard deviation in pixels of [`Prefilter::Bilateral`]'s spatial
+    /// weights. Default `2.0`.
+    pub prefilter_sigma_spatial: f64,
+    /// Standard deviation in 8-bit levels of [`Prefilter::Bilateral`]'s range
+    /// weights; neighbours further apart than about three times this are
+    /// left out. Default `16.0`.
+    pub prefilter_sigma_range: f64,
+    /// Number of LUT entries. Default `None`, meaning [`default_lut_size`] of
+    /// the input's dimensions.
+    pub lut_size: Option<u32>,
+    /// Channels that get a LUT column built from their histogram; the rest get
+    /// an identity column, returning the Gaussian value itself. Default `None`,
+    /// meaning every transformed channel.
+    pub lut_channels: Option<Vec<Channel>>,
+    /// Leave pixels whose alpha, in `[0, 1]`, is below this out of the
+    /// histogram. They are still transformed, against the histogram of the
+    /// rest. Default `None`.
+    pub alpha_cutoff: Option<f64>,
+    /// What to do with NaN and infinite samples of float inputs and
+    /// heightmaps. Default error.
+    pub nan_policy: NanPolicy,
+    /// Round the LUT size up to the next power of two. Default `false`.
+    pub pad_lut: bool,
+    /// Build the LUT for a sampler that filters linearly between texels: the
+    /// first and last texels hold the ends of the forward range instead of
+    /// values half a texel in, so nothing is clamped at the tails, and the
+    /// LUT is sampled at [`TransformConfig::lut_coordinate`]. Not available
+    /// with [`LutRowMode::Jitter`]. Default `false`.
+    pub lut_for_linear_filtering: bool,
+    /// Where the sampler the LUT is built for centers its texels. Not
+    /// available with `lut_for_linear_filtering`. Default none.
+    pub lut_texel_offset: LutTexelOffset,
+    /// How each LUT texel samples the sorted source values. Default nearest.
+    pub lut_interp: LutInterp,
+    /// What LUT texels beyond the outermost samples' quantiles hold.
+    /// Default clamp.
+    pub lut_endpoints: LutEndpoints,
+    /// Which forward values the LUT's texels are built for, see
+    /// [`LutSampling`]. Not available with `lut_for_linear_filtering`,
+    /// `lut_texel_offset`, [`LutRowMode::Jitter`] or `profile_guided_lut`.
+    /// Default uniform.
+    pub lut_sampling: LutSampling,
+    /// Quantile each sorted subpixel is ranked at, in the forward image and
+    /// in the LUT alike. Not available with weights, `clip_limit` or
+    /// `histogram_samples`. Default midpoint.
+    pub plotting_position: PlottingPosition,
+    /// Rank every subpixel holding a channel's lowest value at the lowest
+    /// subpixel's quantile, and every one holding its highest at the highest's,
+    /// instead of spreading each tie over its share of the target, so the
+    /// extremes of a mask or similar map to the outermost forward values. Not
+    /// available with `histogram_samples`. Default `false`.
+    pub preserve_extremes: bool,
+    /// Percentage of each channel's lowest and of its highest subpixels left
+    /// out of the histogram, in `[0, 50)`. Those outliers are ranked as the
+    /// nearest subpixel kept, so the target's range goes to the bulk of the
+    /// values and the LUT clamps them to its ends. Not available with weights
+    /// or `histogram_samples`. Default `0.0`.
+    pub trim_tails: f64,
+    /// Clip each level's count, or weight, at this multiple of the mean per
+    /// level of all 256, at least 1, and spread what was clipped evenly over
+    /// all levels, as CLAHE does, so a dominant level isn't stretched over
+    /// the target and its noise amplified. A level that doesn't occur drops
+    /// its share of the excess, since no subpixel could take it. Applies to
+    /// whole-image, atlas and local-tile transforms. Not available with
+    /// `histogram_samples` or plotting positions but the midpoint. Default
+    /// `None`.
+    pub clip_limit: Option<f64>,
+    /// How LUT values between two 8-bit levels are quantized. Default round.
+    pub rounding: Rounding,
+    /// Rows in the LUT, for hardware that filters across a thin 2D texture.
+    /// Default `1`.
+    pub lut_rows: u32,
+    /// What the rows after the first hold. Default replicate.
+    pub lut_row_mode: LutRowMode,
+    /// Rank all channels together and give every channel the same LUT,
+    /// instead of one histogram per channel. Default `false`.
+    pub shared_lut: bool,
+    /// Quantile of the target the source median maps to: ranks are warped
+    /// monotonically towards one tail so the middle sample lands there, and
+    /// the rest follow. `mean` and `std` still describe the unwarped target,
+    /// so anything but `0.5` moves the forward image's own mean and median
+    /// away from `mean`. Default `0.5` (none).
+    pub median_target: f64,
+    /// Strength of a contrast curve applied around the mean of the forward
+    /// image; the LUT is built through the same curve. Default `1.0` (none).
+    pub post_contrast: f64,
+    /// Store each forward value `v` as `0.5 + v / 2`, so a target centered on
+    /// zero, as for signed channels such as chroma, keeps its sign inside the
+    /// LUT's `[0, 1]` domain with `0.5` meaning zero. The LUT is indexed by
+    /// the stored value; see [`TransformConfig::forward_encoding`]. Not
+    /// available with `mean_gradient`. Default `false`.
+    pub signed_encoding: bool,
+    /// Break ties between equal subpixels by their position in the image, so
+    /// outputs are byte-identical across runs and thread counts. Default `false`.
+    pub deterministic: bool,
+    /// Build the histogram from this many subpixels per channel, drawn at
+    /// random, instead of sorting them all. Each subpixel's quantile is then
+    /// interpolated from the sample's CDF. Ties are broken in image order, so
+    /// a sample at least as large as the input matches a `deterministic`
+    /// transform. Not available with weights. Default `None`, every subpixel.
+    pub histogram_samples: Option<usize>,
+    /// Seed for every random choice a bake makes, which for now is only the
+    /// subpixels `histogram_samples` draws. The same seed and input give the
+    /// same bake on any machine; without `deterministic`, ties between equal
+    /// subpixels can still come out differently. Default `0`.
+    #[serde(alias = "histogram_seed")]
+    pub seed: u64,
+    /// Map ranks onto a Gaussian target through [`FastGaussian`], which
+    /// approximates its quantile function several values at a time on CPUs
+    /// with AVX. Forward values stay within a float32 rounding step of the
+    /// exact ones, but can differ from them, and between machines with and
+    /// without AVX, in the last bit. Default `false`.
+    pub fast_quantiles: bool,
+    /// How precisely Gaussian targets' quantile function and CDF are
+    /// evaluated, see [`QuantilePrecision`]. Not available with
+    /// `fast_quantiles`. Default [`QuantilePrecision::Default`].
+    pub quantile_precision: QuantilePrecision,
+    /// Give each LUT column only as many distinct texels as its channel's
+    /// histogram needs, per [`TransformConfig::effective_lut_width`], each
+    /// repeated to fill the full width. Columns stay `lut_width` wide, so
+    /// shaders sample them as before. Default `false`.
+    pub profile_guided_lut: bool,
+}
+
+impl Default for TransformConfig {
+    fn default() -> Self {
+        Self {
+            mean: GAUSSIAN_AVERAGE,
+            std: *GAUSSIAN_STD,
+            distribution: DistributionKind::default(),
+            channel_distributions: None,
+            target_cdf: None,
+            mean_gradient: None,
+            channels: Channels::default(),
+            prefilter: Prefilter::default(),
+            prefilter_sigma_spatial: 2.0,
+            prefilter_sigma_range: 16.0,
+            lut_size: None,
+            lut_channels: None,
+            alpha_cutoff: None,
+            nan_policy: NanPolicy::default(),
+            pad_lut: false,
+            lut_for_linear_filtering: false,
+            lut_texel_offset: LutTexelOffset::default(),
+            lut_interp: LutInterp::default(),
+            lut_endpoints: LutEndpoints::default(),
+            lut_sampling: LutSampling::default(),
+            plotting_position: PlottingPosition::default(),
+            preserve_extremes: false,
+            trim_tails: 0.0,
+            clip_limit: None,
+            rounding: Rounding::default(),
+            lut_rows: 1,
+            lut_row_mode: LutRowMode::default(),
+            shared_lut: false,
+            median_target: 0.5,
+            post_contrast: 1.0,
+            signed_encoding: false,
+            deterministic: false,
+            histogram_samples: None,
+            seed: 0,
+            fast_quantiles: false,
+            quantile_precision: QuantilePrecision::Default,
+            profile_guided_lut: false,
+        }
+    }
+}
+
+/// Smallest LUT [`default_lut_size`] picks, one texel per 8-bit level.
+pub const MIN_DEFAULT_LUT_SIZE: u32 = 256;
+
+/// The LUT size used without `lut_size`: the geometric mean of the input's
+/// sides, so a square input gets a LUT as wide as itself and a strip one as
+/// wide as a square of the same pixel count, clamped to
+/// [`MIN_DEFAULT_LUT_SIZE`] and [`MAX_FITTED_LUT_SIZE`].
+pub fn default_lut_size(width: u32, height: u32) -> u32 {
+    let side = (width as f64 * height as f64).sqrt().round() as u32;
+    side.clamp(MIN_DEFAULT_LUT_SIZE, MAX_FITTED_LUT_SIZE)
+}
+
+/// Largest LUT [`TransformConfig::fit_lut_size`] tries, the widest 1D texture
+/// most GPUs support.
+pub const MAX_FITTED_LUT_SIZE: u32 = 16384;
+
+/// Texels `profile_guided_lut` keeps per distinct source value of a channel,
+/// so a channel using every 8-bit level keeps up to 4096.
+pub const PROFILE_TEXELS_PER_VALUE: u32 = 16;
+
+/// How the inverse LUT picks a source value for each texel's quantile.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LutInterp {
+    /// The sorted sample at that quantile. LUT values are always source values.
+    #[default]
+    Nearest,
+    /// Interpolate between the two neighbouring sorted samples, for smoother
+    /// steps on gradients. Best kept unrounded with a float LUT.
+    Linear,
+}
+
+/// What the inverse LUT holds at texels whose quantile lies beyond the
+/// lowest or highest source sample's, which only the ends of the target's
+/// range reach.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LutEndpoints {
+    /// The lowest or highest source value, so reconstruction never leaves the
+    /// observed range.
+    #[default]
+    Clamp,
+    /// Continue the tail's slope past the observed range, from the outermost
+    /// value to the nearest different one. 8-bit LUTs still stop at 0 and 255.
+    Extend,
+}
+
+/// Texture coordinate a sampler centers texel `i` of a `W`-wide LUT at, which
+/// the LUT is built for so that sampling it at the stored forward value `g`
+/// itself reconstructs.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LutTexelOffset {
+    /// `(i + 0.5) / W`, as OpenGL, Vulkan, Metal and Direct3D 10 and later
+    /// sample, and as `lut[floor(g * W)]` looks up: texel `i` holds the
+    /// source value for `g = (i + 0.5) / W`.
+    #[default]
+    None,
+    /// `i / W`, half a texel lower, as Direct3D 9 samples without the
+    /// half-texel correction, and as `lut[round(g * W)]` looks up: texel `i`
+    /// holds the source value for `g = i / W`.
+    Half,
+}
+
+/// Which forward values the texels of a `W`-wide LUT are built for. A
+/// stratified LUT is sampled through its [`TransformConfig::lut_knots`].
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LutSampling {
+    /// Evenly spaced, texel `i` at `(i + 0.5) / W`, sampled at the forward
+    /// value itself.
+    #[default]
+    Uniform,
+    /// Spaced per channel for the smallest nearest-texel reconstruction
+    /// error, denser where many pixels fall and the source changes quickly
+    /// between texels, sparser in flat stretches and the tails. The LUT is
+    /// sampled at the coordinate a knot map gives each forward value.
+    Stratified,
+}
+
+/// Where the `k`th of `n` sorted samples, counting from zero, sits on the CDF.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlottingPosition {
+    /// `(k + 0.5) / n`, the center of each sample's equal share.
+    #[default]
+    Midpoint,
+    /// `k / (n - 1)`, anchoring the smallest and largest samples to quantiles
+    /// 0 and 1, the target's extremes. Only for bounded targets, a uniform
+    /// distribution or a tabulated CDF.
+    Linear,
+    /// Blom's `(k + 0.625) / (n + 0.25)`, close to the expected quantiles of
+    /// Gaussian order statistics.
+    Blom,
+}
+
+impl PlottingPosition {
+    pub fn quantile(self, k: usize, n: usize) -> f64 {
+        let (k, n) = (k as f64, n as f64);
+        match self {
+            PlottingPosition::Midpoint => (k + 0.5) / n,
+            PlottingPosition::Linear if n == 1.0 => 0.5,
+            PlottingPosition::Linear => k / (n - 1.0),
+            PlottingPosition::Blom => (k + 0.625) / (n + 0.25),
+        }
+    }
+
+    /// Where quantile `u` falls among `n` samples whose quantiles are placed at
+    /// `k + 0.5`, so the sample nearest `u` is at its floor. The inverse of
+    /// [`PlottingPosition::quantile`], shifted by half a sample.
+    pub fn position(self, u: f64, n: usize) -> f64 {
+        let n = n as f64;
+        match self {
+            PlottingPosition::Midpoint => u * n,
+            PlottingPosition::Linear => u * (n - 1.0) + 0.5,
+            PlottingPosition::Blom => u * (n + 0.25) - 0.125,
+        }
+    }
+
+    /// The quantile at `position`, the inverse of [`PlottingPosition::position`].
+    pub(crate) fn quantile_at(self, position: f64, n: usize) -> f64 {
+        let n = n as f64;
+        match self {
+            PlottingPosition::Midpoint => position / n,
+            PlottingPosition::Linear if n == 1.0 => 0.5,
+            PlottingPosition::Linear => (position - 0.5) / (n - 1.0),
+            PlottingPosition::Blom => (position + 0.125) / (n + 0.25),
+        }
+    }
+}
+
+/// Filter run over the source channels before the transform.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Prefilter {
+    #[default]
+    None,
+    /// Smooths away noise while keeping edges sharp. Costs about
+    /// `(6 * sigma_spatial + 1)^2` samples per subpixel.
+    Bilateral,
+}
+
+/// What to do with NaN and infinite samples of a 32-bit float input, such as
+/// an EXR left over from a broken bake.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NanPolicy {
+    /// Fail, naming how many pixels have them and where the first is.
+    #[default]
+    Error,
+    /// Replace them with zero before the transform, like any other sample.
+    Zero,
+    /// Leave their pixels out of the histogram, as [`TransformConfig::alpha_cutoff`]
+    /// does, and keep the non-finite samples as they are in the forward image.
+    Skip,
+}
+
+/// A pixel [`NanPolicy::Skip`] leaves out: its index and samples, as
+/// [`non_finite_pixels`] returns them.
+type SkippedPixel = (usize, [f32; 4]);
+
+/// Logs what was left out of each histogram channel at debug level.
+fn log_exclusions(exclusions: &[Exclusions]) {
+    for (channel, exclusions) in exclusions.iter().enumerate() {
+        tracing::debug!(
+            channel,
+            non_finite = exclusions.non_finite,
+            alpha_cutoff = exclusions.alpha_cutoff,
+            zero_weight = exclusions.zero_weight,
+            trimmed = exclusions.trimmed,
+            "subpixels left out of the histogram"
+        );
+    }
+}
+
+/// The weights [`TransformConfig::resolve_weights`] settles on, and the
+/// pixels they leave out.
+type ResolvedWeights<'a> = (Option<Cow<'a, [u16]>>, Exclusions);
+
+/// How the 8-bit LUT quantizes values that fall between levels, which only
+/// [`LutInterp::Linear`] and identity columns produce.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rounding {
+    /// To the nearest level, halves away from zero. Unbiased on average.
+    #[default]
+    Round,
+    /// Down, biasing reconstruction half a level low.
+    Floor,
+    /// Up, biasing reconstruction half a level high.
+    Ceil,
+}
+
+impl Rounding {
+    pub fn apply(self, value: f64) -> f64 {
+        match self {
+            Rounding::Round => value.round(),
+            Rounding::Floor => value.floor(),
+            Rounding::Ceil => value.ceil(),
+        }
+    }
+}
+
+/// How a LUT with several rows fills them.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LutRowMode {
+    /// Every row is the same.
+    #[default]
+    Replicate,
+    /// Row `r` of `n` samples each texel at `(i + (r + 0.5) / n) / lut_width`
+    /// instead of its center, so averaging the rows supersamples the LUT.
+    Jitter,
+}
+
+/// A target mean running from `start` at one edge of a page to `end` at the
+/// other: at texture coordinate `t` along `axis` it is `start + (end - start) * t`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MeanGradient {
+    pub start: f64,
+    pub end: f64,
+    pub axis: GradientAxis,
+}
+
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GradientAxis {
+    /// Left to right.
+    X,
+    /// Top to bottom.
+    #[default]
+    Y,
+}
+
+impl MeanGradient {
+    pub fn midpoint(&self) -> f64 {
+        (self.start + self.end) / 2.0
+    }
+
+    /// Mean at pixel `(x, y)` of a `width x height` page.
+    pub fn mean_at(&self, x: u32, y: u32, width: u32, height: u32) -> f64 {
+        let (position, extent) = match self.axis {
+            GradientAxis::X => (x, width),
+            GradientAxis::Y => (y, height),
+        };
+        let t = (position as f64 + 0.5) / extent as f64;
+        self.start + (self.end - self.start) * t
+    }
+
+    /// How far the mean at `(x, y)` is from [`MeanGradient::midpoint`], which
+    /// is what the forward image adds to the midpoint's transform there.
+    pub fn offset(&self, x: u32, y: u32, width: u32, height: u32) -> f64 {
+        self.mean_at(x, y, width, height) - self.midpoint()
+    }
+
+    /// Adds `sign` times each pixel's offset to the first `channels` samples
+    /// of every pixel in an interleaved RGB image of stacked pages. A sign of
+    /// `-1.0` undoes the gradient, ready for the LUT.
+    pub fn shift(
+        &self,
+        image: &mut [f32],
+        width: u32,
+        page_heights: &[u32],
+        channels: usize,
+        sign: f32,
+    ) {
+        let mut rows = image.chunks_exact_mut(width as usize * 3);
+        for &height in page_heights {
+            for (y, row) in rows.by_ref().take(height as usize).enumerate() {
+                for (x, px) in row.chunks_exact_mut(3).enumerate() {
+                    let offset = self.offset(x as u32, y as u32, width, height) as f32;
+                    px[..channels].iter_mut().for_each(|v| *v += sign * offset);
+                }
+            }
+        }
+    }
+}
+
+/// Output of a transform: the interleaved RGB forward image and RGB inverse LUT.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GaussianResult {
+    pub width: u32,
+    pub height: u32,
+    /// Heights of the pages stacked into `image`, top to bottom. A single
+    /// image has one page of the full height.
+    pub page_heights: Vec<u32>,
+    pub lut_width: u32,
+    /// Rows in `lut` and `lut_f32`: `lut_rows`, or one per tile for
+    /// [`TransformConfig::run_atlas`].
+    pub lut_height: u32,
+    pub image: Vec<f32>,
+    pub lut: Vec<u8>,
+    /// `lut` before rounding, scaled to `[0, 1]`. Only differs from `lut / 255`
+    /// with [`LutInterp::Linear`].
+    pub lut_f32: Vec<f32>,
+    /// Sorted source values the transform was built from, one channel per
+    /// LUT column, or a single channel with `shared_lut`.
+    pub histogram: Histogram<u8>,
+    /// What was left out of each channel of `histogram`.
+    pub exclusions: Vec<Exclusions>,
+    pub timings: PhaseTimings,
+}
+
+impl GaussianResult {
+    /// Checks that every row of every LUT column, 8-bit and float, never
+    /// decreases, as a LUT mapping increasing forward values to increasing
+    /// source values must. A decrease means a bug in how ties or texels were
+    /// indexed; the error names the first one.
+    pub fn check_lut_monotonic(&self) -> Result<(), anyhow::Error> {
+        let width = self.lut_width as usize;
+        for (row, (lut, lut_f32)) in self
+            .lut
+            .chunks_exact(width * 3)
+            .zip(self.lut_f32.chunks_exact(width * 3))
+            .enumerate()
+        {
+            for (channel, label) in ["R", "G", "B"].iter().enumerate() {
+                let column = lut.iter().skip(channel).step_by(3).map(|&v| v as f32);
+                let column_f32 = lut_f32.iter().skip(channel).step_by(3).copied();
+                let decreases = [
+                    ("8-bit", first_decrease(column)),
+                    ("Float", first_decrease(column_f32)),
+                ];
+                for (kind, decrease) in decreases {
+                    if let Some((texel, previous, value)) = decrease {
+                        return Err(anyhow::anyhow!(
+                            "{} LUT column {} isn't monotonic: in row {}, texel {} holds {} after {}",
+                            kind,
+                            label,
+                            row,
+                            texel,
+                            value,
+                            previous
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The first texel of `column` below the texel before it, with the two values.
+pub(crate) fn first_decrease<T: PartialOrd + Copy>(
+    column: impl Iterator<Item = T>,
+) -> Option<(usize, T, T)> {
+    let mut previous = None;
+    for (texel, value) in column.enumerate() {
+        match previous {
+            Some(previous) if value < previous => return Some((texel, previous, value)),
+            _ => previous = Some(value),
+        }
+    }
+    None
+}
+
+/// Wall-clock time spent in each phase of the transform.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PhaseTimings {
+    /// Copying subpixels out of the decoded image into per-channel buffers.
+    pub extract: Duration,
+    /// Sorting each channel and ranking its subpixels.
+    pub sort: Duration,
+    /// Mapping ranks onto the target distribution and interleaving the forward image.
+    pub forward: Duration,
+    /// Sampling the sorted values into the inverse LUT.
+    pub inverse_lut: Duration,
+}
+
+/// Subpixels of one histogram channel a transform left out of it, by what
+/// left them out. A pixel left out for several reasons counts under the first
+/// of them here.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Exclusions {
+    /// With a NaN or infinite sample in their pixel, under [`NanPolicy::Skip`].
+    pub non_finite: usize,
+    /// Of pixels whose alpha is below `alpha_cutoff`.
+    pub alpha_cutoff: usize,
+    /// Of pixels the weights give no weight.
+    pub zero_weight: usize,
+    /// Dropped from either end by `trim_tails`, and ranked as the nearest
+    /// subpixel kept.
+    pub trimmed: usize,
+}
+
+impl Exclusions {
+    pub fn total(&self) -> usize {
+        self.non_finite + self.alpha_cutoff + self.zero_weight + self.trimmed
+    }
+}
+
+impl TransformConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mean(mut self, mean: f64) -> Self {
+        self.mean = mean;
+        self
+    }
+
+    pub fn std(mut self, std: f64) -> Self {
+        self.std = std;
+        self
+    }
+
+    pub fn distribution(mut self, distribution: DistributionKind) -> Self {
+        self.distribution = distribution;
+        self
+    }
+
+    pub fn channel_distributions(
+        mut self,
+        channel_distributions: Option<Vec<DistributionKind>>,
+    ) -> Self {
+        self.channel_distributions = channel_distributions;
+        self
+    }
+
+    pub fn target_cdf(mut self, target_cdf: Option<Tabulated>) -> Self {
+        self.target_cdf = target_cdf;
+        self
+    }
+
+    pub fn mean_gradient(mut self, mean_gradient: Option<MeanGradient>) -> Self {
+        self.mean_gradient = mean_gradient;
+        self
+    }
+
+    pub fn channels(mut self, channels: Channels) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    pub fn prefilter(mut self, prefilter: Prefilter) -> Self {
+        self.prefilter = prefilter;
+        self
+    }
+
+    pub fn prefilter_sigma_spatial(mut self, prefilter_sigma_spatial: f64) -> Self {
+        self.prefilter_sigma_spatial = prefilter_sigma_spatial;
+        self
+    }
+
+    pub fn prefilter_sigma_range(mut self, prefilter_sigma_range: f64) -> Self {
+        self.prefilter_sigma_range = prefilter_sigma_range;
+        self
+    }
+
+    pub fn lut_size(mut self, lut_size: Option<u32>) -> Self {
+        self.lut_size = lut_size;
+        self
+    }
+
+    pub fn lut_channels(mut self, lut_channels: Option<Vec<Channel>>) -> Self {
+        self.lut_channels = lut_channels;
+        self
+    }
+
+    pub fn alpha_cutoff(mut self, alpha_cutoff: Option<f64>) -> Self {
+        self.alpha_cutoff = alpha_cutoff;
+        self
+    }
+
+    pub fn nan_policy(mut self, nan_policy: NanPolicy) -> Self {
+        self.nan_policy = nan_policy;
+        self
+    }
+
+    pub fn lut_for_linear_filtering(mut self, lut_for_linear_filtering: bool) -> Self {
+        self.lut_for_linear_filtering = lut_for_linear_filtering;
+        self
+    }
+
+    pub fn lut_texel_offset(mut self, lut_texel_offset: LutTexelOffset) -> Self {
+        self.lut_texel_offset = lut_texel_offset;
+        self
+    }
+
+    pub fn pad_lut(mut self, pad_lut: bool) -> Self {
+        self.pad_lut = pad_lut;
+        self
+    }
+
+    pub fn lut_interp(mut self, lut_interp: LutInterp) -> Self {
+        self.lut_interp = lut_interp;
+        self
+    }
+
+    pub fn lut_endpoints(mut self, lut_endpoints: LutEndpoints) -> Self {
+        self.lut_endpoints = lut_endpoints;
+        self
+    }
+
+    pub fn lut_sampling(mut self, lut_sampling: LutSampling) -> Self {
+        self.lut_sampling = lut_sampling;
+        self
+    }
+
+    pub fn plotting_position(mut self, plotting_position: PlottingPosition) -> Self {
+        self.plotting_position = plotting_position;
+        self
+    }
+
+    pub fn preserve_extremes(mut self, preserve_extremes: bool) -> Self {
+        self.preserve_extremes = preserve_extremes;
+        self
+    }
+
+    pub fn trim_tails(mut self, percent: f64) -> Self {
+        self.trim_tails = percent;
+        self
+    }
+
+    pub fn clip_limit(mut self, clip_limit: Option<f64>) -> Self {
+        self.clip_limit = clip_limit;
+        self
+    }
+
+    pub fn rounding(mut self, rounding: Rounding) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    pub fn lut_rows(mut self, lut_rows: u32) -> Self {
+        self.lut_rows = lut_rows;
+        self
+    }
+
+    pub fn lut_row_mode(mut self, lut_row_mode: LutRowMode) -> Self {
+        self.lut_row_mode = lut_row_mode;
+        self
+    }
+
+    pub fn shared_lut(mut self, shared_lut: bool) -> Self {
+        self.shared_lut = shared_lut;
+        self
+    }
+
+    pub fn median_target(mut self, median_target: f64) -> Self {
+        self.median_target = median_target;
+        self
+    }
+
+    pub fn post_contrast(mut self, post_contrast: f64) -> Self {
+        self.post_contrast = post_contrast;
+        self
+    }
+
+    pub fn signed_encoding(mut self, signed_encoding: bool) -> Self {
+        self.signed_encoding = signed_encoding;
+        self
+    }
+
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    pub fn histogram_samples(mut self, histogram_samples: Option<usize>) -> Self {
+        self.histogram_samples = histogram_samples;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn fast_quantiles(mut self, fast_quantiles: bool) -> Self {
+        self.fast_quantiles = fast_quantiles;
+        self
+    }
+
+    pub fn quantile_precision(mut self, quantile_precision: QuantilePrecision) -> Self {
+        self.quantile_precision = quantile_precision;
+        self
+    }
+
+    pub fn profile_guided_lut(mut self, profile_guided_lut: bool) -> Self {
+        self.profile_guided_lut = profile_guided_lut;
+        self
+    }
+
+    /// Width of the LUT for a `width x height` input: `lut_size`, or
+    /// [`default_lut_size`], rounded up to a power of two with `pad_lut`.
+    pub fn lut_width(&self, width: u32, height: u32) -> u32 {
+        self.padded_lut_width(
+            self.lut_size
+                .unwrap_or_else(|| default_lut_size(width, height)),
+        )
+    }
+
+    fn padded_lut_width(&self, lut_width: u32) -> u32 {
+        if self.pad_lut {
+            lut_width.next_power_of_two()
+        } else {
+            lut_width
+        }
+    }
+
+    /// Distinct texels in `channel`'s column of a `lut_width` wide LUT built
+    /// from `histogram_channel` of `histogram`. `lut_width` unless
+    /// `profile_guided_lut` is set, when it's halved for as long as it stays
+    /// a whole divisor of `lut_width` and keeps [`PROFILE_TEXELS_PER_VALUE`]
+    /// texels for each distinct source value, so a flat channel gets a short
+    /// column and a detailed one the full width. Identity columns keep the
+    /// full width.
+    pub fn effective_lut_width(
+        &self,
+        histogram: &Histogram<u8>,
+        histogram_channel: usize,
+        channel: usize,
+        lut_width: u32,
+    ) -> u32 {
+        if !self.profile_guided_lut || !self.lut_channel_enabled(channel) {
+            return lut_width;
+        }
+        let needed =
+            PROFILE_TEXELS_PER_VALUE as usize * histogram.distinct_values(histogram_channel);
+        let mut width = lut_width;
+        while width.is_multiple_of(2) && (width / 2) as usize >= needed {
+            width /= 2;
+        }
+        width
+    }
+
+    /// Whether the LUT column for `channel` is built from its histogram
+    /// rather than left as identity.
+    pub fn lut_channel_enabled(&self, channel: usize) -> bool {
+        match &self.lut_channels {
+            Some(channels) => channels.iter().any(|c| c.index() == channel),
+            None => true,
+        }
+    }
+
+    /// The mean the LUT is built for: `mean`, or the midpoint of `mean_gradient`.
+    pub fn lut_mean(&self) -> f64 {
+        self.mean_gradient
+            .map_or(self.mean, |gradient| gradient.midpoint())
+    }
+
+    /// `(scale, offset)` mapping a stored forward value `g` to the texture
+    /// coordinate a `lut_width` wide LUT is sampled at, `offset + scale * g`.
+    /// `(1, 0)` unless `lut_for_linear_filtering` is set, when it's
+    /// `((lut_width - 1) / lut_width, 0.5 / lut_width)`, which puts `g = 0`
+    /// and `g = 1` on the centers of the first and last texels. With
+    /// [`LutTexelOffset::Half`] it's `(1, 0.5 / lut_width)`: the coordinate a
+    /// sampler centering texels at `(i + 0.5) / lut_width` needs to read
+    /// what one centering them at `i / lut_width` reads at `g`.
+    pub fn lut_coordinate(&self, lut_width: u32) -> (f64, f64) {
+        if self.lut_for_linear_filtering && lut_width > 1 {
+            let width = lut_width as f64;
+            ((width - 1.0) / width, 0.5 / width)
+        } else if self.lut_texel_offset == LutTexelOffset::Half {
+            (1.0, 0.5 / lut_width as f64)
+        } else {
+            (1.0, 0.0)
+        }
+    }
+
+    /// The knot map of `result`'s LUT with [`LutSampling::Stratified`], `None`
+    /// with uniform sampling: one row as wide as the LUT, interleaved RGB
+    /// `[0, 1]` floats, whose texel `j` holds the coordinate the LUT is
+    /// sampled at for the stored forward value `j / (lut_width - 1)`. Between
+    /// those values the coordinate is linear, so a shader reads it with
+    /// linear filtering at `(g * (lut_width - 1) + 0.5) / lut_width` and
+    /// samples the LUT there; [`warp_coordinates`] does the same on the CPU.
+    /// The map is built from `result`'s histogram, like the LUT.
+    pub fn lut_knots(&self, result: &GaussianResult) -> Option<Vec<f32>> {
+        (self.lut_sampling == LutSampling::Stratified)
+            .then(|| lut_knots(&result.histogram, self, result.lut_width))
+    }
+
+    /// Stored forward value texel `i` of a `lut_width` wide LUT row is built
+    /// for, the inverse of [`TransformConfig::lut_coordinate`] at the texel's
+    /// center: `(i + offset) / lut_width`, where `offset` is the point within
+    /// the texel the row samples, half a texel less with
+    /// [`LutTexelOffset::Half`], or `i / (lut_width - 1)` with
+    /// `lut_for_linear_filtering`.
+    pub(crate) fn lut_texel_value(&self, i: u32, lut_width: u32, offset: f64) -> f64 {
+        if self.lut_for_linear_filtering && lut_width > 1 {
+            i as f64 / (lut_width - 1) as f64
+        } else {
+            let shift = match self.lut_texel_offset {
+                LutTexelOffset::None => 0.0,
+                LutTexelOffset::Half => 0.5,
+            };
+            (i as f64 + offset - shift) / lut_width as f64
+        }
+    }
+
+    /// `(scale, offset)` the forward image stores values with: a target
+    /// value `v` is stored as `offset + scale * v`, and read back as
+    /// `(stored - offset) / scale`. `(1, 0)` unless `signed_encoding` is set.
+    pub fn forward_encoding(&self) -> (f64, f64) {
+        if self.signed_encoding {
+            (0.5, 0.5)
+        } else {
+            (1.0, 0.0)
+        }
+    }
+
+    /// The distribution of stored forward values, ignoring
+    /// `channel_distributions`; see [`TransformConfig::channel_target`].
+    pub fn target(&self) -> Box<dyn Distribution> {
+        self.target_of(self.distribution)
+    }
+
+    /// Shape of `channel`'s target distribution: its entry in
+    /// `channel_distributions`, or `distribution`.
+    pub fn channel_distribution(&self, channel: usize) -> DistributionKind {
+        match &self.channel_distributions {
+            Some(kinds) => kinds[channel],
+            None => self.distribution,
+        }
+    }
+
+    /// How a bake with this config evaluates its targets' quantile functions
+    /// on this machine, and if it falls back to the exact scalar path, why.
+    /// Heightmaps always take the scalar path.
+    pub fn quantile_backend(&self) -> QuantileBackend {
+        let gaussian_target = self.target_cdf.is_none()
+            && (0..self.channels.count())
+                .any(|channel| self.channel_distribution(channel) == DistributionKind::Gaussian);
+        QuantileBackend::select(self.fast_quantiles, gaussian_target, avx_available())
+    }
+
+    /// The distribution of `channel`'s stored forward values.
+    pub fn channel_target(&self, channel: usize) -> Box<dyn Distribution> {
+        self.target_of(self.channel_distribution(channel))
+    }
+
+    fn target_of(&self, kind: DistributionKind) -> Box<dyn Distribution> {
+        let target: Box<dyn Distribution> = match &self.target_cdf {
+            Some(tabulated) => Box::new(tabulated.clone()),
+            None if self.fast_quantiles && kind == DistributionKind::Gaussian => {
+                Box::new(FastGaussian {
+                    mean: self.lut_mean(),
+                    std: self.std,
+                })
+            }
+            None if kind == DistributionKind::Gaussian => {
+                let (mean, std) = (self.lut_mean(), self.std);
+                match self.quantile_precision {
+                    QuantilePrecision::Low => Box::new(RationalGaussian { mean, std }),
+                    QuantilePrecision::Default => kind.with_params(mean, std),
+                    QuantilePrecision::High => Box::new(RefinedGaussian { mean, std }),
+                }
+            }
+            None => kind.with_params(self.lut_mean(), self.std),
+        };
+        let target = if self.median_target == 0.5 {
+            target
+        } else {
+            Box::new(Pivoted {
+                inner: target,
+                median: self.median_target,
+            })
+        };
+        let target = if self.post_contrast == 1.0 {
+            target
+        } else {
+            Box::new(Contrasted {
+                inner: target,
+                mean: self.lut_mean(),
+                std: self.std,
+                strength: self.post_contrast,
+            })
+        };
+        if self.signed_encoding {
+            let (scale, offset) = self.forward_encoding();
+            Box::new(Encoded {
+                inner: target,
+                scale,
+                offset,
+            })
+        } else {
+            target
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if !(self.std.is_finite() && self.std > 0.0) {
+            return Err(anyhow::anyhow!("std must be positive, got {}", self.std));
+        }
+        if !self.mean.is_finite() {
+            return Err(anyhow::anyhow!("mean must be finite, got {}", self.mean));
+        }
+        if !(self.median_target > 0.0 && self.median_target < 1.0) {
+            return Err(anyhow::anyhow!(
+                "median_target must be between 0 and 1, got {}",
+                self.median_target
+            ));
+        }
+        if !(self.post_contrast.is_finite() && self.post_contrast > 0.0) {
+            return Err(anyhow::anyhow!(
+                "post_contrast must be positive, got {}",
+                self.post_contrast
+            ));
+        }
+        if let Some(tabulated) = &self.target_cdf {
+            tabulated.validate()?;
+        }
+        if let Some(gradient) = &self.mean_gradient {
+            if !(gradient.start.is_finite() && gradient.end.is_finite()) {
+                return Err(anyhow::anyhow!(
+                    "mean_gradient must be finite, got {} to {}",
+                    gradient.start,
+                    gradient.end
+                ));
+            }
+            if self.target_cdf.is_some() {
+                return Err(anyhow::anyhow!(
+                    "mean_gradient shifts a distribution, it can't be used with target_cdf"
+                ));
+            }
+            if self.signed_encoding {
+                return Err(anyhow::anyhow!(
+                    "mean_gradient can't be used with signed_encoding"
+                ));
+            }
+        }
+        if let Some(kinds) = &self.channel_distributions {
+            if kinds.len() != self.channels.count() {
+                return Err(anyhow::anyhow!(
+                    "channel_distributions has {} entries, but channels {:?} has {}",
+                    kinds.len(),
+                    self.channels,
+                    self.channels.count()
+                ));
+            }
+            if self.target_cdf.is_some() {
+                return Err(anyhow::anyhow!(
+                    "channel_distributions can't be used with target_cdf, which replaces every channel's"
+                ));
+            }
+        }
+        // Quantiles 0 and 1 of a Gaussian are infinite; its inverse CDF only
+        // returns finite values there by saturating, far out on the tails.
+        let unbounded_target = self.target_cdf.is_none()
+            && (0..self.channels.count())
+                .any(|channel| self.channel_distribution(channel) == DistributionKind::Gaussian);
+        if self.plotting_position == PlottingPosition::Linear && unbounded_target {
+            return Err(anyhow::anyhow!(
+                "plotting_position linear ranks the extremes at quantiles 0 and 1, which a gaussian target has no finite value for; use a uniform distribution or target_cdf"
+            ));
+        }
+        if let Some(channel) = self
+            .lut_channels
+            .iter()
+            .flatten()
+            .find(|c| c.index() >= self.channels.count())
+        {
+            return Err(anyhow::anyhow!(
+                "lut_channels includes {:?}, which isn't in channels {:?}",
+                channel,
+                self.channels
+            ));
+        }
+        if let Some(cutoff) = self.alpha_cutoff {
+            if !(0.0..=1.0).contains(&cutoff) {
+                return Err(anyhow::anyhow!(
+                    "alpha_cutoff must be in [0, 1], got {}",
+                    cutoff
+                ));
+            }
+        }
+        for (name, sigma) in [
+            ("prefilter_sigma_spatial", self.prefilter_sigma_spatial),
+            ("prefilter_sigma_range", self.prefilter_sigma_range),
+        ] {
+            if !(sigma.is_finite() && sigma > 0.0) {
+                return Err(anyhow::anyhow!("{} must be positive, got {}", name, sigma));
+            }
+        }
+        if self.fast_quantiles && self.quantile_precision != QuantilePrecision::Default {
+            return Err(anyhow::anyhow!(
+                "fast_quantiles has its own approximation, it can't be used with quantile_precision {}",
+                format!("{:?}", self.quantile_precision).to_lowercase()
+            ));
+        }
+        if self.histogram_samples == Some(0) {
+            return Err(anyhow::anyhow!("histogram_samples must be at least 1"));
+        }
+        if self.histogram_samples.is_some() && self.alpha_cutoff.is_some() {
+            return Err(anyhow::anyhow!(
+                "histogram_samples doesn't support alpha_cutoff"
+            ));
+        }
+        if self.histogram_samples.is_some() && self.plotting_position != PlottingPosition::Midpoint
+        {
+            return Err(anyhow::anyhow!(
+                "histogram_samples doesn't support plotting_position"
+            ));
+        }
+        if self.histogram_samples.is_some() && self.preserve_extremes {
+            return Err(anyhow::anyhow!(
+                "histogram_samples doesn't support preserve_extremes"
+            ));
+        }
+        if !(0.0..50.0).contains(&self.trim_tails) {
+            return Err(anyhow::anyhow!(
+                "trim_tails must be in [0, 50), got {}",
+                self.trim_tails
+            ));
+        }
+        if self.histogram_samples.is_some() && self.trim_tails != 0.0 {
+            return Err(anyhow::anyhow!(
+                "histogram_samples doesn't support trim_tails"
+            ));
+        }
+        if let Some(clip_limit) = self.clip_limit {
+            if !(clip_limit.is_finite() && clip_limit >= 1.0) {
+                return Err(anyhow::anyhow!(
+                    "clip_limit must be at least 1, got {}",
+                    clip_limit
+                ));
+            }
+            if self.histogram_samples.is_some() {
+                return Err(anyhow::anyhow!(
+                    "histogram_samples doesn't support clip_limit"
+                ));
+            }
+            if self.plotting_position != PlottingPosition::Midpoint {
+                return Err(anyhow::anyhow!(
+                    "clip_limit weights the histogram, it can't be used with plotting_position"
+                ));
+            }
+        }
+        if self.lut_size == Some(0) {
+            return Err(anyhow::anyhow!("lut_size must be at least 1"));
+        }
+        if self.lut_rows == 0 {
+            return Err(anyhow::anyhow!("lut_rows must be at least 1"));
+        }
+        if self.lut_for_linear_filtering && self.lut_texel_offset != LutTexelOffset::None {
+            return Err(anyhow::anyhow!(
+                "lut_for_linear_filtering can't be used with lut_texel_offset"
+            ));
+        }
+        if self.lut_for_linear_filtering
+            && self.lut_row_mode == LutRowMode::Jitter
+            && self.lut_rows > 1
+        {
+            return Err(anyhow::anyhow!(
+                "lut_for_linear_filtering can't be used with lut_row_mode jitter"
+            ));
+        }
+        if self.lut_sampling == LutSampling::Stratified {
+            for (unsupported, name) in [
+                (self.lut_for_linear_filtering, "lut_for_linear_filtering"),
+                (
+                    self.lut_texel_offset != LutTexelOffset::None,
+                    "lut_texel_offset",
+                ),
+                (
+                    self.lut_row_mode == LutRowMode::Jitter && self.lut_rows > 1,
+                    "lut_row_mode jitter",
+                ),
+                (self.profile_guided_lut, "profile_guided_lut"),
+            ] {
+                if unsupported {
+                    return Err(anyhow::anyhow!(
+                        "lut_sampling stratified can't be used with {}",
+                        name
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn run(&self, input: &DynamicImage) -> Result<GaussianResult, anyhow::Error> {
+        self.run_with_cancel(input, &CancelToken::new())
+    }
+
+    pub fn run_with_cancel(
+        &self,
+        input: &DynamicImage,
+        cancel: &CancelToken,
+    ) -> Result<GaussianResult, anyhow::Error> {
+        self.validate()?;
+        validate_image(input)?;
+        let (input, skipped) = self.apply_nan_policy(input)?;
+        let mut result = self.transform(&input, None, &skipped, cancel)?;
+        self.apply_mean_gradient(&mut result);
+        Ok(result)
+    }
+
+    /// Transforms `input` with each pixel counted in the histogram by its
+    /// weight in `weights`, one per pixel in row-major order. Heavier pixels
+    /// get a larger share of the target distribution. Equal weights give the
+    /// same result as [`TransformConfig::run_with_cancel`].
+    pub fn run_weighted(
+        &self,
+        input: &DynamicImage,
+        weights: &[u16],
+        cancel: &CancelToken,
+    ) -> Result<GaussianResult, anyhow::Error> {
+        self.validate()?;
+        validate_image(input)?;
+        let pixel_count = input.width() as usize * input.height() as usize;
+        if weights.len() != pixel_count {
+            return Err(anyhow::anyhow!(
+                "Expected {} weights for a {}x{} input, got {}",
+                pixel_count,
+                input.width(),
+                input.height(),
+                weights.len()
+            ));
+        }
+        let (input, skipped) = self.apply_nan_policy(input)?;
+        let mut result = self.transform(&input, Some(weights), &skipped, cancel)?;
+        self.apply_mean_gradient(&mut result);
+        Ok(result)
+    }
+
+    /// Shifts `result`'s forward image from the midpoint mean to the local
+    /// mean of `mean_gradient`, page by page.
+    fn apply_mean_gradient(&self, result: &mut GaussianResult) {
+        if let Some(gradient) = &self.mean_gradient {
+            let channels = self.channels.count();
+            let (width, page_heights) = (result.width, &result.page_heights);
+            gradient.shift(&mut result.image, width, page_heights, channels, 1.0);
+        }
+    }
+
+    /// Like [`TransformConfig::run_with_cancel`], but only sorts and builds
+    /// the LUT up front, then computes the forward image a row at a time as
+    /// the returned iterator is advanced. Saves holding the whole forward
+    /// image for embedders that encode it row by row.
+    pub fn run_rows(
+        &self,
+        input: &DynamicImage,
+        cancel: &CancelToken,
+    ) -> Result<ForwardRows, anyhow::Error> {
+        self.validate()?;
+        validate_image(input)?;
+        let (input, skipped) = self.apply_nan_policy(input)?;
+        if !skipped.is_empty() {
+            return Err(anyhow::anyhow!("run_rows doesn't support nan_policy skip"));
+        }
+        let (weights, _) = self.resolve_weights(&input, None, &[])?;
+        let ranked = rank(&input, weights.as_deref(), self, cancel)?;
+        let (width, height) = (input.width(), input.height());
+        Ok(ForwardRows::new(ranked, self, width, height))
+    }
+
+    /// Runs the transform with `weights`, see [`TransformConfig::resolve_weights`],
+    /// then puts the non-finite samples of the `skipped` pixels back into the
+    /// forward image.
+    fn transform(
+        &self,
+        input: &DynamicImage,
+        weights: Option<&[u16]>,
+        skipped: &[SkippedPixel],
+        cancel: &CancelToken,
+    ) -> Result<GaussianResult, anyhow::Error> {
+        let (weights, excluded) = self.resolve_weights(input, weights, skipped)?;
+        let mut result = transform_histogram(input, weights.as_deref(), self, cancel)?;
+        let channels = self.channels.count();
+        // A shared histogram holds every transformed subpixel of a pixel.
+        let subpixels = if self.shared_lut { channels } else { 1 };
+        for exclusions in &mut result.exclusions {
+            exclusions.non_finite = excluded.non_finite * subpixels;
+            exclusions.alpha_cutoff = excluded.alpha_cutoff * subpixels;
+            exclusions.zero_weight = excluded.zero_weight * subpixels;
+        }
+        log_exclusions(&result.exclusions);
+        for (i, samples) in skipped {
+            let forward = &mut result.image[i * 3..i * 3 + channels];
+            for (v, &sample) in forward
+                .iter_mut()
+                .zip(samples)
+                .filter(|(_, s)| !s.is_finite())
+            {
+                *v = sample;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Applies `nan_policy` to `input`, returning the image to transform and,
+    /// for [`NanPolicy::Skip`], the pixels to leave out of the histogram, see
+    /// [`read::non_finite_pixels`](crate::read::non_finite_pixels).
+    pub(crate) fn apply_nan_policy<'a>(
+        &self,
+        input: &'a DynamicImage,
+    ) -> Result<(Cow<'a, DynamicImage>, Vec<SkippedPixel>), anyhow::Error> {
+        let found = non_finite_pixels(input);
+        let Some(&(first, _)) = found.first() else {
+            return Ok((Cow::Borrowed(input), found));
+        };
+        match self.nan_policy {
+            NanPolicy::Error => {
+                let width = input.width() as usize;
+                Err(anyhow::anyhow!(
+                    "{} pixels have NaN or infinite samples, the first at ({}, {}); \
+                     set nan_policy to zero or skip to transform them anyway",
+                    found.len(),
+                    first % width,
+                    first / width
+                ))
+            }
+            NanPolicy::Zero | NanPolicy::Skip => {
+                // Skipped samples are zeroed too, since quantizing a NaN panics.
+                let mut zeroed = input.clone();
+                zero_non_finite(&mut zeroed);
+                let skipped = if self.nan_policy == NanPolicy::Skip {
+                    found
+                } else {
+                    Vec::new()
+                };
+                Ok((Cow::Owned(zeroed), skipped))
+            }
+        }
+    }
+
+    /// [`TransformConfig::apply_nan_policy`] for each of `pages`, with the
+    /// skipped pixels indexed into the pages stacked top to bottom.
+    fn apply_nan_policy_pages<'a>(
+        &self,
+        pages: &'a [DynamicImage],
+    ) -> Result<(Vec<Cow<'a, DynamicImage>>, Vec<SkippedPixel>), anyhow::Error> {
+        let (mut checked, mut skipped) = (Vec::with_capacity(pages.len()), Vec::new());
+        let mut offset = 0;
+        for page in pages {
+            let (page, page_skipped) = self.apply_nan_policy(page)?;
+            skipped.extend(
+                page_skipped
+                    .into_iter()
+                    .map(|(i, samples)| (offset + i, samples)),
+            );
+            offset += page.width() as usize * page.height() as usize;
+            checked.push(page);
+        }
+        Ok((checked, skipped))
+    }
+
+    /// Applies `alpha_cutoff` to `weights` and gives the `skipped` pixels no
+    /// weight, returning `None` instead if every pixel ends up with the same
+    /// weight, which ranks the same unweighted. Also returns how many pixels
+    /// were left without weight, by what left them out.
+    fn resolve_weights<'a>(
+        &self,
+        input: &DynamicImage,
+        weights: Option<&'a [u16]>,
+        skipped: &[SkippedPixel],
+    ) -> Result<ResolvedWeights<'a>, anyhow::Error> {
+        let mut excluded = Exclusions::default();
+        let mut masked: Option<Vec<u16>> = self.alpha_cutoff.map(|cutoff| {
+            input
+                .pixels()
+                .enumerate()
+                .map(|(i, (_, _, px))| {
+                    let visible = px[3] as f64 / 255.0 >= cutoff;
+                    match (visible, weights) {
+                        (false, _) => 0,
+                        (true, Some(weights)) => weights[i],
+                        (true, None) => 1,
+                    }
+                })
+                .collect()
+        });
+        if !skipped.is_empty() {
+            let pixel_count = input.width() as usize * input.height() as usize;
+            let mut skipping = masked
+                .or_else(|| weights.map(<[u16]>::to_vec))
+                .unwrap_or_else(|| vec![1; pixel_count]);
+            for &(i, _) in skipped {
+                skipping[i] = 0;
+            }
+            masked = Some(skipping);
+        }
+        let weights = match masked {
+            Some(masked) => Some(Cow::Owned(masked)),
+            None => weights.map(Cow::Borrowed),
+        };
+        if let Some(weights) = &weights {
+            excluded.non_finite = skipped.len();
+            let unweighted = weights.iter().filter(|&&w| w == 0).count() - skipped.len();
+            let is_skipped = |i: usize| skipped.binary_search_by_key(&i, |&(i, _)| i).is_ok();
+            excluded.alpha_cutoff = match self.alpha_cutoff {
+                Some(cutoff) => input
+                    .pixels()
+                    .enumerate()
+                    .filter(|&(i, (_, _, px))| (px[3] as f64 / 255.0) < cutoff && !is_skipped(i))
+                    .count(),
+                None => 0,
+            };
+            excluded.zero_weight = unweighted - excluded.alpha_cutoff;
+            if weights.iter().all(|&w| w == 0) {
+                return Err(match self.alpha_cutoff {
+                    Some(cutoff) => {
+                        anyhow::anyhow!("No pixels with weight have alpha of at least {}", cutoff)
+                    }
+                    None if !skipped.is_empty() => {
+                        anyhow::anyhow!("No pixels with weight have only finite samples")
+                    }
+                    None => anyhow::anyhow!("Weights are all zero"),
+                });
+            }
+            if weights.iter().all(|&w| w == weights[0]) {
+                return Ok((None, excluded));
+            }
+            let weighting = if skipped.is_empty() {
+                "weights"
+            } else {
+                "nan_policy skip"
+            };
+            for (unsupported, name) in [
+                (self.histogram_samples.is_some(), "histogram_samples"),
+                (
+                    self.plotting_position != PlottingPosition::Midpoint,
+                    "plotting_position",
+                ),
+                (self.trim_tails != 0.0, "trim_tails"),
+            ] {
+                if unsupported {
+                    return Err(anyhow::anyhow!("{} doesn't support {}", name, weighting));
+                }
+            }
+        }
+        Ok((weights, excluded))
+    }
+
+    /// Transforms each tile of a `columns x rows` atlas independently, with its
+    /// own histogram. The LUT stacks one row per tile, in the order of
+    /// [`AtlasManifest`](crate::atlas::AtlasManifest)'s `lut_row`. The
+    /// histogram is the whole atlas's.
+    pub fn run_atlas(
+        &self,
+        input: &DynamicImage,
+        columns: u32,
+        rows: u32,
+        cancel: &CancelToken,
+    ) -> Result<GaussianResult, anyhow::Error> {
+        let (width, height) = (input.width(), input.height());
+        if columns == 0 || rows == 0 || width % columns != 0 || height % rows != 0 {
+            return Err(anyhow::anyhow!(
+                "A {}x{} input can't be split into {}x{} equal tiles",
+                width,
+                height,
+                columns,
+                rows
+            ));
+        }
+        if self.lut_rows != 1 {
+            return Err(anyhow::anyhow!(
+                "Atlases need lut_rows 1, got {}",
+                self.lut_rows
+            ));
+        }
+        if self.lut_sampling != LutSampling::Uniform {
+            return Err(anyhow::anyhow!("Atlases need lut_sampling uniform"));
+        }
+        let (tile_width, tile_height) = (width / columns, height / rows);
+        let mut image = vec![0.0; width as usize * height as usize * 3];
+        let mut lut = Vec::new();
+        let mut lut_f32 = Vec::new();
+        let mut timings = PhaseTimings::default();
+        let mut exclusions: Vec<Exclusions> = Vec::new();
+        for tile in atlas_tiles(columns, rows, width, height) {
+            let _tile = tracing::info_span!("atlas_tile", tile.column, tile.row).entered();
+            let crop = input.crop_imm(tile.x, tile.y, tile_width, tile_height);
+            let result = self.run_with_cancel(&crop, cancel)?;
+            let row_len = tile_width as usize * 3;
+            for (y, src) in result.image.chunks(row_len).enumerate() {
+                let start = ((tile.y as usize + y) * width as usize + tile.x as usize) * 3;
+                image[start..start + row_len].copy_from_slice(src);
+            }
+            lut.extend_from_slice(&result.lut);
+            lut_f32.extend_from_slice(&result.lut_f32);
+            timings.extract += result.timings.extract;
+            timings.sort += result.timings.sort;
+            timings.forward += result.timings.forward;
+            timings.inverse_lut += result.timings.inverse_lut;
+            exclusions.resize(result.exclusions.len(), Exclusions::default());
+            for (total, tile) in exclusions.iter_mut().zip(&result.exclusions) {
+                total.non_finite += tile.non_finite;
+                total.alpha_cutoff += tile.alpha_cutoff;
+                total.zero_weight += tile.zero_weight;
+                total.trimmed += tile.trimmed;
+            }
+        }
+        Ok(GaussianResult {
+            width,
+            height,
+            page_heights: vec![height],
+            lut_width: self.lut_width(tile_width, tile_height),
+            lut_height: columns * rows,
+            image,
+            lut,
+            lut_f32,
+            histogram: self.histogram(input)?,
+            exclusions,
+            timings,
+        })
+    }
+
+    /// Rebuilds `result`'s LUT, and nothing else, at the smallest width up to
+    /// [`MAX_FITTED_LUT_SIZE`] whose nearest-texel reconstruction of `input`
+    /// has an RMSE of at most `max_error`, in 8-bit levels. Only channels with
+    /// a LUT built from their histogram are measured. Returns the RMSE reached,
+    /// which is above `max_error` if even the largest LUT misses it.
+    pub fn fit_lut_size(
+        &self,
+        input: &DynamicImage,
+        result: &mut GaussianResult,
+        max_error: f64,
+    ) -> f64 {
+        let (measured, source, image) = self.reconstruction_source(input, result);
+        let rmse_at = |lut_size: u32| {
+            let lut_width = self.padded_lut_width(lut_size);
+            let (lut, lut_f32) = build_lut(&result.histogram, self, lut_width);
+            let reconstructed =
+                self.reconstruct(&image, &result.histogram, &lut, lut_width, &measured);
+            let rmse = difference(&source, &reconstructed).rmse;
+            (rmse, lut_width, lut, lut_f32)
+        };
+
+        // Double until the error is met, then binary search the last doubling.
+        let mut low = 0;
+        let mut high = 1;
+        let mut best = rmse_at(high);
+        while best.0 > max_error && high < MAX_FITTED_LUT_SIZE {
+            low = high;
+            high = (high * 2).min(MAX_FITTED_LUT_SIZE);
+            best = rmse_at(high);
+        }
+        if best.0 <= max_error {
+            while high - low > 1 {
+                let mid = low + (high - low) / 2;
+                let fit = rmse_at(mid);
+                if fit.0 <= max_error {
+                    high = mid;
+                    best = fit;
+                } else {
+                    low = mid;
+                }
+            }
+        }
+        let (rmse, lut_width, lut, lut_f32) = best;
+        result.lut_width = lut_width;
+        result.lut = lut;
+        result.lut_f32 = lut_f32;
+        rmse
+    }
+
+    /// How far `result`'s LUT misses each pixel of `input`, in 8-bit levels:
+    /// the largest error over the channels [`fit_lut_size`](Self::fit_lut_size)
+    /// measures, with the same nearest-texel reconstruction, one per pixel in
+    /// row-major order, for finding where a LUT is too small or coarse rather
+    /// than just by how much.
+    pub fn reconstruction_errors(&self, input: &DynamicImage, result: &GaussianResult) -> Vec<u8> {
+        let (measured, source, image) = self.reconstruction_source(input, result);
+        let reconstructed = self.reconstruct(
+            &image,
+            &result.histogram,
+            &result.lut,
+            result.lut_width,
+            &measured,
+        );
+        match measured.len() {
+            0 => vec![0; image.len() / self.channels.count()],
+            n => source
+                .par_chunks(n)
+                .zip(reconstructed.par_chunks(n))
+                .map(|(a, b)| {
+                    a.iter()
+                        .zip(b)
+                        .map(|(a, b)| a.abs_diff(*b))
+                        .max()
+                        .unwrap_or(0)
+                })
+                .collect(),
+        }
+    }
+
+    /// The channels a reconstruction of `input` by `result` is measured on,
+    /// `input`'s values in them, interleaved, and `result`'s forward image
+    /// with any mean gradient taken back out.
+    fn reconstruction_source(
+        &self,
+        input: &DynamicImage,
+        result: &GaussianResult,
+    ) -> (Vec<usize>, Vec<u8>, Vec<f32>) {
+        let measured: Vec<usize> = (0..self.channels.count())
+            .filter(|&channel| self.lut_channel_enabled(channel))
+            .collect();
+        // Compared against the prefiltered channels, which are what the LUT holds.
+        let channels = &extract_channels(input, self);
+        let source: Vec<u8> = (0..channels[0].len())
+            .flat_map(|i| measured.iter().map(move |&channel| channels[channel][i]))
+            .collect();
+        let mut image = result.image.clone();
+        if let Some(gradient) = &self.mean_gradient {
+            let channels = self.channels.count();
+            gradient.shift(
+                &mut image,
+                result.width,
+                &result.page_heights,
+                channels,
+                -1.0,
+            );
+        }
+        (measured, source, image)
+    }
+
+    /// Nearest-texel reconstruction of the `measured` channels of `image` by
+    /// `lut`, built from `histogram`, interleaved like
+    /// [`reconstruction_source`](Self::reconstruction_source)'s source.
+    fn reconstruct(
+        &self,
+        image: &[f32],
+        histogram: &Histogram<u8>,
+        lut: &[u8],
+        lut_width: u32,
+        measured: &[usize],
+    ) -> Vec<u8> {
+        let mut coordinates = lut_coordinates(image, self.lut_coordinate(lut_width));
+        if self.lut_sampling == LutSampling::Stratified {
+            let knots = lut_knots(histogram, self, lut_width);
+            coordinates = warp_coordinates(&coordinates, &knots, lut_width);
+        }
+        sample_lut(&coordinates, lut, lut_width)
+            .chunks_exact(3)
+            .flat_map(|px| measured.iter().map(|&channel| px[channel]))
+            .collect()
+    }
+
+    /// Each transformed subpixel's index into its channel of the sorted
+    /// histogram, `channels.count()` per pixel, interleaved in row-major order.
+    /// [`Histogram::sample_quantile`] turns a rank into the quantile the forward
+    /// image maps onto the target. This sorts again, so the ranks only match an
+    /// earlier run's forward image when `deterministic` breaks ties the same way.
+    pub fn ranks(
+        &self,
+        input: &DynamicImage,
+        cancel: &CancelToken,
+    ) -> Result<Vec<u32>, anyhow::Error> {
+        self.validate()?;
+        validate_image(input)?;
+        // Skipped pixels only change the weights, which don't change the order.
+        let (input, _) = self.apply_nan_policy(input)?;
+        let Ranks::Sorted(sort_indices) = rank(&input, None, self, cancel)?.ranks else {
+            return Err(anyhow::anyhow!(
+                "histogram_samples ranks against a sample, so there are no subpixel ranks"
+            ));
+        };
+        let len = sort_indices[0].len();
+        (0..len)
+            .flat_map(|i| sort_indices.iter().map(move |channel| channel[i]))
+            .map(|rank| {
+                u32::try_from(rank).map_err(|_| anyhow::anyhow!("Rank {} doesn't fit a u32", rank))
+            })
+            .collect()
+    }
+
+    /// Classic histogram equalization of `input` to 8-bit RGB, each channel
+    /// through [`Histogram::equalization_table`] of the histogram it would be
+    /// transformed with (one shared table with `shared_lut`), and the
+    /// channels left out of `channels` zero. None of the target, LUT or
+    /// encoding options apply.
+    pub fn equalize(&self, input: &DynamicImage) -> Result<Vec<u8>, anyhow::Error> {
+        self.validate()?;
+        validate_image(input)?;
+        if self.nan_policy == NanPolicy::Skip {
+            return Err(anyhow::anyhow!(
+                "Equalizing doesn't support nan_policy skip"
+            ));
+        }
+        let (input, _) = self.apply_nan_policy(input)?;
+        let histogram = self.histogram(&input)?;
+        let tables: Vec<_> = (0..histogram.channel_count())
+            .map(|channel| histogram.equalization_table(channel))
+            .collect();
+        let equalized: Vec<Vec<u8>> = extract_channels(&input, self)
+            .into_iter()
+            .enumerate()
+            .map(|(channel, values)| {
+                let table = &tables[channel.min(tables.len() - 1)];
+                values.into_iter().map(|v| table[v as usize]).collect()
+            })
+            .collect();
+        Ok(interleave(&equalized, 3))
+    }
+
+    /// A debug overlay of where tie-breaking decides the forward image, as
+    /// 8-bit RGB: each pixel whose value in some transformed channel is held
+    /// by at least `min_size` samples of that channel's histogram gets a color
+    /// of its own for that combination of tie groups, and every other pixel
+    /// is black. The forward image orders the subpixels of a group by where
+    /// they sit in the image, so these are the pixels whose forward values
+    /// don't follow from their own value alone.
+    pub fn tie_overlay(
+        &self,
+        input: &DynamicImage,
+        min_size: usize,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        self.validate()?;
+        validate_image(input)?;
+        let (input, _) = self.apply_nan_policy(input)?;
+        let histogram = self.histogram(&input)?;
+        let counts: Vec<[usize; 256]> = (0..histogram.channel_count())
+            .map(|channel| {
+                let sorted = histogram.sorted_values(channel);
+                let mut counts = [0; 256];
+                sorted.iter().for_each(|&v| counts[v as usize] += 1);
+                counts
+            })
+            .collect();
+        let channels = extract_channels(&input, self);
+        let pixels = channels[0].len();
+        Ok((0..pixels)
+            .flat_map(|i| {
+                // Mixes the pixel's large groups into one key, and the key into a color.
+                let mut key = 0u64;
+                for (channel, values) in channels.iter().enumerate() {
+                    let value = values[i] as usize;
+                    if counts[channel.min(counts.len() - 1)][value] >= min_size {
+                        key ^= (((channel as u64) << 8 | value as u64) + 1)
+                            .wrapping_mul(0x9E37_79B9_7F4A_7C15);
+                        key = key.rotate_left(17);
+                    }
+                }
+                if key == 0 {
+                    return [0; 3];
+                }
+                let hash = (key ^ (key >> 31)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                [0, 1, 2].map(|c| 64 + ((hash >> (c * 16 + 8)) as u8 % 192))
+            })
+            .collect())
+    }
+
+    /// Forward image of `input` under `lut`, the interleaved RGB LUT of an
+    /// earlier bake, `lut_width` texels wide, of which the first row is used.
+    /// Nothing of `input` is ranked: each source value maps to the middle, in
+    /// quantile, of the forward values whose texels hold it, so sampling
+    /// `lut` at the result gives it back, and a value the LUT doesn't hold
+    /// maps to the texels of the nearest value it holds. Equal subpixels all
+    /// get the same value, where a bake spreads them over that span. The
+    /// target, channel and LUT coordinate options must match the bake's;
+    /// `mean_gradient` isn't available.
+    pub fn apply_lut(
+        &self,
+        input: &DynamicImage,
+        lut: &[u8],
+        lut_width: u32,
+    ) -> Result<Vec<f32>, anyhow::Error> {
+        self.validate()?;
+        validate_image(input)?;
+        if self.nan_policy == NanPolicy::Skip {
+            return Err(anyhow::anyhow!(
+                "Applying a LUT doesn't support nan_policy skip"
+            ));
+        }
+        if self.mean_gradient.is_some() {
+            return Err(anyhow::anyhow!(
+                "Applying a LUT doesn't support mean_gradient"
+            ));
+        }
+        if lut_width == 0 || lut.len() < lut_width as usize * 3 {
+            return Err(anyhow::anyhow!(
+                "Expected at least {} LUT samples, got {}",
+                lut_width as usize * 3,
+                lut.len()
+            ));
+        }
+        let lut = &lut[..lut_width as usize * 3];
+        let (input, _) = self.apply_nan_policy(input)?;
+        let forward: Vec<Vec<f32>> = extract_channels(&input, self)
+            .into_iter()
+            .enumerate()
+            .map(|(channel, values)| {
+                let table = self.forward_table(lut, lut_width, channel);
+                values.into_iter().map(|v| table[v as usize]).collect()
+            })
+            .collect();
+        Ok(interleave(&forward, 3))
+    }
+
+    /// The forward value [`TransformConfig::apply_lut`] gives each 8-bit
+    /// source value of `channel`.
+    fn forward_table(&self, lut: &[u8], lut_width: u32, channel: usize) -> [f32; 256] {
+        let target = self.channel_target(channel);
+        let (scale, offset) = self.lut_coordinate(lut_width);
+        let column: Vec<u8> = lut.iter().skip(channel).step_by(3).copied().collect();
+        // Quantile of the stored value on the edge between texels `i - 1` and `i`.
+        let edge = |i: usize| target.cdf((i as f64 / lut_width as f64 - offset) / scale);
+        let span = |value: u8| {
+            let first = column.partition_point(|&v| v < value);
+            (first, column.partition_point(|&v| v <= value))
+        };
+        let mut table = [0.0; 256];
+        for (value, out) in (0..=u8::MAX).zip(table.iter_mut()) {
+            let (mut first, mut end) = span(value);
+            if first == end {
+                // Not in the LUT: take the texels of the nearest value that is.
+                let below = column[..first].last().map(|&v| value - v);
+                let above = column.get(first).map(|&v| v - value);
+                let nearest = match (below, above) {
+                    (Some(below), Some(above)) if below < above => value - below,
+                    (_, Some(above)) => value + above,
+                    (Some(below), None) => value - below,
+                    (None, None) => value,
+                };
+                (first, end) = span(nearest);
+            }
+            *out = target.inv_cdf((edge(first) + edge(end)) / 2.0) as f32;
+        }
+        table
+    }
+
+    /// How densely `result`'s source values are packed at each LUT texel,
+    /// one value per texel in `[0, 1]`: the share of its channel's samples
+    /// equal to the value the texel holds, over the largest share of any
+    /// value of that channel, averaged over the channels with a LUT column
+    /// of their own. 1 where the texel holds the channel's most common
+    /// value, near 0 for rare values in sparse tails. Read from the LUT as
+    /// built, so it follows every option that shapes it. Results of
+    /// [`TransformConfig::run_atlas`] aren't supported, since each tile has
+    /// its own histogram.
+    pub fn lut_density(&self, result: &GaussianResult) -> Vec<f32> {
+        let columns: Vec<(usize, [f64; 256])> = (0..self.channels.count())
+            .filter(|&channel| self.lut_channel_enabled(channel))
+            .map(|channel| (channel, self.level_densities(result, channel)))
+            .collect();
+        result
+            .lut_f32
+            .chunks_exact(3)
+            .map(|texel| {
+                let total: f64 = columns
+                    .iter()
+                    .map(|(channel, shares)| {
+                        let value = (texel[*channel] as f64 * 255.0).round().clamp(0.0, 255.0);
+                        shares[value as usize]
+                    })
+                    .sum();
+                (total / columns.len().max(1) as f64) as f32
+            })
+            .collect()
+    }
+
+    /// How densely `result`'s source values are packed where each pixel of
+    /// the forward image sits, one value per pixel in `[0, 1]`, as
+    /// [`TransformConfig::lut_density`] measures it for texels: each
+    /// transformed channel's forward value is taken back through the
+    /// target's CDF to the source value at that quantile, and that value's
+    /// share of the channel over the largest share of any value, averaged
+    /// over the channels. Pixels in the sparse tails, which reconstruct
+    /// least reliably, get the lowest. `mean_gradient` isn't supported, and
+    /// neither are results of [`TransformConfig::run_atlas`] or
+    /// [`TransformConfig::run_local_tiles`].
+    pub fn forward_density(&self, result: &GaussianResult) -> Result<Vec<f32>, anyhow::Error> {
+        if self.mean_gradient.is_some() {
+            return Err(anyhow::anyhow!(
+                "Forward densities don't support mean_gradient"
+            ));
+        }
+        let channels: Vec<(Box<dyn Distribution>, usize, [f64; 256])> = (0..self.channels.count())
+            .map(|channel| {
+                let histogram_channel = if self.shared_lut { 0 } else { channel };
+                let shares = self.level_densities(result, channel);
+                (self.channel_target(channel), histogram_channel, shares)
+            })
+            .collect();
+        Ok(result
+            .image
+            .par_chunks_exact(3)
+            .map(|pixel| {
+                let total: f64 = channels
+                    .iter()
+                    .enumerate()
+                    .map(|(channel, (target, histogram_channel, shares))| {
+                        let u = target.cdf(pixel[channel] as f64);
+                        shares[result.histogram.value_at_quantile(*histogram_channel, u) as usize]
+                    })
+                    .sum();
+                (total / channels.len() as f64) as f32
+            })
+            .collect())
+    }
+
+    /// Each 8-bit level's share of `channel`'s histogram in `result`, over
+    /// the largest share of any level.
+    fn level_densities(&self, result: &GaussianResult, channel: usize) -> [f64; 256] {
+        let histogram_channel = if self.shared_lut { 0 } else { channel };
+        let mut shares = [0.0; 256];
+        for (value, share) in shares.iter_mut().enumerate() {
+            *share = result.histogram.share(histogram_channel, value as u8);
+        }
+        let peak = shares.iter().copied().fold(0.0, f64::max);
+        shares.iter_mut().for_each(|share| *share /= peak);
+        shares
+    }
+
+    /// The forward transform of `result` as a table from source level to
+    /// forward value: 256 texels, one per 8-bit level, of three channels
+    /// interleaved like the forward image, so a shader can apply the
+    /// transform to an 8-bit texture with one texture read. A bake spreads
+    /// equal subpixels over their level's share of the target, so each level
+    /// gets the forward value of the middle of that share; a level the
+    /// channel doesn't hold gets the value halfway between its neighbours'.
+    /// Levels are the ranked ones, after `prefilter`, and channels that
+    /// aren't transformed hold 0. `mean_gradient`, whose forward values
+    /// depend on position, isn't supported, and neither are results of
+    /// [`TransformConfig::run_atlas`] or
+    /// [`TransformConfig::run_local_tiles`], whose tiles each have their own
+    /// mapping.
+    pub fn forward_lut(&self, result: &GaussianResult) -> Result<Vec<f32>, anyhow::Error> {
+        if self.mean_gradient.is_some() {
+            return Err(anyhow::anyhow!(
+                "A forward LUT doesn't support mean_gradient"
+            ));
+        }
+        let columns: Vec<Vec<f32>> = (0..self.channels.count())
+            .map(|channel| {
+                let histogram_channel = if self.shared_lut { 0 } else { channel };
+                let target = self.channel_target(channel);
+                (0..=u8::MAX)
+                    .map(|value| {
+                        let u = result.histogram.middle_quantile(histogram_channel, value);
+                        target.inv_cdf(u) as f32
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(interleave(&columns, 3))
+    }
+
+    /// Builds the histogram `input` would be transformed with, skipping the
+    /// transform itself; for analysis such as [`Histogram::channel_stats`].
+    pub fn histogram(&self, input: &DynamicImage) -> Result<Histogram<u8>, anyhow::Error> {
+        validate_image(input)?;
+        build_histogram(input, self)
+    }
+
+    /// Covariance and correlation between the transformed channels of
+    /// `input`, after `prefilter` and `nan_policy`, for judging how much the
+    /// channels move together. Every pixel counts once: weights,
+    /// `alpha_cutoff`, `histogram_samples` and `shared_lut` don't apply.
+    pub fn channel_correlation(
+        &self,
+        input: &DynamicImage,
+    ) -> Result<ChannelCorrelation, anyhow::Error> {
+        validate_image(input)?;
+        let (input, _) = self.apply_nan_policy(input)?;
+        Ok(ChannelCorrelation::from_channels(&extract_channels(
+            &input, self,
+        )))
+    }
+
+    /// Transforms a single-channel heightmap of `width x height` heights in
+    /// row-major order, ranking them at full precision so the LUT holds the
+    /// exact source heights rather than 8-bit levels: floats, or 32-bit
+    /// integers, which keep their type in the LUT. Channel, LUT
+    /// row and rounding options don't apply; options that change which
+    /// values are ranked or where are rejected.
+    pub fn run_height<T: HeightSample>(
+        &self,
+        heights: &[T],
+        width: u32,
+        height: u32,
+        cancel: &CancelToken,
+    ) -> Result<HeightResult<T>, anyhow::Error> {
+        self.validate()?;
+        let expected = width as usize * height as usize;
+        if expected == 0 || heights.len() != expected {
+            return Err(anyhow::anyhow!(
+                "Expected {} heights for a {}x{} heightmap, got {}",
+                expected,
+                width,
+                height,
+                heights.len()
+            ));
+        }
+        let non_finite = heights.iter().filter(|h| !h.is_finite()).count();
+        let zeroed: Vec<T>;
+        let heights = match self.nan_policy {
+            _ if non_finite == 0 => heights,
+            NanPolicy::Error => {
+                let first = heights.iter().position(|h| !h.is_finite()).unwrap();
+                return Err(anyhow::anyhow!(
+                    "{} heights are NaN or infinite, the first at ({}, {}); \
+                     set nan_policy to zero or skip to transform them anyway",
+                    non_finite,
+                    first % width as usize,
+                    first / width as usize
+                ));
+            }
+            NanPolicy::Zero => {
+                zeroed = heights
+                    .iter()
+                    .map(|&h| if h.is_finite() { h } else { T::ZERO })
+                    .collect();
+                &zeroed
+            }
+            NanPolicy::Skip if non_finite == heights.len() => {
+                return Err(anyhow::anyhow!("Every height is NaN or infinite"));
+            }
+            NanPolicy::Skip => heights,
+        };
+        for (unsupported, name) in [
+            (self.mean_gradient.is_some(), "mean_gradient"),
+            (self.prefilter != Prefilter::None, "prefilter"),
+            (self.histogram_samples.is_some(), "histogram_samples"),
+            (self.alpha_cutoff.is_some(), "alpha_cutoff"),
+            (self.lut_rows != 1, "lut_rows"),
+            (self.trim_tails != 0.0, "trim_tails"),
+            (
+                self.channel_distributions.is_some(),
+                "channel_distributions",
+            ),
+            (self.lut_endpoints != LutEndpoints::Clamp, "lut_endpoints"),
+            (self.lut_sampling != LutSampling::Uniform, "lut_sampling"),
+            (self.clip_limit.is_some(), "clip_limit"),
+        ] {
+            if unsupported {
+                return Err(anyhow::anyhow!("{} isn't supported for heightmaps", name));
+            }
+        }
+        Ok(transform_heights(heights, width, height, self, cancel)?)
+    }
+
+    /// Transforms several pages with one shared histogram and LUT.
+    pub fn run_pages(
+        &self,
+        pages: &[DynamicImage],
+        cancel: &CancelToken,
+    ) -> Result<GaussianResult, anyhow::Error> {
+        if let [page] = pages {
+            return self.run_with_cancel(page, cancel);
+        }
+        self.validate()?;
+        let (pages, skipped) = self.apply_nan_policy_pages(pages)?;
+        let stacked = stack_pages(&pages)?;
+        validate_image(&stacked)?;
+        let mut result = self.transform(&stacked, None, &skipped, cancel)?;
+        result.page_heights = pages.iter().map(|page| page.height()).collect();
+        self.apply_mean_gradient(&mut result);
+        Ok(result)
+    }
+
+    /// Like [`TransformConfig::run_pages`], but transforms only the HSV value
+    /// of each pixel, leaving hue and saturation as they are. The forward
+    /// image holds each pixel's hue and saturation in `[0, 1]` (see
+    /// [`rgb_to_hsv`]) in R and G and its forward value in B; the LUT has
+    /// identity columns in R and G and reconstructs the value, the source's
+    /// largest channel, in B. Recombine in a shader, or with
+    /// [`hsv_to_rgb`](crate::hsv::hsv_to_rgb), from the stored hue and
+    /// saturation and the LUT's value. Every channel of the result's
+    /// histogram holds the values, and timings are left at zero. Needs all
+    /// three `channels`, and doesn't support weights, `alpha_cutoff`,
+    /// `shared_lut`, `histogram_samples`, `mean_gradient`, `clip_limit`,
+    /// nan_policy skip or `lut_channels` other than B alone, which describes
+    /// the LUT it builds.
+    pub fn run_hsv_value(
+        &self,
+        pages: &[DynamicImage],
+        cancel: &CancelToken,
+    ) -> Result<GaussianResult, anyhow::Error> {
+        self.validate()?;
+        for (unsupported, name) in [
+            (self.channels != Channels::Rgb, "channels other than rgb"),
+            (self.alpha_cutoff.is_some(), "alpha_cutoff"),
+            (self.shared_lut, "shared_lut"),
+            (self.histogram_samples.is_some(), "histogram_samples"),
+            (
+                self.lut_channels
+                    .as_ref()
+                    .is_some_and(|c| c != &[Channel::B]),
+                "lut_channels other than b",
+            ),
+            (self.mean_gradient.is_some(), "mean_gradient"),
+            (self.clip_limit.is_some(), "clip_limit"),
+        ] {
+            if unsupported {
+                return Err(anyhow::anyhow!(
+                    "{} isn't supported for HSV value transforms",
+                    name
+                ));
+            }
+        }
+        let (checked, skipped) = self.apply_nan_policy_pages(pages)?;
+        if !skipped.is_empty() {
+            return Err(anyhow::anyhow!(
+                "nan_policy skip isn't supported for HSV value transforms"
+            ));
+        }
+        let stacked = stack_pages(&checked)?;
+        validate_image(&stacked)?;
+        let (width, height) = (stacked.width(), stacked.height());
+        let lut_width = self.lut_width(width, height);
+        let rgb = extract_raw_channels(&stacked, self);
+        let hsv: Vec<[f32; 3]> = (0..rgb[0].len())
+            .map(|i| rgb_to_hsv([rgb[0][i], rgb[1][i], rgb[2][i]]))
+            .collect();
+        let hue: Vec<f32> = hsv.iter().map(|px| px[0]).collect();
+        let saturation: Vec<f32> = hsv.iter().map(|px| px[1]).collect();
+        // The largest channel, exactly, rather than through the float value.
+        let value: Vec<u8> = (0..rgb[0].len())
+            .map(|i| rgb[0][i].max(rgb[1][i]).max(rgb[2][i]))
+            .collect();
+        let value = match self.prefilter {
+            Prefilter::None => value,
+            _ => prefilter_channel(&value, width, height, self),
+        };
+        cancel.check()?;
+
+        let config = self.clone().lut_channels(Some(vec![Channel::B]));
+        let subpixels = value.len();
+        let bake = transform_channel(value, Channel::B.index(), &config, lut_width, cancel)?;
+        cancel.check()?;
+        let identity = identity_column(&config, lut_width);
+        let identity_u8: Vec<u8> = identity
+            .iter()
+            .map(|&v| self.rounding.apply(v) as u8)
+            .collect();
+        let identity_f32: Vec<f32> = identity.iter().map(|&v| (v / 255.0) as f32).collect();
+        let histogram = Histogram::from_sorted(vec![bake.sorted; 3])
+            .with_plotting_position(self.plotting_position)
+            .with_preserve_extremes(self.preserve_extremes);
+        let exclusions = trimmed_exclusions(&histogram, self, subpixels);
+        log_exclusions(&exclusions);
+        Ok(GaussianResult {
+            width,
+            height,
+            page_heights: pages.iter().map(|page| page.height()).collect(),
+            lut_width,
+            lut_height: self.lut_rows,
+            image: interleave(&[hue, saturation, bake.forward], 3),
+            lut: interleave(&[identity_u8.clone(), identity_u8, bake.lut], 3),
+            lut_f32: interleave(&[identity_f32.clone(), identity_f32, bake.lut_f32], 3),
+            exclusions,
+            histogram,
+            timings: PhaseTimings::default(),
+        })
+    }
+
+    /// Like [`TransformConfig::run_pages`], but transforms each channel on
+    /// its own and keeps it in `cache`, loading it from there instead when
+    /// the channel's source values and the parameters it depends on are
+    /// unchanged. Also returns how many channels were loaded. Timings are
+    /// left at zero, since part of the work may have been done by an earlier
+    /// bake. Doesn't support weights, `alpha_cutoff`, `shared_lut` or
+    /// `histogram_samples`, which tie the channels together, or `clip_limit`.
+    pub fn run_cached(
+        &self,
+        pages: &[DynamicImage],
+        cache: &ChannelCache,
+        cancel: &CancelToken,
+    ) -> Result<(GaussianResult, usize), anyhow::Error> {
+        self.validate()?;
+        for (unsupported, name) in [
+            (self.alpha_cutoff.is_some(), "alpha_cutoff"),
+            (self.shared_lut, "shared_lut"),
+            (self.histogram_samples.is_some(), "histogram_samples"),
+            (self.clip_limit.is_some(), "clip_limit"),
+        ] {
+            if unsupported {
+                return Err(anyhow::anyhow!(
+                    "{} isn't supported with a channel cache",
+                    name
+                ));
+            }
+        }
+        let (checked, skipped) = self.apply_nan_policy_pages(pages)?;
+        if !skipped.is_empty() {
+            return Err(anyhow::anyhow!(
+                "nan_policy skip isn't supported with a channel cache"
+            ));
+        }
+        let stacked = stack_pages(&checked)?;
+        validate_image(&stacked)?;
+        let (width, height) = (stacked.width(), stacked.height());
+        let lut_width = self.lut_width(width, height);
+        let mut reused = 0;
+        let mut bakes = Vec::with_capacity(self.channels.count());
+        for (channel, values) in extract_raw_channels(&stacked, self).into_iter().enumerate() {
+            let key = ChannelCache::key(&values, channel, width, height, lut_width, self);
+            let bake = match cache.load(key) {
+                Some(bake) => {
+                    reused += 1;
+                    bake
+                }
+                None => {
+                    let values = match self.prefilter {
+                        Prefilter::None => values,
+                        _ => prefilter_channel(&values, width, height, self),
+                    };
+                    let bake = transform_channel(values, channel, self, lut_width, cancel)?;
+                    cache.store(key, &bake)?;
+                    bake
+                }
+            };
+            bakes.push(bake);
+            cancel.check()?;
+        }
+
+        let forward: Vec<_> = bakes
+            .iter_mut()
+            .map(|b| std::mem::take(&mut b.forward))
+            .collect();
+        let lut: Vec<_> = bakes
+            .iter_mut()
+            .map(|b| std::mem::take(&mut b.lut))
+            .collect();
+        let lut_f32: Vec<_> = bakes
+            .iter_mut()
+            .map(|b| std::mem::take(&mut b.lut_f32))
+            .collect();
+        let sorted = bakes.into_iter().map(|b| b.sorted).collect();
+        let histogram = Histogram::from_sorted(sorted)
+            .with_plotting_position(self.plotting_position)
+            .with_preserve_extremes(self.preserve_extremes);
+        let exclusions = trimmed_exclusions(&histogram, self, width as usize * height as usize);
+        log_exclusions(&exclusions);
+        let mut result = GaussianResult {
+            width,
+            height,
+            page_heights: pages.iter().map(|page| page.height()).collect(),
+            lut_width,
+            lut_height: self.lut_rows,
+            image: interleave(&forward, 3),
+            lut: interleave(&lut, 3),
+            lut_f32: interleave(&lut_f32, 3),
+            exclusions,
+            histogram,
+            timings: PhaseTimings::default(),
+        };
+        self.apply_mean_gradient(&mut result);
+        Ok((result, reused))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compare::difference;
+    use crate::inverse::{lut_coordinates, sample_lut};
+    use crate::self_test::synthetic_input;
+
+    /// Column `channel` of the first LUT row.
+    fn lut_column(result: &GaussianResult, channel: usize) -> Vec<u8> {
+        (0..result.lut_width as usize)
+            .map(|i| result.lut[i * 3 + channel])
+            .collect()
+    }
+
+    #[test]
+    fn padded_lut_continues_the_cdf() {
+        let input = synthetic_input();
+        let config = TransformConfig::default()
+            .deterministic(true)
+            .lut_size(Some(100));
+        let padded = config.clone().pad_lut(true).run(&input).unwrap();
+        assert_eq!(padded.lut_width, 128);
+        // Resampled at the padded width, not the unpadded LUT with a zero tail.
+        let resampled = config.lut_size(Some(128)).run(&input).unwrap();
+        assert_eq!(padded.lut, resampled.lut);
+        for channel in 0..3 {
+            let column = lut_column(&padded, channel);
+            assert!(column.windows(2).all(|pair| pair[0] <= pair[1]));
+            let max = *padded.histogram.sorted_values(channel).last().unwrap();
+            assert!(column[100..].iter().all(|&v| v >= column[99] && v <= max));
+            assert!(column[127] >= max - max / 20, "channel {}", channel);
+        }
+    }
+
+    /// RMSE in 8-bit levels of `result`'s nearest-texel reconstruction of `input`.
+    fn reconstruction_rmse(
+        config: &TransformConfig,
+        input: &DynamicImage,
+        result: &GaussianResult,
+    ) -> f64 {
+        let coordinates = lut_coordinates(&result.image, config.lut_coordinate(result.lut_width));
+        let reconstructed = sample_lut(&coordinates, &result.lut, result.lut_width);
+        difference(input.to_rgb8().as_raw(), &reconstructed).rmse
+    }
+
+    /// Kolmogorov-Smirnov distance between `channel` of an interleaved RGB
+    /// forward image and `target`.
+    fn forward_distance(image: &[f32], channel: usize, target: &dyn Distribution) -> f64 {
+        let mut values: Vec<f64> = image
+            .iter()
+            .skip(channel)
+            .step_by(3)
+            .map(|v| *v as f64)
+            .collect();
+        values.sort_by(f64::total_cmp);
+        let n = values.len() as f64;
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let cdf = target.cdf(*v);
+                (cdf - i as f64 / n)
+                    .abs()
+                    .max((cdf - (i + 1) as f64 / n).abs())
+            })
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn each_channel_matches_its_own_target_distribution() {
+        use crate::distribution::DistributionKind::{Gaussian, Uniform};
+        let input = synthetic_input();
+        let config = TransformConfig::default()
+            .deterministic(true)
+            .channel_distributions(Some(vec![Gaussian, Uniform, Gaussian]));
+        let result = config.run(&input).unwrap();
+        for (channel, kind) in [Gaussian, Uniform, Gaussian].into_iter().enumerate() {
+            assert_eq!(config.channel_distribution(channel), kind);
+            let own = forward_distance(
+                &result.image,
+                channel,
+                config.channel_target(channel).as_ref(),
+            );
+            let other = match kind {
+                Gaussian => Uniform,
+                Uniform => Gaussian,
+            };
+            let other = forward_distance(&result.image, channel, config.target_of(other).as_ref());
+            assert!(
+                own < 0.01 && other > 0.03,
+                "channel {}: {} vs {}",
+                channel,
+                own,
+                other
+            );
+        }
+
+        let uniform = TransformConfig::default()
+            .deterministic(true)
+            .distribution(Uniform)
+            .run(&input)
+            .unwrap();
+        let green = |image: &[f32]| image.iter().skip(1).step_by(3).copied().collect::<Vec<_>>();
+        assert_eq!(green(&result.image), green(&uniform.image));
+        assert!(config
+            .channel_distributions(Some(vec![Gaussian, Uniform]))
+            .run(&input)
+            .is_err());
+    }
+
+    #[test]
+    fn applying_a_bakes_lut_to_its_input_decodes_it_back() {
+        let input = synthetic_input();
+        let config = TransformConfig::default().deterministic(true);
+        let baked = config.run(&input).unwrap();
+        let applied = config
+            .apply_lut(&input, &baked.lut, baked.lut_width)
+            .unwrap();
+        assert_eq!(applied.len(), baked.image.len());
+
+        // Values the LUT holds decode exactly, and the rest to the nearest it
+        // holds, closer overall than the bake's own forward image decodes.
+        let coordinates = lut_coordinates(&applied, config.lut_coordinate(baked.lut_width));
+        let reconstructed = sample_lut(&coordinates, &baked.lut, baked.lut_width);
+        let source = input.to_rgb8().into_raw();
+        for (i, (&decoded, &value)) in reconstructed.iter().zip(&source).enumerate() {
+            if lut_column(&baked, i % 3).contains(&value) {
+                assert_eq!(decoded, value, "subpixel {}", i);
+            }
+        }
+        let rmse = difference(&source, &reconstructed).rmse;
+        assert!(
+            rmse < reconstruction_rmse(&config, &input, &baked),
+            "{}",
+            rmse
+        );
+        // Nothing is ranked, so equal subpixels all get the same value.
+        for channel in 0..3 {
+            let mut values = [None; 256];
+            for i in (channel..source.len()).step_by(3) {
+                let value = values[source[i] as usize].get_or_insert(applied[i]);
+                assert_eq!(*value, applied[i]);
+            }
+        }
+        assert!(config
+            .apply_lut(&input, &baked.lut[..30], baked.lut_width)
+            .is_err());
+    }
+
+    #[test]
+    fn strips_get_the_same_default_lut_either_way_round() {
+        assert_eq!(default_lut_size(1024, 1024), 1024);
+        assert_eq!(default_lut_size(64, 64), MIN_DEFAULT_LUT_SIZE);
+        assert_eq!(default_lut_size(1 << 16, 1 << 16), MAX_FITTED_LUT_SIZE);
+        assert_eq!(default_lut_size(8192, 16), 362);
+        assert_eq!(default_lut_size(16, 8192), 362);
+
+        let rng = crate::rng::Rng::new(5);
+        let noise = |width: u32, height: u32| {
+            DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, |x, y| {
+                let i = (y * width + x) as u64 * 3;
+                image::Rgb([0, 1, 2].map(|c| rng.below(i + c, 256) as u8))
+            }))
+        };
+        let config = TransformConfig::default().deterministic(true);
+        for (width, height) in [(8192, 16), (16, 8192)] {
+            let input = noise(width, height);
+            let result = config.run(&input).unwrap();
+            assert_eq!(result.lut_width, 362);
+            let rmse = reconstruction_rmse(&config, &input, &result);
+            assert!(rmse < 1.0, "{}x{}: {}", width, height, rmse);
+        }
+        // The tall strip's width, as the LUT size used to be, is far too coarse.
+        let input = noise(16, 8192);
+        let narrow = config.clone().lut_size(Some(16));
+        let coarse = reconstruction_rmse(&narrow, &input, &narrow.run(&input).unwrap());
+        assert!(coarse > 4.0, "{}", coarse);
+    }
+
+    #[test]
+    fn flat_channels_get_shorter_profiled_columns() {
+        // Red uses four levels, green every level, blue one.
+        let input = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x / 16 * 60) as u8, ((y * 64 + x) % 256) as u8, 128])
+        }));
+        let config = TransformConfig::default()
+            .deterministic(true)
+            .lut_size(Some(4096))
+            .profile_guided_lut(true);
+        let result = config.run(&input).unwrap();
+        assert_eq!(result.lut_width, 4096);
+        let widths: Vec<u32> = (0..3)
+            .map(|channel| config.effective_lut_width(&result.histogram, channel, channel, 4096))
+            .collect();
+        assert_eq!(widths, [64, 4096, 16]);
+        for (channel, width) in widths.into_iter().enumerate() {
+            // Each distinct texel repeated to fill the full width.
+            let column = lut_column(&result, channel);
+            let repeat = 4096 / width as usize;
+            assert!(column
+                .chunks(repeat)
+                .all(|run| run.iter().all(|&v| v == run[0])));
+        }
+        // Still holds every level its channel uses.
+        let source = input.to_rgb8().into_raw();
+        for channel in 0..3 {
+            let column = lut_column(&result, channel);
+            assert!((channel..source.len())
+                .step_by(3)
+                .all(|i| column.contains(&source[i])));
+        }
+    }
+
+    #[test]
+    fn lut_density_is_highest_where_values_crowd() {
+        // Three quarters of the pixels on levels 100 to 103, the rest spread
+        // over every level.
+        let input = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            let i = y * 64 + x;
+            let v = if i < 3072 { 100 + i % 4 } else { i % 256 };
+            image::Rgb([v as u8; 3])
+        }));
+        let config = TransformConfig::default().deterministic(true);
+        let result = config.run(&input).unwrap();
+        let density = config.lut_density(&result);
+        assert_eq!(density.len(), result.lut_width as usize);
+        assert!(density.iter().all(|d| (0.0..=1.0).contains(d)));
+        let (mut crowded, mut sparse) = (Vec::new(), Vec::new());
+        for (texel, &d) in result.lut.chunks_exact(3).zip(&density) {
+            match (100..=103).contains(&texel[0]) {
+                true => crowded.push(d),
+                false => sparse.push(d),
+            }
+        }
+        assert!(!crowded.is_empty() && !sparse.is_empty());
+        let lowest_crowded = crowded.iter().copied().fold(f32::MAX, f32::min);
+        let highest_sparse = sparse.iter().copied().fold(0.0, f32::max);
+        assert!(
+            lowest_crowded > 10.0 * highest_sparse,
+            "{} vs {}",
+            lowest_crowded,
+            highest_sparse
+        );
+        assert!(density.contains(&1.0));
+    }
+
+    #[test]
+    fn corrupted_luts_fail_the_monotonic_check() {
+        let config = TransformConfig::default().deterministic(true).lut_rows(2);
+        let result = config.run(&synthetic_input()).unwrap();
+        result.check_lut_monotonic().unwrap();
+
+        let texel = |row: usize, i: usize, channel: usize| {
+            (row * result.lut_width as usize + i) * 3 + channel
+        };
+        let mut swapped = result.clone();
+        let at = texel(1, 40, 2);
+        swapped.lut[at] = swapped.lut[texel(1, 39, 2)].saturating_sub(1);
+        assert_eq!(
+            swapped.check_lut_monotonic().unwrap_err().to_string(),
+            format!(
+                "8-bit LUT column B isn't monotonic: in row 1, texel 40 holds {} after {}",
+                swapped.lut[at],
+                result.lut[texel(1, 39, 2)]
+            )
+        );
+        let mut float = result.clone();
+        float.lut_f32[texel(0, 200, 0)] = -1.0;
+        let err = float.check_lut_monotonic().unwrap_err().to_string();
+        assert!(
+            err.starts_with("Float LUT column R isn't monotonic: in row 0, texel 200 holds -1"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn extended_endpoints_continue_a_steep_tail() {
+        // Most values between 40 and 103, with a steep top tail to 220.
+        let input = DynamicImage::ImageRgb8(image::RgbImage::from_fn(16, 16, |x, y| {
+            let i = y * 16 + x;
+            let v = match i {
+                255 => 220,
+                254 => 180,
+                _ => 40 + i % 64,
+            };
+            image::Rgb([v as u8; 3])
+        }));
+        let clamp_config = TransformConfig::default().deterministic(true);
+        let extend_config = clamp_config.clone().lut_endpoints(LutEndpoints::Extend);
+        let clamped = clamp_config.run(&input).unwrap();
+        let extended = extend_config.run(&input).unwrap();
+        let (clamped, extended) = (lut_column(&clamped, 0), lut_column(&extended, 0));
+        // Texels past the highest sample's quantile: clamped to 220, or
+        // climbing on the 180-to-220 slope.
+        let beyond: Vec<usize> = (0..clamped.len()).filter(|&i| extended[i] > 220).collect();
+        assert!(!beyond.is_empty());
+        assert!(beyond.iter().all(|&i| clamped[i] == 220));
+        assert!(extended.windows(2).all(|pair| pair[0] <= pair[1]));
+        // The bottom tail rises a level per four samples, too gently to
+        // extend below 40 within the LUT.
+        assert_eq!((clamped[0], extended[0]), (40, 40));
+        // Both modes agree within the observed range.
+        for i in 0..clamped.len() {
+            if (40..=220).contains(&extended[i]) && clamped[i] != 220 && clamped[i] != 40 {
+                assert_eq!(clamped[i], extended[i], "texel {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn tie_overlay_colors_each_checkerboard_group_apart() {
+        // A checkerboard of two colors, and one pixel of a third.
+        let input =
+            DynamicImage::ImageRgb8(image::RgbImage::from_fn(16, 16, |x, y| match (x, y) {
+                (0, 0) => image::Rgb([10, 200, 30]),
+                _ if (x + y) % 2 == 0 => image::Rgb([40, 40, 40]),
+                _ => image::Rgb([220, 220, 220]),
+            }));
+        let overlay = TransformConfig::default().tie_overlay(&input, 8).unwrap();
+        assert_eq!(overlay.len(), 16 * 16 * 3);
+        let color = |x: usize, y: usize| &overlay[(y * 16 + x) * 3..][..3];
+        let (dark, light) = (color(2, 0), color(1, 0));
+        assert_ne!(dark, light);
+        assert!(dark != [0; 3] && light != [0; 3]);
+        for y in 0..16 {
+            for x in 0..16 {
+                match (x, y) {
+                    (0, 0) => assert_eq!(color(x, y), [0; 3]),
+                    _ if (x + y) % 2 == 0 => assert_eq!(color(x, y), dark),
+                    _ => assert_eq!(color(x, y), light),
+                }
+            }
+        }
+        // With groups that large required, nothing is highlighted.
+        assert!(TransformConfig::default()
+            .tie_overlay(&input, 256)
+            .unwrap()
+            .iter()
+            .all(|&v| v == 0));
+    }
+
+    #[test]
+    fn preserved_extremes_take_the_outermost_quantiles() {
+        // 30% of each channel at 0 and 30% at 255, the rest in between.
+        let tied = |x: u32, y: u32| match (y * 64 + x) % 10 {
+            0..=2 => 0,
+            3..=5 => 255,
+            k => (k * 30 + y) as u8,
+        };
+        let input = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([tied(x, y); 3])
+        }));
+        // One subpixel each at 0 and 255, so those are ranked first and last.
+        let unique = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb(
+                [match (x, y) {
+                    (0, 0) => 0,
+                    (1, 0) => 255,
+                    _ => 1 + (y * 64 + x) as u8 % 250,
+                }; 3],
+            )
+        }));
+        for position in [PlottingPosition::Midpoint, PlottingPosition::Blom] {
+            let config = TransformConfig::default()
+                .deterministic(true)
+                .plotting_position(position);
+            let outermost = config.clone().run(&unique).unwrap();
+            let (lowest, highest) = (outermost.image[0], outermost.image[3]);
+            let plain = config.clone().run(&input).unwrap();
+            let preserved = config.clone().preserve_extremes(true).run(&input).unwrap();
+            let source = input.to_rgb8().into_raw();
+            for ((&value, &forward), &plain) in
+                source.iter().zip(&preserved.image).zip(&plain.image)
+            {
+                match value {
+                    0 => assert_eq!(forward, lowest),
+                    255 => assert_eq!(forward, highest),
+                    _ => assert_eq!(forward, plain),
+                }
+            }
+            // Without the flag the tie spreads over its share of the target.
+            let spread = source.iter().zip(&plain.image).filter(|(&v, _)| v == 0);
+            assert!(spread.map(|(_, &f)| f).fold(f32::MIN, f32::max) > lowest);
+            assert_eq!(preserved.lut, plain.lut);
+        }
+        let sampled = TransformConfig::default()
+            .histogram_samples(Some(1000))
+            .preserve_extremes(true);
+        assert!(sampled.run(&input).is_err());
+    }
+
+    #[test]
+    fn exclusions_count_each_reason_once() {
+        let input = DynamicImage::ImageRgba32F(image::Rgba32FImage::from_fn(32, 32, |x, y| {
+            let (i, v) = (y * 32 + x, (x * 7 + y * 3) as f32 / 320.0);
+            match i {
+                0..40 => image::Rgba([f32::NAN, v, v, 1.0]),
+                // Also zero weight, but counted under alpha_cutoff.
+                40..140 => image::Rgba([v, v, v, 0.0]),
+                _ => image::Rgba([v, v, v, 1.0]),
+            }
+        }));
+        // No weight for the transparent pixels and the next 50.
+        let weights: Vec<u16> = (0..1024)
+            .map(|i| (!(40..190).contains(&i)) as u16)
+            .collect();
+        let config = TransformConfig::default()
+            .deterministic(true)
+            .nan_policy(NanPolicy::Skip)
+            .alpha_cutoff(Some(0.5));
+        let expected = |subpixels: usize| Exclusions {
+            non_finite: 40 * subpixels,
+            alpha_cutoff: 100 * subpixels,
+            zero_weight: 50 * subpixels,
+            trimmed: 0,
+        };
+        let cancel = CancelToken::new();
+        let result = config.run_weighted(&input, &weights, &cancel).unwrap();
+        assert_eq!(result.exclusions, vec![expected(1); 3]);
+        let shared = config
+            .shared_lut(true)
+            .run_weighted(&input, &weights, &cancel);
+        assert_eq!(shared.unwrap().exclusions, vec![expected(3)]);
+
+        let trimmed = TransformConfig::default()
+            .deterministic(true)
+            .trim_tails(1.0)
+            .run(&synthetic_input())
+            .unwrap();
+        let subpixels = trimmed.histogram.sorted_values(0).len() as f64;
+        for exclusions in &trimmed.exclusions {
+            // 1% from each end.
+            assert_eq!(exclusions.trimmed, 2 * (subpixels * 0.01) as usize);
+            assert_eq!(exclusions.total(), exclusions.trimmed);
+        }
+    }
+
+    #[test]
+    fn source_median_maps_to_the_median_target() {
+        let input = synthetic_input();
+        let plain = TransformConfig::default().deterministic(true);
+        let unwarped = crate::distribution::Gaussian {
+            mean: plain.mean,
+            std: plain.std,
+        };
+        let source = input.to_rgb8().into_raw();
+        for median_target in [0.2, 0.4, 0.5, 0.8] {
+            let config = plain.clone().median_target(median_target);
+            let result = config.run(&input).unwrap();
+            result.check_lut_monotonic().unwrap();
+            for channel in 0..3 {
+                // The forward value of the source's median subpixel.
+                let mut pairs: Vec<(u8, f32)> = (source.iter().skip(channel).step_by(3))
+                    .zip(result.image.iter().skip(channel).step_by(3))
+                    .map(|(&v, &f)| (v, f))
+                    .collect();
+                pairs.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+                let median = pairs[pairs.len() / 2].1 as f64;
+                let expected = unwarped.inv_cdf(median_target);
+                assert!(
+                    (median - expected).abs() < 0.01 * plain.std,
+                    "{} channel {}: {} vs {}",
+                    median_target,
+                    channel,
+                    median,
+                    expected
+                );
+            }
+        }
+        assert!(plain.clone().median_target(1.0).run(&input).is_err());
+    }
+
+    #[test]
+    fn forward_lut_reproduces_the_forward_image() {
+        // Every level once per channel, so no ties are spread apart and each
+        // pixel's forward value is its level's.
+        let unique = DynamicImage::ImageRgb8(image::RgbImage::from_fn(16, 16, |x, y| {
+            let i = (y * 16 + x) as u8;
+            image::Rgb([i, i.wrapping_mul(7), 255 - i])
+        }));
+        let config = TransformConfig::default().deterministic(true);
+        let result = config.run(&unique).unwrap();
+        let lut = config.forward_lut(&result).unwrap();
+        assert_eq!(lut.len(), 256 * 3);
+        let source = unique.to_rgb8();
+        for (i, (&level, &forward)) in source.as_raw().iter().zip(&result.image).enumerate() {
+            let texel = lut[level as usize * 3 + i % 3];
+            assert!(
+                (texel - forward).abs() < 1e-6,
+                "{} {} {}",
+                i,
+                texel,
+                forward
+            );
+        }
+
+        // With ties, each level's texel sits among its pixels' forward values.
+        let input = synthetic_input();
+        let result = config.run(&input).unwrap();
+        let lut = config.forward_lut(&result).unwrap();
+        let mut ranges = vec![(f32::INFINITY, f32::NEG_INFINITY); 256 * 3];
+        let source = input.to_rgb8();
+        for (i, (&level, &forward)) in source.as_raw().iter().zip(&result.image).enumerate() {
+            let range = &mut ranges[level as usize * 3 + i % 3];
+            *range = (range.0.min(forward), range.1.max(forward));
+        }
+        for (&texel, &(lo, hi)) in lut.iter().zip(&ranges) {
+            assert!(lo > hi || (lo - 1e-6..=hi + 1e-6).contains(&texel));
+        }
+
+        let gradient = config.mean_gradient(Some(MeanGradient {
+            start: 0.3,
+            end: 0.7,
+            axis: GradientAxis::X,
+        }));
+        assert!(gradient.forward_lut(&result).is_err());
+    }
+
+    #[test]
+    fn forward_density_is_high_in_the_middle_and_low_in_the_tails() {
+        // A bell of gray levels around 126: the sum of four uniform draws.
+        let rng = crate::rng::Rng::new(11);
+        let input = DynamicImage::ImageLuma8(image::GrayImage::from_fn(128, 128, |x, y| {
+            let i = (y * 128 + x) as u64 * 4;
+            image::Luma([(0..4).map(|j| rng.below(i + j, 64)).sum::<u64>() as u8])
+        }));
+        let config = TransformConfig::default().deterministic(true);
+        let result = config.run(&input).unwrap();
+        let density = config.forward_density(&result).unwrap();
+        assert_eq!(density.len(), 128 * 128);
+        assert!(density.iter().all(|d| (0.0..=1.0).contains(d)));
+
+        let mean_over = |levels: std::ops::RangeInclusive<u8>| {
+            let picked: Vec<f32> = input
+                .as_luma8()
+                .unwrap()
+                .as_raw()
+                .iter()
+                .zip(&density)
+                .filter(|(level, _)| levels.contains(level))
+                .map(|(_, &d)| d)
+                .collect();
+            assert!(!picked.is_empty());
+            picked.iter().sum::<f32>() / picked.len() as f32
+        };
+        let (middle, low, high) = (
+            mean_over(116..=136),
+            mean_over(0..=50),
+            mean_over(202..=255),
+        );
+        assert!(middle > 0.8, "{}", middle);
+        assert!(low < 0.1 && high < 0.1, "{} {}", low, high);
+    }
+
+    #[test]
+    fn half_texel_offset_reconstructs_under_an_uncorrected_sampler() {
+        let input = synthetic_input();
+        let source = input.to_rgb8().into_raw();
+        // A sampler centering texel `i` at `i / W`, read at `g` as is.
+        let engine_nearest = |lut: &[u8], width: u32, i: usize, g: f32| {
+            let texel =
+                ((g as f64 * width as f64).round().max(0.0) as usize).min(width as usize - 1);
+            lut[texel * 3 + i % 3] as f64
+        };
+        let engine_linear = |lut: &[f32], width: u32, i: usize, g: f32| {
+            let x = (g as f64 * width as f64).clamp(0.0, (width - 1) as f64);
+            let (texel, t) = (x.floor() as usize, x.fract());
+            let next = (texel + 1).min(width as usize - 1);
+            let (a, b) = (lut[texel * 3 + i % 3] as f64, lut[next * 3 + i % 3] as f64);
+            (a + (b - a) * t) * 255.0
+        };
+        let mean_error = |offset: LutTexelOffset, interp: LutInterp| {
+            let config = TransformConfig::default()
+                .deterministic(true)
+                .lut_interp(interp)
+                .lut_texel_offset(offset);
+            let result = config.run(&input).unwrap();
+            let total: f64 = result
+                .image
+                .iter()
+                .enumerate()
+                .map(|(i, &g)| {
+                    let value = match interp {
+                        LutInterp::Nearest => engine_nearest(&result.lut, result.lut_width, i, g),
+                        LutInterp::Linear => engine_linear(&result.lut_f32, result.lut_width, i, g),
+                    };
+                    (value - source[i] as f64).abs()
+                })
+                .sum();
+            total / source.len() as f64
+        };
+        for interp in [LutInterp::Nearest, LutInterp::Linear] {
+            let (half, none) = (
+                mean_error(LutTexelOffset::Half, interp),
+                mean_error(LutTexelOffset::None, interp),
+            );
+            assert!(
+                half < none,
+                "{:?}: {} with half, {} without",
+                interp,
+                half,
+                none
+            );
+        }
+
+        // Offsetting the coordinate by half a texel makes the usual sampler read
+        // what the uncorrected one reads at `g`.
+        let config = TransformConfig::default()
+            .deterministic(true)
+            .lut_texel_offset(LutTexelOffset::Half);
+        let result = config.run(&input).unwrap();
+        let coordinates = lut_coordinates(&result.image, config.lut_coordinate(result.lut_width));
+        let sampled = sample_lut(&coordinates, &result.lut, result.lut_width);
+        for (i, (&sample, &g)) in sampled.iter().zip(&result.image).enumerate() {
+            let x = g as f64 * result.lut_width as f64;
+            // Exactly half-way, the two samplers round the other way.
+            if (x.fract() - 0.5).abs() > 1e-4 {
+                assert_eq!(
+                    sample as f64,
+                    engine_nearest(&result.lut, result.lut_width, i, g)
+                );
+            }
+        }
+        assert_eq!(config.lut_coordinate(256), (1.0, 0.5 / 256.0));
+        assert!(config.lut_for_linear_filtering(true).validate().is_err());
+    }
+
+    #[test]
+    fn clip_limit_tames_a_spiky_histogram() {
+        // 90% of the subpixels at levels 127 to 129, the rest spread evenly.
+        let rng = crate::rng::Rng::new(23);
+        let input = DynamicImage::ImageRgb8(image::RgbImage::from_fn(128, 128, |x, y| {
+            let i = (y * 128 + x) as u64 * 3;
+            image::Rgb([0, 1, 2].map(|c| match rng.below(i + c, 10) {
+                0 => rng.below(!(i + c), 256) as u8,
+                _ => 127 + (x + y + c as u32) as u8 % 3,
+            }))
+        }));
+        let spread = |config: TransformConfig| {
+            let result = config.deterministic(true).run(&input).unwrap();
+            let source = input.to_rgb8();
+            let (lo, hi) = source
+                .as_raw()
+                .iter()
+                .zip(&result.image)
+                .filter(|(level, _)| (127..=129).contains(*level))
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), (_, &g)| {
+                    (lo.min(g), hi.max(g))
+                });
+            (hi - lo, result)
+        };
+        let (unclipped, plain) = spread(TransformConfig::default());
+        let (clipped, _) = spread(TransformConfig::default().clip_limit(Some(2.0)));
+        // Unclipped, the spike takes up most of the target's range.
+        assert!(unclipped > 0.25, "{}", unclipped);
+        assert!(
+            clipped < unclipped / 10.0,
+            "{} against {}",
+            clipped,
+            unclipped
+        );
+
+        // A limit above every level's count clips nothing.
+        let (_, unchanged) = spread(TransformConfig::default().clip_limit(Some(256.0)));
+        assert_eq!(unchanged.image, plain.image);
+        assert_eq!(unchanged.lut, plain.lut);
+        for bad in [0.5, f64::NAN] {
+            assert!(TransformConfig::default()
+                .clip_limit(Some(bad))
+                .validate()
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn small_luts_miss_in_steep_regions_only() {
+        // Noise over a few levels, crossed by a stripe ramping over all 256.
+        let rng = crate::rng::Rng::new(29);
+        let stripe = |y: u32| (24..40).contains(&y);
+        let input = DynamicImage::ImageRgb8(image::RgbImage::from_fn(256, 64, |x, y| {
+            let i = (y * 256 + x) as u64 * 3;
+            image::Rgb([0, 1, 2].map(|c| match stripe(y) {
+                true => x as u8,
+                false => 120 + rng.below(i + c, 16) as u8,
+            }))
+        }));
+        let hotspots = |lut_size: u32| {
+            let config = TransformConfig::default()
+                .deterministic(true)
+                .lut_size(Some(lut_size));
+            let result = config.run(&input).unwrap();
+            let errors = config.reconstruction_errors(&input, &result);
+            assert_eq!(errors.len(), 256 * 64);
+            let (mut inside, mut outside) = (0, 0);
+            for (i, &error) in errors.iter().enumerate() {
+                if error > 2 {
+                    match stripe(i as u32 / 256) {
+                        true => inside += 1,
+                        false => outside += 1,
+                    }
+                }
+            }
+            (inside, outside)
+        };
+        let (inside, outside) = hotspots(64);
+        assert!(inside > 1000 && outside == 0, "{} {}", inside, outside);
+        let (inside, outside) = hotspots(4096);
+        assert!(inside < 100 && outside < 5, "{} {}", inside, outside);
+
+        // Pixels over the tolerance are red, the rest the dimmed texture.
+        let map = crate::compare::threshold_map(&[5, 2], &[200, 100], 2.0);
+        assert_eq!(map, [255, 0, 0, 25, 25, 25]);
+    }
+
+    #[test]
+    fn stratified_luts_reconstruct_better_than_uniform_ones() {
+        // Most pixels in a narrow band, a few spread over the whole range.
+        let rng = crate::rng::Rng::new(31);
+        let input = DynamicImage::ImageRgb8(image::RgbImage::from_fn(128, 128, |x, y| {
+            let i = (y * 128 + x) as u64 * 3;
+            image::Rgb([0, 1, 2].map(|c| match rng.below(i + c, 10) {
+                0 => rng.u64(i + c) as u8,
+                _ => 100 + rng.below(i + c, 40) as u8,
+            }))
+        }));
+        let rmse = |lut_sampling| {
+            let config = TransformConfig::default()
+                .deterministic(true)
+                .lut_size(Some(64))
+                .lut_sampling(lut_sampling);
+            let result = config.run(&input).unwrap();
+            let knots = config.lut_knots(&result);
+            if let Some(knots) = &knots {
+                assert_eq!(knots.len(), result.lut_width as usize * 3);
+                for channel in 0..3 {
+                    let column: Vec<_> = knots.iter().skip(channel).step_by(3).collect();
+                    assert!(column.windows(2).all(|pair| pair[0] <= pair[1]));
+                    assert_eq!((*column[0], **column.last().unwrap()), (0.0, 1.0));
+                }
+            }
+            let errors = config.reconstruction_errors(&input, &result);
+            let squared: f64 = errors.iter().map(|&e| (e as f64).powi(2)).sum();
+            (knots.is_some(), (squared / errors.len() as f64).sqrt())
+        };
+        let (uniform_knots, uniform) = rmse(LutSampling::Uniform);
+        let (stratified_knots, stratified) = rmse(LutSampling::Stratified);
+        assert!(!uniform_knots && stratified_knots);
+        assert!(stratified < uniform * 0.75, "{} {}", stratified, uniform);
+    }
+
+    #[test]
+    fn forward_values_follow_the_source_order() {
+        // A second pass would rank the forward values as the first ranked the
+        // source, so it can only map each value to itself.
+        let input = crate::self_test::synthetic_input();
+        let source = input.to_rgb8().into_raw();
+        let result = TransformConfig::default()
+            .deterministic(true)
+            .run(&input)
+            .unwrap();
+        for channel in 0..3 {
+            let mut order: Vec<usize> = (0..source.len() / 3).collect();
+            order.sort_by(|&a, &b| {
+                result.image[a * 3 + channel].total_cmp(&result.image[b * 3 + channel])
+            });
+            assert!(order
+                .windows(2)
+                .all(|pair| source[pair[0] * 3 + channel] <= source[pair[1] * 3 + channel]));
+        }
+    }
+
+    #[test]
+    fn every_channel_spans_the_same_forward_range() {
+        // Channels over very different source ranges.
+        let rng = crate::rng::Rng::new(37);
+        let input = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            let i = (y * 64 + x) as u64 * 3;
+            image::Rgb([
+                rng.below(i, 16) as u8,
+                100 + rng.below(i + 1, 50) as u8,
+                rng.u64(i + 2) as u8,
+            ])
+        }));
+        let result = TransformConfig::default()
+            .deterministic(true)
+            .run(&input)
+            .unwrap();
+        let range = |channel: usize| {
+            let values = result.image.iter().skip(channel).step_by(3);
+            values.fold((f32::MAX, f32::MIN), |(lo, hi), &v| (lo.min(v), hi.max(v)))
+        };
+        assert_eq!(range(0), range(1));
+        assert_eq!(range(0), range(2));
+    }
+
+    #[test]
+    fn each_nan_policy_handles_an_exr_with_non_finite_samples() {
+        let rng = crate::rng::Rng::new(9);
+        let mut pixels = image::Rgb32FImage::from_fn(32, 32, |x, y| {
+            let i = (y * 32 + x) as u64 * 3;
+            image::Rgb([0, 1, 2].map(|c| rng.below(i + c, 1000) as f32 / 1000.0))
+        });
+        pixels.put_pixel(3, 1, image::Rgb([f32::NAN, 0.5, 0.5]));
+        pixels.put_pixel(7, 2, image::Rgb([0.5, f32::INFINITY, f32::NEG_INFINITY]));
+        let path = std::env::temp_dir().join(format!("precompute-nan-{}.exr", std::process::id()));
+        DynamicImage::ImageRgb32F(pixels.clone())
+            .save(&path)
+            .unwrap();
+        let input = crate::read::read_input(&path, &crate::read::ReadOptions::default());
+        std::fs::remove_file(&path).unwrap();
+        let input = input.unwrap().remove(0);
+        let config = TransformConfig::default().deterministic(true);
+        let (nan, inf) = (32 + 3, 2 * 32 + 7);
+
+        let err = config.clone().run(&input).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("2 pixels have NaN or infinite samples, the first at (3, 1)"));
+
+        let zeroed = config
+            .clone()
+            .nan_policy(NanPolicy::Zero)
+            .run(&input)
+            .unwrap();
+        let mut zeros = pixels;
+        zeros.put_pixel(3, 1, image::Rgb([0.0, 0.5, 0.5]));
+        zeros.put_pixel(7, 2, image::Rgb([0.5, 0.0, 0.0]));
+        let expected = config.run(&DynamicImage::ImageRgb32F(zeros)).unwrap();
+        assert_eq!(zeroed.image, expected.image);
+
+        let skipped = config.nan_policy(NanPolicy::Skip).run(&input).unwrap();
+        assert!(skipped.exclusions.iter().all(|e| e.non_finite == 2));
+        assert!(zeroed.exclusions.iter().all(|e| e.non_finite == 0));
+        assert!(skipped.image[3 * nan].is_nan());
+        assert_eq!(skipped.image[3 * inf + 1], f32::INFINITY);
+        assert_eq!(skipped.image[3 * inf + 2], f32::NEG_INFINITY);
+        // The finite samples of those pixels are still transformed.
+        assert!(skipped.image[3 * nan + 1].is_finite() && skipped.image[3 * inf].is_finite());
+        assert!(skipped.image.iter().filter(|v| !v.is_finite()).count() == 3);
+    }
+
+    #[test]
+    fn seed_reproduces_a_sampled_bake() {
+        let input = synthetic_input();
+        assert_eq!(TransformConfig::default().seed, 0);
+        let sampled = TransformConfig::default().histogram_samples(Some(512));
+        let bake = |seed: u64, threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let result = pool
+                .install(|| sampled.clone().seed(seed).run(&input))
+                .unwrap();
+            (result.image, result.lut)
+        };
+        let first = bake(3, 1);
+        assert!(first == bake(3, 4), "seed 3 differs between runs");
+        assert!(first != bake(4, 1), "seeds 3 and 4 give the same bake");
+        assert!(bake(0, 2) == bake(0, 3));
+    }
+
+    #[test]
+    fn signed_values_survive_the_round_trip() {
+        let input = synthetic_input();
+        let zero_centered = TransformConfig::default()
+            .deterministic(true)
+            .mean(0.0)
+            .std(0.15);
+        let signed_config = zero_centered.clone().signed_encoding(true);
+        let plain = zero_centered.run(&input).unwrap();
+        let signed = signed_config.run(&input).unwrap();
+
+        // Half the plain values are negative, outside the LUT's domain.
+        assert!(plain.image.iter().filter(|v| **v < 0.0).count() > plain.image.len() / 3);
+        assert!(signed.image.iter().all(|v| (0.0..=1.0).contains(v)));
+        let (scale, offset) = signed_config.forward_encoding();
+        assert_eq!((scale, offset), (0.5, 0.5));
+        for (stored, value) in signed.image.iter().zip(&plain.image) {
+            let decoded = (*stored as f64 - offset) / scale;
+            assert!((decoded - *value as f64).abs() < 1e-5);
+        }
+
+        let rmse = reconstruction_rmse(&signed_config, &input, &signed);
+        let clamped = reconstruction_rmse(&zero_centered, &input, &plain);
+        assert!(
+            rmse < 2.0 && clamped > 10.0 * rmse,
+            "{} vs {}",
+            rmse,
+            clamped
+        );
+    }
+
+    #[test]
+    fn post_contrast_lut_still_reconstructs() {
+        let input = synthetic_input();
+        let plain_config = TransformConfig::default().deterministic(true);
+        let contrast_config = plain_config.clone().post_contrast(2.0);
+        let plain = plain_config.run(&input).unwrap();
+        let contrast = contrast_config.run(&input).unwrap();
+
+        assert_ne!(plain.image, contrast.image);
+        let spread = |image: &[f32]| {
+            let mean = image.iter().map(|&v| v as f64).sum::<f64>() / image.len() as f64;
+            image.iter().map(|&v| (v as f64 - mean).abs()).sum::<f64>() / image.len() as f64
+        };
+        assert!(spread(&contrast.image) > spread(&plain.image));
+
+        let plain_rmse = reconstruction_rmse(&plain_config, &input, &plain);
+        let contrast_rmse = reconstruction_rmse(&contrast_config, &input, &contrast);
+        assert!(
+            contrast_rmse < 2.5,
+            "rmse {} against {} without contrast",
+            contrast_rmse,
+            plain_rmse
+        );
+    }
+
+    #[test]
+    fn weight_map_shifts_the_mapping() {
+        let input = synthetic_input();
+        let config = TransformConfig::default().deterministic(true);
+        let cancel = CancelToken::new();
+        let unweighted = config.run(&input).unwrap();
+        let uniform = config
+            .run_weighted(&input, &[1000; 64 * 64], &cancel)
+            .unwrap();
+        assert_eq!(uniform.image, unweighted.image);
+        assert_eq!(uniform.lut, unweighted.lut);
+
+        // Red darkens to the left; weighting the left half moves the rest up.
+        let left_heavy: Vec<u16> = (0..64 * 64)
+            .map(|i| if i % 64 < 32 { 1000 } else { 1 })
+            .collect();
+        let skewed = config.run_weighted(&input, &left_heavy, &cancel).unwrap();
+        let right_red_mean = |result: &GaussianResult| {
+            let right: Vec<f32> = (0..64 * 64)
+                .filter(|i| i % 64 >= 32)
+                .map(|i| result.image[i * 3])
+                .collect();
+            right.iter().sum::<f32>() / right.len() as f32
+        };
+        assert!(right_red_mean(&skewed) > right_red_mean(&unweighted) + 0.1);
+        assert!(skewed.histogram.is_weighted());
+    }
+
+    #[test]
+    fn linear_lut_interp_reconstructs_a_ramp_better() {
+        // Every level once, with a LUT too narrow to hold each.
+        let input = DynamicImage::ImageRgb8(image::RgbImage::from_fn(256, 1, |x, _| {
+            image::Rgb([x as u8; 3])
+        }));
+        let reconstruction_rmse = |lut_interp: LutInterp| {
+            let config = TransformConfig::default()
+                .deterministic(true)
+                .lut_size(Some(48))
+                .lut_interp(lut_interp);
+            let result = config.run(&input).unwrap();
+            let coordinates =
+                lut_coordinates(&result.image, config.lut_coordinate(result.lut_width));
+            // Linear filtering of the float LUT, as a shader samples it.
+            let last = result.lut_width as usize - 1;
+            let reconstructed: Vec<f64> = coordinates
+                .iter()
+                .enumerate()
+                .map(|(i, &c)| {
+                    let x = (c as f64 * result.lut_width as f64 - 0.5).clamp(0.0, last as f64);
+                    let texel = x.floor() as usize;
+                    let next = (texel + 1).min(last);
+                    let (a, b) = (
+                        result.lut_f32[texel * 3 + i % 3] as f64,
+                        result.lut_f32[next * 3 + i % 3] as f64,
+                    );
+                    (a + (b - a) * x.fract()) * 255.0
+                })
+                .collect();
+            let source: Vec<f64> = input.to_rgb8().iter().map(|&v| v as f64).collect();
+            difference(&source, &reconstructed).rmse
+        };
+        let nearest = reconstruction_rmse(LutInterp::Nearest);
+        let linear = reconstruction_rmse(LutInterp::Linear);
+        assert!(
+            linear < nearest,
+            "linear {} against nearest {}",
+            linear,
+            nearest
+        );
+    }
+
+    #[test]
+    fn transparent_pixels_dont_shift_the_mapping() {
+        let visible = synthetic_input().to_rgba8();
+        // The visible texture on top of as many transparent junk pixels.
+        let junk = crate::rng::Rng::new(3);
+        let atlas = image::RgbaImage::from_fn(64, 128, |x, y| match y < 64 {
+            true => *visible.get_pixel(x, y),
+            false => image::Rgba([junk.below((y * 64 + x) as u64, 256) as u8, 0, 0, 0]),
+        });
+        let config = TransformConfig::default().deterministic(true);
+        let alone = config.run(&DynamicImage::ImageRgba8(visible)).unwrap();
+        let atlas = DynamicImage::ImageRgba8(atlas);
+        let polluted = config.run(&atlas).unwrap();
+        let cut = config.alpha_cutoff(Some(0.5)).run(&atlas).unwrap();
+        assert_ne!(&polluted.image[..alone.image.len()], &alone.image[..]);
+        assert!(cut.exclusions.iter().all(|e| e.alpha_cutoff == 64 * 64));
+        assert_eq!(&cut.image[..alone.image.len()], &alone.image[..]);
+        assert_eq!(cut.lut, alone.lut);
+    }
+
+    #[test]
+    fn smooth_inputs_fit_smaller_luts() {
+        // A few flat levels against uniform noise over the whole range.
+        let smooth = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, _| {
+            image::Rgb([100 + (x / 16) as u8; 3])
+        }));
+        let noise = crate::rng::Rng::new(11);
+        let noisy = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            let i = (y * 64 + x) as u64 * 3;
+            image::Rgb([0, 1, 2].map(|c| noise.below(i + c, 256) as u8))
+        }));
+        let config = TransformConfig::default().deterministic(true);
+        let fit = |input: &DynamicImage| {
+            let mut result = config.run(input).unwrap();
+            let rmse = config.fit_lut_size(input, &mut result, 1.0);
+            assert!(rmse <= 1.0, "rmse {}", rmse);
+            result.lut_width
+        };
+        let (smooth_width, noisy_width) = (fit(&smooth), fit(&noisy));
+        assert!(
+            smooth_width < noisy_width,
+            "{} against {}",
+            smooth_width,
+            noisy_width
+        );
+        assert!(noisy_width <= MAX_FITTED_LUT_SIZE);
+    }
+
+    #[test]
+    fn lut_rows_replicate_or_jitter_the_base_row() {
+        let input = synthetic_input();
+        let config = TransformConfig::default().deterministic(true);
+        let single = config.run(&input).unwrap();
+        let row_len = single.lut_width as usize * 3;
+        assert_eq!((single.lut_height, single.lut.len()), (1, row_len));
+
+        let replicated = config.clone().lut_rows(4).run(&input).unwrap();
+        assert_eq!(replicated.lut_height, 4);
+        assert_eq!(replicated.lut.len(), 4 * row_len);
+        assert_eq!(replicated.image, single.image);
+        for row in replicated.lut.chunks_exact(row_len) {
+            assert_eq!(row, &single.lut[..]);
+        }
+
+        let jittered = config
+            .lut_rows(4)
+            .lut_row_mode(LutRowMode::Jitter)
+            .run(&input)
+            .unwrap();
+        assert_eq!(jittered.lut.len(), 4 * row_len);
+        let rows: Vec<&[u8]> = jittered.lut.chunks_exact(row_len).collect();
+        assert_ne!(rows[0], rows[3]);
+        // Later rows sample further along each texel, never lower.
+        assert!(rows
+            .windows(2)
+            .all(|pair| pair[0].iter().zip(pair[1]).all(|(a, b)| a <= b)));
+    }
+
+    #[test]
+    fn forward_mean_tracks_the_gradient() {
+        let input = synthetic_input();
+        let gradient = MeanGradient {
+            start: 0.3,
+            end: 0.7,
+            axis: GradientAxis::Y,
+        };
+        let config = TransformConfig::default().deterministic(true);
+        let flat = config.run(&input).unwrap();
+        let graded = config.mean_gradient(Some(gradient)).run(&input).unwrap();
+        assert_eq!(graded.lut, flat.lut);
+
+        let row_mean = |image: &[f32], y: usize| {
+            let row = &image[y * 64 * 3..(y + 1) * 64 * 3];
+            row.iter().map(|&v| v as f64).sum::<f64>() / row.len() as f64
+        };
+        for y in 0..64 {
+            let local = gradient.mean_at(0, y as u32, 64, 64);
+            let shift = row_mean(&graded.image, y) - row_mean(&flat.image, y);
+            assert!((shift - (local - 0.5)).abs() < 1e-5, "row {}", y);
+        }
+        assert!((gradient.mean_at(0, 0, 64, 64) - 0.3).abs() < 0.01);
+        assert!((gradient.mean_at(0, 63, 64, 64) - 0.7).abs() < 0.01);
+    }
+
+    #[test]
+    fn rounding_modes_bias_the_lut_as_documented() {
+        let input = synthetic_input();
+        let bias = |rounding: Rounding| {
+            let result = TransformConfig::default()
+                .deterministic(true)
+                .lut_size(Some(2048))
+                .lut_interp(LutInterp::Linear)
+                .rounding(rounding)
+                .run(&input)
+                .unwrap();
+            // Only texels between two levels are rounded at all.
+            let errors: Vec<f64> = (result.lut.iter().zip(&result.lut_f32))
+                .map(|(&q, &v)| (q as f64, v as f64 * 255.0))
+                .filter(|(_, v)| (v - v.round()).abs() > 1e-3)
+                .map(|(q, v)| q - v)
+                .collect();
+            assert!(errors.len() > 100, "{} texels between levels", errors.len());
+            errors.iter().sum::<f64>() / errors.len() as f64
+        };
+        let (round, floor, ceil) = (
+            bias(Rounding::Round),
+            bias(Rounding::Floor),
+            bias(Rounding::Ceil),
+        );
+        assert!(floor < -0.3 && ceil > 0.3, "floor {} ceil {}", floor, ceil);
+        assert!(round.abs() < 0.1, "round {}", round);
+    }
+
+    #[test]
+    fn linear_plotting_position_anchors_the_extremes_to_the_target_ends() {
+        let input = synthetic_input();
+        let uniform = TransformConfig::default()
+            .deterministic(true)
+            .distribution(DistributionKind::Uniform);
+        let target = uniform.target();
+        let (low, high) = (target.inv_cdf(0.0), target.inv_cdf(1.0));
+        let extremes = |position: PlottingPosition| {
+            let result = uniform
+                .clone()
+                .plotting_position(position)
+                .run(&input)
+                .unwrap();
+            (0..3)
+                .map(|channel| {
+                    let values = result.image.iter().skip(channel).step_by(3);
+                    let min = values.clone().fold(f32::MAX, |a, &b| a.min(b)) as f64;
+                    let max = values.fold(f32::MIN, |a, &b| a.max(b)) as f64;
+                    (min, max)
+                })
+                .collect::<Vec<_>>()
+        };
+        for (min, max) in extremes(PlottingPosition::Linear) {
+            assert!((min - low).abs() < 1e-6 && (max - high).abs() < 1e-6);
+        }
+        for (min, max) in extremes(PlottingPosition::Midpoint) {
+            assert!(min > low + 1e-5 && max < high - 1e-5);
+        }
+
+        let gaussian = TransformConfig::default().plotting_position(PlottingPosition::Linear);
+        let err = gaussian.run(&input).unwrap_err().to_string();
+        assert!(
+            err.contains("gaussian target has no finite value"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn ranks_through_the_target_reproduce_the_forward_image() {
+        let input = synthetic_input();
+        let config = TransformConfig::default().deterministic(true);
+        let result = config.run(&input).unwrap();
+        let ranks = config.ranks(&input, &CancelToken::new()).unwrap();
+        assert_eq!(ranks.len(), result.image.len());
+
+        // The documented formula, without the crate's own quantile helpers.
+        let target = crate::distribution::Gaussian {
+            mean: config.mean,
+            std: config.std,
+        };
+        let n = (64 * 64) as f64;
+        let forward: Vec<f32> = ranks
+            .iter()
+            .map(|&k| target.inv_cdf((k as f64 + 0.5) / n) as f32)
+            .collect();
+        let diff = difference(&forward, &result.image);
+        assert!(diff.max_abs < 1e-6, "max {}", diff.max_abs);
+
+        // Every channel's ranks are a permutation.
+        for channel in 0..3 {
+            let mut channel: Vec<u32> = ranks.iter().skip(channel).step_by(3).copied().collect();
+            channel.sort_unstable();
+            assert!(channel.iter().enumerate().all(|(i, &k)| k == i as u32));
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::io::Read;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Hash [`hash_file`] computes.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// SHA-256, for content addressing.
+    #[default]
+    Sha256,
+    /// CRC-32 as zip and PNG use it, for cheap integrity checks only.
+    Crc32,
+}
+
+/// The lowercase hex digest of the file at `path` and its size in bytes.
+pub fn hash_file(path: &Path, algorithm: HashAlgorithm) -> Result<(String, u64), anyhow::Error> {
+    let mut file = std::fs::File::open(path)
+        .map_err(|e| anyhow::anyhow!("Couldn't open {:?} to hash it: {}", path, e))?;
+    let mut buffer = vec![0; 1 << 16];
+    let mut size = 0;
+    let mut sha256 = Sha256::new();
+    let mut crc32 = Crc32::new();
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        match algorithm {
+            HashAlgorithm::Sha256 => sha256.write(&buffer[..n]),
+            HashAlgorithm::Crc32 => crc32.write(&buffer[..n]),
+        }
+        size += n as u64;
+    }
+    Ok((hex_digest(algorithm, sha256, crc32), size))
+}
+
+/// The lowercase hex digest of `bytes`.
+pub fn hash_bytes(bytes: &[u8], algorithm: HashAlgorithm) -> String {
+    let mut sha256 = Sha256::new();
+    let mut crc32 = Crc32::new();
+    match algorithm {
+        HashAlgorithm::Sha256 => sha256.write(bytes),
+        HashAlgorithm::Crc32 => crc32.write(bytes),
+    }
+    hex_digest(algorithm, sha256, crc32)
+}
+
+fn hex_digest(algorithm: HashAlgorithm, sha256: Sha256, crc32: Crc32) -> String {
+    match algorithm {
+        HashAlgorithm::Sha256 => sha256
+            .finish()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+        HashAlgorithm::Crc32 => format!("{:08x}", crc32.finish()),
+    }
+}
+
+/// SHA-256 as FIPS 180-4 specifies it.
+pub(crate) struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    len: u64,
+}
+
+impl Sha256 {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+
+    pub(crate) fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: [0; 64],
+            block_len: 0,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn write(&mut self, mut bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+        while !bytes.is_empty() {
+            let n = (64 - self.block_len).min(bytes.len());
+            self.block[self.block_len..self.block_len + n].copy_from_slice(&bytes[..n]);
+            self.block_len += n;
+            bytes = &bytes[n..];
+            if self.block_len == 64 {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> [u8; 32] {
+        let bits = self.len.wrapping_mul(8);
+        // A one bit, then zeros up to the last 8 bytes of a block, which hold the length.
+        self.write(&[0x80]);
+        while self.block_len != 56 {
+            self.write(&[0]);
+        }
+        self.write(&bits.to_be_bytes());
+        let mut digest = [0; 32];
+        for (out, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            out.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, word) in self.block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for (k, w) in Self::K.iter().zip(w) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(*k)
+                .wrapping_add(w);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(v);
+        }
+    }
+}
+
+/// The CRC-32 zip and PNG checksum their data with.
+pub(crate) struct Crc32(u32);
+
+impl Crc32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut c = i as u32;
+            let mut k = 0;
+            while k < 8 {
+                c = if c & 1 != 0 {
+                    0xEDB8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+                k += 1;
+            }
+            table[i] = c;
+            i += 1;
+        }
+        table
+    };
+
+    pub(crate) fn new() -> Self {
+        Self(!0)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = Self::TABLE[((self.0 ^ b as u32) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digests_match_the_reference_values() {
+        // The test vectors of FIPS 180-4 and of the CRC catalogue.
+        assert_eq!(
+            hash_bytes(b"abc", HashAlgorithm::Sha256),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hash_bytes(b"", HashAlgorithm::Sha256),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(hash_bytes(b"123456789", HashAlgorithm::Crc32), "cbf43926");
+
+        // hash_file reads in chunks, so a file several chunks long hashes
+        // the same as its bytes do at once.
+        let bytes: Vec<u8> = (0..200_001u32).map(|i| (i * 7 % 251) as u8).collect();
+        let path = std::env::temp_dir().join(format!("precompute-digest-{}", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        for algorithm in [HashAlgorithm::Sha256, HashAlgorithm::Crc32] {
+            let (digest, size) = hash_file(&path, algorithm).unwrap();
+            assert_eq!(
+                (digest, size),
+                (hash_bytes(&bytes, algorithm), bytes.len() as u64)
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
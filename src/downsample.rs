@@ -0,0 +1,205 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config::GaussianResult;
+
+/// How [`downsample`] combines the samples under each output pixel.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownsampleFilter {
+    /// The mean of the `factor x factor` block the output pixel covers.
+    #[default]
+    Box,
+    /// A Gaussian with a standard deviation of half the factor, centered on
+    /// the block and reaching one block into its neighbours, for less
+    /// aliasing at the cost of some blur.
+    Gaussian,
+}
+
+/// Shrinks an interleaved `width x height` float image by `factor` on both
+/// axes, rounding up, so the last row and column of blocks may be partial.
+/// `factor` must be at least 1.
+/// Returns the image and its new width and height.
+pub fn downsample(
+    image: &[f32],
+    width: u32,
+    height: u32,
+    channels: usize,
+    factor: u32,
+    filter: DownsampleFilter,
+) -> (Vec<f32>, u32, u32) {
+    let (out_width, out_height) = (width.div_ceil(factor), height.div_ceil(factor));
+    let columns = taps(width, out_width, factor, filter);
+    let rows = taps(height, out_height, factor, filter);
+    let row_len = width as usize * channels;
+    let out_row_len = out_width as usize * channels;
+
+    let mut horizontal = vec![0.0; out_row_len * height as usize];
+    horizontal
+        .par_chunks_mut(out_row_len)
+        .zip(image.par_chunks(row_len))
+        .for_each(|(out, row)| {
+            for (out, taps) in out.chunks_exact_mut(channels).zip(&columns) {
+                for (c, out) in out.iter_mut().enumerate() {
+                    *out = taps
+                        .iter()
+                        .map(|&(x, weight)| weight * row[x * channels + c] as f64)
+                        .sum::<f64>() as f32;
+                }
+            }
+        });
+    let mut out = vec![0.0; out_row_len * out_height as usize];
+    out.par_chunks_mut(out_row_len)
+        .zip(&rows)
+        .for_each(|(out, taps)| {
+            for (i, out) in out.iter_mut().enumerate() {
+                *out = taps
+                    .iter()
+                    .map(|&(y, weight)| weight * horizontal[y * out_row_len + i] as f64)
+                    .sum::<f64>() as f32;
+            }
+        });
+    (out, out_width, out_height)
+}
+
+/// For each of `out_len` outputs along an axis of `len` samples, the samples
+/// it reads and their weights, which sum to one.
+fn taps(len: u32, out_len: u32, factor: u32, filter: DownsampleFilter) -> Vec<Vec<(usize, f64)>> {
+    let (len, factor) = (len as usize, factor as usize);
+    (0..out_len as usize)
+        .map(|o| {
+            let taps: Vec<(usize, f64)> = match filter {
+                DownsampleFilter::Box => (o * factor..((o + 1) * factor).min(len))
+                    .map(|i| (i, 1.0))
+                    .collect(),
+                DownsampleFilter::Gaussian => {
+                    let center = (o * factor) as f64 + (factor as f64 - 1.0) / 2.0;
+                    let sigma = factor as f64 / 2.0;
+                    let first = (o * factor).saturating_sub(factor);
+                    let last = ((o + 2) * factor).min(len);
+                    (first..last)
+                        .map(|i| {
+                            let d = i as f64 - center;
+                            (i, (-d * d / (2.0 * sigma * sigma)).exp())
+                        })
+                        .collect()
+                }
+            };
+            let total: f64 = taps.iter().map(|(_, weight)| weight).sum();
+            taps.into_iter()
+                .map(|(i, weight)| (i, weight / total))
+                .collect()
+        })
+        .collect()
+}
+
+impl GaussianResult {
+    /// Shrinks the forward image by `factor` with [`downsample`], each page
+    /// on its own. The LUT is left as it is: it maps forward values back to
+    /// source values wherever they come from, so the full-resolution histogram
+    /// still decodes the smaller image. A `factor` of 0, or one that leaves an
+    /// empty image, is an error.
+    pub fn downsample(
+        &mut self,
+        factor: u32,
+        filter: DownsampleFilter,
+    ) -> Result<(), anyhow::Error> {
+        if factor == 0 {
+            return Err(anyhow::anyhow!("Can't downsample by a factor of 0"));
+        }
+        let mut image = Vec::new();
+        let mut page_heights = Vec::with_capacity(self.page_heights.len());
+        let mut offset = 0;
+        let mut out_width = self.width;
+        for &height in &self.page_heights {
+            let len = self.width as usize * height as usize * 3;
+            let (page, width, height) = downsample(
+                &self.image[offset..offset + len],
+                self.width,
+                height,
+                3,
+                factor,
+                filter,
+            );
+            image.extend(page);
+            page_heights.push(height);
+            out_width = width;
+            offset += len;
+        }
+        if out_width == 0 || page_heights.contains(&0) {
+            return Err(anyhow::anyhow!(
+                "Downsampling by {} left an empty image",
+                factor
+            ));
+        }
+        self.image = image;
+        self.width = out_width;
+        self.height = page_heights.iter().sum();
+        self.page_heights = page_heights;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::self_test::synthetic_input;
+    use crate::TransformConfig;
+
+    #[test]
+    fn downsampled_bake_keeps_the_full_resolution_lut() {
+        let full = TransformConfig::default()
+            .deterministic(true)
+            .run(&synthetic_input())
+            .unwrap();
+        for (factor, size) in [(2, 32), (3, 22)] {
+            for filter in [DownsampleFilter::Box, DownsampleFilter::Gaussian] {
+                let mut shrunk = full.clone();
+                shrunk.downsample(factor, filter).unwrap();
+                assert_eq!((shrunk.width, shrunk.height), (size, size));
+                assert_eq!(shrunk.page_heights, [size]);
+                assert_eq!(shrunk.image.len(), (size * size * 3) as usize);
+                assert_eq!(shrunk.lut, full.lut);
+                assert_eq!(
+                    (shrunk.lut_width, shrunk.lut_height),
+                    (full.lut_width, full.lut_height)
+                );
+            }
+        }
+
+        // A box filter's pixels are the means of their blocks, here the
+        // partial 1x1 block in the corner of a 64x64 image shrunk by 3.
+        let mut shrunk = full.clone();
+        shrunk.downsample(3, DownsampleFilter::Box).unwrap();
+        let (x, y) = (21, 21);
+        for c in 0..3 {
+            let expected = full.image[(63 * 64 + 63) * 3 + c];
+            assert!((shrunk.image[(y * 22 + x) * 3 + c] - expected).abs() < 1e-6);
+        }
+        let image = &full.image;
+        let block_mean = |c: usize| {
+            let samples = (0..3).flat_map(|y| (0..3).map(move |x| image[(y * 64 + x) * 3 + c]));
+            samples.map(f64::from).sum::<f64>() / 9.0
+        };
+        for c in 0..3 {
+            assert!((shrunk.image[c] as f64 - block_mean(c)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn degenerate_factors_are_errors_or_one_pixel() {
+        let full = TransformConfig::default().run(&synthetic_input()).unwrap();
+        let err = full
+            .clone()
+            .downsample(0, DownsampleFilter::Box)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Can't downsample by a factor of 0");
+        // Rounding up, a factor larger than the image leaves one pixel.
+        for filter in [DownsampleFilter::Box, DownsampleFilter::Gaussian] {
+            let mut shrunk = full.clone();
+            shrunk.downsample(1000, filter).unwrap();
+            assert_eq!((shrunk.width, shrunk.height, shrunk.image.len()), (1, 1, 3));
+        }
+    }
+}
@@ -0,0 +1,1306 @@
+use std::ffi::OsStr;
+use std::io::{Cursor, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tiff::encoder::compression::{Compression, Packbits, Uncompressed};
+use tiff::encoder::{colortype, Rational, TiffEncoder, TiffValue};
+use tiff::tags::{ResolutionUnit, Tag};
+use tiff::TiffError;
+
+use crate::config::{GaussianResult, LutTexelOffset};
+use crate::{ktx2, tiff_writer};
+
+/// Interleaved samples handed to an [`OutputEncoder`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputData<'a> {
+    U8(&'a [u8]),
+    /// 16-bit integers; TIFF, npz and cube outputs hold them.
+    U16(&'a [u16]),
+    F32(&'a [f32]),
+    /// 32-bit integers, such as the LUT of an integer heightmap; only
+    /// one-channel TIFF and npz outputs hold them.
+    U32(&'a [u32]),
+    I32(&'a [i32]),
+}
+
+impl OutputData<'_> {
+    pub fn len(&self) -> usize {
+        match self {
+            OutputData::U8(data) => data.len(),
+            OutputData::U16(data) => data.len(),
+            OutputData::F32(data) => data.len(),
+            OutputData::U32(data) => data.len(),
+            OutputData::I32(data) => data.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The samples as `target`, for outputs that store another type than
+    /// the buffer they're made from: integers are scaled so their full
+    /// ranges match, 255 to 65535, float samples are taken as `[0, 1]`, and
+    /// conversions to integers round to nearest, clamp to the range and map
+    /// NaN to 0. Converting to the same type copies. 32-bit integer samples
+    /// aren't fractions of a range, so they only convert to themselves, which
+    /// isn't a [`SampleType`], and fail.
+    pub fn convert(&self, target: SampleType) -> Result<OutputSamples, anyhow::Error> {
+        fn quantize(v: f32, max: f32) -> f32 {
+            (v * max).round().clamp(0.0, max)
+        }
+        Ok(match (*self, target) {
+            (OutputData::U8(data), SampleType::U8) => OutputSamples::U8(data.to_vec()),
+            (OutputData::U8(data), SampleType::U16) => {
+                OutputSamples::U16(data.iter().map(|&v| v as u16 * 257).collect())
+            }
+            (OutputData::U8(data), SampleType::F32) => {
+                OutputSamples::F32(data.iter().map(|&v| v as f32 / 255.0).collect())
+            }
+            (OutputData::U16(data), SampleType::U8) => OutputSamples::U8(
+                data.iter()
+                    .map(|&v| ((v as u32 + 128) / 257) as u8)
+                    .collect(),
+            ),
+            (OutputData::U16(data), SampleType::U16) => OutputSamples::U16(data.to_vec()),
+            (OutputData::U16(data), SampleType::F32) => {
+                OutputSamples::F32(data.iter().map(|&v| v as f32 / 65535.0).collect())
+            }
+            // `as` maps NaN to 0.
+            (OutputData::F32(data), SampleType::U8) => {
+                OutputSamples::U8(data.iter().map(|&v| quantize(v, 255.0) as u8).collect())
+            }
+            (OutputData::F32(data), SampleType::U16) => {
+                OutputSamples::U16(data.iter().map(|&v| quantize(v, 65535.0) as u16).collect())
+            }
+            (OutputData::F32(data), SampleType::F32) => OutputSamples::F32(data.to_vec()),
+            (OutputData::U32(_) | OutputData::I32(_), target) => {
+                return Err(anyhow::anyhow!(
+                    "32-bit integer samples can't be converted to {:?}",
+                    target
+                ))
+            }
+        })
+    }
+}
+
+/// Sample type of an output, for [`OutputData::convert`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleType {
+    U8,
+    U16,
+    F32,
+}
+
+/// Samples [`OutputData::convert`] made.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutputSamples {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    F32(Vec<f32>),
+}
+
+impl OutputSamples {
+    /// The samples, to hand to an [`OutputEncoder`].
+    pub fn data(&self) -> OutputData<'_> {
+        match self {
+            OutputSamples::U8(data) => OutputData::U8(data),
+            OutputSamples::U16(data) => OutputData::U16(data),
+            OutputSamples::F32(data) => OutputData::F32(data),
+        }
+    }
+}
+
+/// Writes interleaved image data to a file in one format. Implement it to add
+/// a format when embedding the library.
+pub trait OutputEncoder: Send + Sync {
+    /// Extension, without the dot, of files this encoder writes.
+    fn extension(&self) -> &str;
+
+    fn encode(
+        &self,
+        path: &Path,
+        width: u32,
+        height: u32,
+        channels: usize,
+        data: OutputData,
+    ) -> Result<(), anyhow::Error>;
+
+    /// Writes equally wide pages stacked top to bottom in `data`. Formats
+    /// without pages only accept one.
+    fn encode_pages(
+        &self,
+        path: &Path,
+        width: u32,
+        page_heights: &[u32],
+        channels: usize,
+        data: OutputData,
+    ) -> Result<(), anyhow::Error> {
+        match page_heights {
+            [height] => self.encode(path, width, *height, channels, data),
+            _ => Err(anyhow::anyhow!(
+                "{} outputs can't hold {} pages",
+                self.extension(),
+                page_heights.len()
+            )),
+        }
+    }
+
+    /// Writes what [`OutputEncoder::encode_pages`] would write to a file to
+    /// `writer` instead, for [`encode_to_vec`]. Encoders that only write
+    /// files keep the default, which fails.
+    fn encode_pages_to(
+        &self,
+        writer: &mut dyn WriteSeek,
+        width: u32,
+        page_heights: &[u32],
+        channels: usize,
+        data: OutputData,
+    ) -> Result<(), anyhow::Error> {
+        let _ = (writer, width, page_heights, channels, data);
+        Err(anyhow::anyhow!(
+            "{} outputs can only be written to files",
+            self.extension()
+        ))
+    }
+}
+
+/// A seekable writer, such as a file or a [`Cursor`], for
+/// [`OutputEncoder::encode_pages_to`].
+pub trait WriteSeek: Write + Seek {}
+
+impl<T: Write + Seek + ?Sized> WriteSeek for T {}
+
+/// The bytes `encoder` would write to a file for `data`, built in memory.
+pub fn encode_to_vec(
+    encoder: &dyn OutputEncoder,
+    width: u32,
+    page_heights: &[u32],
+    channels: usize,
+    data: OutputData,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let mut cursor = Cursor::new(Vec::new());
+    encoder.encode_pages_to(&mut cursor, width, page_heights, channels, data)?;
+    Ok(cursor.into_inner())
+}
+
+/// `result`'s forward image, every page, as `encoder` would write it to a file.
+pub fn encode_image_to_vec(
+    result: &GaussianResult,
+    encoder: &dyn OutputEncoder,
+) -> Result<Vec<u8>, anyhow::Error> {
+    encode_to_vec(
+        encoder,
+        result.width,
+        &result.page_heights,
+        3,
+        OutputData::F32(&result.image),
+    )
+}
+
+/// `result`'s 8-bit LUT as `encoder` would write it to a file. Pass
+/// `result.lut_f32` to [`encode_to_vec`] for the float LUT.
+pub fn encode_lut_to_vec(
+    result: &GaussianResult,
+    encoder: &dyn OutputEncoder,
+) -> Result<Vec<u8>, anyhow::Error> {
+    encode_to_vec(
+        encoder,
+        result.lut_width,
+        &[result.lut_height],
+        3,
+        OutputData::U8(&result.lut),
+    )
+}
+
+/// Returns the built-in encoder for a file extension, if there is one.
+pub fn encoder_for_extension(extension: &str) -> Option<Box<dyn OutputEncoder>> {
+    match extension.to_ascii_lowercase().as_str() {
+        "tif" | "tiff" => Some(Box::new(Tiff::default())),
+        "ktx2" => Some(Box::new(Ktx2::default())),
+        "cube" => Some(Box::new(Cube::default())),
+        _ => None,
+    }
+}
+
+/// Packbits-compressed TIFF, one page per page of the output. 8-bit data is
+/// written as RGB8, 16-bit data as RGB16, float data as RGB32Float, or
+/// Gray8, Gray16 and Gray32Float with one channel; 32-bit integer data only
+/// with one channel, as Gray32 and GrayI32. Files in the other byte order
+/// than the host's, planar or tiled, are written uncompressed, and so is a
+/// file the `tiff` encoder refuses to compress, with a warning.
+#[derive(Clone, Debug, Default)]
+pub struct Tiff {
+    /// Byte order of the file. Default little-endian.
+    pub byte_order: ByteOrder,
+    /// How samples are arranged. Default interleaved.
+    pub layout: Layout,
+    /// Side of the square tiles to write pages as, a multiple of 16. Default
+    /// `None`, one strip per page.
+    pub tile_size: Option<u32>,
+    /// Text of the first page's ImageDescription tag, such as the
+    /// [`metadata::describe`](crate::metadata::describe) parameters a later
+    /// bake picks up. Default none.
+    pub description: Option<String>,
+    /// Pixels per inch to record in every page's XResolution and
+    /// YResolution tags, for tools that size imports by them. Default
+    /// `None`, which leaves the `tiff` encoder's one pixel per unit without
+    /// a unit, and files it doesn't write without resolution tags.
+    pub dpi: Option<u32>,
+    pub write_options: WriteOptions,
+}
+
+/// Byte order of a TIFF file.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// `II`, what most writers produce.
+    #[default]
+    #[cfg_attr(feature = "cli", value(name = "le"))]
+    LittleEndian,
+    /// `MM`, for readers that only accept Motorola order.
+    #[cfg_attr(feature = "cli", value(name = "be"))]
+    BigEndian,
+}
+
+/// How the LUT's three channels are laid out in its texture.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LutPack {
+    /// `RGB`, one LUT per channel.
+    #[default]
+    Rgb,
+    /// `RGBA`: the red and green LUTs in RG, the blue LUT in B, and in A a
+    /// flag that is 1 where the blue channel was transformed and 0 where its
+    /// LUT is identity or unused, for targets that can't sample RGB textures.
+    #[cfg_attr(feature = "cli", value(name = "rg-ba"))]
+    RgBa,
+}
+
+impl LutPack {
+    /// Channels per texel of a LUT in this layout.
+    pub fn channels(self) -> usize {
+        match self {
+            LutPack::Rgb => 3,
+            LutPack::RgBa => 4,
+        }
+    }
+
+    /// Rearranges an interleaved RGB `lut` into this layout, with `flag` as
+    /// the value of a set flag: 255 for an 8-bit LUT, 1.0 for a float one.
+    pub fn pack<T: Copy + Default>(self, lut: &[T], blue_transformed: bool, flag: T) -> Vec<T> {
+        match self {
+            LutPack::Rgb => lut.to_vec(),
+            LutPack::RgBa => {
+                let flag = if blue_transformed { flag } else { T::default() };
+                lut.chunks_exact(3)
+                    .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], flag])
+                    .collect()
+            }
+        }
+    }
+
+    /// Recovers the interleaved RGB LUT from one packed by [`LutPack::pack`].
+    pub fn unpack<T: Copy>(self, packed: &[T]) -> Vec<T> {
+        packed
+            .chunks_exact(self.channels())
+            .flat_map(|texel| [texel[0], texel[1], texel[2]])
+            .collect()
+    }
+}
+
+/// Arrangement of a TIFF's samples.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// `RGBRGB...`, one strip per page.
+    #[default]
+    Interleaved,
+    /// `RR...GG...BB...`, one strip per channel per page.
+    Planar,
+}
+
+impl ByteOrder {
+    fn is_native(self) -> bool {
+        (self == ByteOrder::LittleEndian) == cfg!(target_endian = "little")
+    }
+}
+
+impl OutputEncoder for Tiff {
+    fn extension(&self) -> &str {
+        "tif"
+    }
+
+    fn encode(
+        &self,
+        path: &Path,
+        width: u32,
+        height: u32,
+        channels: usize,
+        data: OutputData,
+    ) -> Result<(), anyhow::Error> {
+        self.encode_pages(path, width, &[height], channels, data)
+    }
+
+    fn encode_pages(
+        &self,
+        path: &Path,
+        width: u32,
+        page_heights: &[u32],
+        channels: usize,
+        data: OutputData,
+    ) -> Result<(), anyhow::Error> {
+        check_layout(width, page_heights.iter().sum(), channels, data)?;
+        write_atomic(path, &self.write_options, |file| {
+            self.encode_pages_to(file, width, page_heights, channels, data)
+        })
+    }
+
+    fn encode_pages_to(
+        &self,
+        writer: &mut dyn WriteSeek,
+        width: u32,
+        page_heights: &[u32],
+        channels: usize,
+        data: OutputData,
+    ) -> Result<(), anyhow::Error> {
+        check_layout(width, page_heights.iter().sum(), channels, data)?;
+        if channels == 1 {
+            if !self.byte_order.is_native() || self.tile_size.is_some() {
+                return Err(anyhow::anyhow!(
+                    "One-channel TIFFs are only written in the host's byte order, in strips"
+                ));
+            }
+            return match data {
+                OutputData::U8(data) => {
+                    write_tiff::<colortype::Gray8>(writer, self, width, page_heights, data)
+                }
+                OutputData::U16(data) => {
+                    write_tiff::<colortype::Gray16>(writer, self, width, page_heights, data)
+                }
+                OutputData::F32(data) => {
+                    write_tiff::<colortype::Gray32Float>(writer, self, width, page_heights, data)
+                }
+                OutputData::U32(data) => {
+                    write_tiff::<colortype::Gray32>(writer, self, width, page_heights, data)
+                }
+                OutputData::I32(data) => {
+                    write_tiff::<colortype::GrayI32>(writer, self, width, page_heights, data)
+                }
+            };
+        }
+        if matches!(data, OutputData::U32(_) | OutputData::I32(_)) {
+            return Err(anyhow::anyhow!(
+                "32-bit integer TIFFs are only written with one channel, got {}",
+                channels
+            ));
+        }
+        if channels == 4 {
+            if !self.byte_order.is_native()
+                || self.layout == Layout::Planar
+                || self.tile_size.is_some()
+            {
+                return Err(anyhow::anyhow!(
+                    "Four-channel TIFFs are only written in the host's byte order, interleaved in strips"
+                ));
+            }
+            return match data {
+                OutputData::U8(data) => {
+                    write_tiff::<colortype::RGBA8>(writer, self, width, page_heights, data)
+                }
+                OutputData::U16(data) => {
+                    write_tiff::<colortype::RGBA16>(writer, self, width, page_heights, data)
+                }
+                OutputData::F32(data) => {
+                    write_tiff::<colortype::RGBA32Float>(writer, self, width, page_heights, data)
+                }
+                OutputData::U32(_) | OutputData::I32(_) => unreachable!("rejected above"),
+            };
+        }
+        if let Some(tile_size) = self.tile_size.filter(|size| *size == 0 || size % 16 != 0) {
+            return Err(anyhow::anyhow!(
+                "TIFF tiles must be a positive multiple of 16, got {}",
+                tile_size
+            ));
+        }
+        if !self.byte_order.is_native() || self.layout == Layout::Planar || self.tile_size.is_some()
+        {
+            return tiff_writer::write_rgb(writer, self, width, page_heights, data);
+        }
+        match data {
+            OutputData::U8(data) => {
+                write_tiff::<colortype::RGB8>(writer, self, width, page_heights, data)
+            }
+            OutputData::U16(data) => {
+                write_tiff::<colortype::RGB16>(writer, self, width, page_heights, data)
+            }
+            OutputData::F32(data) => {
+                write_tiff::<colortype::RGB32Float>(writer, self, width, page_heights, data)
+            }
+            OutputData::U32(_) | OutputData::I32(_) => unreachable!("rejected above"),
+        }
+    }
+}
+
+/// KTX2 texture, `R8G8B8_UNORM` or `R32G32B32_SFLOAT`. A single row is
+/// written as a 1D texture.
+#[derive(Clone, Debug, Default)]
+pub struct Ktx2 {
+    /// Extra key/value entries; keys must not start with `KTX`.
+    pub metadata: Vec<(String, String)>,
+    pub write_options: WriteOptions,
+}
+
+impl OutputEncoder for Ktx2 {
+    fn extension(&self) -> &str {
+        "ktx2"
+    }
+
+    fn encode(
+        &self,
+        path: &Path,
+        width: u32,
+        height: u32,
+        channels: usize,
+        data: OutputData,
+    ) -> Result<(), anyhow::Error> {
+        check_layout(width, height, channels, data)?;
+        write_atomic(path, &self.write_options, |file| {
+            self.encode_pages_to(file, width, &[height], channels, data)
+        })
+    }
+
+    fn encode_pages_to(
+        &self,
+        writer: &mut dyn WriteSeek,
+        width: u32,
+        page_heights: &[u32],
+        channels: usize,
+        data: OutputData,
+    ) -> Result<(), anyhow::Error> {
+        let &[height] = page_heights else {
+            return Err(anyhow::anyhow!(
+                "ktx2 outputs can't hold {} pages",
+                page_heights.len()
+            ));
+        };
+        check_layout(width, height, channels, data)?;
+        if channels != 3 {
+            return Err(anyhow::anyhow!(
+                "KTX2 outputs need 3 channels, got {}",
+                channels
+            ));
+        }
+        let height = if height == 1 { 0 } else { height };
+        match data {
+            OutputData::U8(data) => ktx2::write_rgb8(writer, width, height, data, &self.metadata),
+            OutputData::F32(data) => {
+                ktx2::write_rgb32f(writer, width, height, data, &self.metadata)
+            }
+            OutputData::U16(_) => Err(anyhow::anyhow!("KTX2 outputs can't hold 16-bit data")),
+            OutputData::U32(_) | OutputData::I32(_) => Err(anyhow::anyhow!(
+                "KTX2 outputs can't hold 32-bit integer data"
+            )),
+        }
+    }
+}
+
+/// Adobe/Resolve 1D `.cube` LUT, for color-grading tools: one entry per
+/// texel of a single-row LUT, scaled to `[0, 1]`, with a one-channel LUT
+/// repeated across R, G and B. The tools spread the entries evenly from
+/// `DOMAIN_MIN` to `DOMAIN_MAX` and interpolate linearly between them, so
+/// the domain runs between the first and last texels' centers.
+#[derive(Clone, Debug, Default)]
+pub struct Cube {
+    pub title: String,
+    /// Whether the LUT was built with `lut_for_linear_filtering`, which puts
+    /// its end texels' centers at 0 and 1 rather than half a texel in.
+    pub lut_for_linear_filtering: bool,
+    /// The `lut_texel_offset` the LUT was built with, which with
+    /// [`LutTexelOffset::Half`] puts its texels' centers half a texel lower.
+    pub lut_texel_offset: LutTexelOffset,
+    pub write_options: WriteOptions,
+}
+
+impl OutputEncoder for Cube {
+    fn extension(&self) -> &str {
+        "cube"
+    }
+
+    fn encode(
+        &self,
+        path: &Path,
+        width: u32,
+        height: u32,
+        channels: usize,
+        data: OutputData,
+    ) -> Result<(), anyhow::Error> {
+        check_layout(width, height, channels, data)?;
+        write_atomic(path, &self.write_options, |file| {
+            self.encode_pages_to(file, width, &[height], channels, data)
+        })
+    }
+
+    fn encode_pages_to(
+        &self,
+        writer: &mut dyn WriteSeek,
+        width: u32,
+        page_heights: &[u32],
+        channels: usize,
+        data: OutputData,
+    ) -> Result<(), anyhow::Error> {
+        let &[height] = page_heights else {
+            return Err(anyhow::anyhow!(
+                "cube outputs can't hold {} pages",
+                page_heights.len()
+            ));
+        };
+        check_layout(width, height, channels, data)?;
+        if height != 1 {
+            return Err(anyhow::anyhow!(
+                "cube outputs hold a single LUT row, got {}",
+                height
+            ));
+        }
+        if channels == 4 {
+            return Err(anyhow::anyhow!("cube outputs need 1 or 3 channels, got 4"));
+        }
+        let values: Vec<f64> = match data {
+            OutputData::U8(data) => data.iter().map(|&v| v as f64 / 255.0).collect(),
+            OutputData::U16(data) => data.iter().map(|&v| v as f64 / 65535.0).collect(),
+            OutputData::F32(data) => data.iter().map(|&v| v as f64).collect(),
+            OutputData::U32(_) | OutputData::I32(_) => {
+                return Err(anyhow::anyhow!(
+                    "cube outputs can't hold 32-bit integer data"
+                ))
+            }
+        };
+        let (min, max) = match self.lut_for_linear_filtering || width == 1 {
+            true => (0.0, 1.0),
+            false if self.lut_texel_offset == LutTexelOffset::Half => {
+                (0.0, 1.0 - 1.0 / width as f64)
+            }
+            false => (0.5 / width as f64, 1.0 - 0.5 / width as f64),
+        };
+        let mut writer = std::io::BufWriter::new(writer);
+        writeln!(writer, "TITLE \"{}\"", self.title.replace('"', "'"))?;
+        writeln!(writer, "LUT_1D_SIZE {}", width)?;
+        writeln!(writer, "DOMAIN_MIN {min:.6} {min:.6} {min:.6}")?;
+        writeln!(writer, "DOMAIN_MAX {max:.6} {max:.6} {max:.6}")?;
+        for texel in values.chunks_exact(channels) {
+            let [r, g, b] = match *texel {
+                [v] => [v; 3],
+                [r, g, b] => [r, g, b],
+                _ => unreachable!("checked above"),
+            };
+            writeln!(writer, "{:.6} {:.6} {:.6}", r, g, b)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn check_layout(
+    width: u32,
+    height: u32,
+    channels: usize,
+    data: OutputData,
+) -> Result<(), anyhow::Error> {
+    if !matches!(channels, 1 | 3 | 4) {
+        return Err(anyhow::anyhow!(
+            "Expected 1, 3 or 4 channels, got {}",
+            channels
+        ));
+    }
+    let expected = width as usize * height as usize * channels;
+    if data.len() != expected {
+        return Err(anyhow::anyhow!(
+            "Expected {} samples for {}x{}, got {}",
+            expected,
+            width,
+            height,
+            data.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Writes `data` Packbits-compressed, or uncompressed as
+/// [`compress_or_fall_back`] decides.
+fn write_tiff<T>(
+    writer: &mut dyn WriteSeek,
+    tiff: &Tiff,
+    width: u32,
+    page_heights: &[u32],
+    data: &[T::Inner],
+) -> Result<(), anyhow::Error>
+where
+    T: colortype::ColorType,
+    [T::Inner]: TiffValue,
+{
+    compress_or_fall_back(
+        writer,
+        &tiff.write_options,
+        (width, page_heights.iter().sum()),
+        |writer| write_tiff_with::<T, _>(writer, tiff, width, page_heights, data, Packbits),
+        |writer| write_tiff_with::<T, _>(writer, tiff, width, page_heights, data, Uncompressed),
+    )
+}
+
+/// Runs `compressed`, and when the encoder itself fails in it, as opposed
+/// to the writer, rewrites the `size` image with `uncompressed` over whatever
+/// it wrote, with a warning, or with [`WriteOptions::strict`] fails instead.
+/// Bytes of the failed attempt past the end of the uncompressed file, if any,
+/// are left behind, but nothing in the file points at them.
+fn compress_or_fall_back(
+    writer: &mut dyn WriteSeek,
+    options: &WriteOptions,
+    (width, height): (u32, u32),
+    compressed: impl FnOnce(&mut dyn WriteSeek) -> Result<(), TiffError>,
+    uncompressed: impl FnOnce(&mut dyn WriteSeek) -> Result<(), TiffError>,
+) -> Result<(), anyhow::Error> {
+    let start = writer.stream_position()?;
+    let e = match compressed(writer) {
+        Ok(()) => return Ok(()),
+        Err(TiffError::IoError(e)) => return Err(e.into()),
+        Err(e) => e,
+    };
+    let message = format!(
+        "The TIFF encoder couldn't compress a {}x{} image ({})",
+        width, height, e
+    );
+    if options.strict {
+        return Err(anyhow::anyhow!("{} (--strict)", message));
+    }
+    eprintln!("Warning: {}, writing it uncompressed", message);
+    writer.seek(SeekFrom::Start(start))?;
+    Ok(uncompressed(writer)?)
+}
+
+fn write_tiff_with<T, C>(
+    writer: &mut dyn WriteSeek,
+    tiff: &Tiff,
+    width: u32,
+    page_heights: &[u32],
+    data: &[T::Inner],
+    compression: C,
+) -> Result<(), TiffError>
+where
+    T: colortype::ColorType,
+    [T::Inner]: TiffValue,
+    C: Compression + Copy,
+{
+    let mut encoder = TiffEncoder::new(writer)?;
+    let page_len = |height: u32| width as usize * height as usize * T::SAMPLE_FORMAT.len();
+    let mut offset = 0;
+    for (i, &height) in page_heights.iter().enumerate() {
+        let page = &data[offset..offset + page_len(height)];
+        let mut image = encoder.new_image_with_compression::<T, _>(width, height, compression)?;
+        if let Some(description) = tiff.description.as_deref().filter(|_| i == 0) {
+            image
+                .encoder()
+                .write_tag(Tag::ImageDescription, description)?;
+        }
+        if let Some(dpi) = tiff.dpi {
+            image.resolution(ResolutionUnit::Inch, Rational { n: dpi, d: 1 });
+        }
+        image.write_data(page)?;
+        offset += page_len(height);
+    }
+    Ok(())
+}
+
+/// How [`write_atomic`], and so every encoder, writes files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct WriteOptions {
+    /// Times a write is retried from the start when it fails with an error a
+    /// network filesystem might not repeat, such as a timeout or a dropped
+    /// connection. Permanent errors like permission denied still fail at
+    /// once. Default `0`.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each one after it. Default
+    /// 100 ms.
+    pub retry_backoff: Duration,
+    /// Where to note the path of every file written. Default `None`.
+    pub log: Option<WriteLog>,
+    /// Fail instead of warning when a TIFF has to be written uncompressed
+    /// because the encoder couldn't compress it, as `--strict` does with
+    /// every other warning. Default `false`.
+    pub strict: bool,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            retry_backoff: Duration::from_millis(100),
+            log: None,
+            strict: false,
+        }
+    }
+}
+
+impl WriteOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn retry_backoff(mut self, retry_backoff: Duration) -> Self {
+        self.retry_backoff = retry_backoff;
+        self
+    }
+
+    pub fn log(mut self, log: Option<WriteLog>) -> Self {
+        self.log = log;
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+}
+
+/// The paths of the files written with the [`WriteOptions`] holding it, in
+/// the order they finished. Clones share one list, so a caller keeps one and
+/// hands clones to the encoders.
+#[derive(Clone, Debug, Default)]
+pub struct WriteLog(Arc<Mutex<Vec<PathBuf>>>);
+
+impl WriteLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The paths written so far.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Two logs are equal when they share a list.
+impl PartialEq for WriteLog {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for WriteLog {}
+
+/// Writes through a temporary file in the same directory and renames it into
+/// place, so `path` only ever holds a complete file. Transient failures are
+/// retried from the start as `options` says, with a warning for each.
+pub fn write_atomic<F>(
+    path: &Path,
+    options: &WriteOptions,
+    mut write: F,
+) -> Result<(), anyhow::Error>
+where
+    F: FnMut(&mut std::fs::File) -> Result<(), anyhow::Error>,
+{
+    let retries = options.retries;
+    let mut attempt = 0;
+    loop {
+        match write_atomic_once(path, &mut write) {
+            Ok(()) => {
+                if let Some(log) = &options.log {
+                    log.0.lock().unwrap().push(path.to_path_buf());
+                }
+                return Ok(());
+            }
+            Err(e) if attempt < retries && is_transient(&e) => {
+                let delay = options.retry_backoff * 2u32.pow(attempt.min(16));
+                attempt += 1;
+                tracing::warn!(
+                    "Writing {:?} failed ({}), retrying in {:?} ({}/{})",
+                    path,
+                    e,
+                    delay,
+                    attempt,
+                    retries
+                );
+                std::thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn write_atomic_once<F>(path: &Path, write: &mut F) -> Result<(), anyhow::Error>
+where
+    F: FnMut(&mut std::fs::File) -> Result<(), anyhow::Error>,
+{
+    let tmp_path = temp_path(path);
+    let result = std::fs::File::create(&tmp_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|mut file| write(&mut file))
+        .and_then(|()| Ok(std::fs::rename(&tmp_path, path)?));
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Whether `e` comes from an I/O error that might not happen again.
+fn is_transient(e: &anyhow::Error) -> bool {
+    use std::io::ErrorKind::*;
+    let io_error = e
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<std::io::Error>());
+    io_error.is_some_and(|e| {
+        matches!(
+            e.kind(),
+            Interrupted
+                | WouldBlock
+                | TimedOut
+                | ResourceBusy
+                | StaleNetworkFileHandle
+                | ConnectionReset
+                | ConnectionAborted
+                | NotConnected
+                | BrokenPipe
+                | NetworkDown
+                | NetworkUnreachable
+                | HostUnreachable
+        )
+    })
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .unwrap_or(OsStr::new("output"))
+        .to_string_lossy();
+    path.with_file_name(format!(".{}.tmp", file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read::decode_bytes;
+    use crate::self_test::synthetic_input;
+    use crate::TransformConfig;
+
+    /// The level data of a single-level KTX2 file.
+    fn ktx2_level(bytes: &[u8]) -> &[u8] {
+        let field = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap()) as usize;
+        let (offset, len) = (field(80), field(88));
+        &bytes[offset..offset + len]
+    }
+
+    #[test]
+    fn lossless_encoders_round_trip_the_same_data() {
+        let result = TransformConfig::default().run(&synthetic_input()).unwrap();
+        let tiffs = [ByteOrder::LittleEndian, ByteOrder::BigEndian].map(|byte_order| Tiff {
+            byte_order,
+            ..Default::default()
+        });
+        for tiff in &tiffs {
+            let bytes = encode_image_to_vec(&result, tiff).unwrap();
+            let pages = decode_bytes(&bytes, Some(image::ImageFormat::Tiff)).unwrap();
+            assert_eq!(
+                pages[0].to_rgb32f().into_raw(),
+                result.image,
+                "{:?}",
+                tiff.byte_order
+            );
+
+            let bytes = encode_lut_to_vec(&result, tiff).unwrap();
+            let pages = decode_bytes(&bytes, Some(image::ImageFormat::Tiff)).unwrap();
+            assert_eq!(
+                pages[0].to_rgb8().into_raw(),
+                result.lut,
+                "{:?}",
+                tiff.byte_order
+            );
+        }
+
+        let ktx2 = Ktx2::default();
+        let image: Vec<f32> = ktx2_level(&encode_image_to_vec(&result, &ktx2).unwrap())
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(image, result.image);
+        let lut = encode_lut_to_vec(&result, &ktx2).unwrap();
+        assert_eq!(ktx2_level(&lut), &result.lut[..]);
+    }
+
+    #[test]
+    fn in_memory_bytes_match_the_written_files() {
+        let result = TransformConfig::default().run(&synthetic_input()).unwrap();
+        let path =
+            std::env::temp_dir().join(format!("precompute-in-memory-{}", std::process::id()));
+        let encoders: Vec<Box<dyn OutputEncoder>> = vec![
+            Box::new(Tiff::default()),
+            Box::new(Tiff {
+                byte_order: ByteOrder::BigEndian,
+                layout: Layout::Planar,
+                ..Default::default()
+            }),
+            Box::new(Tiff {
+                tile_size: Some(16),
+                description: Some("bake".to_string()),
+                dpi: Some(72),
+                ..Default::default()
+            }),
+            Box::new(Ktx2::default()),
+        ];
+        for encoder in &encoders {
+            encoder
+                .encode_pages(
+                    &path,
+                    result.width,
+                    &result.page_heights,
+                    3,
+                    OutputData::F32(&result.image),
+                )
+                .unwrap();
+            assert_eq!(
+                encode_image_to_vec(&result, encoder.as_ref()).unwrap(),
+                std::fs::read(&path).unwrap()
+            );
+            encoder
+                .encode(
+                    &path,
+                    result.lut_width,
+                    result.lut_height,
+                    3,
+                    OutputData::U8(&result.lut),
+                )
+                .unwrap();
+            assert_eq!(
+                encode_lut_to_vec(&result, encoder.as_ref()).unwrap(),
+                std::fs::read(&path).unwrap()
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tiff_header_has_the_requested_byte_order() {
+        let result = TransformConfig::default().run(&synthetic_input()).unwrap();
+        for (byte_order, marker) in [
+            (ByteOrder::LittleEndian, b"II\x2a\x00"),
+            (ByteOrder::BigEndian, b"MM\x00\x2a"),
+        ] {
+            let tiff = Tiff {
+                byte_order,
+                ..Default::default()
+            };
+            assert_eq!(&encode_image_to_vec(&result, &tiff).unwrap()[..4], marker);
+            assert_eq!(&encode_lut_to_vec(&result, &tiff).unwrap()[..4], marker);
+        }
+        assert_eq!(Tiff::default().byte_order, ByteOrder::LittleEndian);
+    }
+
+    #[test]
+    fn sample_counts_past_u32_dont_wrap() {
+        // 100000 x 100000 x 3 is 3e10 samples; in u32 arithmetic it would
+        // wrap to 4230196224 and ask for the wrong buffer length.
+        let data = vec![0u8; 16];
+        let err = check_layout(100_000, 100_000, 3, OutputData::U8(&data)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Expected 30000000000 samples for 100000x100000, got 16"
+        );
+    }
+
+    #[test]
+    fn transient_write_failures_are_retried_from_the_start() {
+        let path =
+            std::env::temp_dir().join(format!("precompute-retry-{}.bin", std::process::id()));
+        let options = WriteOptions::new()
+            .retries(3)
+            .retry_backoff(Duration::from_millis(1));
+        let failing = |kind: std::io::ErrorKind, failures: u32| {
+            let mut calls = 0;
+            let result = write_atomic(&path, &options, |file| {
+                calls += 1;
+                file.write_all(b"partial")?;
+                if calls <= failures {
+                    return Err(std::io::Error::from(kind).into());
+                }
+                Ok(file.write_all(b" and whole")?)
+            });
+            (result, calls)
+        };
+
+        let (result, calls) = failing(std::io::ErrorKind::TimedOut, 2);
+        assert!(result.is_ok());
+        assert_eq!(calls, 3);
+        assert_eq!(std::fs::read(&path).unwrap(), b"partial and whole");
+        std::fs::remove_file(&path).unwrap();
+
+        let (result, calls) = failing(std::io::ErrorKind::TimedOut, 4);
+        assert!(result.is_err());
+        assert_eq!(calls, 4);
+        let (result, calls) = failing(std::io::ErrorKind::PermissionDenied, 1);
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+        assert!(!path.exists());
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[test]
+    fn rg_ba_lut_unpacks_to_the_three_channel_luts() {
+        let result = TransformConfig::default().run(&synthetic_input()).unwrap();
+        assert_eq!(LutPack::Rgb.pack(&result.lut, true, u8::MAX), result.lut);
+
+        let packed = LutPack::RgBa.pack(&result.lut, true, u8::MAX);
+        assert_eq!(packed.len(), result.lut.len() / 3 * 4);
+        assert!(packed.chunks_exact(4).all(|texel| texel[3] == u8::MAX));
+        assert_eq!(LutPack::RgBa.unpack(&packed), result.lut);
+        let unflagged = LutPack::RgBa.pack(&result.lut_f32, false, 1.0);
+        assert!(unflagged.chunks_exact(4).all(|texel| texel[3] == 0.0));
+        assert_eq!(LutPack::RgBa.unpack(&unflagged), result.lut_f32);
+
+        // And the same after a round trip through the RGBA TIFF it's written as.
+        let bytes = encode_to_vec(
+            &Tiff::default(),
+            result.lut_width,
+            &[result.lut_height],
+            4,
+            OutputData::U8(&packed),
+        )
+        .unwrap();
+        let decoded = decode_bytes(&bytes, Some(image::ImageFormat::Tiff)).unwrap();
+        let decoded = decoded[0].to_rgba8().into_raw();
+        assert_eq!(LutPack::RgBa.unpack(&decoded), result.lut);
+    }
+
+    #[test]
+    fn cube_lut_parses_to_the_lut_scaled_to_one() {
+        let result = TransformConfig::default()
+            .lut_size(Some(256))
+            .run(&synthetic_input())
+            .unwrap();
+        let cube = Cube {
+            title: "bake".to_string(),
+            ..Default::default()
+        };
+        let text = String::from_utf8(encode_lut_to_vec(&result, &cube).unwrap()).unwrap();
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some("TITLE \"bake\""));
+        assert_eq!(lines.next(), Some("LUT_1D_SIZE 256"));
+        let domain = |line: Option<&str>, keyword: &str| -> f64 {
+            let values: Vec<f64> = line
+                .unwrap()
+                .strip_prefix(keyword)
+                .unwrap()
+                .split_whitespace()
+                .map(|v| v.parse().unwrap())
+                .collect();
+            assert_eq!(values.len(), 3);
+            values[0]
+        };
+        assert_eq!(domain(lines.next(), "DOMAIN_MIN"), 0.001953);
+        assert_eq!(domain(lines.next(), "DOMAIN_MAX"), 0.998047);
+        let entries: Vec<f64> = lines
+            .flat_map(|line| line.split_whitespace().map(|v| v.parse::<f64>().unwrap()))
+            .collect();
+        assert_eq!(entries.len(), 256 * 3);
+        assert!(entries.iter().all(|v| (0.0..=1.0).contains(v)));
+        for (entry, &texel) in entries.iter().zip(&result.lut) {
+            assert!((entry - texel as f64 / 255.0).abs() < 1e-6);
+        }
+
+        let rows = TransformConfig::default()
+            .lut_rows(2)
+            .run(&synthetic_input())
+            .unwrap();
+        assert!(encode_lut_to_vec(&rows, &cube).is_err());
+    }
+
+    #[test]
+    fn compressor_failures_fall_back_to_uncompressed_unless_strict() {
+        let result = TransformConfig::default().run(&synthetic_input()).unwrap();
+        let (width, height) = (result.lut_width, result.lut_height);
+        let lut = &result.lut;
+        let tiff = Tiff::default();
+        let write = |options: &WriteOptions, failure: fn() -> TiffError| {
+            let mut cursor = Cursor::new(Vec::new());
+            let written = compress_or_fall_back(
+                &mut cursor,
+                options,
+                (width, height),
+                |writer| {
+                    // A partly written file, then the simulated failure.
+                    writer.write_all(&[0xff; 64])?;
+                    Err(failure())
+                },
+                |writer| {
+                    write_tiff_with::<colortype::RGB8, _>(
+                        writer,
+                        &tiff,
+                        width,
+                        &[height],
+                        lut,
+                        Uncompressed,
+                    )
+                },
+            );
+            written.map(|()| cursor.into_inner())
+        };
+
+        let bytes = write(&WriteOptions::new(), || TiffError::LimitsExceeded).unwrap();
+        let mut decoder = tiff::decoder::Decoder::new(Cursor::new(&bytes)).unwrap();
+        let compression = decoder.get_tag_u32(tiff::tags::Tag::Compression).unwrap();
+        assert_eq!(compression, 1);
+        let pages = decode_bytes(&bytes, Some(image::ImageFormat::Tiff)).unwrap();
+        assert_eq!(pages[0].to_rgb8().into_raw(), result.lut);
+
+        let strict = WriteOptions::new().strict(true);
+        let err = write(&strict, || TiffError::LimitsExceeded).unwrap_err();
+        assert!(err.to_string().ends_with("(--strict)"), "{}", err);
+        // A failed write isn't the encoder's doing, so it isn't retried here.
+        let io = || TiffError::IoError(std::io::ErrorKind::WriteZero.into());
+        assert!(write(&WriteOptions::new(), io).is_err());
+    }
+
+    #[test]
+    fn dpi_is_recorded_on_every_page_of_every_layout() {
+        let (width, heights) = (16, [16, 16]);
+        let pixels = (width * 32) as usize;
+        let u8s: Vec<u8> = (0..pixels * 4).map(|i| i as u8).collect();
+        let f32s: Vec<f32> = u8s.iter().map(|&v| v as f32 / 255.0).collect();
+        // `tiff` can't decode planar files, which the hand-rolled writer tags
+        // as it does big-endian and tiled ones.
+        let tiffs = |dpi| {
+            let tiff = Tiff {
+                dpi,
+                ..Default::default()
+            };
+            [
+                (tiff.clone(), 1, OutputData::U8(&u8s[..pixels])),
+                (tiff.clone(), 3, OutputData::F32(&f32s[..pixels * 3])),
+                (tiff.clone(), 4, OutputData::U8(&u8s)),
+                (
+                    Tiff {
+                        byte_order: ByteOrder::BigEndian,
+                        ..tiff.clone()
+                    },
+                    3,
+                    OutputData::U8(&u8s[..pixels * 3]),
+                ),
+                (
+                    Tiff {
+                        tile_size: Some(16),
+                        ..tiff
+                    },
+                    3,
+                    OutputData::U8(&u8s[..pixels * 3]),
+                ),
+            ]
+        };
+        // XResolution, YResolution and ResolutionUnit of each page.
+        let resolutions = |bytes: &[u8]| {
+            let mut decoder = tiff::decoder::Decoder::new(std::io::Cursor::new(bytes)).unwrap();
+            let mut pages = Vec::new();
+            loop {
+                let mut tag = |tag| decoder.find_tag(tag).unwrap();
+                let rational = |value| match value {
+                    Some(tiff::decoder::ifd::Value::Rational(n, d)) => Some((n, d)),
+                    None => None,
+                    Some(value) => panic!("{:?} isn't a rational", value),
+                };
+                pages.push((
+                    rational(tag(Tag::XResolution)),
+                    rational(tag(Tag::YResolution)),
+                    tag(Tag::ResolutionUnit).map(|unit| unit.into_u16().unwrap()),
+                ));
+                if !decoder.more_images() {
+                    return pages;
+                }
+                decoder.next_image().unwrap();
+            }
+        };
+        let inch = Some(ResolutionUnit::Inch.to_u16());
+        for (i, (tiff, channels, data)) in tiffs(Some(300)).into_iter().enumerate() {
+            let bytes = encode_to_vec(&tiff, width, &heights, channels, data).unwrap();
+            let dpi = Some((300, 1));
+            assert_eq!(resolutions(&bytes), [(dpi, dpi, inch); 2], "case {}", i);
+        }
+        for (i, (tiff, channels, data)) in tiffs(None).into_iter().enumerate() {
+            let bytes = encode_to_vec(&tiff, width, &heights, channels, data).unwrap();
+            assert!(
+                resolutions(&bytes).iter().all(|page| page.2 != inch),
+                "case {}",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn every_sample_conversion_scales_rounds_and_clamps() {
+        let convert = |data: OutputData, target| data.convert(target).unwrap();
+        let u8s = [0u8, 1, 128, 255];
+        let u16s = [0u16, 128, 129, 385, 32896, 65535];
+        let f32s = [-0.5f32, 0.0, 0.5, 1.0, 1.5, f32::NAN];
+
+        assert_eq!(
+            convert(OutputData::U8(&u8s), SampleType::U8),
+            OutputSamples::U8(u8s.to_vec())
+        );
+        assert_eq!(
+            convert(OutputData::U8(&u8s), SampleType::U16),
+            OutputSamples::U16(vec![0, 257, 32896, 65535])
+        );
+        assert_eq!(
+            convert(OutputData::U8(&u8s), SampleType::F32),
+            OutputSamples::F32(vec![0.0, 1.0 / 255.0, 128.0 / 255.0, 1.0])
+        );
+
+        // Halfway between levels, 128 / 257, rounds down and 129 / 257 up.
+        assert_eq!(
+            convert(OutputData::U16(&u16s), SampleType::U8),
+            OutputSamples::U8(vec![0, 0, 1, 1, 128, 255])
+        );
+        assert_eq!(
+            convert(OutputData::U16(&u16s), SampleType::U16),
+            OutputSamples::U16(u16s.to_vec())
+        );
+        let OutputSamples::F32(floats) = convert(OutputData::U16(&u16s), SampleType::F32) else {
+            panic!("expected float samples");
+        };
+        assert_eq!((floats[0], floats[5]), (0.0, 1.0));
+        assert!((floats[4] - 128.0 / 255.0).abs() < 1e-7);
+
+        assert_eq!(
+            convert(OutputData::F32(&f32s), SampleType::U8),
+            OutputSamples::U8(vec![0, 0, 128, 255, 255, 0])
+        );
+        assert_eq!(
+            convert(OutputData::F32(&f32s), SampleType::U16),
+            OutputSamples::U16(vec![0, 0, 32768, 65535, 65535, 0])
+        );
+        let OutputSamples::F32(floats) = convert(OutputData::F32(&f32s), SampleType::F32) else {
+            panic!("expected float samples");
+        };
+        assert_eq!(floats[..5], f32s[..5]);
+        assert!(floats[5].is_nan());
+
+        // Every 8-bit level survives widening and narrowing, through either type.
+        let levels: Vec<u8> = (0..=255).collect();
+        for through in [SampleType::U16, SampleType::F32] {
+            let wide = convert(OutputData::U8(&levels), through);
+            assert_eq!(
+                convert(wide.data(), SampleType::U8),
+                OutputSamples::U8(levels.clone())
+            );
+        }
+
+        for target in [SampleType::U8, SampleType::U16, SampleType::F32] {
+            assert!(OutputData::U32(&[1]).convert(target).is_err());
+            assert!(OutputData::I32(&[-1]).convert(target).is_err());
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::sync::{mpsc, Arc};
+
+use eframe::egui;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use precompute::{inverse, CancelToken, Cancelled, TransformConfig};
+
+/// Longest side of the copy the preview transforms, so it keeps up with the sliders.
+const PREVIEW_SIZE: u32 = 512;
+
+/// Opens a window showing `input`, its forward image and the reconstruction
+/// side by side, rebaked on a background thread whenever a slider moves.
+pub fn run(input: &DynamicImage, config: TransformConfig) -> Result<(), anyhow::Error> {
+    let input = if input.width().max(input.height()) > PREVIEW_SIZE {
+        input.resize(PREVIEW_SIZE, PREVIEW_SIZE, FilterType::Triangle)
+    } else {
+        input.clone()
+    };
+    let app = Preview {
+        input: Arc::new(input),
+        config,
+        requested: None,
+        baking: None,
+        textures: None,
+        error: None,
+    };
+    eframe::run_native(
+        "precompute preview",
+        eframe::NativeOptions::default(),
+        Box::new(move |_| -> Box<dyn eframe::App> { Box::new(app) }),
+    )
+    .map_err(|e| anyhow::anyhow!("Preview window failed: {}", e))
+}
+
+/// 8-bit RGB views of one bake: the input, the forward image, and its reconstruction.
+struct Views {
+    width: usize,
+    height: usize,
+    images: [Vec<u8>; 3],
+}
+
+/// A bake running on the background thread.
+struct Bake {
+    cancel: CancelToken,
+    rx: mpsc::Receiver<Result<Views, String>>,
+}
+
+struct Preview {
+    input: Arc<DynamicImage>,
+    config: TransformConfig,
+    /// The config of the last bake started, which the sliders are compared against.
+    requested: Option<TransformConfig>,
+    baking: Option<Bake>,
+    /// The views of the last finished bake.
+    textures: Option<[egui::TextureHandle; 3]>,
+    error: Option<String>,
+}
+
+impl Preview {
+    /// Cancels any bake in flight and starts one for the current sliders.
+    fn start_bake(&mut self, ctx: &egui::Context) {
+        if let Some(bake) = self.baking.take() {
+            bake.cancel.cancel();
+        }
+        let (tx, rx) = mpsc::channel();
+        let cancel = CancelToken::new();
+        let (input, config, thread_cancel, ctx) = (
+            self.input.clone(),
+            self.config.clone(),
+            cancel.clone(),
+            ctx.clone(),
+        );
+        std::thread::spawn(move || {
+            let views = bake_views(&input, &config, &thread_cancel);
+            if !matches!(&views, Err(e) if e.is::<Cancelled>()) {
+                let _ = tx.send(views.map_err(|e| e.to_string()));
+                ctx.request_repaint();
+            }
+        });
+        self.baking = Some(Bake { cancel, rx });
+        self.requested = Some(self.config.clone());
+    }
+
+    /// Picks up the result of the bake in flight, if it has finished.
+    fn poll_bake(&mut self, ctx: &egui::Context) {
+        let Some(bake) = &self.baking else {
+            return;
+        };
+        let Ok(result) = bake.rx.try_recv() else {
+            return;
+        };
+        self.baking = None;
+        match result {
+            Ok(views) => {
+                let size = [views.width, views.height];
+                let textures = ["input", "gaussian", "reconstructed"]
+                    .into_iter()
+                    .zip(&views.images)
+                    .map(|(name, rgb)| {
+                        let image = egui::ColorImage::from_rgb(size, rgb);
+                        ctx.load_texture(name, image, egui::TextureOptions::default())
+                    })
+                    .collect::<Vec<_>>();
+                self.textures = textures.try_into().ok();
+                self.error = None;
+            }
+            Err(e) => self.error = Some(e),
+        }
+    }
+}
+
+impl eframe::App for Preview {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.poll_bake(ctx);
+
+        egui::SidePanel::left("controls").show(ctx, |ui| {
+            ui.add(egui::Slider::new(&mut self.config.mean, 0.0..=1.0).text("mean"));
+            ui.add(egui::Slider::new(&mut self.config.std, 0.01..=0.5).text("std"));
+            ui.add(
+                egui::Slider::new(&mut self.config.post_contrast, 0.25..=4.0)
+                    .logarithmic(true)
+                    .text("strength"),
+            );
+            ui.label(format!(
+                "Previewing at {}x{}",
+                self.input.width(),
+                self.input.height()
+            ));
+            if self.baking.is_some() {
+                ui.spinner();
+            }
+            if let Some(error) = &self.error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+        });
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            let Some(textures) = &self.textures else {
+                return;
+            };
+            let size = textures[0].size_vec2();
+            let spacing = ui.spacing().item_spacing.x * 2.0;
+            let scale = ((ui.available_width() - spacing) / (size.x * 3.0)).min(1.0);
+            ui.horizontal(|ui| {
+                let labels = ["Input", "Gaussian", "Reconstructed"];
+                for (label, texture) in labels.iter().zip(textures) {
+                    ui.vertical(|ui| {
+                        ui.label(*label);
+                        ui.image(texture.id(), size * scale);
+                    });
+                }
+            });
+        });
+
+        if self.requested.as_ref() != Some(&self.config) {
+            self.start_bake(ctx);
+        }
+    }
+}
+
+/// Bakes `input` and renders the three views, the forward image stretched
+/// over `mean ± 3 std` as `--preview-output` does.
+fn bake_views(
+    input: &DynamicImage,
+    config: &TransformConfig,
+    cancel: &CancelToken,
+) -> Result<Views, anyhow::Error> {
+    let result = config.run_with_cancel(input, cancel)?;
+    let (scale, offset) = config.forward_encoding();
+    let low = offset + scale * (config.mean - 3.0 * config.std);
+    let range = scale * 6.0 * config.std;
+    let gaussian = result
+        .image
+        .iter()
+        .map(|&v| ((v as f64 - low) / range * 255.0).round().clamp(0.0, 255.0) as u8)
+        .collect();
+    let mut coordinates =
+        inverse::lut_coordinates(&result.image, config.lut_coordinate(result.lut_width));
+    if let Some(knots) = config.lut_knots(&result) {
+        coordinates = inverse::warp_coordinates(&coordinates, &knots, result.lut_width);
+    }
+    let reconstructed = inverse::sample_lut(&coordinates, &result.lut, result.lut_width);
+    Ok(Views {
+        width: result.width as usize,
+        height: result.height as usize,
+        images: [input.to_rgb8().into_raw(), gaussian, reconstructed],
+    })
+}
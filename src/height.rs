@@ -0,0 +1,343 @@
+use std::cmp::Ordering;
+use std::fmt::Display;
+
+use image::DynamicImage;
+use rayon::prelude::*;
+
+use crate::cancel::{CancelToken, Cancelled};
+use crate::config::{first_decrease, LutInterp, TransformConfig};
+use crate::encode::OutputData;
+
+/// A sample type [`TransformConfig::run_height`] ranks: `f32` heights, or
+/// `u32` and `i32` for integer data such as elevations or labels, which are
+/// ranked on their full 32-bit value.
+pub trait HeightSample: Copy + PartialOrd + Display + Send + Sync {
+    /// What [`NanPolicy::Zero`](crate::NanPolicy::Zero) replaces NaN and
+    /// infinite heights with.
+    const ZERO: Self;
+
+    /// Whether the sample takes part in the ranking; always true for integers.
+    fn is_finite(self) -> bool;
+
+    fn total_cmp(&self, other: &Self) -> Ordering;
+
+    /// The sample as a float, which is what NaN and infinite heights are
+    /// kept as in the forward image.
+    fn to_f32(self) -> f32;
+
+    /// The sample a share `t` of the way from `self` to `next`, rounded to
+    /// the nearest integer for integer types.
+    fn lerp(self, next: Self, t: f64) -> Self;
+
+    /// `lut` for an [`OutputEncoder`](crate::encode::OutputEncoder).
+    fn output_data(lut: &[Self]) -> OutputData<'_>;
+}
+
+impl HeightSample for f32 {
+    const ZERO: Self = 0.0;
+
+    fn is_finite(self) -> bool {
+        f32::is_finite(self)
+    }
+
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        f32::total_cmp(self, other)
+    }
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn lerp(self, next: Self, t: f64) -> Self {
+        let t = t as f32;
+        self * (1.0 - t) + next * t
+    }
+
+    fn output_data(lut: &[Self]) -> OutputData<'_> {
+        OutputData::F32(lut)
+    }
+}
+
+impl HeightSample for u32 {
+    const ZERO: Self = 0;
+
+    fn is_finite(self) -> bool {
+        true
+    }
+
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        self.cmp(other)
+    }
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn lerp(self, next: Self, t: f64) -> Self {
+        (self as f64 + (next as f64 - self as f64) * t).round() as u32
+    }
+
+    fn output_data(lut: &[Self]) -> OutputData<'_> {
+        OutputData::U32(lut)
+    }
+}
+
+impl HeightSample for i32 {
+    const ZERO: Self = 0;
+
+    fn is_finite(self) -> bool {
+        true
+    }
+
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        self.cmp(other)
+    }
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn lerp(self, next: Self, t: f64) -> Self {
+        (self as f64 + (next as f64 - self as f64) * t).round() as i32
+    }
+
+    fn output_data(lut: &[Self]) -> OutputData<'_> {
+        OutputData::I32(lut)
+    }
+}
+
+/// Output of [`TransformConfig::run_height`]: a one-channel forward image and
+/// a one-channel LUT holding the source heights themselves, unquantized, in
+/// their own sample type.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeightResult<T = f32> {
+    pub width: u32,
+    pub height: u32,
+    pub lut_width: u32,
+    pub image: Vec<f32>,
+    pub lut: Vec<T>,
+}
+
+impl<T: HeightSample> HeightResult<T> {
+    /// Like [`GaussianResult::check_lut_monotonic`](crate::GaussianResult::check_lut_monotonic),
+    /// for the height LUT.
+    pub fn check_lut_monotonic(&self) -> Result<(), anyhow::Error> {
+        match first_decrease(self.lut.iter().copied()) {
+            Some((texel, previous, value)) => Err(anyhow::anyhow!(
+                "Height LUT isn't monotonic: texel {} holds {} after {}",
+                texel,
+                value,
+                previous
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Alpha-weighted luminance of each pixel of `input` in row-major order: the
+/// Rec. 709 luma of its encoded color times its alpha, in `[0, 1]`. Pass it to
+/// [`TransformConfig::run_height`] to bake a soft mask by its coverage.
+pub fn weighted_luminance(input: &DynamicImage) -> Vec<f32> {
+    input
+        .to_rgba32f()
+        .pixels()
+        .map(|px| (0.2126 * px[0] + 0.7152 * px[1] + 0.0722 * px[2]) * px[3])
+        .collect()
+}
+
+/// Ranks `heights` in full float precision and maps them onto the target.
+/// Ties are broken in image order, so the result is always deterministic.
+/// NaN and infinite heights are left out of the ranking and kept as they are
+/// in the forward image. With `preserve_extremes`, the lowest and highest
+/// heights' ties take the first and last sample's quantile.
+pub(crate) fn transform_heights<T: HeightSample>(
+    heights: &[T],
+    width: u32,
+    height: u32,
+    config: &TransformConfig,
+    cancel: &CancelToken,
+) -> Result<HeightResult<T>, Cancelled> {
+    let _transform = tracing::info_span!("transform_heights", width, height).entered();
+    let target = config.target();
+    let mut order: Vec<usize> = (0..heights.len())
+        .filter(|&i| heights[i].is_finite())
+        .collect();
+    order.par_sort_by(|&a, &b| heights[a].total_cmp(&heights[b]));
+    let sorted: Vec<T> = order.par_iter().map(|&i| heights[i]).collect();
+    cancel.check()?;
+
+    let (n, plotting_position) = (sorted.len(), config.plotting_position);
+    let mut image: Vec<f32> = heights.iter().map(|&h| h.to_f32()).collect();
+    for (k, &i) in order.iter().enumerate() {
+        let k = match config.preserve_extremes {
+            true if sorted[k] == sorted[0] => 0,
+            true if sorted[k] == sorted[n - 1] => n - 1,
+            _ => k,
+        };
+        image[i] = target.inv_cdf(plotting_position.quantile(k, n)) as f32;
+    }
+    cancel.check()?;
+
+    let lut_width = config.lut_width(width, height);
+    let lut = (0..lut_width)
+        .into_par_iter()
+        .map(|i| {
+            let u = target.cdf(config.lut_texel_value(i, lut_width, 0.5));
+            // Position of `u` among the samples' midpoints `k + 0.5`.
+            let position = plotting_position.position(u, n).max(0.0);
+            match config.lut_interp {
+                LutInterp::Nearest => sorted[(position.floor() as usize).min(n - 1)],
+                LutInterp::Linear => {
+                    let hi = ((position + 0.5).floor() as usize).min(n);
+                    if hi == 0 {
+                        return sorted[0];
+                    }
+                    if hi == n {
+                        return sorted[hi - 1];
+                    }
+                    let t = position - (hi as f64 - 0.5);
+                    sorted[hi - 1].lerp(sorted[hi], t)
+                }
+            }
+        })
+        .collect();
+    cancel.check()?;
+
+    Ok(HeightResult {
+        width,
+        height,
+        lut_width,
+        image,
+        lut,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::OutputEncoder;
+    use crate::inverse::sample_height_lut;
+    use crate::rng::Rng;
+
+    #[test]
+    fn float_lut_reconstructs_heights() {
+        // Heights from -120 m to 2900 m in millimetres, far finer than 8-bit
+        // levels. A narrow target keeps the extreme ranks' forward values
+        // inside the LUT.
+        let rng = Rng::new(3);
+        let (width, height) = (64, 64);
+        let heights: Vec<f32> = (0..width * height)
+            .map(|i| -120.0 + rng.below(i as u64, 3_020_000) as f32 / 1000.0)
+            .collect();
+        let config = TransformConfig::default()
+            .std(0.1)
+            .lut_size(Some(16 * width * height));
+        let result = config
+            .run_height(&heights, width, height, &CancelToken::new())
+            .unwrap();
+        result.check_lut_monotonic().unwrap();
+        assert_eq!(result.lut.len(), 16 * (width * height) as usize);
+
+        let reconstructed = sample_height_lut(&result.image, &result.lut);
+        let max_error = (heights.iter().zip(&reconstructed))
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        // An 8-bit LUT over the same range would be off by up to 6 m; with
+        // sixteen texels per sample, each height comes back exactly.
+        assert!(max_error < 1e-3, "max error {} m", max_error);
+    }
+
+    /// Bakes `heights`, 64x64, and checks their LUT decodes each one exactly.
+    fn integer_lut_reconstructs<T: HeightSample + PartialEq + std::fmt::Debug>(
+        heights: &[T],
+    ) -> HeightResult<T> {
+        let config = TransformConfig::default()
+            .std(0.1)
+            .lut_size(Some(16 * 64 * 64));
+        let result = config
+            .run_height(heights, 64, 64, &CancelToken::new())
+            .unwrap();
+        result.check_lut_monotonic().unwrap();
+        assert_eq!(sample_height_lut(&result.image, &result.lut), heights);
+        result
+    }
+
+    #[test]
+    fn integer_luts_keep_the_full_32_bit_range() {
+        // Values crowded at both ends of the type, too close together for a
+        // float to tell apart up there.
+        let rng = Rng::new(4);
+        let unsigned: Vec<u32> = (0..64 * 64)
+            .map(|i| match rng.below(i, 2) {
+                0 => rng.below(i + 4096, 1000) as u32,
+                _ => u32::MAX - rng.below(i + 8192, 1000) as u32,
+            })
+            .collect();
+        let result = integer_lut_reconstructs(&unsigned);
+        let (lowest, highest) = (
+            *unsigned.iter().min().unwrap(),
+            *unsigned.iter().max().unwrap(),
+        );
+        assert_eq!(
+            (result.lut[0], *result.lut.last().unwrap()),
+            (lowest, highest)
+        );
+        assert!(highest as f32 == (highest - 1) as f32);
+
+        let mut signed: Vec<i32> = unsigned.iter().map(|&v| (v ^ 0x8000_0000) as i32).collect();
+        signed[0] = i32::MIN;
+        signed[1] = i32::MAX;
+        let result = integer_lut_reconstructs(&signed);
+        assert_eq!(
+            (result.lut[0], *result.lut.last().unwrap()),
+            (i32::MIN, i32::MAX)
+        );
+
+        // Written and read back as a signed integer TIFF.
+        let path =
+            std::env::temp_dir().join(format!("precompute-int-lut-{}.tif", std::process::id()));
+        let width = result.lut.len() as u32;
+        crate::encode::Tiff::default()
+            .encode(&path, width, 1, 1, i32::output_data(&result.lut))
+            .unwrap();
+        let read = crate::read::read_gray_int32(&path);
+        std::fs::remove_file(&path).unwrap();
+        match read.unwrap() {
+            crate::read::GrayInt32::I32(read) => assert_eq!(read.data, result.lut),
+            crate::read::GrayInt32::U32(_) => panic!("read back as unsigned"),
+        }
+    }
+
+    #[test]
+    fn solid_color_mask_tracks_alpha() {
+        // One color everywhere, with alpha shuffled over all 256 levels.
+        let rng = Rng::new(8);
+        let alpha = |i: u32| rng.below(i as u64, 256) as u8;
+        let input = image::RgbaImage::from_fn(64, 64, |x, y| {
+            image::Rgba([200, 120, 40, alpha(y * 64 + x)])
+        });
+        let alphas: Vec<u8> = input.pixels().map(|px| px[3]).collect();
+        let input = DynamicImage::ImageRgba8(input);
+        let mask = weighted_luminance(&input);
+        let luma = (0.2126 * 200.0 + 0.7152 * 120.0 + 0.0722 * 40.0) / 255.0;
+        for (value, alpha) in mask.iter().zip(&alphas) {
+            assert!((value - luma * *alpha as f32 / 255.0).abs() < 1e-5);
+        }
+
+        let result = TransformConfig::default()
+            .lut_size(Some(4 * 64 * 64))
+            .run_height(&mask, 64, 64, &CancelToken::new())
+            .unwrap();
+        // The forward mask orders the pixels by alpha alone.
+        let mut pairs: Vec<(u8, f32)> = alphas.iter().cloned().zip(result.image).collect();
+        pairs.sort_by(|a, b| a.1.total_cmp(&b.1));
+        assert!(pairs.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        assert!(pairs[0].0 < 8 && pairs[pairs.len() - 1].0 > 248);
+
+        let forward: Vec<f32> = pairs.iter().map(|pair| pair.1).collect();
+        let reconstructed = sample_height_lut(&forward, &result.lut);
+        for ((alpha, _), value) in pairs.iter().zip(reconstructed) {
+            assert!((value - luma * *alpha as f32 / 255.0).abs() < 0.01);
+        }
+    }
+}
@@ -0,0 +1,829 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config::PlottingPosition;
+use crate::distribution::Distribution;
+use crate::stats;
+
+/// Per-channel sorted source values, the shared basis for the forward
+/// (rank -> quantile) and inverse (quantile -> value) mappings. A weighted
+/// histogram counts each sample by its weight instead of once. Every channel
+/// holds at least one sample, which deserializing checks along with the
+/// order and the weights.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(
+    try_from = "HistogramFields<T>",
+    bound(deserialize = "T: Deserialize<'de> + Ord")
+)]
+pub struct Histogram<T> {
+    channels: Vec<Vec<T>>,
+    /// Running total of the sorted samples' weights, inclusive, per channel.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cumulative_weights: Option<Vec<Vec<u64>>>,
+    /// Quantiles of unweighted samples; weighted ones always use the middle
+    /// of their share of the weight.
+    #[serde(default)]
+    plotting_position: PlottingPosition,
+    /// Whether samples tied with a channel's lowest or highest value take
+    /// the lowest or highest sample's quantile.
+    #[serde(default)]
+    preserve_extremes: bool,
+}
+
+/// A [`Histogram`] as serialized, before its invariants are checked.
+#[derive(Deserialize)]
+struct HistogramFields<T> {
+    channels: Vec<Vec<T>>,
+    #[serde(default)]
+    cumulative_weights: Option<Vec<Vec<u64>>>,
+    #[serde(default)]
+    plotting_position: PlottingPosition,
+    #[serde(default)]
+    preserve_extremes: bool,
+}
+
+impl<T: Ord> TryFrom<HistogramFields<T>> for Histogram<T> {
+    type Error = anyhow::Error;
+
+    fn try_from(fields: HistogramFields<T>) -> Result<Self, Self::Error> {
+        let histogram = Self {
+            channels: fields.channels,
+            cumulative_weights: fields.cumulative_weights,
+            plotting_position: fields.plotting_position,
+            preserve_extremes: fields.preserve_extremes,
+        };
+        histogram.validate()?;
+        Ok(histogram)
+    }
+}
+
+impl<T: Ord> Histogram<T> {
+    /// Checks what every method relies on: at least one channel, each with
+    /// samples sorted ascending, and if weighted, one running total per
+    /// sample that never decreases and ends above zero.
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.channels.is_empty() {
+            return Err(anyhow::anyhow!("A histogram needs at least one channel"));
+        }
+        for (channel, sorted) in self.channels.iter().enumerate() {
+            if sorted.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Channel {} of the histogram has no samples",
+                    channel
+                ));
+            }
+            if sorted.windows(2).any(|pair| pair[0] > pair[1]) {
+                return Err(anyhow::anyhow!(
+                    "Channel {} of the histogram isn't sorted ascending",
+                    channel
+                ));
+            }
+        }
+        let Some(cumulative) = &self.cumulative_weights else {
+            return Ok(());
+        };
+        if cumulative.len() != self.channels.len() {
+            return Err(anyhow::anyhow!(
+                "The histogram has {} channels but weights for {}",
+                self.channels.len(),
+                cumulative.len()
+            ));
+        }
+        for (channel, (cumulative, sorted)) in cumulative.iter().zip(&self.channels).enumerate() {
+            if cumulative.len() != sorted.len() {
+                return Err(anyhow::anyhow!(
+                    "Channel {} of the histogram has {} samples but {} weights",
+                    channel,
+                    sorted.len(),
+                    cumulative.len()
+                ));
+            }
+            if cumulative.windows(2).any(|pair| pair[0] > pair[1]) || cumulative.last() == Some(&0)
+            {
+                return Err(anyhow::anyhow!(
+                    "The weights of channel {} of the histogram must be running totals ending above zero",
+                    channel
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T> Histogram<T>
+where
+    T: Copy + Ord + Send + Sync,
+{
+    /// Sorts `channels`, failing if there are none or one is empty.
+    pub fn from_channels(mut channels: Vec<Vec<T>>) -> Result<Self, anyhow::Error> {
+        channels
+            .par_iter_mut()
+            .for_each(|channel| channel.par_sort_unstable());
+        let histogram = Self {
+            channels,
+            cumulative_weights: None,
+            plotting_position: PlottingPosition::default(),
+            preserve_extremes: false,
+        };
+        histogram.validate()?;
+        Ok(histogram)
+    }
+
+    /// Builds a histogram from channels that are already sorted ascending.
+    pub(crate) fn from_sorted(channels: Vec<Vec<T>>) -> Self {
+        debug_assert!(channels
+            .iter()
+            .all(|c| !c.is_empty() && c.windows(2).all(|w| w[0] <= w[1])));
+        Self {
+            channels,
+            cumulative_weights: None,
+            plotting_position: PlottingPosition::default(),
+            preserve_extremes: false,
+        }
+    }
+
+    /// Like [`Histogram::from_sorted`], with each sorted sample's weight.
+    pub(crate) fn from_sorted_weighted(channels: Vec<Vec<T>>, weights: Vec<Vec<u64>>) -> Self {
+        debug_assert!(channels
+            .iter()
+            .zip(&weights)
+            .all(|(c, w)| c.len() == w.len()));
+        let cumulative_weights = weights
+            .into_par_iter()
+            .map(|weights| {
+                weights
+                    .into_iter()
+                    .scan(0, |total, weight| {
+                        *total += weight;
+                        Some(*total)
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            channels,
+            cumulative_weights: Some(cumulative_weights),
+            plotting_position: PlottingPosition::default(),
+            preserve_extremes: false,
+        }
+    }
+
+    pub fn with_plotting_position(mut self, plotting_position: PlottingPosition) -> Self {
+        self.plotting_position = plotting_position;
+        self
+    }
+
+    pub fn with_preserve_extremes(mut self, preserve_extremes: bool) -> Self {
+        self.preserve_extremes = preserve_extremes;
+        self
+    }
+
+    pub fn is_weighted(&self) -> bool {
+        self.cumulative_weights.is_some()
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn sample_count(&self, channel: usize) -> usize {
+        self.channels[channel].len()
+    }
+
+    pub fn sorted_values(&self, channel: usize) -> &[T] {
+        &self.channels[channel]
+    }
+
+    /// Number of different values in `channel`.
+    pub fn distinct_values(&self, channel: usize) -> usize {
+        let values = &self.channels[channel];
+        values.windows(2).filter(|pair| pair[0] != pair[1]).count()
+            + usize::from(!values.is_empty())
+    }
+
+    /// Mean midpoint quantile `(i + 0.5) / n` of all samples equal to `value`,
+    /// or the boundary between neighbours if `value` does not occur.
+    pub fn rank(&self, channel: usize, value: T) -> f64 {
+        let sorted = &self.channels[channel];
+        let lo = sorted.partition_point(|x| *x < value);
+        let hi = sorted.partition_point(|x| *x <= value);
+        match &self.cumulative_weights {
+            None => (lo + hi) as f64 / 2.0 / sorted.len() as f64,
+            Some(cumulative) => {
+                let cumulative = &cumulative[channel];
+                let before = |i: usize| if i == 0 { 0 } else { cumulative[i - 1] };
+                let total = *cumulative.last().unwrap() as f64;
+                (before(lo) + before(hi)) as f64 / 2.0 / total
+            }
+        }
+    }
+
+    /// Quantile of the middle of the samples equal to `value`, as the forward
+    /// pass ranks them: halfway between the first and last of them at the
+    /// histogram's plotting position, or the middle of their weight. A value
+    /// that doesn't occur takes the point halfway between its neighbours',
+    /// and one outside the samples the first or last sample's.
+    pub(crate) fn middle_quantile(&self, channel: usize, value: T) -> f64 {
+        let sorted = &self.channels[channel];
+        let lo = sorted.partition_point(|x| *x < value);
+        let hi = sorted.partition_point(|x| *x <= value);
+        match (lo, hi) {
+            (_, 0) => self.sample_quantile(channel, 0),
+            (lo, _) if lo == sorted.len() => self.sample_quantile(channel, lo - 1),
+            (lo, hi) if lo == hi => {
+                (self.sample_quantile(channel, lo - 1) + self.sample_quantile(channel, lo)) / 2.0
+            }
+            _ if self.is_weighted() => self.rank(channel, value),
+            (lo, hi) => {
+                (self.sample_quantile(channel, lo) + self.sample_quantile(channel, hi - 1)) / 2.0
+            }
+        }
+    }
+
+    /// Share of the samples, or of their weight, equal to `value`.
+    pub fn share(&self, channel: usize, value: T) -> f64 {
+        let sorted = &self.channels[channel];
+        let lo = sorted.partition_point(|x| *x < value);
+        let hi = sorted.partition_point(|x| *x <= value);
+        match &self.cumulative_weights {
+            None => (hi - lo) as f64 / sorted.len() as f64,
+            Some(cumulative) => {
+                let cumulative = &cumulative[channel];
+                let before = |i: usize| if i == 0 { 0 } else { cumulative[i - 1] };
+                let total = *cumulative.last().unwrap() as f64;
+                (before(hi) - before(lo)) as f64 / total
+            }
+        }
+    }
+
+    /// Quantile of the `index`th sorted sample at the histogram's plotting
+    /// position, `(index + 0.5) / n` by default, or the middle of its share of
+    /// the total weight. Weighted quantiles are
+    /// clamped to the unweighted extremes so zero-weight samples at either end
+    /// stay finite under an unbounded target. With preserved extremes, a
+    /// sample equal to the lowest or highest one takes that sample's quantile.
+    pub fn sample_quantile(&self, channel: usize, index: usize) -> f64 {
+        let sorted = &self.channels[channel];
+        let last = sorted.len() - 1;
+        let index = match self.preserve_extremes {
+            true if sorted[index] == sorted[0] => 0,
+            true if sorted[index] == sorted[last] => last,
+            _ => index,
+        };
+        let n = sorted.len() as f64;
+        match &self.cumulative_weights {
+            None => self.plotting_position.quantile(index, n as usize),
+            Some(cumulative) => {
+                let cumulative = &cumulative[channel];
+                let before = if index == 0 { 0 } else { cumulative[index - 1] };
+                let total = *cumulative.last().unwrap() as f64;
+                let u = (before + cumulative[index]) as f64 / 2.0 / total;
+                u.clamp(0.5 / n, 1.0 - 0.5 / n)
+            }
+        }
+    }
+
+    /// Value below which a share `u` of the samples, or of their weight, falls:
+    /// the sample whose quantile is nearest `u`.
+    pub fn value_at_quantile(&self, channel: usize, u: f64) -> T {
+        let sorted = &self.channels[channel];
+        let index = match &self.cumulative_weights {
+            None => self.plotting_position.position(u, sorted.len()).floor() as usize,
+            Some(cumulative) => {
+                let cumulative = &cumulative[channel];
+                let target = u * *cumulative.last().unwrap() as f64;
+                cumulative.partition_point(|&c| c as f64 <= target)
+            }
+        };
+        sorted[index.min(sorted.len() - 1)]
+    }
+}
+
+/// Summary of one channel's samples. Percentiles interpolate linearly between
+/// the two nearest sorted samples; weights, if any, are ignored.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChannelStats {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    /// Population standard deviation.
+    pub std: f64,
+    pub p1: f64,
+    pub p5: f64,
+    pub p25: f64,
+    pub p75: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+/// Population covariance and Pearson correlation between every pair of an
+/// image's channels, over its pixels, indexed `[row][column]` by channel.
+/// Unlike [`ChannelStats`] it needs the channels' pixels paired up, so it is
+/// taken from the image rather than a histogram.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChannelCorrelation {
+    /// In 8-bit levels squared; the diagonal holds each channel's variance.
+    pub covariance: Vec<Vec<f64>>,
+    /// Covariance over the product of both channels' standard deviations, in
+    /// `[-1, 1]`, and 0 where either channel is constant.
+    pub correlation: Vec<Vec<f64>>,
+}
+
+impl ChannelCorrelation {
+    /// The matrices of equally long `channels`. Sums are kept in integers,
+    /// so the result doesn't depend on summation order.
+    pub fn from_channels(channels: &[Vec<u8>]) -> Self {
+        let n = channels[0].len() as i128;
+        let sums: Vec<i128> = channels
+            .iter()
+            .map(|values| values.iter().map(|&v| v as u64).sum::<u64>() as i128)
+            .collect();
+        let covariance: Vec<Vec<f64>> = (0..channels.len())
+            .map(|i| {
+                (0..channels.len())
+                    .map(|j| {
+                        let products: u64 = channels[i]
+                            .iter()
+                            .zip(&channels[j])
+                            .map(|(&a, &b)| a as u64 * b as u64)
+                            .sum();
+                        match n {
+                            0 => 0.0,
+                            n => (n * products as i128 - sums[i] * sums[j]) as f64 / (n * n) as f64,
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let correlation = (0..channels.len())
+            .map(|i| {
+                (0..channels.len())
+                    .map(|j| {
+                        let deviations = (covariance[i][i] * covariance[j][j]).sqrt();
+                        if deviations > 0.0 {
+                            (covariance[i][j] / deviations).clamp(-1.0, 1.0)
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            covariance,
+            correlation,
+        }
+    }
+}
+
+impl<T> Histogram<T>
+where
+    T: Copy + Into<f64>,
+{
+    /// Like [`Histogram::value_at_quantile`], but interpolates linearly between
+    /// the two samples whose midpoint quantiles bracket `u` instead of
+    /// picking one, clamping to the first and last sample outside them.
+    pub fn interpolated_value_at_quantile(&self, channel: usize, u: f64) -> f64 {
+        let sorted = &self.channels[channel];
+        let midpoint = |k: usize| self.midpoint(channel, k);
+        let position = self.position(channel, u);
+        let hi = partition_point(sorted.len(), |k| midpoint(k) <= position);
+        if hi == 0 {
+            return sorted[0].into();
+        }
+        if hi == sorted.len() {
+            return sorted[hi - 1].into();
+        }
+        let (m0, m1) = (midpoint(hi - 1), midpoint(hi));
+        let t = if m1 > m0 {
+            (position - m0) / (m1 - m0)
+        } else {
+            0.0
+        };
+        sorted[hi - 1].into() * (1.0 - t) + sorted[hi].into() * t
+    }
+
+    /// The value below which a share `p` of `channel` falls, interpolated as
+    /// in [`Histogram::interpolated_value_at_quantile`]: `0.9` asks for the
+    /// 90th percentile. [`Histogram::probability`] is its inverse.
+    pub fn quantile(&self, channel: usize, p: f64) -> f64 {
+        self.interpolated_value_at_quantile(channel, p)
+    }
+
+    /// The share of `channel` below `value`, the inverse of
+    /// [`Histogram::quantile`]: linear between the midpoint quantiles of the
+    /// nearest samples on either side, and the middle of the run of samples
+    /// it ties with if it occurs. Values outside the samples take the first
+    /// or last sample's quantile.
+    pub fn probability(&self, channel: usize, value: f64) -> f64 {
+        let sorted = &self.channels[channel];
+        let lo = sorted.partition_point(|x| (*x).into() < value);
+        let hi = sorted.partition_point(|x| (*x).into() <= value);
+        let position = if lo < hi {
+            (self.midpoint(channel, lo) + self.midpoint(channel, hi - 1)) / 2.0
+        } else if lo == 0 {
+            self.midpoint(channel, 0)
+        } else if lo == sorted.len() {
+            self.midpoint(channel, lo - 1)
+        } else {
+            let (v0, v1) = (sorted[lo - 1].into(), sorted[lo].into());
+            let (m0, m1) = (self.midpoint(channel, lo - 1), self.midpoint(channel, lo));
+            m0 + (m1 - m0) * (value - v0) / (v1 - v0)
+        };
+        match &self.cumulative_weights {
+            None => self.plotting_position.quantile_at(position, sorted.len()),
+            Some(cumulative) => position / *cumulative[channel].last().unwrap() as f64,
+        }
+    }
+
+    /// The value at `u` on the line through the outermost sample and the
+    /// nearest one with a different value, when `u` lies beyond that
+    /// outermost sample's midpoint quantile, so the tail's slope continues
+    /// past the observed range. `None` between the first and last samples'
+    /// midpoints, and when every sample holds the same value.
+    pub fn extrapolated_value_at_quantile(&self, channel: usize, u: f64) -> Option<f64> {
+        let sorted = &self.channels[channel];
+        let last = sorted.len() - 1;
+        let position = self.position(channel, u);
+        let (edge, inner) = if position < self.midpoint(channel, 0) {
+            let edge = sorted[0].into();
+            (0, (1..=last).find(|&k| sorted[k].into() != edge)?)
+        } else if position > self.midpoint(channel, last) {
+            let edge = sorted[last].into();
+            (last, (0..last).rev().find(|&k| sorted[k].into() != edge)?)
+        } else {
+            return None;
+        };
+        let (m0, m1) = (self.midpoint(channel, edge), self.midpoint(channel, inner));
+        let slope = (sorted[inner].into() - sorted[edge].into()) / (m1 - m0);
+        Some(sorted[edge].into() + slope * (position - m0))
+    }
+
+    /// Sample `k`'s midpoint on the count or weight axis [`Histogram::position`] maps quantiles to.
+    fn midpoint(&self, channel: usize, k: usize) -> f64 {
+        match &self.cumulative_weights {
+            None => k as f64 + 0.5,
+            Some(cumulative) => {
+                let cumulative = &cumulative[channel];
+                let before = if k == 0 { 0 } else { cumulative[k - 1] };
+                (before + cumulative[k]) as f64 / 2.0
+            }
+        }
+    }
+
+    /// Quantile `u` on the count or weight axis of `channel`'s samples.
+    fn position(&self, channel: usize, u: f64) -> f64 {
+        match &self.cumulative_weights {
+            None => self
+                .plotting_position
+                .position(u, self.channels[channel].len()),
+            Some(cumulative) => u * *cumulative[channel].last().unwrap() as f64,
+        }
+    }
+
+    /// Kolmogorov-Smirnov distance between `channel` and `target`: the largest
+    /// gap between the two CDFs. Each value `v` is read as covering
+    /// `(v ± 0.5) / scale`, the range quantizing to `scale` levels leaves it.
+    pub fn cdf_distance(&self, channel: usize, target: &dyn Distribution, scale: f64) -> f64 {
+        let sorted = &self.channels[channel];
+        let cumulative = self.cumulative_weights.as_ref().map(|c| &c[channel]);
+        // Share of the samples, or of their weight, before index `i`.
+        let share = |i: usize| match cumulative {
+            None => i as f64 / sorted.len() as f64,
+            Some(_) if i == 0 => 0.0,
+            Some(cumulative) => cumulative[i - 1] as f64 / *cumulative.last().unwrap() as f64,
+        };
+        let mut distance: f64 = 0.0;
+        let mut start = 0;
+        while start < sorted.len() {
+            let value = sorted[start].into();
+            let end = start + sorted[start..].partition_point(|x| (*x).into() <= value);
+            let below = target.cdf((value - 0.5) / scale);
+            let through = target.cdf((value + 0.5) / scale);
+            distance = distance
+                .max((share(start) - below).abs())
+                .max((share(end) - through).abs());
+            start = end;
+        }
+        distance
+    }
+
+    pub fn channel_stats(&self, channel: usize) -> ChannelStats
+    where
+        T: Sync,
+    {
+        let sorted = &self.channels[channel];
+        let summary = stats::channel_stats(sorted, 1, 0, true);
+        let mean = summary.mean();
+        let variance = summary.variance().unwrap_or_default();
+        let percentile = |p: f64| {
+            let position = p * (sorted.len() - 1) as f64;
+            let lo = position.floor() as usize;
+            let hi = position.ceil() as usize;
+            let t = position - lo as f64;
+            sorted[lo].into() * (1.0 - t) + sorted[hi].into() * t
+        };
+        ChannelStats {
+            min: sorted[0].into(),
+            max: sorted[sorted.len() - 1].into(),
+            mean,
+            median: percentile(0.5),
+            std: variance.sqrt(),
+            p1: percentile(0.01),
+            p5: percentile(0.05),
+            p25: percentile(0.25),
+            p75: percentile(0.75),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+        }
+    }
+}
+
+impl Histogram<u8> {
+    /// Classic histogram equalization of `channel`: each value `v` maps to
+    /// `round((cdf(v) - cdf_min) / (n - cdf_min) * 255)`, where `cdf(v)` counts
+    /// the samples at most `v` and `cdf_min` those equal to the smallest, so
+    /// the smallest value maps to 0 and the largest to 255. A channel of a
+    /// single value has nothing to spread and maps to itself. Weights, if
+    /// any, are ignored.
+    pub fn equalization_table(&self, channel: usize) -> [u8; 256] {
+        let sorted = &self.channels[channel];
+        let mut table = [0; 256];
+        let cdf_min = sorted.partition_point(|&x| x <= sorted[0]);
+        let n = sorted.len();
+        for (v, out) in table.iter_mut().enumerate() {
+            *out = if n == cdf_min {
+                v as u8
+            } else {
+                let cdf = sorted.partition_point(|&x| x as usize <= v).max(cdf_min);
+                ((cdf - cdf_min) as f64 / (n - cdf_min) as f64 * 255.0).round() as u8
+            };
+        }
+        table
+    }
+}
+
+/// First index in `0..len` for which `pred` is false, given `pred` holds for a prefix.
+fn partition_point(len: usize, pred: impl Fn(usize) -> bool) -> usize {
+    let (mut lo, mut hi) = (0, len);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if pred(mid) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    const POSITIONS: [PlottingPosition; 3] = [
+        PlottingPosition::Midpoint,
+        PlottingPosition::Linear,
+        PlottingPosition::Blom,
+    ];
+
+    /// `len` samples with plenty of ties, in no particular order.
+    fn samples(len: u64, seed: u64) -> Vec<u8> {
+        let rng = Rng::new(seed);
+        (0..len).map(|i| rng.below(i, 40) as u8 * 3).collect()
+    }
+
+    #[test]
+    fn channel_correlation_matches_known_relations() {
+        let rng = crate::rng::Rng::new(4);
+        let r: Vec<u8> = (0..4096).map(|i| rng.below(i, 256) as u8).collect();
+        let b: Vec<u8> = (0..4096).map(|i| rng.below(4096 + i, 256) as u8).collect();
+        let inverted: Vec<u8> = r.iter().map(|&v| 255 - v).collect();
+        let constant = vec![7u8; 4096];
+        let matrices = ChannelCorrelation::from_channels(&[r.clone(), b, inverted, constant]);
+        let correlation = &matrices.correlation;
+        for (i, row) in correlation.iter().take(3).enumerate() {
+            assert!((row[i] - 1.0).abs() < 1e-12);
+        }
+        assert!((correlation[0][2] + 1.0).abs() < 1e-12);
+        assert!(correlation[0][1].abs() < 0.05, "{}", correlation[0][1]);
+        assert_eq!(correlation[0][1], correlation[1][0]);
+        assert!(correlation[3].iter().all(|&c| c == 0.0));
+        // Inverting a channel keeps its variance and flips its covariance.
+        let covariance = &matrices.covariance;
+        let mean = r.iter().map(|&v| v as f64).sum::<f64>() / 4096.0;
+        let variance = r.iter().map(|&v| (v as f64 - mean).powi(2)).sum::<f64>() / 4096.0;
+        assert!((covariance[0][0] - variance).abs() < 1e-9);
+        assert!((covariance[2][2] - variance).abs() < 1e-9);
+        assert!((covariance[0][2] + variance).abs() < 1e-9);
+        assert_eq!(covariance[3][3], 0.0);
+    }
+
+    #[test]
+    fn sample_quantile_round_trips_through_value_at_quantile() {
+        for position in POSITIONS {
+            let histogram = Histogram::from_channels(vec![(0..=255).rev().collect()])
+                .unwrap()
+                .with_plotting_position(position);
+            for k in 0..256 {
+                let u = histogram.sample_quantile(0, k);
+                assert_eq!(histogram.value_at_quantile(0, u), k as u8, "{:?}", position);
+            }
+        }
+    }
+
+    #[test]
+    fn rank_round_trips_through_value_at_quantile() {
+        let histogram = Histogram::from_channels(vec![samples(1000, 1), samples(7, 2)]).unwrap();
+        for channel in 0..2 {
+            for &value in histogram.sorted_values(channel) {
+                let u = histogram.rank(channel, value);
+                assert_eq!(histogram.value_at_quantile(channel, u), value);
+            }
+        }
+    }
+
+    #[test]
+    fn weighted_rank_round_trips_through_value_at_quantile() {
+        let histogram =
+            Histogram::from_sorted_weighted(vec![vec![10, 20, 30]], vec![vec![1, 2, 5]]);
+        assert_eq!(histogram.rank(0, 20), 0.25);
+        for value in [10, 20, 30] {
+            assert_eq!(
+                histogram.value_at_quantile(0, histogram.rank(0, value)),
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn probability_inverts_quantile() {
+        for position in POSITIONS {
+            let histogram = Histogram::from_channels(vec![(0..=255).collect::<Vec<u8>>()])
+                .unwrap()
+                .with_plotting_position(position);
+            for p in (1..100).map(|p| p as f64 / 100.0) {
+                let value = histogram.quantile(0, p);
+                assert!(
+                    (histogram.probability(0, value) - p).abs() < 1e-12,
+                    "{:?}",
+                    position
+                );
+            }
+        }
+
+        // Evenly spaced values are a uniform distribution, so a share `p` of
+        // them falls below about `p` of the range.
+        let values: Vec<u16> = (0..1000).map(|i| i * 2).collect();
+        for position in POSITIONS {
+            let histogram = Histogram::from_channels(vec![values.clone()])
+                .unwrap()
+                .with_plotting_position(position);
+            let (first, last) = (
+                histogram.sample_quantile(0, 0),
+                histogram.sample_quantile(0, 999),
+            );
+            for step in 0..=100 {
+                let p = first + (last - first) * step as f64 / 100.0;
+                let value = histogram.quantile(0, p);
+                assert!((value - 1998.0 * p).abs() <= 2.0, "{:?} {}", position, p);
+                assert!((histogram.probability(0, value) - p).abs() < 1e-10);
+            }
+            // Values between samples and beyond them.
+            for value in [1.0, 777.5, 1997.0] {
+                let p = histogram.probability(0, value);
+                assert!((histogram.quantile(0, p) - value).abs() < 1e-9);
+            }
+            assert_eq!(histogram.probability(0, -5.0), first);
+            assert_eq!(histogram.probability(0, 5000.0), last);
+        }
+
+        // A tied value takes the middle of its run, and comes back as itself.
+        let ties = Histogram::from_channels(vec![samples(1000, 3)]).unwrap();
+        let sorted = ties.sorted_values(0);
+        let run = sorted.iter().filter(|&&v| v == sorted[500]).count() as f64;
+        let p = ties.probability(0, sorted[500] as f64);
+        let below = sorted.iter().filter(|&&v| v < sorted[500]).count() as f64;
+        assert!((p - (below + run / 2.0) / 1000.0).abs() < 1e-12);
+        assert_eq!(ties.quantile(0, p), sorted[500] as f64);
+
+        let weighted = Histogram::from_sorted_weighted(vec![vec![10, 20, 30]], vec![vec![1, 2, 5]]);
+        for value in [10.0, 15.0, 20.0, 27.0, 30.0] {
+            let p = weighted.probability(0, value);
+            assert!((weighted.quantile(0, p) - value).abs() < 1e-9, "{}", value);
+        }
+    }
+
+    #[test]
+    fn serde_round_trips() {
+        let unweighted = Histogram::from_channels(vec![samples(100, 3)])
+            .unwrap()
+            .with_plotting_position(PlottingPosition::Blom)
+            .with_preserve_extremes(true);
+        let weighted = Histogram::from_sorted_weighted(vec![vec![1u8, 4, 9]], vec![vec![3, 0, 2]]);
+        for histogram in [unweighted, weighted] {
+            let json = serde_json::to_string(&histogram).unwrap();
+            assert_eq!(
+                serde_json::from_str::<Histogram<u8>>(&json).unwrap(),
+                histogram
+            );
+        }
+    }
+
+    #[test]
+    fn deserializing_rejects_broken_invariants() {
+        for json in [
+            r#"{"channels":[]}"#,
+            r#"{"channels":[[]]}"#,
+            r#"{"channels":[[200,3,100,7]]}"#,
+            r#"{"channels":[[1,2]],"cumulative_weights":[[1]]}"#,
+            r#"{"channels":[[1,2]],"cumulative_weights":[[1,2],[3,4]]}"#,
+            r#"{"channels":[[1,2]],"cumulative_weights":[[3,2]]}"#,
+            r#"{"channels":[[1,2]],"cumulative_weights":[[0,0]]}"#,
+        ] {
+            assert!(
+                serde_json::from_str::<Histogram<u8>>(json).is_err(),
+                "{}",
+                json
+            );
+        }
+    }
+
+    #[test]
+    fn from_channels_rejects_empty_channels() {
+        assert!(Histogram::<u8>::from_channels(vec![]).is_err());
+        assert!(Histogram::<u8>::from_channels(vec![vec![1], vec![]]).is_err());
+    }
+
+    #[test]
+    fn channel_stats_of_known_distributions() {
+        // Each of 0..=200 once, shuffled: percentiles fall on whole values.
+        let ramp: Vec<u8> = (0..=200).map(|i| ((i * 50) % 201) as u8).collect();
+        let histogram = Histogram::from_channels(vec![ramp]).unwrap();
+        let stats = histogram.channel_stats(0);
+        assert_eq!((stats.min, stats.max), (0.0, 200.0));
+        assert_eq!((stats.p1, stats.p5, stats.p25), (2.0, 10.0, 50.0));
+        assert_eq!(
+            (stats.median, stats.p75, stats.p95, stats.p99),
+            (100.0, 150.0, 190.0, 198.0)
+        );
+        assert!((stats.mean - 100.0).abs() < 1e-9);
+        let std = ((201.0_f64 * 201.0 - 1.0) / 12.0).sqrt();
+        assert!((stats.std - std).abs() < 1e-9);
+
+        // The synthetic input's blue channel is uniform noise over 0..=255.
+        let blue = crate::self_test::synthetic_input()
+            .to_rgb8()
+            .pixels()
+            .map(|p| p[2])
+            .collect();
+        let stats = Histogram::from_channels(vec![blue])
+            .unwrap()
+            .channel_stats(0);
+        for (percentile, p) in [(stats.p5, 0.05), (stats.median, 0.5), (stats.p95, 0.95)] {
+            assert!(
+                (percentile - p * 255.0).abs() < 8.0,
+                "{} at {}",
+                percentile,
+                p
+            );
+        }
+    }
+
+    #[test]
+    fn equalization_matches_the_textbook_example() {
+        // The 8x8 example Wikipedia's histogram equalization article works
+        // through, and its equalized result.
+        #[rustfmt::skip]
+        let source: [u8; 64] = [
+            52, 55, 61, 59, 79, 61, 76, 61,
+            62, 59, 55, 104, 94, 85, 59, 71,
+            63, 65, 66, 113, 144, 104, 63, 72,
+            64, 70, 70, 126, 154, 109, 71, 69,
+            67, 73, 68, 106, 122, 88, 68, 68,
+            68, 79, 60, 70, 77, 66, 58, 75,
+            69, 85, 64, 58, 55, 61, 65, 83,
+            70, 87, 69, 68, 65, 73, 78, 90,
+        ];
+        #[rustfmt::skip]
+        let expected: [u8; 64] = [
+            0, 12, 53, 32, 190, 53, 174, 53,
+            57, 32, 12, 227, 219, 202, 32, 154,
+            65, 85, 93, 239, 251, 227, 65, 158,
+            73, 146, 146, 247, 255, 235, 154, 130,
+            97, 166, 117, 231, 243, 210, 117, 117,
+            117, 190, 36, 146, 178, 93, 20, 170,
+            130, 202, 73, 20, 12, 53, 85, 194,
+            146, 206, 130, 117, 85, 166, 182, 215,
+        ];
+        let gray = image::GrayImage::from_raw(8, 8, source.to_vec()).unwrap();
+        let input = image::DynamicImage::ImageLuma8(gray);
+        let equalized = crate::TransformConfig::default().equalize(&input).unwrap();
+        for channel in 0..3 {
+            let values: Vec<u8> = equalized.iter().skip(channel).step_by(3).cloned().collect();
+            assert_eq!(values, expected, "channel {}", channel);
+        }
+    }
+}
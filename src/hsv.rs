@@ -0,0 +1,98 @@
+/// Hue, saturation and value of an 8-bit RGB pixel, each in `[0, 1]`. Hue
+/// is a fraction of the way round the color wheel from red. Hue is undefined
+/// for grays, and saturation for black; both are returned as 0 there.
+pub fn rgb_to_hsv(rgb: [u8; 3]) -> [f32; 3] {
+    let [r, g, b] = rgb.map(|c| c as f64 / 255.0);
+    let max = r.max(g).max(b);
+    let chroma = max - r.min(g).min(b);
+    let saturation = if max == 0.0 { 0.0 } else { chroma / max };
+    let hue = if chroma == 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / chroma).rem_euclid(6.0) / 6.0
+    } else if max == g {
+        ((b - r) / chroma + 2.0) / 6.0
+    } else {
+        ((r - g) / chroma + 4.0) / 6.0
+    };
+    [hue as f32, saturation as f32, max as f32]
+}
+
+/// RGB in `[0, 1]` of a hue, saturation and value in `[0, 1]`, the inverse
+/// of [`rgb_to_hsv`]. Values outside `[0, 1]` are clamped, and a hue of 1
+/// wraps to 0.
+pub fn hsv_to_rgb(hsv: [f32; 3]) -> [f32; 3] {
+    let [h, s, v] = hsv.map(|c| (c as f64).clamp(0.0, 1.0));
+    let sector = (h * 6.0).rem_euclid(6.0);
+    let f = sector.fract();
+    let (p, q, t) = (v * (1.0 - s), v * (1.0 - s * f), v * (1.0 - s * (1.0 - f)));
+    let rgb = match sector as u32 {
+        0 => [v, t, p],
+        1 => [q, v, p],
+        2 => [p, v, t],
+        3 => [p, q, v],
+        4 => [t, p, v],
+        _ => [v, p, q],
+    };
+    rgb.map(|c| c as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::CancelToken;
+    use crate::inverse::{lut_coordinates, sample_lut};
+    use crate::self_test::synthetic_input;
+    use crate::TransformConfig;
+
+    #[test]
+    fn hsv_round_trips_and_handles_grays() {
+        for r in (0..=255).step_by(5) {
+            for g in (0..=255).step_by(5) {
+                for b in (0..=255).step_by(5) {
+                    let rgb = [r as u8, g as u8, b as u8];
+                    let back = hsv_to_rgb(rgb_to_hsv(rgb));
+                    for (c, back) in rgb.iter().zip(back) {
+                        assert!((*c as f32 / 255.0 - back).abs() < 1e-5, "{:?}", rgb);
+                    }
+                }
+            }
+        }
+        assert_eq!(rgb_to_hsv([0, 0, 0]), [0.0, 0.0, 0.0]);
+        assert_eq!(rgb_to_hsv([128, 128, 128]), [0.0, 0.0, 128.0 / 255.0]);
+        assert_eq!(rgb_to_hsv([255, 0, 0]), [0.0, 1.0, 1.0]);
+        assert_eq!(hsv_to_rgb([1.0, 1.0, 1.0]), hsv_to_rgb([0.0, 1.0, 1.0]));
+    }
+
+    #[test]
+    fn value_transform_keeps_hue_and_saturation() {
+        let input = synthetic_input();
+        let config = TransformConfig::default().deterministic(true);
+        let result = config
+            .run_hsv_value(std::slice::from_ref(&input), &CancelToken::new())
+            .unwrap();
+        let source = input.to_rgb8();
+        let coordinates = lut_coordinates(&result.image, config.lut_coordinate(result.lut_width));
+        let reconstructed = sample_lut(&coordinates, &result.lut, result.lut_width);
+        let mut value_error = 0.0;
+        for (i, pixel) in source.pixels().enumerate() {
+            let [hue, saturation, value] = rgb_to_hsv(pixel.0);
+            assert_eq!(result.image[i * 3], hue);
+            assert_eq!(result.image[i * 3 + 1], saturation);
+            let decoded = reconstructed[i * 3 + 2];
+            value_error += (decoded as f64 - value as f64 * 255.0).powi(2);
+            // Recombined, only the value's rounding moves the pixel.
+            let rgb = hsv_to_rgb([hue, saturation, decoded as f32 / 255.0]);
+            let scale = decoded as f32 / (value * 255.0).max(1.0);
+            for (c, recombined) in pixel.0.iter().zip(rgb) {
+                assert!(
+                    (*c as f32 / 255.0 * scale - recombined).abs() < 1e-4,
+                    "pixel {}",
+                    i
+                );
+            }
+        }
+        let rmse = (value_error / source.pixels().len() as f64).sqrt();
+        assert!(rmse < 2.0, "{}", rmse);
+    }
+}
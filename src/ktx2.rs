@@ -0,0 +1,265 @@
+use std::io::Write;
+use std::path::Path;
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const VK_FORMAT_R8G8B8_UNORM: u32 = 23;
+const VK_FORMAT_R32G32B32_SFLOAT: u32 = 106;
+const HEADER_LEN: usize = 80;
+const LEVEL_INDEX_LEN: usize = 24;
+
+/// Writes an 8-bit RGB LUT as a `width x 1` one-dimensional KTX2 texture
+/// (`VK_FORMAT_R8G8B8_UNORM`, one mip level, no supercompression).
+/// `metadata` is stored in the key/value data alongside `KTXwriter`.
+pub fn write_lut<W: Write>(
+    writer: &mut W,
+    width: u32,
+    data: &[u8],
+    metadata: &[(String, String)],
+) -> Result<(), anyhow::Error> {
+    write_rgb8(writer, width, 0, data, metadata)
+}
+
+/// Writes an `R8G8B8_UNORM` texture. A `height` of 0 makes it 1D.
+pub fn write_rgb8<W: Write + ?Sized>(
+    writer: &mut W,
+    width: u32,
+    height: u32,
+    data: &[u8],
+    metadata: &[(String, String)],
+) -> Result<(), anyhow::Error> {
+    assert_eq!(data.len(), width as usize * height.max(1) as usize * 3);
+    let format = Format {
+        vk_format: VK_FORMAT_R8G8B8_UNORM,
+        type_size: 1,
+        sample_type: 0,
+        lower: 0,
+        upper: 255,
+    };
+    write_texture(writer, &format, width, height, data, metadata)
+}
+
+/// Writes an `R32G32B32_SFLOAT` texture. A `height` of 0 makes it 1D.
+pub fn write_rgb32f<W: Write + ?Sized>(
+    writer: &mut W,
+    width: u32,
+    height: u32,
+    data: &[f32],
+    metadata: &[(String, String)],
+) -> Result<(), anyhow::Error> {
+    assert_eq!(data.len(), width as usize * height.max(1) as usize * 3);
+    let format = Format {
+        vk_format: VK_FORMAT_R32G32B32_SFLOAT,
+        type_size: 4,
+        // KHR_DF_SAMPLE_DATATYPE_FLOAT | KHR_DF_SAMPLE_DATATYPE_SIGNED
+        sample_type: 0xC0,
+        lower: (-1.0f32).to_bits(),
+        upper: 1.0f32.to_bits(),
+    };
+    let bytes: Vec<u8> = data.iter().flat_map(|v| v.to_le_bytes()).collect();
+    write_texture(writer, &format, width, height, &bytes, metadata)
+}
+
+/// An uncompressed three-channel format.
+struct Format {
+    vk_format: u32,
+    type_size: u32,
+    /// Upper bits of each sample's channel type.
+    sample_type: u8,
+    lower: u32,
+    upper: u32,
+}
+
+fn write_texture<W: Write + ?Sized>(
+    writer: &mut W,
+    format: &Format,
+    width: u32,
+    height: u32,
+    data: &[u8],
+    metadata: &[(String, String)],
+) -> Result<(), anyhow::Error> {
+    let dfd = rgb_dfd(format);
+    let kvd = key_value_data(metadata);
+    let dfd_offset = HEADER_LEN + LEVEL_INDEX_LEN;
+    let kvd_offset = dfd_offset + dfd.len();
+    // Level data is aligned to lcm(texel block size, 4).
+    let texel_size = 3 * format.type_size as usize;
+    let alignment = (texel_size..)
+        .step_by(texel_size)
+        .find(|n| n % 4 == 0)
+        .unwrap();
+    let level_offset = (kvd_offset + kvd.len()).next_multiple_of(alignment);
+
+    let mut out = Vec::with_capacity(level_offset + data.len());
+    out.extend_from_slice(&IDENTIFIER);
+    for value in [
+        format.vk_format,
+        format.type_size,
+        width,  // pixelWidth
+        height, // pixelHeight, 0 marks a 1D texture
+        0,      // pixelDepth
+        0,      // layerCount
+        1,      // faceCount
+        1,      // levelCount
+        0,      // supercompressionScheme
+        dfd_offset as u32,
+        dfd.len() as u32,
+        kvd_offset as u32,
+        kvd.len() as u32,
+    ] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    // No supercompression global data.
+    out.extend_from_slice(&0u64.to_le_bytes());
+    out.extend_from_slice(&0u64.to_le_bytes());
+
+    for value in [level_offset, data.len(), data.len()] {
+        out.extend_from_slice(&(value as u64).to_le_bytes());
+    }
+    out.extend_from_slice(&dfd);
+    out.extend_from_slice(&kvd);
+    out.resize(level_offset, 0);
+    out.extend_from_slice(data);
+
+    writer.write_all(&out)?;
+    Ok(())
+}
+
+/// Basic data format descriptor for linear RGB with one sample per channel.
+fn rgb_dfd(format: &Format) -> Vec<u8> {
+    const SAMPLE_COUNT: usize = 3;
+    let block_size = 24 + 16 * SAMPLE_COUNT;
+    let bits = format.type_size as u16 * 8;
+
+    let mut dfd = Vec::with_capacity(4 + block_size);
+    dfd.extend_from_slice(&((4 + block_size) as u32).to_le_bytes());
+    dfd.extend_from_slice(&0u32.to_le_bytes()); // Khronos vendor, basic descriptor type
+    dfd.extend_from_slice(&2u16.to_le_bytes()); // version 1.3
+    dfd.extend_from_slice(&(block_size as u16).to_le_bytes());
+    dfd.extend_from_slice(&[
+        1, // KHR_DF_MODEL_RGBSDA
+        1, // KHR_DF_PRIMARIES_BT709
+        1, // KHR_DF_TRANSFER_LINEAR
+        0, // straight alpha
+    ]);
+    dfd.extend_from_slice(&[0; 4]); // 1x1x1 texel blocks
+    let plane_bytes = SAMPLE_COUNT as u8 * format.type_size as u8;
+    dfd.extend_from_slice(&[plane_bytes, 0, 0, 0, 0, 0, 0, 0]); // bytes per plane
+    for channel in 0..SAMPLE_COUNT as u8 {
+        dfd.extend_from_slice(&(channel as u16 * bits).to_le_bytes()); // bit offset
+        dfd.push((bits - 1) as u8); // bit length - 1
+        dfd.push(format.sample_type | channel); // KHR_DF_CHANNEL_RGBSDA_{R,G,B}
+        dfd.extend_from_slice(&[0; 4]); // sample position
+        dfd.extend_from_slice(&format.lower.to_le_bytes());
+        dfd.extend_from_slice(&format.upper.to_le_bytes());
+    }
+    dfd
+}
+
+/// The key/value data of the KTX2 file at `path`, such as the
+/// `precompute.*` entries written with its outputs, in file order.
+pub fn read_key_values(path: &Path) -> Result<Vec<(String, String)>, anyhow::Error> {
+    let bytes = std::fs::read(path)?;
+    if bytes.len() < HEADER_LEN || bytes[..IDENTIFIER.len()] != IDENTIFIER {
+        return Err(anyhow::anyhow!("{:?} isn't a KTX2 file", path));
+    }
+    let field = |i: usize| {
+        let start = IDENTIFIER.len() + 4 * i;
+        u32::from_le_bytes(bytes[start..start + 4].try_into().unwrap()) as usize
+    };
+    // kvdByteOffset and kvdByteLength follow the nine image fields and the
+    // data format descriptor's offset and length.
+    let (offset, len) = (field(11), field(12));
+    let kvd = bytes
+        .get(offset..offset + len)
+        .ok_or_else(|| anyhow::anyhow!("{:?} has key/value data past its end", path))?;
+    let mut entries = Vec::new();
+    let mut at = 0;
+    while at + 4 <= kvd.len() {
+        let entry_len = u32::from_le_bytes(kvd[at..at + 4].try_into().unwrap()) as usize;
+        let entry = kvd
+            .get(at + 4..at + 4 + entry_len)
+            .ok_or_else(|| anyhow::anyhow!("{:?} has a key/value entry past its end", path))?;
+        let mut parts = entry.split(|&b| b == 0);
+        let key = String::from_utf8_lossy(parts.next().unwrap_or_default()).into_owned();
+        let value = String::from_utf8_lossy(parts.next().unwrap_or_default()).into_owned();
+        entries.push((key, value));
+        at = (at + 4 + entry_len).next_multiple_of(4);
+    }
+    Ok(entries)
+}
+
+fn key_value_data(metadata: &[(String, String)]) -> Vec<u8> {
+    let writer = format!("precompute {}", env!("CARGO_PKG_VERSION"));
+    let mut entries: Vec<(&str, &str)> = metadata
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    entries.push(("KTXwriter", &writer));
+    // The spec requires entries sorted by key.
+    entries.sort_by_key(|(key, _)| key.as_bytes());
+
+    let mut kvd = Vec::new();
+    for (key, value) in entries {
+        let len = key.len() + 1 + value.len() + 1;
+        kvd.extend_from_slice(&(len as u32).to_le_bytes());
+        kvd.extend_from_slice(key.as_bytes());
+        kvd.push(0);
+        kvd.extend_from_slice(value.as_bytes());
+        kvd.push(0);
+        kvd.resize(kvd.len().next_multiple_of(4), 0);
+    }
+    kvd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    fn u64_at(bytes: &[u8], at: usize) -> usize {
+        u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap()) as usize
+    }
+
+    #[test]
+    fn lut_parses_with_its_dimensions_and_texels() {
+        let width = 5;
+        let lut: Vec<u8> = (0..width as u8 * 3).map(|v| v * 17).collect();
+        let metadata = vec![("precompute.mean".to_string(), "0.5".to_string())];
+        let mut bytes = Vec::new();
+        write_lut(&mut bytes, width, &lut, &metadata).unwrap();
+
+        assert_eq!(bytes[..12], IDENTIFIER);
+        let field = |i: usize| u32_at(&bytes, 12 + 4 * i);
+        assert_eq!(field(0), VK_FORMAT_R8G8B8_UNORM);
+        assert_eq!(field(1), 1); // typeSize
+        assert_eq!((field(2), field(3), field(4)), (width, 0, 0)); // 1D
+        assert_eq!((field(5), field(6), field(7)), (0, 1, 1)); // layers, faces, levels
+        assert_eq!(field(8), 0); // no supercompression
+
+        let (dfd_offset, dfd_len) = (field(9) as usize, field(10) as usize);
+        assert_eq!(dfd_offset, HEADER_LEN + LEVEL_INDEX_LEN);
+        assert_eq!(u32_at(&bytes, dfd_offset) as usize, dfd_len);
+
+        let level_offset = u64_at(&bytes, HEADER_LEN);
+        let level_len = u64_at(&bytes, HEADER_LEN + 8);
+        assert_eq!(level_len, lut.len());
+        assert_eq!(level_offset % 12, 0);
+        assert_eq!(&bytes[level_offset..], &lut[..]);
+
+        let path =
+            std::env::temp_dir().join(format!("precompute-ktx2-{}.ktx2", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let entries = read_key_values(&path);
+        std::fs::remove_file(&path).unwrap();
+        let entries = entries.unwrap();
+        assert!(entries.contains(&metadata[0]));
+        assert!(entries
+            .iter()
+            .any(|(key, value)| key == "KTXwriter" && value.starts_with("precompute")));
+    }
+}
@@ -0,0 +1,59 @@
+pub mod atlas;
+pub mod cache;
+pub mod cancel;
+mod channels;
+pub mod compare;
+pub mod compute;
+pub mod config;
+pub mod digest;
+pub mod distribution;
+pub mod downsample;
+pub mod encode;
+pub mod height;
+pub mod histogram;
+pub mod hsv;
+pub mod inverse;
+pub mod ktx2;
+pub mod local;
+pub mod lut3d;
+pub mod metadata;
+pub mod npz;
+pub mod pack;
+pub mod phash;
+pub mod plot;
+mod prefilter;
+pub mod read;
+pub mod reference;
+mod rng;
+pub mod rows;
+pub mod sampler;
+pub mod self_test;
+pub mod sidecar;
+pub mod stats;
+pub mod stream;
+mod tiff_writer;
+pub mod tiles;
+pub mod transfer;
+mod transform;
+pub mod uniforms;
+
+use lazy_static::lazy_static;
+
+pub use cancel::{CancelToken, Cancelled};
+pub use channels::{Channel, Channels};
+pub use config::{
+    default_lut_size, Exclusions, GaussianResult, GradientAxis, LutEndpoints, LutInterp,
+    LutRowMode, LutSampling, LutTexelOffset, MeanGradient, NanPolicy, PhaseTimings,
+    PlottingPosition, Prefilter, Rounding, TransformConfig, MAX_FITTED_LUT_SIZE,
+    MIN_DEFAULT_LUT_SIZE, PROFILE_TEXELS_PER_VALUE,
+};
+pub use distribution::{
+    Distribution, DistributionKind, QuantileBackend, QuantilePrecision, Tabulated,
+};
+pub use histogram::{ChannelCorrelation, ChannelStats, Histogram};
+
+pub const GAUSSIAN_AVERAGE: f64 = 0.5;
+
+lazy_static! {
+    pub static ref GAUSSIAN_STD: f64 = (1.0_f64 / 36.0_f64).sqrt();
+}
@@ -0,0 +1,270 @@
+use image::DynamicImage;
+use rayon::prelude::*;
+
+use crate::atlas::atlas_tiles;
+use crate::cancel::CancelToken;
+use crate::config::{
+    Exclusions, GaussianResult, LutEndpoints, LutInterp, LutSampling, NanPolicy, PhaseTimings,
+    PlottingPosition, TransformConfig,
+};
+use crate::distribution::Distribution;
+use crate::read::validate_image;
+use crate::transform::{extract_channels, interleave};
+
+/// One tile's mapping of a channel: its 8-bit histogram, after clipping, as
+/// a running total.
+struct TileHistogram {
+    /// Count of the subpixels at or below each level, inclusive.
+    cumulative: [f64; 256],
+}
+
+impl TileHistogram {
+    /// Counts `values` and clips each level at `clip_limit` times the mean
+    /// count, spreading what was clipped evenly over all 256 levels.
+    fn new(values: impl Iterator<Item = u8>, clip_limit: Option<f64>) -> Self {
+        let mut counts = [0.0; 256];
+        values.for_each(|v| counts[v as usize] += 1.0);
+        if let Some(clip_limit) = clip_limit {
+            let limit = clip_limit * counts.iter().sum::<f64>() / 256.0;
+            let excess: f64 = counts.iter().map(|&c| (c - limit).max(0.0)).sum();
+            counts
+                .iter_mut()
+                .for_each(|c| *c = c.min(limit) + excess / 256.0);
+        }
+        let mut cumulative = [0.0; 256];
+        let mut total = 0.0;
+        for (cumulative, count) in cumulative.iter_mut().zip(counts) {
+            total += count;
+            *cumulative = total;
+        }
+        Self { cumulative }
+    }
+
+    fn total(&self) -> f64 {
+        self.cumulative[255]
+    }
+
+    /// The forward value of each level: the target's quantile at the middle
+    /// of the level's share, kept half a subpixel inside `(0, 1)` so levels
+    /// the tile doesn't hold stay finite.
+    fn forward(&self, target: &dyn Distribution) -> [f32; 256] {
+        let total = self.total();
+        let mut forward = [0.0; 256];
+        for (v, forward) in forward.iter_mut().enumerate() {
+            let before = if v == 0 { 0.0 } else { self.cumulative[v - 1] };
+            let u =
+                ((before + self.cumulative[v]) / 2.0 / total).clamp(0.5 / total, 1.0 - 0.5 / total);
+            *forward = target.inv_cdf(u) as f32;
+        }
+        forward
+    }
+
+    /// The level below which a share `u` of the tile falls.
+    fn value_at_quantile(&self, u: f64) -> u8 {
+        let target = u * self.total();
+        self.cumulative.partition_point(|&c| c <= target).min(255) as u8
+    }
+}
+
+impl TransformConfig {
+    /// Adaptive histogram equalization towards the target: splits `input`
+    /// into a `columns x rows` grid of equal tiles, maps each tile's values
+    /// through its own histogram, and blends every pixel's forward value
+    /// bilinearly between the mappings of the four tiles whose centers
+    /// surround it, so the image follows local contrast without seams at tile
+    /// edges. With [`TransformConfig::clip_limit`], each tile's histogram is
+    /// clipped at that multiple of its mean count per level, as in CLAHE,
+    /// which keeps flat regions from being stretched into noise.
+    ///
+    /// Equal subpixels of a pixel's neighbourhood get equal forward values
+    /// rather than being spread apart. The LUT stacks one row per tile, in
+    /// the order of [`AtlasManifest`](crate::atlas::AtlasManifest)'s
+    /// `lut_row`, each the inverse of that tile's mapping alone; since the
+    /// forward image blends four mappings, a shader that blends the four
+    /// tiles' reconstructions with the same weights only recovers the source
+    /// approximately. The histogram is the whole image's.
+    pub fn run_local_tiles(
+        &self,
+        input: &DynamicImage,
+        columns: u32,
+        rows: u32,
+        cancel: &CancelToken,
+    ) -> Result<GaussianResult, anyhow::Error> {
+        self.validate()?;
+        validate_image(input)?;
+        let (width, height) = (input.width(), input.height());
+        if columns == 0 || rows == 0 || width % columns != 0 || height % rows != 0 {
+            return Err(anyhow::anyhow!(
+                "A {}x{} input can't be split into {}x{} equal tiles",
+                width,
+                height,
+                columns,
+                rows
+            ));
+        }
+        for (unsupported, name) in [
+            (self.alpha_cutoff.is_some(), "alpha_cutoff"),
+            (self.nan_policy == NanPolicy::Skip, "nan_policy skip"),
+            (self.histogram_samples.is_some(), "histogram_samples"),
+            (self.trim_tails != 0.0, "trim_tails"),
+            (self.shared_lut, "shared_lut"),
+            (self.mean_gradient.is_some(), "mean_gradient"),
+            (self.lut_channels.is_some(), "lut_channels"),
+            (self.lut_rows != 1, "lut_rows"),
+            (self.lut_interp != LutInterp::Nearest, "lut_interp"),
+            (self.lut_endpoints != LutEndpoints::Clamp, "lut_endpoints"),
+            (
+                self.plotting_position != PlottingPosition::Midpoint,
+                "plotting_position",
+            ),
+            (self.preserve_extremes, "preserve_extremes"),
+            (self.profile_guided_lut, "profile_guided_lut"),
+            (self.lut_sampling != LutSampling::Uniform, "lut_sampling"),
+        ] {
+            if unsupported {
+                return Err(anyhow::anyhow!("{} isn't supported with local tiles", name));
+            }
+        }
+        let (input, _) = self.apply_nan_policy(input)?;
+        let _local = tracing::info_span!("local_tiles", columns, rows).entered();
+        let (tile_width, tile_height) = ((width / columns) as usize, (height / rows) as usize);
+        let tiles = atlas_tiles(columns, rows, width, height);
+        let lut_width = self.lut_width(tile_width as u32, tile_height as u32);
+        let channel_values = extract_channels(&input, self);
+        cancel.check()?;
+
+        let mut forward = Vec::with_capacity(channel_values.len());
+        let mut luts = Vec::with_capacity(channel_values.len());
+        for (channel, values) in channel_values.iter().enumerate() {
+            let target = self.channel_target(channel);
+            let histograms: Vec<TileHistogram> = tiles
+                .par_iter()
+                .map(|tile| {
+                    let (x, y) = (tile.x as usize, tile.y as usize);
+                    let tile_values = (y..y + tile_height).flat_map(|row| {
+                        let start = row * width as usize + x;
+                        values[start..start + tile_width].iter().copied()
+                    });
+                    TileHistogram::new(tile_values, self.clip_limit)
+                })
+                .collect();
+            let mappings: Vec<[f32; 256]> = histograms
+                .iter()
+                .map(|histogram| histogram.forward(target.as_ref()))
+                .collect();
+            cancel.check()?;
+
+            // Each axis's pair of tiles whose centers surround a pixel, and
+            // how far it is from the first towards the second.
+            let neighbours = |position: usize, tile_size: usize, count: u32| {
+                let t = ((position as f64 + 0.5) / tile_size as f64 - 0.5)
+                    .clamp(0.0, (count - 1) as f64);
+                let first = t.floor() as usize;
+                (
+                    first,
+                    (first + 1).min(count as usize - 1),
+                    (t - first as f64) as f32,
+                )
+            };
+            let mut channel_forward = vec![0.0; values.len()];
+            channel_forward
+                .par_chunks_mut(width as usize)
+                .enumerate()
+                .for_each(|(y, out)| {
+                    let (top, bottom, fy) = neighbours(y, tile_height, rows);
+                    for (x, out) in out.iter_mut().enumerate() {
+                        let (left, right, fx) = neighbours(x, tile_width, columns);
+                        let v = values[y * width as usize + x] as usize;
+                        let at = |row: usize, column: usize| {
+                            mappings[row * columns as usize + column][v]
+                        };
+                        let upper = at(top, left) * (1.0 - fx) + at(top, right) * fx;
+                        let lower = at(bottom, left) * (1.0 - fx) + at(bottom, right) * fx;
+                        *out = upper * (1.0 - fy) + lower * fy;
+                    }
+                });
+            forward.push(channel_forward);
+            luts.push(
+                histograms
+                    .iter()
+                    .flat_map(|histogram| {
+                        (0..lut_width).map(|i| {
+                            let u = target.cdf(self.lut_texel_value(i, lut_width, 0.5));
+                            histogram.value_at_quantile(u)
+                        })
+                    })
+                    .collect::<Vec<u8>>(),
+            );
+            cancel.check()?;
+        }
+        let lut = interleave(&luts, 3);
+        let histogram = self.histogram(&input)?;
+        Ok(GaussianResult {
+            width,
+            height,
+            page_heights: vec![height],
+            lut_width,
+            lut_height: columns * rows,
+            image: interleave(&forward, 3),
+            lut_f32: lut.iter().map(|&v| v as f32 / 255.0).collect(),
+            lut,
+            exclusions: vec![Exclusions::default(); histogram.channel_count()],
+            histogram,
+            timings: PhaseTimings::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The step between the 32nd and 33rd of 64 values along a line, and
+    /// the largest step anywhere else on it.
+    fn step_at_edge(value: impl Fn(usize) -> f32) -> (f32, f32) {
+        let steps: Vec<f32> = (0..63).map(|j| (value(j + 1) - value(j)).abs()).collect();
+        let elsewhere = steps[..31]
+            .iter()
+            .chain(&steps[32..])
+            .fold(0.0, |a: f32, &b| a.max(b));
+        (steps[31], elsewhere)
+    }
+
+    #[test]
+    fn tile_boundaries_have_no_seams() {
+        // A ramp across the image, so each tile alone would stretch its own
+        // quarter of it over the whole target and jump at the tile edges.
+        let input = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8])
+        }));
+        let config = TransformConfig::default();
+        let cancel = CancelToken::new();
+        let result = config.run_local_tiles(&input, 2, 2, &cancel).unwrap();
+        assert_eq!(result.lut_height, 4);
+        // Levels just past a tile's range sit half a subpixel further out
+        // than its first level, so a step across an edge can be a few times
+        // an ordinary one, but nothing like the jump between tiles mapped
+        // alone.
+        let at = |x: usize, y: usize, channel: usize| result.image[(y * 64 + x) * 3 + channel];
+        for i in 0..64 {
+            let across = step_at_edge(|j| at(j, i, 0));
+            assert!(across.0 <= 3.0 * across.1, "row {}: {:?}", i, across);
+            let down = step_at_edge(|j| at(i, j, 1));
+            assert!(down.0 <= 3.0 * down.1, "column {}: {:?}", i, down);
+        }
+
+        // Mapped alone, the left tile's last column is near the top of the
+        // target and the right tile's first near the bottom.
+        let left = input.crop_imm(0, 0, 32, 64);
+        let right = input.crop_imm(32, 0, 32, 64);
+        let left = config.run(&left).unwrap().image[31 * 3];
+        let right = config.run(&right).unwrap().image[0];
+        let blended = (at(32, 0, 0) - at(31, 0, 0)).abs();
+        assert!(
+            left - right > 4.0 * blended,
+            "{} vs {}",
+            left - right,
+            blended
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use std::io::Write;
+
+use crate::config::{GaussianResult, TransformConfig};
+
+/// A `size^3` RGB LUT mapping a forward-image triple back to source color,
+/// with red varying fastest, then green, then blue, as `.cube` files order it.
+/// Values are source colors scaled to `[0, 1]`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut3d {
+    pub size: u32,
+    pub data: Vec<f32>,
+}
+
+impl Lut3d {
+    /// Samples the inverse of `result`'s transform at each point of a `size^3`
+    /// grid spanning the LUT's `[0, 1]` domain. Channels are transformed
+    /// independently, so each grid point's color is the first LUT row sampled
+    /// per channel at [`TransformConfig::lut_coordinate`], interpolating
+    /// linearly between texel centers.
+    pub fn new(
+        result: &GaussianResult,
+        config: &TransformConfig,
+        size: u32,
+    ) -> Result<Self, anyhow::Error> {
+        if size < 2 {
+            return Err(anyhow::anyhow!(
+                "3D LUTs need a size of at least 2, got {}",
+                size
+            ));
+        }
+        if result.lut_height != 1 {
+            return Err(anyhow::anyhow!(
+                "3D LUTs need a single LUT row, got {}",
+                result.lut_height
+            ));
+        }
+        let (lut, lut_width) = (&result.lut_f32, result.lut_width as usize);
+        let (scale, offset) = config.lut_coordinate(result.lut_width);
+        let last = lut_width - 1;
+        let channel = |channel: usize, g: f64| {
+            let x = ((offset + scale * g) * lut_width as f64 - 0.5).clamp(0.0, last as f64);
+            let texel = x.floor() as usize;
+            let next = (texel + 1).min(last);
+            let (a, b) = (lut[texel * 3 + channel], lut[next * 3 + channel]);
+            a + (b - a) * x.fract() as f32
+        };
+        let grid = |i: u32| i as f64 / (size - 1) as f64;
+        let mut data = Vec::with_capacity(size as usize * size as usize * size as usize * 3);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.extend([
+                        channel(0, grid(r)),
+                        channel(1, grid(g)),
+                        channel(2, grid(b)),
+                    ]);
+                }
+            }
+        }
+        Ok(Self { size, data })
+    }
+
+    /// Writes the LUT as an Adobe/Resolve `.cube` file.
+    pub fn write_cube(&self, writer: &mut impl Write, title: &str) -> std::io::Result<()> {
+        writeln!(writer, "TITLE \"{}\"", title.replace('"', "'"))?;
+        writeln!(writer, "LUT_3D_SIZE {}", self.size)?;
+        writeln!(writer, "DOMAIN_MIN 0.0 0.0 0.0")?;
+        writeln!(writer, "DOMAIN_MAX 1.0 1.0 1.0")?;
+        for rgb in self.data.chunks_exact(3) {
+            writeln!(writer, "{:.6} {:.6} {:.6}", rgb[0], rgb[1], rgb[2])?;
+        }
+        Ok(())
+    }
+
+    /// The LUT as a `size^2 x size` RGB image of `size` square slices side
+    /// by side, one per blue value, each with red along x and green along y,
+    /// the layout engines use for 3D LUTs stored as 2D textures.
+    pub fn tiled(&self) -> (u32, u32, Vec<f32>) {
+        let size = self.size as usize;
+        let mut image = vec![0.0; self.data.len()];
+        for (i, rgb) in self.data.chunks_exact(3).enumerate() {
+            let (r, g, b) = (i % size, i / size % size, i / (size * size));
+            let offset = (g * size * size + b * size + r) * 3;
+            image[offset..offset + 3].copy_from_slice(rgb);
+        }
+        (self.size * self.size, self.size, image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::self_test::synthetic_input;
+
+    #[test]
+    fn corners_reconstruct_the_extreme_source_colors() {
+        let config = TransformConfig::default().deterministic(true);
+        let result = config.run(&synthetic_input()).unwrap();
+        let lut = Lut3d::new(&result, &config, 3).unwrap();
+        assert_eq!(lut.data.len(), 27 * 3);
+
+        // Each corner reconstructs every channel from its first or last 1D
+        // texel, the darkest or lightest source color the LUT holds.
+        let last = (result.lut_width - 1) as usize;
+        let end =
+            |channel: usize, high: bool| result.lut_f32[if high { last } else { 0 } * 3 + channel];
+        for corner in 0..8 {
+            let high = [corner & 1 != 0, corner & 2 != 0, corner & 4 != 0];
+            let index = high
+                .iter()
+                .rev()
+                .fold(0, |index, &high| index * 3 + if high { 2 } else { 0 });
+            let expected = [end(0, high[0]), end(1, high[1]), end(2, high[2])];
+            assert_eq!(lut.data[index * 3..index * 3 + 3], expected, "{:?}", high);
+        }
+        let mut cube = Vec::new();
+        lut.write_cube(&mut cube, "test").unwrap();
+        let cube = String::from_utf8(cube).unwrap();
+        assert!(cube.contains("LUT_3D_SIZE 3"));
+        assert_eq!(cube.lines().count(), 4 + 27);
+        let (width, height, tiled) = lut.tiled();
+        assert_eq!((width, height), (9, 3));
+        // The last slice's top-right texel is red and blue high, green low.
+        assert_eq!(tiled[8 * 3..9 * 3], lut.data[20 * 3..21 * 3]);
+    }
+}
@@ -1,24 +1,153 @@
-#![feature(path_file_prefix)]
-
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
+use std::io::Write;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use clap::Parser;
-use image::{DynamicImage, GenericImageView};
-use lazy_static::lazy_static;
-use puruspe::{erf, inverf};
+use clap::{CommandFactory, FromArgMatches, Parser};
+use image::{DynamicImage, ImageBuffer, ImageEncoder, Luma};
+use precompute::atlas::AtlasManifest;
+use precompute::cache::ChannelCache;
+use precompute::digest::{self, HashAlgorithm};
+use precompute::distribution::Gaussian;
+use precompute::downsample::DownsampleFilter;
+use precompute::encode::{
+    ByteOrder, Layout, LutPack, OutputData, OutputEncoder, OutputSamples, SampleType, WriteLog,
+    WriteOptions,
+};
+use precompute::height::HeightSample;
+use precompute::inverse::LutFilter;
+use precompute::lut3d::Lut3d;
+use precompute::metadata;
+use precompute::pack::PackSpec;
+use precompute::read::{GrayInt32, RawFormat, RawLayout, ReadOptions, ResizeFilter};
+use precompute::sampler::LutSampler;
+use precompute::sidecar::Sidecar;
+use precompute::tiles::TileManifest;
+use precompute::transfer::{DisplayCurve, Transfer};
+use precompute::uniforms::Uniforms;
+use precompute::{
+    compare, compute, encode, height, hsv, inverse, npz, phash, plot, read, self_test, CancelToken,
+    Cancelled, Channel, ChannelCorrelation, Channels, DistributionKind, Exclusions, GaussianResult,
+    Histogram, LutEndpoints, LutInterp, LutRowMode, LutSampling, LutTexelOffset, MeanGradient,
+    NanPolicy, PlottingPosition, Prefilter, QuantilePrecision, Rounding, Tabulated,
+    TransformConfig,
+};
 use rayon::prelude::*;
 use rfd::FileDialog;
-use tiff::encoder::compression::Packbits;
-use tiff::encoder::{colortype, TiffEncoder, TiffValue};
+
+mod batch;
+#[cfg(feature = "gui")]
+mod gui;
+mod manifest;
+mod resume;
+
+use batch::{discover_inputs, exclude_own_outputs, read_input_list, run_batch, EventTarget};
+use manifest::write_manifest;
+use resume::outputs_up_to_date;
 
 const IMG_SUFFIX: &str = "gaussian";
 const LUT_SUFFIX: &str = "lut";
-const GAUSSIAN_AVERAGE: f64 = 0.5;
+const RECONSTRUCTED_SUFFIX: &str = "reconstructed";
+const EQUALIZED_SUFFIX: &str = "equalized";
+
+type WeightMap = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+/// What the transform operates on.
+#[derive(clap::ValueEnum, Clone, Debug, PartialEq, Eq, Copy)]
+enum Colorspace {
+    /// The source's channels as they are.
+    Source,
+    /// Only the HSV value, with hue and saturation kept in the forward image.
+    HsvValue,
+}
+
+#[derive(clap::ValueEnum, Clone, Debug, PartialEq, Eq, Copy)]
+enum OutputFormat {
+    Tiff,
+    Ktx2,
+}
+
+/// Container for the LUT: those of the forward image, or a 1D `.cube` file.
+#[derive(clap::ValueEnum, Clone, Debug, PartialEq, Eq, Copy)]
+enum LutFormat {
+    Tiff,
+    Ktx2,
+    Cube,
+}
+
+/// Single file holding both the forward image and the LUT.
+#[derive(clap::ValueEnum, Clone, Debug, PartialEq, Eq, Copy)]
+enum CombinedFormat {
+    Npz,
+}
 
-lazy_static! {
-    static ref GAUSSIAN_STD: f64 = (1.0_f64 / 36.0_f64).sqrt();
+#[derive(clap::ValueEnum, Clone, Debug, PartialEq, Eq, Copy)]
+enum Lut3dFormat {
+    Cube,
+    Tiff,
+}
+
+impl OutputFormat {
+    /// The encoder for this format: `tiff` itself, or a KTX2 encoder with
+    /// `metadata` and `tiff`'s write options.
+    fn encoder(
+        self,
+        metadata: Vec<(String, String)>,
+        tiff: encode::Tiff,
+    ) -> Box<dyn OutputEncoder> {
+        match self {
+            OutputFormat::Tiff => Box::new(tiff),
+            OutputFormat::Ktx2 => Box::new(encode::Ktx2 {
+                metadata,
+                write_options: tiff.write_options,
+            }),
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Debug, PartialEq, Eq, Copy)]
+enum LutPrecision {
+    U8,
+    U16,
+    F32,
+}
+
+#[derive(clap::Subcommand, Clone, Debug)]
+enum SubCommand {
+    /// Print a completion script for SHELL to stdout
+    #[command(hide = true)]
+    Completions {
+        #[arg(value_enum)]
+        shell: CompletionShell,
+    },
+    /// Combine the red, green and blue LUTs of a --split-channels bake back into one RGB LUT, written as --lut-format asks
+    MergeLuts {
+        /// The red channel's single-channel LUT
+        red: PathBuf,
+        /// The green channel's single-channel LUT
+        green: PathBuf,
+        /// The blue channel's single-channel LUT
+        blue: PathBuf,
+        /// Where to write the combined LUT
+        output: PathBuf,
+    },
+    /// Print what an output of an earlier bake records about it: its header, KTX2 metadata and the parameters in its --sidecar file
+    Describe {
+        /// A forward image, LUT or .meta.json sidecar
+        file: PathBuf,
+    },
+    /// Bake a built-in synthetic texture and check every stage against known values, exiting non-zero if any differ
+    SelfTest,
+}
+
+#[derive(clap::ValueEnum, Clone, Debug, PartialEq, Eq, Copy)]
+enum CompletionShell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+    Elvish,
 }
 
 #[derive(clap::ValueEnum, Clone, Debug, PartialEq, Eq, Copy)]
@@ -27,201 +156,5831 @@ enum Output {
     Lut,
 }
 
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[derive(Parser, Clone, Debug)]
+#[command(author, version, about, long_about = None, args_override_self = true)]
+// Options in `single_file` only work on one input, not a batch, so they
+// conflict with the `batch` group; those in `not_with_<option>` conflict
+// with that option. The `mode` options each run instead of a bake, so
+// only one of them can be given.
+#[command(group(clap::ArgGroup::new("batch").args(["in_dir", "input_list"]).conflicts_with("single_file")))]
+#[command(group(clap::ArgGroup::new("single_file").multiple(true)))]
+#[command(group(clap::ArgGroup::new("not_with_atlas").multiple(true)))]
+#[command(group(clap::ArgGroup::new("not_with_local_tiles").multiple(true)))]
+#[command(group(clap::ArgGroup::new("not_with_colorspace").multiple(true)))]
+#[command(group(clap::ArgGroup::new("not_with_weight_map").multiple(true)))]
+#[command(group(clap::ArgGroup::new("not_with_histogram_samples").multiple(true)))]
+#[command(group(clap::ArgGroup::new("not_with_format").multiple(true)))]
+#[command(group(clap::ArgGroup::new("not_with_split_channels").multiple(true)))]
+#[command(group(clap::ArgGroup::new("not_with_tile_output").multiple(true)))]
+#[command(group(clap::ArgGroup::new("not_with_channel_cache").multiple(true)))]
+#[command(group(clap::ArgGroup::new("not_with_lut_sampling").multiple(true)))]
+#[command(group(clap::ArgGroup::new("mode").args(["invert", "in_place_preview", "stats_only", "equalize_only", "apply_lut", "benchmark_compare", "validate_only"])))]
 struct Args {
+    #[command(subcommand)]
+    command: Option<SubCommand>,
+
+    /// Only print warnings, errors and final reports, not per-file progress
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Print debug events to stderr, such as how many subpixels each exclusion left out of a histogram
     #[arg(short, long)]
+    verbose: bool,
+
+    /// Read default option values from a TOML file; flags on the command line take precedence
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Don't default to the parameters an --invert reconstruction, or a forward image, embeds from the bake it came from
+    #[arg(long)]
+    ignore_embedded_params: bool,
+
+    #[arg(short, long, group = "single_file")]
     in_file: Option<PathBuf>,
 
+    /// Bake each entry of a TOML or JSON list of jobs, each an input with its own option overrides
+    #[arg(long, value_name = "PATH", group = "single_file")]
+    jobs_from_file: Option<PathBuf>,
+
+    /// Process every image in a directory
+    #[arg(long)]
+    in_dir: Option<PathBuf>,
+
+    /// Process every image listed in a file, one path per line, or in stdin with -
+    #[arg(long, value_name = "PATH")]
+    input_list: Option<PathBuf>,
+
+    /// Only process the first N inputs of --in-dir, in file name order, or of --input-list, in list order
+    #[arg(long, value_name = "N", requires = "batch")]
+    limit: Option<NonZeroUsize>,
+
+    /// Give up on any input of a batch whose bake takes longer than this many seconds, and move on to the next
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..), requires = "batch")]
+    timeout: Option<u64>,
+
+    /// Bake files of --in-dir that this batch writes itself when --out-dir is the same directory, rather than leaving them out
+    #[arg(long, requires = "in_dir")]
+    allow_overlap: bool,
+
     #[arg(short, long, default_value = "./")]
     out_dir: PathBuf,
 
-    #[arg(long)]
+    #[arg(long, conflicts_with_all = ["name_template", "pair_naming"], group = "single_file")]
     img_prefix: Option<String>,
 
-    #[arg(long)]
+    #[arg(long, conflicts_with_all = ["name_template", "pair_naming"], group = "single_file")]
     lut_prefix: Option<String>,
-}
 
-fn main() {
-    if let Err(e) = _main(Args::parse()) {
-        let exe_name = std::env::args()
-            .next()
-            .unwrap_or_else(|| "precompute".to_string());
-        eprintln!("[{}] Precompute Error: {}", exe_name, e);
-        std::process::exit(1);
-    }
-}
+    /// Name outputs from a template such as "{input}-{suffix}-{std}", see the README
+    #[arg(long, value_parser = NameTemplate::parse, value_name = "TEMPLATE", conflicts_with = "pair_naming")]
+    name_template: Option<NameTemplate>,
 
-fn _main(args: Args) -> Result<(), anyhow::Error> {
-    let cwd = std::env::current_dir().unwrap_or(PathBuf::from("."));
-    let input_path = args.in_file.map(Ok).unwrap_or_else(|| {
-        FileDialog::new()
-            .set_title("Select input texture")
-            .set_directory(cwd)
-            .pick_file()
-            .ok_or(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "No input file specified",
-            ))
-    })?;
-    let out_dir = path_directory(args.out_dir);
-    let input_file_prefix = input_path
-        .file_prefix()
-        .unwrap_or(OsStr::new("Texture"))
-        .to_string_lossy();
-    let img_file_name = format!(
-        "{}.tif",
-        args.img_prefix
-            .unwrap_or(format!("{}-{}", input_file_prefix, IMG_SUFFIX))
-    );
-    let lut_file_name = format!(
-        "{}.tif",
-        args.lut_prefix
-            .unwrap_or(format!("{}-{}", input_file_prefix, LUT_SUFFIX))
-    );
+    /// Name the forward image and LUT as an engine pairs them: dotted (rock.gauss, rock.gauss.lut), unreal (T_rock_Gauss, T_rock_GaussLUT), snake (rock_gauss, rock_gauss_lut), or IMAGE,LUT templates in --name-template's placeholders
+    #[arg(long, value_parser = PairNaming::parse, value_name = "CONVENTION")]
+    pair_naming: Option<PairNaming>,
 
-    let input_img = image::open(&input_path)?;
+    /// Mean of the target distribution
+    #[arg(long, default_value_t = TransformConfig::default().mean, conflicts_with_all = ["mean_gradient", "sigma_from_range"])]
+    mean: f64,
 
-    println!("Processing {:?}...", input_path);
-    let start = Instant::now();
+    /// Standard deviation of the target distribution
+    #[arg(long, default_value_t = TransformConfig::default().std)]
+    std: f64,
 
-    let (t, t_inv) = transform_histogram(&input_img);
+    /// Set --mean and --std so that PERCENT% of a Gaussian target falls between LOW and HIGH, e.g. 0.1,0.9,99
+    #[arg(long, value_parser = parse_sigma_from_range, value_name = "LOW,HIGH,PERCENT", conflicts_with_all = ["std", "target_cdf"])]
+    sigma_from_range: Option<Gaussian>,
 
-    println!("Finished processing. Took {:?}", start.elapsed());
+    /// Shape of the target distribution, or one per channel, e.g. gaussian,gaussian,uniform
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [TransformConfig::default().distribution], conflicts_with = "target_cdf")]
+    distribution: Vec<DistributionKind>,
 
-    println!(
-        "Writing output to {} and {} in directory {:?}",
-        img_file_name, lut_file_name, out_dir
-    );
+    /// Vary the mean linearly across the image instead of --mean, e.g. 0.3,0.7,y
+    #[arg(long, value_parser = parse_mean_gradient, value_name = "START,END,AXIS", conflicts_with_all = ["sigma_from_range", "signed_encoding", "target_cdf"])]
+    mean_gradient: Option<MeanGradient>,
 
-    let (width, height) = (input_img.width(), input_img.height());
-    let (res1, res2) = rayon::join(
-        || write_rgb::<colortype::RGB32Float>(&out_dir.join(img_file_name), width, height, &t),
-        || write_rgb::<colortype::RGB8>(&out_dir.join(lut_file_name), width, 1, &t_inv),
-    );
-    res1.and(res2)
-}
+    /// CSV of (u, value) points on a target quantile function, used instead of --distribution
+    #[arg(long, value_parser = read_target_cdf)]
+    target_cdf: Option<Tabulated>,
 
-struct ChannelPixel {
-    subpx_idx: usize,
-    sort_idx: usize,
-}
+    /// Number of LUT entries [default: the geometric mean of the input's sides, from 256 to 16384]
+    #[arg(long, conflicts_with = "max_lut_error")]
+    lut_size: Option<u32>,
+
+    /// Pick the smallest LUT whose reconstruction RMSE, in 8-bit levels, is at most this
+    #[arg(long, groups = ["not_with_atlas", "not_with_local_tiles", "not_with_colorspace"])]
+    max_lut_error: Option<f64>,
 
-fn transform_histogram(input: &DynamicImage) -> (Vec<f32>, Vec<u8>) {
-    let mut t_r = vec![0.0; (input.width() * input.height()) as usize];
-    let mut t_g = vec![0.0; (input.width() * input.height()) as usize];
-    let mut t_b = vec![0.0; (input.width() * input.height()) as usize];
+    /// Also write an 8-bit PNG of the input dimmed to gray, with the pixels the LUT reconstructs off by more than --tolerance 8-bit levels in red
+    #[arg(long, value_name = "PNG", groups = ["single_file", "not_with_atlas", "not_with_local_tiles", "not_with_colorspace"])]
+    error_map: Option<PathBuf>,
 
-    let mut t_inv_r = vec![0; input.width() as usize];
-    let mut t_inv_g = vec![0; input.width() as usize];
-    let mut t_inv_b = vec![0; input.width() as usize];
+    /// Round the LUT width up to the next power of two
+    #[arg(long)]
+    pad_lut: bool,
 
-    let mut input_r = vec![0; (input.width() * input.height()) as usize];
-    let mut input_g = vec![0; (input.width() * input.height()) as usize];
-    let mut input_b = vec![0; (input.width() * input.height()) as usize];
+    /// Build the LUT for GPU linear filtering, with its end texels at the ends of the Gaussian range; sample it at (g * (width - 1) + 0.5) / width
+    #[arg(
+        long,
+        conflicts_with = "lut_texel_offset",
+        group = "not_with_lut_sampling"
+    )]
+    lut_for_linear_filtering: bool,
 
-    input.pixels().enumerate().for_each(|(i, (_, _, px))| {
-        input_r[i] = px[0];
-        input_g[i] = px[1];
-        input_b[i] = px[2];
-    });
+    /// Texel centers of the sampler the LUT is built for, so it reconstructs when sampled at the forward value itself: none for (i + 0.5) / width, as OpenGL, Vulkan, Metal and Direct3D 10+ sample; half for i / width, as Direct3D 9 does
+    #[arg(long, value_enum, default_value_t = TransformConfig::default().lut_texel_offset, group = "not_with_lut_sampling")]
+    lut_texel_offset: LutTexelOffset,
 
-    [
-        (input_r, &mut t_r, &mut t_inv_r),
-        (input_g, &mut t_g, &mut t_inv_g),
-        (input_b, &mut t_b, &mut t_inv_b),
-    ]
-    .par_iter_mut()
-    .for_each(|(inp, t, t_inv)| {
-        let mut input_sorted: Vec<_> = inp.clone().into_iter().enumerate().collect();
-        input_sorted.par_sort_unstable_by_key(|(_, val)| *val);
-
-        let mut input_orig_order: Vec<_> = input_sorted
-            .clone()
-            .into_par_iter()
-            .enumerate()
-            .map(|(sort_idx, (subpx_idx, _))| ChannelPixel {
-                subpx_idx,
-                sort_idx,
+    /// How LUT texels sample the sorted source values
+    #[arg(long, value_enum, default_value_t = TransformConfig::default().lut_interp)]
+    lut_interp: LutInterp,
+
+    /// What LUT texels beyond the lowest and highest source samples hold
+    #[arg(long, value_enum, default_value_t = TransformConfig::default().lut_endpoints)]
+    lut_endpoints: LutEndpoints,
+
+    /// Forward values the LUT texels are built for: evenly spaced, or stratified per channel where the reconstruction error is largest, sampled through a <lut>-knots.tif map written beside the LUT
+    #[arg(long, value_enum, default_value_t = TransformConfig::default().lut_sampling, conflicts_with = "not_with_lut_sampling", groups = ["not_with_atlas", "not_with_local_tiles", "not_with_colorspace", "not_with_split_channels"])]
+    lut_sampling: LutSampling,
+
+    /// Quantile each sorted subpixel is ranked at, in both the forward image and the LUT
+    #[arg(long, value_enum, default_value_t = TransformConfig::default().plotting_position, groups = ["not_with_weight_map", "not_with_histogram_samples"])]
+    plotting_position: PlottingPosition,
+
+    /// Rank every subpixel holding a channel's lowest value at the lowest quantile, and every one holding its highest at the highest, so the extremes map to the outermost forward values
+    #[arg(long, group = "not_with_histogram_samples")]
+    preserve_extremes: bool,
+
+    /// Leave this percentage of each channel's lowest and highest values out of the histogram, clamping them to what's left
+    #[arg(long, value_name = "PERCENT", default_value_t = TransformConfig::default().trim_tails, groups = ["not_with_weight_map", "not_with_histogram_samples"])]
+    trim_tails: f64,
+
+    /// How 8-bit LUT values between two levels are quantized
+    #[arg(long, value_enum, default_value_t = TransformConfig::default().rounding)]
+    rounding: Rounding,
+
+    /// Rows in the LUT image, for hardware that filters across a thin 2D LUT
+    #[arg(long, default_value_t = TransformConfig::default().lut_rows, groups = ["not_with_atlas", "not_with_local_tiles"])]
+    lut_rows: u32,
+
+    /// What LUT rows after the first hold; jitter offsets each row's samples within the texel
+    #[arg(long, value_enum, default_value_t = TransformConfig::default().lut_row_mode)]
+    lut_row_mode: LutRowMode,
+
+    /// Sample type of the LUT; u16 promotes the 8-bit levels to 16 bits with zero low bits, f32 keeps --lut-interp linear values unrounded, scaled to [0, 1]
+    #[arg(long, value_enum, default_value_t = LutPrecision::U8)]
+    lut_precision: LutPrecision,
+
+    /// Texel layout of the LUT; rg-ba writes an RGBA TIFF with blue in B and a blue-transformed flag in A
+    #[arg(long, value_enum, default_value_t = LutPack::Rgb)]
+    lut_pack: LutPack,
+
+    /// Write the forward image as an RGBA TIFF with every pixel's alpha set to ALPHA, for importers that need four channels
+    #[arg(long, value_name = "ALPHA", num_args = 0..=1, default_missing_value = "1.0", conflicts_with = "pack", groups = ["not_with_format", "not_with_split_channels", "not_with_tile_output"])]
+    force_alpha: Option<f32>,
+
+    /// Write the forward image as an RGBA TIFF whose alpha is how densely source values are packed where each pixel sits, 1 at the most common value, so a shader can weigh sparse tails down
+    #[arg(long, conflicts_with_all = ["force_alpha", "mean_gradient", "pack"], groups = ["not_with_atlas", "not_with_local_tiles", "not_with_colorspace", "not_with_format", "not_with_split_channels", "not_with_tile_output"])]
+    confidence_alpha: bool,
+
+    /// Write the forward image as a TIFF whose channels are routed by SPEC, comma-separated output=source pairs such as r=source.r,a=forward.r: outputs are r, g, b or a, sources forward.r-b, source.r-a or a number; unnamed RGB outputs keep their forward channel, and alpha is only written if named
+    #[arg(long, value_name = "SPEC", conflicts_with = "sidecar", groups = ["single_file", "not_with_atlas", "not_with_local_tiles", "not_with_format", "not_with_split_channels", "not_with_tile_output"])]
+    pack: Option<PackSpec>,
+
+    /// Write an RGBA TIFF LUT whose alpha is how densely source values are packed at each texel, 1 at the most common value
+    #[arg(long, groups = ["not_with_atlas", "not_with_local_tiles", "not_with_split_channels"])]
+    rgba_lut: bool,
+
+    /// Quantile of the target the source median maps to, warping the ranks monotonically towards one tail
+    #[arg(long, value_name = "U", default_value_t = TransformConfig::default().median_target)]
+    median_target: f64,
+
+    /// Contrast applied around the mean of the forward image; >1 increases, <1 decreases
+    #[arg(long, default_value_t = TransformConfig::default().post_contrast)]
+    post_contrast: f64,
+
+    /// Store forward values v as 0.5 + v / 2, so a target centered on zero keeps its sign in the LUT's [0, 1] domain
+    #[arg(long)]
+    signed_encoding: bool,
+
+    /// Build one LUT from the combined histogram of all channels
+    #[arg(
+        long,
+        visible_alias = "combined-histogram",
+        overrides_with = "channels_independent_lut",
+        group = "not_with_channel_cache"
+    )]
+    shared_lut: bool,
+
+    /// Build a separate LUT column from each channel's own histogram (default)
+    #[arg(long, overrides_with = "shared_lut")]
+    channels_independent_lut: bool,
+
+    /// Produce byte-identical outputs regardless of thread count
+    #[arg(long)]
+    deterministic: bool,
+
+    /// Bake a second time on one thread and fail unless the forward image and LUT come out bit for bit the same; needs --deterministic
+    #[arg(long, requires = "deterministic", group = "not_with_channel_cache")]
+    bit_exact_float: bool,
+
+    /// Build the histogram from this many randomly chosen subpixels per channel instead of sorting every one
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "not_with_histogram_samples", group = "not_with_weight_map")]
+    histogram_samples: Option<u64>,
+
+    /// Seed for everything chosen at random, such as the --histogram-samples subpixels
+    #[arg(long, alias = "histogram-seed", default_value_t = TransformConfig::default().seed)]
+    seed: u64,
+
+    /// Approximate the Gaussian target's inverse CDF, several subpixels at a time on CPUs with AVX
+    #[arg(long, conflicts_with = "quantile_precision")]
+    fast_quantiles: bool,
+
+    /// How precisely Gaussian targets' quantile function and CDF are evaluated: closed-form approximations, puruspe's erf and inverf, or those refined against the tails' cancellation
+    #[arg(long, value_enum, default_value_t = TransformConfig::default().quantile_precision)]
+    quantile_precision: QuantilePrecision,
+
+    /// Give each LUT column as many distinct texels as its channel's distinct values need, repeating them to fill the LUT's width
+    #[arg(long, group = "not_with_lut_sampling")]
+    profile_guided_lut: bool,
+
+    /// Container for the forward image
+    #[arg(long, value_enum, default_value_t = OutputFormat::Tiff, group = "not_with_format")]
+    img_format: OutputFormat,
+
+    /// Container for the LUT; cube writes a 1D .cube file for color-grading tools
+    #[arg(long, value_enum, default_value_t = LutFormat::Tiff, group = "not_with_format")]
+    lut_format: LutFormat,
+
+    /// Write the forward image and LUT together as arrays of one file, named after the forward image, instead of as two
+    #[arg(long, value_enum, conflicts_with = "not_with_format", groups = ["single_file", "not_with_atlas", "not_with_local_tiles"])]
+    format: Option<CombinedFormat>,
+
+    /// Write each transformed channel of the forward image and LUT as its own single-channel TIFF, named <output>-r.tif, <output>-g.tif and <output>-b.tif
+    #[arg(long, conflicts_with = "not_with_split_channels", groups = ["not_with_atlas", "not_with_format"])]
+    split_channels: bool,
+
+    /// Also write the LUT re-encoded in each of these spaces, as <lut>-linear and <lut>-srgb beside it, taking its values to be encoded as --source-transfer says
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "SPACES", conflicts_with = "mask_mode", groups = ["not_with_colorspace", "not_with_format", "not_with_split_channels"])]
+    lut_variants: Vec<Transfer>,
+
+    /// How the source's values, and so the LUT's, encode light, for --lut-variants
+    #[arg(long, value_enum, default_value_t = Transfer::Srgb, requires = "lut_variants")]
+    source_transfer: Transfer,
+
+    /// Shrink the forward image by this factor on both axes before writing it, keeping the LUT of the full-resolution bake
+    #[arg(long, value_name = "FACTOR", value_parser = clap::value_parser!(u32).range(2..), conflicts_with_all = ["emit_ranks", "pack"], groups = ["not_with_atlas", "not_with_local_tiles"])]
+    downsample_output: Option<u32>,
+
+    /// Filter --downsample-output averages each block with
+    #[arg(long, value_enum, default_value_t = DownsampleFilter::default())]
+    downsample_filter: DownsampleFilter,
+
+    /// Sample arrangement of the forward image; planar needs --img-format tiff
+    #[arg(long, value_enum, default_value_t = Layout::default(), group = "not_with_format")]
+    layout: Layout,
+
+    /// Write the forward image as square tiles of this size instead of strips, a multiple of 16; needs --img-format tiff
+    #[arg(long, value_name = "SIZE", value_parser = parse_tiff_tile_size, group = "not_with_format")]
+    tiff_tiled: Option<u32>,
+
+    /// Record this many pixels per inch in the resolution tags of every TIFF written, for tools that scale imports by them
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    dpi: Option<u32>,
+
+    /// Byte order of TIFF outputs
+    #[arg(long, value_enum, default_value_t = ByteOrder::default())]
+    tiff_endian: ByteOrder,
+
+    /// Channels to transform; channels left out are written as zero
+    #[arg(long, value_enum, default_value_t = TransformConfig::default().channels)]
+    channels: Channels,
+
+    /// What to transform; hsv-value transforms only each pixel's HSV value, storing hue and saturation unchanged in the forward image's R and G
+    #[arg(long, value_enum, default_value_t = Colorspace::Source, conflicts_with = "not_with_colorspace", groups = ["not_with_atlas", "not_with_local_tiles"])]
+    colorspace: Colorspace,
+
+    /// Filter the input before transforming it
+    #[arg(long, value_enum, default_value_t = TransformConfig::default().prefilter)]
+    prefilter: Prefilter,
+
+    /// Spatial standard deviation of --prefilter bilateral, in pixels
+    #[arg(long, default_value_t = TransformConfig::default().prefilter_sigma_spatial)]
+    prefilter_sigma_spatial: f64,
+
+    /// Range standard deviation of --prefilter bilateral, in 8-bit levels
+    #[arg(long, default_value_t = TransformConfig::default().prefilter_sigma_range)]
+    prefilter_sigma_range: f64,
+
+    /// Channels given a LUT column, e.g. r,g; the others get an identity column [default: all]
+    #[arg(long, value_enum, value_delimiter = ',', group = "not_with_colorspace")]
+    lut_channels: Option<Vec<Channel>>,
+
+    /// Number of threads writing outputs while the next file is processed in batch mode
+    #[arg(long, default_value = "2")]
+    write_concurrency: NonZeroUsize,
+
+    /// Print batch progress lines in input order rather than as files finish, holding back those that finish ahead of an earlier input
+    #[arg(long, requires = "batch")]
+    deterministic_batch_order: bool,
+
+    /// Number of threads transforming inputs [default: one per core]
+    #[arg(long, value_name = "N")]
+    threads: Option<NonZeroUsize>,
+
+    /// Number of threads encoding outputs, in a pool of their own so writes never hold up the --threads pool
+    #[arg(long, value_name = "N", default_value = "2")]
+    threads_io: NonZeroUsize,
+
+    /// Compare the forward image against a previously baked one
+    #[arg(long, group = "single_file")]
+    compare: Option<PathBuf>,
+
+    /// Also write an 8-bit PNG heat map of how far each pixel of the forward image moved from a previously baked one, black for not at all to yellow for --std or more
+    #[arg(long, value_name = "GAUSSIAN", group = "single_file")]
+    diff_against: Option<PathBuf>,
+
+    /// Largest absolute difference allowed by --compare and --benchmark-compare, and in 8-bit levels by --error-map
+    #[arg(long, default_value_t = 0.0)]
+    tolerance: f64,
+
+    /// Reconstruct a texture from a previously baked forward image and its --lut
+    #[arg(long, requires = "lut", groups = ["single_file", "not_with_lut_sampling"])]
+    invert: Option<PathBuf>,
+
+    /// LUT used by --invert
+    #[arg(long, requires = "invert")]
+    lut: Option<PathBuf>,
+
+    /// Retry each output write up to N times, with exponential backoff, when it fails with a transient I/O error
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    write_retries: u32,
+
+    /// After writing, list every output file with its size and hash in a JSON manifest at PATH
+    #[arg(long, value_name = "PATH")]
+    manifest: Option<PathBuf>,
+
+    /// Hash for --manifest
+    #[arg(long, value_enum, default_value_t = HashAlgorithm::default())]
+    manifest_hash: HashAlgorithm,
+
+    /// With --manifest, where the files written are noted.
+    #[arg(skip)]
+    write_log: Option<WriteLog>,
+
+    /// The command line these options were parsed from, with those of
+    /// --config and the input spliced in, which each job of --jobs-from-file
+    /// adds its own options to.
+    #[arg(skip)]
+    command_line: Vec<OsString>,
+
+    /// Skip inputs whose outputs already exist and are newer than the input, weight map and config
+    #[arg(long)]
+    skip_if_newer: bool,
+
+    /// Record finished inputs in the output directory, and skip those already recorded on the next run
+    #[arg(long, requires = "batch")]
+    resume: bool,
+
+    /// Keep each channel's bake in DIR, and reuse it on later runs while the channel's values and the flags it depends on are unchanged
+    #[arg(long, value_name = "DIR", conflicts_with = "not_with_channel_cache", groups = ["not_with_atlas", "not_with_local_tiles", "not_with_colorspace", "not_with_weight_map", "not_with_histogram_samples"])]
+    channel_cache: Option<PathBuf>,
+
+    /// Write a JSON file next to the outputs describing how to sample them
+    #[arg(long, groups = ["not_with_format", "not_with_split_channels"])]
+    sidecar: bool,
+
+    /// Write each subpixel's rank in its sorted channel as little-endian u32s, pixel by pixel; needs --deterministic
+    #[arg(long, value_name = "PATH", requires = "deterministic", groups = ["single_file", "not_with_atlas", "not_with_local_tiles", "not_with_colorspace", "not_with_histogram_samples"])]
+    emit_ranks: Option<PathBuf>,
+
+    /// Write the constants a sampling shader needs (LUT size, per-channel mean and std, mean gradient) to a JSON file
+    #[arg(long, value_name = "JSON", group = "single_file")]
+    emit_uniforms: Option<PathBuf>,
+
+    /// Write a WGSL compute shader that runs this bake's transform on the GPU at load time, with the target and its parameters built in
+    #[arg(long, value_name = "WGSL", groups = ["single_file", "not_with_atlas", "not_with_local_tiles", "not_with_colorspace", "not_with_weight_map", "not_with_lut_sampling"])]
+    emit_compute: Option<PathBuf>,
+
+    /// Write a WGSL compute shader that reconstructs the source from this bake's forward image and LUT, sampling the LUT exactly as it was built: texel offset, linear filtering, knot map and mean gradient
+    #[arg(long, value_name = "WGSL", groups = ["single_file", "not_with_atlas", "not_with_local_tiles", "not_with_colorspace"])]
+    emit_reconstruction: Option<PathBuf>,
+
+    /// Also write a SIZE^3 3D LUT mapping forward-image colors back to source colors
+    #[arg(long, value_name = "SIZE", value_parser = clap::value_parser!(u32).range(2..=256), groups = ["single_file", "not_with_atlas", "not_with_local_tiles", "not_with_lut_sampling"])]
+    lut_3d: Option<u32>,
+
+    /// File format of --lut-3d: an Adobe .cube file, or a float TIFF of SIZE slices side by side
+    #[arg(long, value_enum, default_value_t = Lut3dFormat::Cube, requires = "lut_3d")]
+    lut_3d_format: Lut3dFormat,
+
+    /// Also write a 256x1 float TIFF holding the forward value of each 8-bit source level per channel, to apply the transform with one texture read; needs an 8-bit input
+    #[arg(long, conflicts_with = "mean_gradient", groups = ["single_file", "not_with_atlas", "not_with_local_tiles", "not_with_colorspace"])]
+    forward_lut: bool,
+
+    /// Also write an 8-bit PNG of the forward image, mean ± 3 std mapped to 0-255, for viewing
+    #[arg(long)]
+    preview_output: bool,
+
+    /// Tone curve the --preview-output PNG is encoded with for viewing, taking its stretched values as linear: linear, srgb, or gamma:X
+    #[arg(long, value_name = "CURVE", default_value_t = DisplayCurve::Linear, requires = "preview_output")]
+    display_curve: DisplayCurve,
+
+    /// Also write an 8-bit PNG marking pixels whose value is shared by at least MIN_SIZE subpixels of its channel, one color per combination of such tie groups
+    #[arg(long, value_name = "MIN_SIZE", value_parser = clap::value_parser!(u64).range(2..), groups = ["single_file", "not_with_atlas", "not_with_local_tiles", "not_with_colorspace"])]
+    debug_ties: Option<u64>,
+
+    /// Also write a PNG per transformed channel to DIR charting its input histogram, and its forward values against the target
+    #[arg(long, value_name = "DIR", groups = ["single_file", "not_with_atlas", "not_with_local_tiles", "not_with_colorspace"])]
+    plot_histograms: Option<PathBuf>,
+
+    /// Write the forward image as tiles of this size, with a JSON manifest, instead of one file
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "not_with_tile_output", groups = ["single_file", "not_with_atlas", "not_with_local_tiles", "not_with_format", "not_with_split_channels"])]
+    tile_output: Option<u32>,
+
+    /// Split the input into a grid of COLS,ROWS equal tiles, each with its own histogram and LUT row
+    #[arg(
+        long,
+        value_name = "COLS,ROWS",
+        value_parser = parse_grid, conflicts_with = "not_with_atlas")]
+    atlas: Option<(u32, u32)>,
+
+    /// Equalize each of a grid of COLS,ROWS equal tiles towards the target on its own, blending the tiles' mappings bilinearly so tile edges don't show
+    #[arg(
+        long,
+        value_name = "COLS,ROWS",
+        value_parser = parse_grid, conflicts_with = "not_with_local_tiles", group = "not_with_atlas")]
+    local_tiles: Option<(u32, u32)>,
+
+    /// Clip the histogram, or each local tile's, at this multiple of its mean count per level, at least 1, spreading the excess over all levels as CLAHE does
+    #[arg(long, conflicts_with = "plotting_position", groups = ["not_with_histogram_samples", "not_with_channel_cache"])]
+    clip_limit: Option<f64>,
+
+    /// Leave pixels with alpha below this, in [0, 1], out of the histogram
+    #[arg(long, groups = ["not_with_histogram_samples", "not_with_channel_cache"])]
+    alpha_cutoff: Option<f64>,
+
+    /// What to do with NaN and infinite samples of float inputs: fail, replace them with zero, or leave their pixels out of the histogram and keep them in the forward image
+    #[arg(long, value_enum, default_value_t = TransformConfig::default().nan_policy)]
+    nan_policy: NanPolicy,
+
+    /// Grayscale mask weighting each pixel's share of the histogram
+    #[arg(long, conflicts_with = "not_with_weight_map", groups = ["single_file", "not_with_atlas", "not_with_local_tiles", "not_with_colorspace"])]
+    weight_map: Option<PathBuf>,
+
+    /// Grayscale image of the input's size to use as its alpha, for --alpha-cutoff and --mask-mode, when opacity is a separate texture
+    #[arg(long, value_name = "PATH", group = "single_file")]
+    mask_file: Option<PathBuf>,
+
+    /// Don't rotate or flip inputs according to their EXIF orientation
+    #[arg(long)]
+    ignore_orientation: bool,
+
+    /// Read the input as headerless interleaved pixels, e.g. 1920,1080,rgba8; see the README for formats
+    #[arg(long, value_name = "WIDTH,HEIGHT,FORMAT", value_parser = parse_raw, group = "single_file")]
+    raw: Option<RawLayout>,
+
+    /// Resample every input to WIDTH,HEIGHT before it is transformed, so an asset library bakes at one size
+    #[arg(long, value_name = "WIDTH,HEIGHT", value_parser = parse_size)]
+    resize_input: Option<(u32, u32)>,
+
+    /// Filter --resize-input resamples with
+    #[arg(long, value_enum, default_value_t = ResizeFilter::default(), requires = "resize_input")]
+    resize_filter: ResizeFilter,
+
+    /// Decode inputs from a memory map of the file, reducing peak memory for very large inputs
+    #[arg(long)]
+    mmap: bool,
+
+    /// Decode striped and tiled TIFF inputs in one pass on one thread, instead of a strip or tile per --threads thread
+    #[arg(long)]
+    serial_decode: bool,
+
+    /// Print each channel's min, max, mean, median, std and percentiles
+    #[arg(long)]
+    channel_stats: bool,
+
+    /// Write the --channel-stats to a JSON file
+    #[arg(long, group = "single_file")]
+    channel_stats_json: Option<PathBuf>,
+
+    /// Print the correlation matrix of the input's transformed channels, and add it and their covariance to --channel-stats-json
+    #[arg(long, groups = ["single_file", "not_with_colorspace"])]
+    channel_correlation: bool,
+
+    /// Fail instead of warning about inputs and outputs that probably aren't what was intended
+    #[arg(long)]
+    strict: bool,
+
+    /// Warn when every channel is within this Kolmogorov-Smirnov distance of the target, as an already transformed input is; 0 disables
+    #[arg(long, default_value_t = 0.02)]
+    transformed_tolerance: f64,
+
+    /// Fail the bake if any LUT column ever decreases, which would mean a bug in how it was built
+    #[arg(long)]
+    validate_lut_monotonic: bool,
+
+    /// Open a window previewing the input, forward image and reconstruction, with sliders for mean, std and strength; needs the gui feature
+    #[arg(long, groups = ["single_file", "not_with_colorspace", "not_with_lut_sampling"])]
+    in_place_preview: bool,
+
+    /// Bake the input's luminance times alpha as a single-channel float mask and LUT, instead of its colors
+    #[arg(long, conflicts_with = "mode", groups = ["single_file", "not_with_colorspace", "not_with_format", "not_with_split_channels"])]
+    mask_mode: bool,
+
+    /// Only compute channel statistics, without transforming or writing outputs
+    #[arg(long, groups = ["single_file", "not_with_local_tiles", "not_with_colorspace"])]
+    stats_only: bool,
+
+    /// Write a classic histogram-equalized 8-bit PNG of the input instead of a forward image and LUT
+    #[arg(long, groups = ["single_file", "not_with_local_tiles", "not_with_colorspace", "not_with_format"])]
+    equalize_only: bool,
+
+    /// Write the forward image of the input under a LUT from an earlier bake, without ranking the input, so related textures share one mapping
+    #[arg(long, value_name = "PATH", conflicts_with = "confidence_alpha", groups = ["single_file", "not_with_atlas", "not_with_local_tiles", "not_with_colorspace", "not_with_weight_map", "not_with_format", "not_with_split_channels", "not_with_tile_output", "not_with_lut_sampling"])]
+    apply_lut: Option<PathBuf>,
+
+    /// Bake the input with both the optimized transform and a plain reference implementation, print how much faster the first was, and fail if they differ by more than --tolerance; writes nothing
+    #[arg(long, groups = ["single_file", "not_with_atlas", "not_with_local_tiles", "not_with_colorspace", "not_with_weight_map", "not_with_channel_cache"])]
+    benchmark_compare: bool,
+
+    /// Print which CPU path a bake with these settings would take for its quantiles on this machine, AVX or scalar and why, then exit; there is no GPU backend
+    #[arg(long)]
+    print_backend: bool,
+
+    /// Run every input check and warning without transforming or writing outputs, failing if any input fails
+    #[arg(long)]
+    validate_only: bool,
+
+    /// Write a Chrome trace of the run's phases, viewable in chrome://tracing or Perfetto
+    #[arg(long)]
+    profile: Option<PathBuf>,
+
+    /// Print a per-file table and totals after a batch: written, skipped, failed, megapixels and time
+    #[arg(long, requires = "batch")]
+    summary: bool,
+
+    /// Write the --summary report to a JSON file
+    #[arg(long, value_name = "JSON", requires = "batch")]
+    summary_json: Option<PathBuf>,
+
+    /// Emit batch progress as newline-delimited JSON events, to stdout in place of the progress lines or to the Unix socket at socket:PATH
+    #[arg(long, value_name = "stdout|socket:PATH", value_parser = EventTarget::parse, requires = "batch")]
+    events: Option<EventTarget>,
+
+    /// Write per-phase timings in milliseconds to a JSON file
+    #[arg(long, groups = ["single_file", "not_with_channel_cache"])]
+    report_timing_json: Option<PathBuf>,
+}
+
+impl Args {
+    fn read_options(&self) -> ReadOptions {
+        ReadOptions::new()
+            .apply_orientation(!self.ignore_orientation)
+            .memory_map(self.mmap)
+            .parallel_decode(!self.serial_decode)
+            .raw(self.raw)
+            .resize(self.resize_input)
+            .resize_filter(self.resize_filter)
+    }
+
+    fn write_options(&self) -> WriteOptions {
+        WriteOptions::new()
+            .retries(self.write_retries)
+            .log(self.write_log.clone())
+            .strict(self.strict)
+    }
+
+    fn transform_config(&self) -> TransformConfig {
+        TransformConfig::new()
+            .mean(self.mean)
+            .std(self.std)
+            .distribution(self.distribution[0])
+            .channel_distributions((self.distribution.len() > 1).then(|| self.distribution.clone()))
+            .target_cdf(self.target_cdf.clone())
+            .mean_gradient(self.mean_gradient)
+            .channels(self.channels)
+            .prefilter(self.prefilter)
+            .prefilter_sigma_spatial(self.prefilter_sigma_spatial)
+            .prefilter_sigma_range(self.prefilter_sigma_range)
+            .lut_size(self.lut_size)
+            .pad_lut(self.pad_lut)
+            .lut_for_linear_filtering(self.lut_for_linear_filtering)
+            .lut_texel_offset(self.lut_texel_offset)
+            .lut_interp(self.lut_interp)
+            .lut_endpoints(self.lut_endpoints)
+            .lut_sampling(self.lut_sampling)
+            .plotting_position(self.plotting_position)
+            .preserve_extremes(self.preserve_extremes)
+            .trim_tails(self.trim_tails)
+            .clip_limit(self.clip_limit)
+            .rounding(self.rounding)
+            .lut_channels(match self.colorspace {
+                Colorspace::Source => self.lut_channels.clone(),
+                Colorspace::HsvValue => Some(vec![Channel::B]),
             })
-            .collect();
-        input_orig_order.par_sort_unstable_by_key(|pixel| pixel.subpx_idx);
+            .lut_rows(self.lut_rows)
+            .lut_row_mode(self.lut_row_mode)
+            .alpha_cutoff(self.alpha_cutoff)
+            .nan_policy(self.nan_policy)
+            .shared_lut(self.shared_lut && !self.channels_independent_lut)
+            .median_target(self.median_target)
+            .post_contrast(self.post_contrast)
+            .signed_encoding(self.signed_encoding)
+            .deterministic(self.deterministic)
+            .histogram_samples(self.histogram_samples.map(|n| n as usize))
+            .seed(self.seed)
+            .fast_quantiles(self.fast_quantiles)
+            .quantile_precision(self.quantile_precision)
+            .profile_guided_lut(self.profile_guided_lut)
+    }
 
-        t.par_iter_mut().enumerate().for_each(|(i, subpx)| {
-            let sort_idx = input_orig_order[i].sort_idx;
-            let u = (sort_idx as f64 + 0.5) / (input_sorted.len() as f64);
-            let g = inv_cdf(u, GAUSSIAN_AVERAGE, *GAUSSIAN_STD);
-            *subpx = g as f32;
-        });
+    /// Files whose changes make existing outputs stale.
+    fn sources<'a>(&'a self, input_path: &'a Path) -> Vec<&'a Path> {
+        let mut sources = vec![input_path];
+        sources.extend(self.weight_map.as_deref());
+        sources.extend(self.mask_file.as_deref());
+        sources.extend(self.config.as_deref());
+        sources
+    }
 
-        t_inv.par_iter_mut().enumerate().for_each(|(i, subpx)| {
-            let g = (i as f64 + 0.5) / (input.width() as f64);
-            let u = cdf(g, GAUSSIAN_AVERAGE, *GAUSSIAN_STD);
-            let index = (u * input_sorted.len() as f64).floor() as usize;
-            let val = input_sorted[index].1;
-            *subpx = val;
-        });
-    });
+    /// Digest of everything that decides what a bake writes: the resolved
+    /// transform config and the options choosing the outputs' names, formats
+    /// and extra files, so `--resume` bakes again what an earlier run wrote
+    /// differently.
+    fn output_settings_digest(&self) -> String {
+        let config =
+            serde_json::to_string(&self.transform_config()).expect("configs always serialize");
+        let outputs = [
+            format!(
+                "{:?}",
+                (
+                    &self.img_prefix,
+                    &self.lut_prefix,
+                    &self.name_template,
+                    &self.pair_naming
+                )
+            ),
+            format!(
+                "{:?}",
+                (
+                    self.img_format,
+                    self.lut_format,
+                    self.format,
+                    self.lut_precision
+                )
+            ),
+            format!(
+                "{:?}",
+                (
+                    self.lut_pack,
+                    self.rgba_lut,
+                    &self.pack,
+                    self.split_channels
+                )
+            ),
+            format!(
+                "{:?}",
+                (
+                    &self.lut_variants,
+                    self.downsample_output,
+                    self.downsample_filter
+                )
+            ),
+            format!(
+                "{:?}",
+                (self.layout, self.tiff_tiled, self.dpi, self.tiff_endian)
+            ),
+            format!(
+                "{:?}",
+                (
+                    self.tile_output,
+                    self.atlas,
+                    self.local_tiles,
+                    self.mask_mode
+                )
+            ),
+            format!(
+                "{:?}",
+                (
+                    self.force_alpha,
+                    self.confidence_alpha,
+                    self.signed_encoding
+                )
+            ),
+            format!(
+                "{:?}",
+                (
+                    self.forward_lut,
+                    self.preview_output,
+                    self.display_curve,
+                    self.sidecar
+                )
+            ),
+        ];
+        let settings = format!("{}\n{}", config, outputs.join("\n"));
+        digest::hash_bytes(settings.as_bytes(), HashAlgorithm::Sha256)
+    }
+
+    /// The forward image and LUT files a bake writes to `out_dir`, one per
+    /// channel with `--split-channels`.
+    fn image_outputs(
+        &self,
+        out_dir: &Path,
+        img_file_name: &str,
+        lut_file_name: &str,
+    ) -> Vec<PathBuf> {
+        let names = if self.split_channels {
+            [img_file_name, lut_file_name]
+                .iter()
+                .flat_map(|name| split_file_names(name, self.channels.count()))
+                .collect()
+        } else {
+            let mut names = vec![img_file_name.to_string(), lut_file_name.to_string()];
+            names.extend(
+                self.lut_variants
+                    .iter()
+                    .map(|&variant| lut_variant_file_name(lut_file_name, variant)),
+            );
+            names
+        };
+        names.into_iter().map(|name| out_dir.join(name)).collect()
+    }
+
+    /// Output file name without its extension, from `--name-template` if given.
+    fn output_stem(&self, input_path: &Path, output: Output) -> Result<String, anyhow::Error> {
+        let (prefix, suffix) = match output {
+            Output::Img => (self.img_prefix.as_deref(), IMG_SUFFIX),
+            Output::Lut => (self.lut_prefix.as_deref(), LUT_SUFFIX),
+        };
+        match (&self.pair_naming, &self.name_template) {
+            (Some(pair), _) => pair.template(output).render(input_path, suffix, self),
+            (None, Some(template)) => template.render(input_path, suffix, self),
+            (None, None) => Ok(output_stem(input_path, prefix, suffix)),
+        }
+    }
+
+    fn output_file_name(
+        &self,
+        input_path: &Path,
+        output: Output,
+        extension: &str,
+    ) -> Result<String, anyhow::Error> {
+        Ok(format!(
+            "{}.{}",
+            self.output_stem(input_path, output)?,
+            extension
+        ))
+    }
+
+    fn img_encoder(&self) -> Box<dyn OutputEncoder> {
+        self.img_format.encoder(
+            self.ktx2_metadata("forward-image", "source texture"),
+            encode::Tiff {
+                byte_order: self.tiff_endian,
+                layout: self.layout,
+                tile_size: self.tiff_tiled,
+                description: Some(metadata::describe(&self.transform_config())),
+                dpi: self.dpi,
+                write_options: self.write_options(),
+            },
+        )
+    }
+
+    /// The pool outputs are encoded and written on, apart from the global
+    /// pool the transform runs on.
+    fn io_pool(&self) -> Result<rayon::ThreadPool, anyhow::Error> {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads_io.get())
+            .thread_name(|i| format!("precompute-io-{}", i))
+            .build()
+            .map_err(|e| anyhow::anyhow!("Couldn't start {} I/O threads: {}", self.threads_io, e))
+    }
+
+    /// Channels per texel of the LUT file.
+    fn lut_texel_channels(&self) -> usize {
+        if self.rgba_lut {
+            4
+        } else {
+            self.lut_pack.channels()
+        }
+    }
 
-    let (out_img, lut) = rayon::join(
-        || {
-            let mut out_img = vec![0.0; (input.width() * input.height() * 3) as usize];
-            out_img
-                .par_chunks_mut(3)
-                .enumerate()
-                .for_each(|(i, subpx)| {
-                    subpx[0] = t_r[i];
-                    subpx[1] = t_g[i];
-                    subpx[2] = t_b[i];
+    fn lut_encoder(&self) -> Box<dyn OutputEncoder> {
+        let domain = if self.lut_for_linear_filtering {
+            "gaussian value in [0, 1], from the first texel's center to the last's"
+        } else if self.lut_texel_offset == LutTexelOffset::Half {
+            "gaussian value in [0, 1], texel i centered at i / width"
+        } else if self.lut_sampling == LutSampling::Stratified {
+            "coordinate the knot map gives the gaussian value"
+        } else {
+            "gaussian value in [0, 1]"
+        };
+        let format = match self.lut_format {
+            LutFormat::Tiff => OutputFormat::Tiff,
+            LutFormat::Ktx2 => OutputFormat::Ktx2,
+            LutFormat::Cube => {
+                let config = self.transform_config();
+                return Box::new(encode::Cube {
+                    title: format!(
+                        "precompute inverse LUT, {} mean {} std {}",
+                        self.distribution_names(","),
+                        config.mean,
+                        config.std
+                    ),
+                    lut_for_linear_filtering: self.lut_for_linear_filtering,
+                    lut_texel_offset: self.lut_texel_offset,
+                    write_options: self.write_options(),
                 });
-            out_img
-        },
-        || {
-            let mut lut = vec![0; (input.width() * 3) as usize];
-            lut.par_chunks_mut(3).enumerate().for_each(|(i, subpx)| {
-                subpx[0] = t_inv_r[i];
-                subpx[1] = t_inv_g[i];
-                subpx[2] = t_inv_b[i];
-            });
-            lut
-        },
-    );
+            }
+        };
+        format.encoder(
+            self.ktx2_metadata("inverse-transfer-function", domain),
+            encode::Tiff {
+                byte_order: self.tiff_endian,
+                dpi: self.dpi,
+                write_options: self.write_options(),
+                ..Default::default()
+            },
+        )
+    }
 
-    (out_img, lut)
+    /// `inputs` cut down to `--limit`.
+    fn limit_inputs(&self, mut inputs: Vec<PathBuf>) -> Vec<PathBuf> {
+        if let Some(limit) = self.limit {
+            inputs.truncate(limit.get());
+        }
+        inputs
+    }
+
+    /// `--distribution`'s values, lowercase and joined by `separator`.
+    fn distribution_names(&self, separator: &str) -> String {
+        self.distribution
+            .iter()
+            .map(|kind| format!("{:?}", kind).to_lowercase())
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+
+    fn ktx2_metadata(&self, role: &str, domain: &str) -> Vec<(String, String)> {
+        let config = self.transform_config();
+        let (scale, offset) = config.forward_encoding();
+        [
+            ("precompute.role", role.to_string()),
+            ("precompute.domain", domain.to_string()),
+            (
+                "precompute.distribution",
+                match config.target_cdf {
+                    Some(_) => "tabulated".to_string(),
+                    None => self.distribution_names(","),
+                },
+            ),
+            ("precompute.mean", config.mean.to_string()),
+            ("precompute.std", config.std.to_string()),
+            (
+                "precompute.forward_encoding",
+                format!("{} {}", scale, offset),
+            ),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect()
+    }
 }
 
-fn write_rgb<T>(
-    path: &Path,
-    width: u32,
-    height: u32,
-    data: &[T::Inner],
-) -> Result<(), anyhow::Error>
-where
-    T: colortype::ColorType,
-    [T::Inner]: TiffValue,
-{
-    let mut file = std::fs::File::create(path)?;
-    TiffEncoder::new(&mut file)?
-        .write_image_with_compression::<T, _>(width, height, Packbits, data)?;
+const EXIT_CANCELLED: i32 = 130;
 
-    Ok(())
+/// Channels with fewer distinct values than this get a warning.
+const MIN_DISTINCT_VALUES: usize = 16;
+
+/// Every warning about a bake goes through here, so `--strict` can turn them
+/// all into errors.
+struct Diagnostics {
+    strict: bool,
 }
 
-fn path_directory(path: PathBuf) -> PathBuf {
-    if path.is_dir() {
-        path
-    } else {
-        path.parent()
-            .map(|x| x.to_path_buf())
-            .unwrap_or_else(|| PathBuf::from(""))
+impl Diagnostics {
+    fn new(strict: bool) -> Self {
+        Self { strict }
+    }
+
+    /// Prints `message`, or returns it as an error in strict mode.
+    fn warn(&self, message: String) -> Result<(), anyhow::Error> {
+        if self.strict {
+            Err(anyhow::anyhow!("{} (--strict)", message))
+        } else {
+            eprintln!("Warning: {}", message);
+            Ok(())
+        }
+    }
+}
+
+/// Runs `args`, then with `--manifest` lists what it wrote.
+fn run_with_manifest(mut args: Args, cancel: &CancelToken) -> Result<(), anyhow::Error> {
+    let Some(path) = args.manifest.clone() else {
+        return _main(args, cancel);
+    };
+    let log = WriteLog::new();
+    args.write_log = Some(log.clone());
+    let (algorithm, write_options) = (args.manifest_hash, args.write_options().log(None));
+    _main(args, cancel)?;
+    write_manifest(&path, algorithm, &log.paths(), &write_options)
+}
+
+fn main() {
+    let exe_name = std::env::args()
+        .next()
+        .unwrap_or_else(|| "precompute".to_string());
+    let cancel = CancelToken::new();
+    let handler_cancel = cancel.clone();
+    if let Err(e) = ctrlc::set_handler(move || {
+        if handler_cancel.is_cancelled() {
+            std::process::exit(EXIT_CANCELLED);
+        }
+        eprintln!("Cancelling, press Ctrl-C again to exit immediately...");
+        handler_cancel.cancel();
+    }) {
+        eprintln!(
+            "[{}] Warning: Could not install Ctrl-C handler: {}",
+            exe_name, e
+        );
+    }
+
+    let result = parse_args().and_then(|args| {
+        // Flushes the trace when dropped, so it must outlive the whole run.
+        let _profile = start_tracing(args.profile.as_deref(), args.verbose)?;
+        run_with_manifest(args, &cancel)
+    });
+    if let Err(e) = result {
+        if e.is::<Cancelled>() {
+            eprintln!("[{}] Cancelled", exe_name);
+            std::process::exit(EXIT_CANCELLED);
+        }
+        eprintln!("[{}] Precompute Error: {}", exe_name, e);
+        std::process::exit(1);
     }
 }
 
-fn cdf(x: f64, mu: f64, sigma: f64) -> f64 {
-    0.5 * (1.0 + erf((x - mu) / (sigma * 2.0_f64.sqrt())))
+/// Parses the command line, splicing options from `--config` in ahead of the
+/// real arguments so that anything given on the command line overrides them.
+fn parse_args() -> Result<Args, anyhow::Error> {
+    let command_line = command_line(std::env::args_os().collect())?;
+    let mut args = Args::from_arg_matches(&get_matches(&command_line))?;
+    args.command_line = command_line;
+    Ok(args)
 }
 
-fn inv_cdf(u: f64, mu: f64, sigma: f64) -> f64 {
-    sigma * 2.0_f64.sqrt() * inverf(2.0 * u - 1.0) + mu
+fn get_matches(argv: &[OsString]) -> clap::ArgMatches {
+    Args::command()
+        .try_get_matches_from(argv)
+        .unwrap_or_else(|err| narrow_conflict(err, argv).exit())
+}
+
+/// clap reports a conflict with a group by listing every member of the
+/// group; keep only the members that were actually passed.
+fn narrow_conflict(mut err: clap::Error, argv: &[OsString]) -> clap::Error {
+    use clap::error::{ContextKind, ContextValue, ErrorKind};
+
+    if err.kind() != ErrorKind::ArgumentConflict {
+        return err;
+    }
+    let given: Vec<&str> = argv
+        .iter()
+        .filter_map(|arg| arg.to_str())
+        .map(|arg| arg.split('=').next().unwrap_or(arg))
+        .collect();
+    if let Some(ContextValue::Strings(prior)) = err.get(ContextKind::PriorArg) {
+        let passed: Vec<String> = prior
+            .iter()
+            .filter(|arg| {
+                arg.split_whitespace()
+                    .next()
+                    .is_some_and(|flag| given.contains(&flag))
+            })
+            .cloned()
+            .collect();
+        match passed.len() {
+            0 => {}
+            1 => {
+                err.insert(
+                    ContextKind::PriorArg,
+                    ContextValue::String(passed[0].clone()),
+                );
+            }
+            _ => {
+                err.insert(ContextKind::PriorArg, ContextValue::Strings(passed));
+            }
+        }
+    }
+    err
+}
+
+/// `argv` with the options embedded in `--in-file` and then the `--config`
+/// file's, if any, inserted before the rest, so the config file overrides
+/// the embedded options and the command line overrides both.
+fn command_line(argv: Vec<OsString>) -> Result<Vec<OsString>, anyhow::Error> {
+    let matches = get_matches(&argv);
+    let embedded = match matches.get_one::<PathBuf>("in_file") {
+        Some(in_file) if !matches.get_flag("ignore_embedded_params") => embedded_args(in_file)?,
+        _ => Vec::new(),
+    };
+    if !embedded.is_empty() && !matches.get_flag("quiet") {
+        let shown: Vec<_> = embedded.iter().map(|arg| arg.to_string_lossy()).collect();
+        println!(
+            "Defaulting to the parameters embedded in the input: {}",
+            shown.join(" ")
+        );
+    }
+    let config = match matches.get_one::<PathBuf>("config") {
+        Some(config_path) => config_file_args(config_path)?,
+        None => Vec::new(),
+    };
+
+    let mut merged = argv[..1].to_vec();
+    merged.extend(embedded);
+    merged.extend(config);
+    merged.extend_from_slice(&argv[1..]);
+    Ok(merged)
+}
+
+/// Flags setting the parameters [`metadata::describe`] embedded in the TIFF
+/// at `path` where they differ from the defaults, so a re-bake of an
+/// `--invert` reconstruction repeats the bake it came from.
+fn embedded_args(path: &Path) -> Result<Vec<OsString>, anyhow::Error> {
+    let Some(config) = metadata::read_config(path)? else {
+        return Ok(Vec::new());
+    };
+    let (serde_json::Value::Object(mut fields), serde_json::Value::Object(defaults)) = (
+        serde_json::to_value(&config)?,
+        serde_json::to_value(TransformConfig::default())?,
+    ) else {
+        unreachable!("a config serializes to an object");
+    };
+    // --distribution takes the per-channel list too.
+    if let Some(distributions) = fields
+        .remove("channel_distributions")
+        .filter(|value| !value.is_null())
+    {
+        fields.insert("distribution".to_string(), distributions);
+    }
+    let command = Args::command();
+    let mut table = toml::Table::new();
+    for (key, value) in fields {
+        let is_arg = command
+            .get_arguments()
+            .any(|arg| arg.get_id() == key.as_str());
+        if value.is_null() || defaults.get(&key) == Some(&value) || !is_arg {
+            continue;
+        }
+        table.insert(key, toml::Value::try_from(value)?);
+    }
+    table_args(table, path, &[])
+}
+
+fn read_target_cdf(path: &str) -> Result<Tabulated, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    Tabulated::from_csv(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn parse_mean_gradient(value: &str) -> Result<MeanGradient, String> {
+    let usage = || format!("expected START,END,AXIS with AXIS x or y, got {:?}", value);
+    let [start, end, axis] = value.split(',').collect::<Vec<_>>()[..] else {
+        return Err(usage());
+    };
+    let mean = |n: &str| n.trim().parse::<f64>().map_err(|_| usage());
+    Ok(MeanGradient {
+        start: mean(start)?,
+        end: mean(end)?,
+        axis: clap::ValueEnum::from_str(axis.trim(), true).map_err(|_| usage())?,
+    })
+}
+
+fn parse_sigma_from_range(value: &str) -> Result<Gaussian, String> {
+    let usage = || {
+        format!("expected LOW,HIGH,PERCENT with LOW below HIGH and PERCENT strictly between 0 and 100, got {:?}", value)
+    };
+    let [low, high, percent] = value.split(',').collect::<Vec<_>>()[..] else {
+        return Err(usage());
+    };
+    let number = |n: &str| n.trim().parse::<f64>().map_err(|_| usage());
+    let (low, high, percent) = (number(low)?, number(high)?, number(percent)?);
+    if !(low < high && high.is_finite() && low.is_finite() && percent > 0.0 && percent < 100.0) {
+        return Err(usage());
+    }
+    Ok(Gaussian::from_range(low, high, percent / 100.0))
+}
+
+fn parse_tiff_tile_size(value: &str) -> Result<u32, String> {
+    match value.trim().parse() {
+        Ok(size) if size > 0 && size % 16 == 0 => Ok(size),
+        _ => Err(format!(
+            "expected a positive multiple of 16, got {:?}",
+            value
+        )),
+    }
+}
+
+fn parse_raw(value: &str) -> Result<RawLayout, String> {
+    let usage = || {
+        format!(
+            "expected WIDTH,HEIGHT,FORMAT with FORMAT one of gray8, rgb8, rgba8, rgb16, rgba16, rgb32f or rgba32f, got {:?}",
+            value
+        )
+    };
+    let [width, height, format] = value.split(',').collect::<Vec<_>>()[..] else {
+        return Err(usage());
+    };
+    let dimension = |n: &str| n.trim().parse::<u32>().map_err(|_| usage());
+    Ok(RawLayout {
+        width: dimension(width)?,
+        height: dimension(height)?,
+        format: <RawFormat as clap::ValueEnum>::from_str(format.trim(), true)
+            .map_err(|_| usage())?,
+    })
+}
+
+fn parse_grid(value: &str) -> Result<(u32, u32), String> {
+    parse_pair(value, "COLS,ROWS")
+}
+
+fn parse_size(value: &str) -> Result<(u32, u32), String> {
+    parse_pair(value, "WIDTH,HEIGHT")
+}
+
+/// Two positive integers separated by a comma, named `names` in errors.
+fn parse_pair(value: &str, names: &str) -> Result<(u32, u32), String> {
+    let parse = |n: &str| match n.trim().parse() {
+        Ok(0) | Err(_) => Err(format!(
+            "expected {} of positive integers, got {:?}",
+            names, value
+        )),
+        Ok(n) => Ok(n),
+    };
+    match value.split_once(',') {
+        Some((first, second)) => Ok((parse(first)?, parse(second)?)),
+        None => Err(format!("expected {}, got {:?}", names, value)),
+    }
+}
+
+/// A parsed `--name-template`. `{date}` is substituted while parsing, so
+/// every output of a run carries the same date.
+#[derive(Clone, Debug)]
+struct NameTemplate(Vec<NamePart>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum NamePart {
+    Literal(String),
+    Input,
+    Suffix,
+    Width,
+    Height,
+    Mean,
+    Std,
+    Distribution,
+}
+
+impl NameTemplate {
+    fn parse(value: &str) -> Result<Self, String> {
+        let template = Self::parse_parts(value)?;
+        if !template.0.contains(&NamePart::Suffix) {
+            return Err(
+                "needs {suffix}, otherwise the image and LUT get the same name".to_string(),
+            );
+        }
+        Ok(template)
+    }
+
+    /// A template that may leave `{suffix}` out, for one output's name.
+    fn parse_parts(value: &str) -> Result<Self, String> {
+        let date = utc_date();
+        let mut parts = Vec::new();
+        let mut rest = value;
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                return Err("unmatched '}'".to_string());
+            }
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| "unmatched '{'".to_string())?;
+            parts.push(NamePart::Literal(rest[..start].to_string()));
+            parts.push(match &rest[start + 1..end] {
+                "input" => NamePart::Input,
+                "suffix" => NamePart::Suffix,
+                "width" => NamePart::Width,
+                "height" => NamePart::Height,
+                "mean" => NamePart::Mean,
+                "std" => NamePart::Std,
+                "distribution" => NamePart::Distribution,
+                "date" => NamePart::Literal(date.clone()),
+                name => {
+                    return Err(format!(
+                        "unknown placeholder {{{}}}, expected one of {{input}}, {{suffix}}, \
+                         {{width}}, {{height}}, {{mean}}, {{std}}, {{distribution}}, {{date}}",
+                        name
+                    ))
+                }
+            });
+            rest = &rest[end + 1..];
+        }
+        parts.push(NamePart::Literal(rest.to_string()));
+        if parts
+            .iter()
+            .any(|part| matches!(part, NamePart::Literal(text) if text.contains(['/', '\\'])))
+        {
+            return Err("names a file, it can't contain a path separator".to_string());
+        }
+        Ok(Self(parts))
+    }
+
+    fn render(
+        &self,
+        input_path: &Path,
+        suffix: &str,
+        args: &Args,
+    ) -> Result<String, anyhow::Error> {
+        let dimensions = if self
+            .0
+            .iter()
+            .any(|part| matches!(part, NamePart::Width | NamePart::Height))
+        {
+            read::input_dimensions(input_path, &args.read_options())?
+        } else {
+            (0, 0)
+        };
+        let mut name = String::new();
+        for part in &self.0 {
+            match part {
+                NamePart::Literal(text) => name.push_str(text),
+                NamePart::Input => name.push_str(&input_stem(input_path)),
+                NamePart::Suffix => name.push_str(suffix),
+                NamePart::Width => name.push_str(&dimensions.0.to_string()),
+                NamePart::Height => name.push_str(&dimensions.1.to_string()),
+                NamePart::Mean => name.push_str(&args.mean.to_string()),
+                NamePart::Std => name.push_str(&args.std.to_string()),
+                NamePart::Distribution => name.push_str(&args.distribution_names("-")),
+            }
+        }
+        Ok(name)
+    }
+}
+
+/// A parsed `--pair-naming`: the templates of the forward image's and the
+/// LUT's names, from a preset or given as `IMAGE,LUT`.
+#[derive(Clone, Debug)]
+struct PairNaming {
+    img: NameTemplate,
+    lut: NameTemplate,
+}
+
+/// `--pair-naming` presets, with the templates of the image's and LUT's names.
+const PAIR_NAMING_PRESETS: [(&str, &str, &str); 3] = [
+    ("dotted", "{input}.gauss", "{input}.gauss.lut"),
+    // Unreal's asset naming: a T_ prefix on textures and PascalCase suffixes.
+    ("unreal", "T_{input}_Gauss", "T_{input}_GaussLUT"),
+    // Godot's snake_case file names, also common in Unity projects.
+    ("snake", "{input}_gauss", "{input}_gauss_lut"),
+];
+
+impl PairNaming {
+    fn parse(value: &str) -> Result<Self, String> {
+        let (img, lut) = match PAIR_NAMING_PRESETS
+            .iter()
+            .find(|(name, _, _)| *name == value)
+        {
+            Some(&(_, img, lut)) => (img, lut),
+            None => value.split_once(',').ok_or_else(|| {
+                format!(
+                    "expected dotted, unreal, snake or IMAGE,LUT templates, got {:?}",
+                    value
+                )
+            })?,
+        };
+        let (img, lut) = (
+            NameTemplate::parse_parts(img)?,
+            NameTemplate::parse_parts(lut)?,
+        );
+        if img.0 == lut.0 {
+            return Err(
+                "the image and LUT templates are the same, so both outputs would get one name"
+                    .to_string(),
+            );
+        }
+        Ok(Self { img, lut })
+    }
+
+    fn template(&self, output: Output) -> &NameTemplate {
+        match output {
+            Output::Img => &self.img,
+            Output::Lut => &self.lut,
+        }
+    }
+}
+
+/// Today's date in UTC as `YYYY-MM-DD`.
+fn utc_date() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    // Days since 1970-01-01 to a civil date, after Howard Hinnant's `civil_from_days`.
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn config_file_args(path: &Path) -> Result<Vec<OsString>, anyhow::Error> {
+    let table: toml::Table = toml::from_str(&std::fs::read_to_string(path)?)?;
+    table_args(table, path, &["config", "help", "version"])
+}
+
+/// Command-line flags setting the options `table` holds, keyed by their
+/// argument ids, from the file at `path`. Keys in `excluded` are rejected.
+fn table_args(
+    table: toml::Table,
+    path: &Path,
+    excluded: &[&str],
+) -> Result<Vec<OsString>, anyhow::Error> {
+    let command = Args::command();
+    let mut file_args = Vec::new();
+    for (key, value) in table {
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == key.as_str() && arg.get_long().is_some())
+            .filter(|arg| !excluded.contains(&arg.get_id().as_str()))
+            .ok_or_else(|| anyhow::anyhow!("Unknown key {:?} in {:?}", key, path))?;
+        let flag = format!("--{}", arg.get_long().unwrap_or_default());
+
+        let value = match value {
+            toml::Value::String(s) => s,
+            toml::Value::Array(items) => items
+                .iter()
+                .map(|item| match item {
+                    toml::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(","),
+            toml::Value::Boolean(enabled) if !arg.get_action().takes_values() => {
+                if enabled {
+                    file_args.push(flag.into());
+                }
+                continue;
+            }
+            other => other.to_string(),
+        };
+        file_args.push(format!("{}={}", flag, value).into());
+    }
+    Ok(file_args)
+}
+
+fn _main(mut args: Args, cancel: &CancelToken) -> Result<(), anyhow::Error> {
+    if let Some(SubCommand::Completions { shell }) = args.command {
+        return print_completions(shell);
+    }
+    if let Some(SubCommand::MergeLuts {
+        red,
+        green,
+        blue,
+        output,
+    }) = &args.command
+    {
+        return merge_luts([red, green, blue], output, &args);
+    }
+    if let Some(SubCommand::Describe { file }) = &args.command {
+        return describe(file, &mut std::io::stdout().lock());
+    }
+    if let Some(SubCommand::SelfTest) = args.command {
+        return run_self_test();
+    }
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.get())
+            .build_global()
+            .map_err(|e| anyhow::anyhow!("Couldn't start {} compute threads: {}", threads, e))?;
+    }
+    if args.layout == Layout::Planar && args.img_format != OutputFormat::Tiff {
+        return Err(anyhow::anyhow!("--layout planar needs --img-format tiff"));
+    }
+    if args.dpi.is_some()
+        && args.img_format != OutputFormat::Tiff
+        && args.lut_format != LutFormat::Tiff
+    {
+        return Err(anyhow::anyhow!(
+            "--dpi needs --img-format tiff or --lut-format tiff"
+        ));
+    }
+    if args.tiff_tiled.is_some() && args.img_format != OutputFormat::Tiff {
+        return Err(anyhow::anyhow!("--tiff-tiled needs --img-format tiff"));
+    }
+    if let Some(target) = args.sigma_from_range {
+        if args
+            .distribution
+            .iter()
+            .any(|&kind| kind != DistributionKind::Gaussian)
+        {
+            return Err(anyhow::anyhow!(
+                "--sigma-from-range needs a gaussian --distribution"
+            ));
+        }
+        (args.mean, args.std) = (target.mean, target.std);
+    }
+    if args.events == Some(EventTarget::Stdout) {
+        if args.summary {
+            return Err(anyhow::anyhow!(
+                "--events stdout can't be combined with --summary, which prints to stdout too"
+            ));
+        }
+        // Events on stdout replace the progress lines.
+        args.quiet = true;
+    }
+    if args.lut_sampling == LutSampling::Stratified && args.lut_format == LutFormat::Cube {
+        return Err(anyhow::anyhow!("--lut-sampling stratified can't be combined with --lut-format cube, whose texels are evenly spaced"));
+    }
+    if args.lut_pack == LutPack::RgBa && args.lut_format != LutFormat::Tiff {
+        return Err(anyhow::anyhow!("--lut-pack rg-ba needs --lut-format tiff"));
+    }
+    if args.rgba_lut {
+        if args.lut_format != LutFormat::Tiff {
+            return Err(anyhow::anyhow!("--rgba-lut needs --lut-format tiff"));
+        }
+        if args.lut_pack != LutPack::Rgb {
+            return Err(anyhow::anyhow!(
+                "--rgba-lut can't be combined with --lut-pack"
+            ));
+        }
+    }
+    if let Some(alpha) = args.force_alpha {
+        if !alpha.is_finite() {
+            return Err(anyhow::anyhow!(
+                "--force-alpha must be finite, got {}",
+                alpha
+            ));
+        }
+        if args.img_format != OutputFormat::Tiff {
+            return Err(anyhow::anyhow!("--force-alpha needs --img-format tiff"));
+        }
+    }
+    if args.confidence_alpha && args.img_format != OutputFormat::Tiff {
+        return Err(anyhow::anyhow!(
+            "--confidence-alpha needs --img-format tiff"
+        ));
+    }
+    if let Some(pack) = &args.pack {
+        if args.img_format != OutputFormat::Tiff {
+            return Err(anyhow::anyhow!("--pack needs --img-format tiff"));
+        }
+        pack.validate(&args.transform_config())?;
+    }
+    if args.split_channels {
+        if args.img_format != OutputFormat::Tiff || args.lut_format != LutFormat::Tiff {
+            return Err(anyhow::anyhow!("--split-channels needs TIFF outputs"));
+        }
+        if args.lut_pack != LutPack::Rgb {
+            return Err(anyhow::anyhow!(
+                "--split-channels can't be combined with --lut-pack"
+            ));
+        }
+    }
+    if args.print_backend {
+        println!("GPU backend: none, bakes always run on the CPU");
+        println!(
+            "Forward quantiles: {}",
+            args.transform_config().quantile_backend()
+        );
+        return Ok(());
+    }
+    if let Some(jobs_path) = &args.jobs_from_file {
+        // Not a clap conflict, since each job's own command line has both.
+        if args.in_file.is_some() {
+            return Err(anyhow::anyhow!(
+                "--jobs-from-file can't be combined with --in-file"
+            ));
+        }
+        return run_jobs(
+            jobs_path,
+            &args.command_line,
+            args.write_log.as_ref(),
+            cancel,
+        );
+    }
+    let out_dir = path_directory(args.out_dir.clone());
+    if args.validate_only {
+        let inputs = match (&args.in_dir, &args.input_list, &args.in_file) {
+            (Some(in_dir), _, _) => args.limit_inputs(discover_inputs(in_dir)?),
+            (None, Some(input_list), _) => args.limit_inputs(read_input_list(input_list)?),
+            (None, None, Some(in_file)) => vec![in_file.clone()],
+            (None, None, None) => {
+                return Err(anyhow::anyhow!(
+                    "--validate-only needs --in-file, --in-dir or --input-list"
+                ))
+            }
+        };
+        return run_validate(&args, &inputs);
+    }
+    if let Some(in_dir) = &args.in_dir {
+        let inputs = exclude_own_outputs(&args, in_dir, &out_dir, discover_inputs(in_dir)?);
+        return run_batch(&args, args.limit_inputs(inputs), &out_dir, cancel);
+    }
+    if let Some(input_list) = &args.input_list {
+        return run_batch(
+            &args,
+            args.limit_inputs(read_input_list(input_list)?),
+            &out_dir,
+            cancel,
+        );
+    }
+
+    if let (Some(gaussian_path), Some(lut_path)) = (&args.invert, &args.lut) {
+        return run_invert(gaussian_path, lut_path, &out_dir, &args);
+    }
+
+    let cwd = std::env::current_dir().unwrap_or(PathBuf::from("."));
+    let input_path = args.in_file.clone().map(Ok).unwrap_or_else(|| {
+        FileDialog::new()
+            .set_title("Select input texture")
+            .set_directory(cwd)
+            .pick_file()
+            .ok_or(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "No input file specified",
+            ))
+    })?;
+    if args.in_place_preview {
+        return run_preview_window(&input_path, &args);
+    }
+    let img_encoder = args.img_encoder();
+    let lut_encoder = args.lut_encoder();
+    let img_stem = args.output_stem(&input_path, Output::Img)?;
+    let img_file_name = format!("{}.{}", img_stem, img_encoder.extension());
+    let lut_file_name = args.output_file_name(&input_path, Output::Lut, lut_encoder.extension())?;
+
+    if args.stats_only {
+        let pages = read::read_input(&input_path, &args.read_options())?;
+        let input = read::stack_pages(&pages)?;
+        let histogram = args.transform_config().histogram(&input)?;
+        let correlation = match args.channel_correlation {
+            true => Some(args.transform_config().channel_correlation(&input)?),
+            false => None,
+        };
+        return report_channel_stats(&input_path, &histogram, None, correlation.as_ref(), &args);
+    }
+    if args.equalize_only {
+        return run_equalize(&input_path, &args, &out_dir);
+    }
+    if let Some(lut_path) = &args.apply_lut {
+        let img_path = out_dir.join(&img_file_name);
+        return run_apply_lut(
+            &input_path,
+            lut_path,
+            &args,
+            &img_path,
+            img_encoder.as_ref(),
+        );
+    }
+    if args.benchmark_compare {
+        return run_benchmark_compare(&input_path, &args);
+    }
+
+    let npz_file_name = format!("{}.npz", img_stem);
+    if args.skip_if_newer {
+        let outputs = match (args.format, args.tile_output) {
+            (Some(CombinedFormat::Npz), _) => vec![out_dir.join(&npz_file_name)],
+            (None, Some(_)) => vec![
+                out_dir.join(format!("{}.tiles.json", img_stem)),
+                out_dir.join(&lut_file_name),
+            ],
+            (None, None) => args.image_outputs(&out_dir, &img_file_name, &lut_file_name),
+        };
+        if outputs_up_to_date(&args.sources(&input_path), &outputs) {
+            if !args.quiet {
+                println!("Skipping {:?}, outputs are up to date", input_path);
+            }
+            return Ok(());
+        }
+    }
+
+    if args.mask_mode
+        || read::is_gray_f32_tiff(&input_path)
+        || read::is_gray_int32_tiff(&input_path)
+    {
+        if args.format.is_some() {
+            return Err(anyhow::anyhow!("--format doesn't support height inputs"));
+        }
+        if args.split_channels {
+            return Err(anyhow::anyhow!(
+                "--split-channels doesn't support height inputs"
+            ));
+        }
+        let img_path = out_dir.join(&img_file_name);
+        return run_height(
+            &args,
+            &input_path,
+            &img_path,
+            &out_dir.join(&lut_file_name),
+            cancel,
+        );
+    }
+
+    let baked = bake(&input_path, &args, &Diagnostics::new(args.strict), cancel)?;
+
+    // Read before writing: the baseline is usually the previous bake at the
+    // path this one is about to overwrite.
+    let baseline = match &args.diff_against {
+        Some(path) => Some((path, read_baseline(&baked, path)?)),
+        None => None,
+    };
+    let write_start = Instant::now();
+    let write_span = tracing::info_span!("write");
+    let _write = write_span.enter();
+    let io_pool = args.io_pool()?;
+    let (res1, res2) = match (args.format, args.tile_output) {
+        (Some(CombinedFormat::Npz), _) => {
+            if !args.quiet {
+                println!(
+                    "Writing output to {} in directory {:?}",
+                    npz_file_name, out_dir
+                );
+            }
+            (
+                io_pool.install(|| write_npz(&baked, &out_dir.join(&npz_file_name), &args)),
+                Ok(()),
+            )
+        }
+        (None, Some(tile_size)) => {
+            if !args.quiet {
+                println!(
+                    "Writing {}x{} tiles of {} and {} in directory {:?}",
+                    tile_size, tile_size, img_stem, lut_file_name, out_dir
+                );
+            }
+            io_pool.join(
+                || {
+                    write_tiles(
+                        &baked,
+                        &out_dir,
+                        &img_stem,
+                        &lut_file_name,
+                        tile_size,
+                        img_encoder.as_ref(),
+                        &args,
+                    )
+                },
+                || {
+                    write_lut(
+                        &baked,
+                        &out_dir.join(&lut_file_name),
+                        lut_encoder.as_ref(),
+                        &args,
+                    )
+                },
+            )
+        }
+        (None, None) if args.split_channels => {
+            if !args.quiet {
+                println!(
+                    "Writing each channel of {} and {} in directory {:?}",
+                    img_file_name, lut_file_name, out_dir
+                );
+            }
+            let written = io_pool.install(|| {
+                write_split_channels(&baked, &out_dir, &img_file_name, &lut_file_name, &args)
+            });
+            (written, Ok(()))
+        }
+        (None, None) => {
+            if !args.quiet {
+                println!(
+                    "Writing output to {} and {} in directory {:?}",
+                    img_file_name, lut_file_name, out_dir
+                );
+            }
+            io_pool.join(
+                || match &args.pack {
+                    Some(pack) => write_packed(
+                        &baked,
+                        pack,
+                        &input_path,
+                        &out_dir.join(&img_file_name),
+                        img_encoder.as_ref(),
+                        &args,
+                    ),
+                    None => write_img(
+                        &baked,
+                        &out_dir.join(&img_file_name),
+                        img_encoder.as_ref(),
+                        &args,
+                    ),
+                },
+                || {
+                    write_lut(
+                        &baked,
+                        &out_dir.join(&lut_file_name),
+                        lut_encoder.as_ref(),
+                        &args,
+                    )
+                },
+            )
+        }
+    };
+    res1.and(res2)?;
+    if args.preview_output {
+        write_preview(
+            &baked,
+            &args,
+            &out_dir.join(format!("{}-preview.png", img_stem)),
+        )?;
+    }
+    if let Some((columns, rows)) = args.atlas.or(args.local_tiles) {
+        write_atlas_manifest(
+            &baked,
+            columns,
+            rows,
+            &out_dir.join(format!("{}.atlas.json", img_stem)),
+            &img_file_name,
+            &lut_file_name,
+            &args,
+        )?;
+    }
+    if args.sidecar {
+        let image = match args.tile_output {
+            Some(_) => format!("{}.tiles.json", img_stem),
+            None => img_file_name,
+        };
+        write_sidecar(
+            &baked,
+            &args,
+            &input_path,
+            &out_dir,
+            &img_stem,
+            &image,
+            &lut_file_name,
+        )?;
+    }
+    if let Some(uniforms_path) = &args.emit_uniforms {
+        write_uniforms(&baked, &args, uniforms_path)?;
+    }
+    if let Some(compute_path) = &args.emit_compute {
+        let shader = compute::compute_shader(&args.transform_config())?;
+        encode::write_atomic(compute_path, &args.write_options(), |file| {
+            Ok(file.write_all(shader.as_bytes())?)
+        })?;
+    }
+    if let Some(reconstruction_path) = &args.emit_reconstruction {
+        let shader = LutSampler::new(&baked, &args.transform_config())?.shader()?;
+        encode::write_atomic(reconstruction_path, &args.write_options(), |file| {
+            Ok(file.write_all(shader.as_bytes())?)
+        })?;
+    }
+    if let Some(size) = args.lut_3d {
+        write_lut_3d(&baked, &args, size, &out_dir, &img_stem)?;
+    }
+    if args.forward_lut {
+        write_forward_lut(
+            &baked,
+            &args,
+            &out_dir.join(format!("{}-forward-lut.tif", img_stem)),
+        )?;
+    }
+    if let Some(ranks_path) = &args.emit_ranks {
+        write_ranks(&input_path, &args, ranks_path, cancel)?;
+    }
+    if let Some(min_size) = args.debug_ties {
+        write_tie_overlay(
+            &input_path,
+            &args,
+            &out_dir.join(format!("{}-ties.png", img_stem)),
+            min_size,
+        )?;
+    }
+    if let Some(plot_dir) = &args.plot_histograms {
+        write_histogram_plots(&baked, &args, plot_dir, &img_stem)?;
+    }
+    let write_time = write_start.elapsed();
+
+    if args.channel_stats || args.channel_stats_json.is_some() || args.channel_correlation {
+        let correlation = match args.channel_correlation {
+            true => {
+                let pages = read::read_input(&input_path, &args.read_options())?;
+                Some(
+                    args.transform_config()
+                        .channel_correlation(&read::stack_pages(&pages)?)?,
+                )
+            }
+            false => None,
+        };
+        report_channel_stats(
+            &input_path,
+            &baked.histogram,
+            Some(&baked.exclusions),
+            correlation.as_ref(),
+            &args,
+        )?;
+    }
+
+    if let Some(report_path) = &args.report_timing_json {
+        write_timing_report(report_path, &baked, write_time)?;
+    }
+
+    if let Some((baseline_path, baseline)) = &baseline {
+        write_diff(
+            &baked,
+            &args,
+            baseline_path,
+            baseline,
+            &out_dir.join(format!("{}-diff.png", img_stem)),
+        )?;
+    }
+    match &args.compare {
+        Some(reference) => compare_to_reference(&baked, reference, args.tolerance),
+        None => Ok(()),
+    }
+}
+
+/// Alpha cutoff that leaves out the pixels of an indexed input's transparent
+/// palette entry, which decode at alpha 0, and keeps every other entry, even
+/// the most transparent.
+const TRANSPARENT_INDEX_CUTOFF: f64 = 0.5 / 255.0;
+
+/// Keys a job of `--jobs-from-file` can't override.
+const JOB_EXCLUDED_KEYS: [&str; 8] = [
+    "config",
+    "help",
+    "version",
+    "jobs_from_file",
+    "in_file",
+    "in_dir",
+    "input_list",
+    "threads",
+];
+
+/// Bakes every `[[job]]` of the TOML, or `.json`, file at `jobs_path`, each
+/// on its own with the command line's options and the job's keys on top.
+/// `input` is the job's input, relative to the file; every other key is an
+/// option as in `--config`, added to `command_line`. A failed job is
+/// reported and the rest still run.
+fn run_jobs(
+    jobs_path: &Path,
+    command_line: &[OsString],
+    write_log: Option<&WriteLog>,
+    cancel: &CancelToken,
+) -> Result<(), anyhow::Error> {
+    let text = std::fs::read_to_string(jobs_path)
+        .map_err(|e| anyhow::anyhow!("Couldn't read {:?}: {}", jobs_path, e))?;
+    let file: toml::Table = match jobs_path.extension().and_then(OsStr::to_str) {
+        Some("json") => serde_json::from_str(&text)?,
+        _ => toml::from_str(&text)?,
+    };
+    let jobs = match file.get("job") {
+        Some(toml::Value::Array(jobs)) if file.len() == 1 => jobs,
+        _ => {
+            return Err(anyhow::anyhow!(
+                "{:?} needs a list of jobs under `job` and nothing else",
+                jobs_path
+            ))
+        }
+    };
+    let base_dir = jobs_path.parent().unwrap_or(Path::new(""));
+    let mut argvs = Vec::with_capacity(jobs.len());
+    for (i, job) in jobs.iter().enumerate() {
+        let Some(mut job) = job.as_table().cloned() else {
+            return Err(anyhow::anyhow!(
+                "Job {} of {:?} isn't a table",
+                i + 1,
+                jobs_path
+            ));
+        };
+        let Some(toml::Value::String(input)) = job.remove("input") else {
+            return Err(anyhow::anyhow!(
+                "Job {} of {:?} needs an `input` path",
+                i + 1,
+                jobs_path
+            ));
+        };
+        let mut argv = command_line.to_vec();
+        argv.extend(table_args(job, jobs_path, &JOB_EXCLUDED_KEYS)?);
+        argv.push("--in-file".into());
+        argv.push(base_dir.join(input).into());
+        argvs.push(argv);
+    }
+
+    let mut failed = 0;
+    for (i, argv) in argvs.into_iter().enumerate() {
+        let input = argv.last().cloned().unwrap_or_default();
+        let mut args = Args::from_arg_matches(&Args::command().try_get_matches_from(argv)?)?;
+        // The outer run already set these up.
+        (
+            args.jobs_from_file,
+            args.threads,
+            args.profile,
+            args.manifest,
+        ) = (None, None, None, None);
+        args.write_log = write_log.cloned();
+        if !args.quiet {
+            println!("[{}/{}] {:?}", i + 1, jobs.len(), input);
+        }
+        match _main(args, cancel) {
+            Ok(()) => {}
+            Err(e) if e.is::<Cancelled>() => return Err(e),
+            Err(e) => {
+                eprintln!("Failed {:?}: {}", input, e);
+                failed += 1;
+            }
+        }
+    }
+    println!("Ran {} of {} jobs", jobs.len() - failed, jobs.len());
+    if failed > 0 {
+        Err(anyhow::anyhow!("{} jobs failed", failed))
+    } else {
+        Ok(())
+    }
+}
+
+/// Bakes a single-channel float or 32-bit integer heightmap, or with
+/// `--mask-mode` the input's alpha-weighted luminance, into a one-channel
+/// forward image and a one-channel LUT of its exact values, in its own type.
+fn run_height(
+    args: &Args,
+    input_path: &Path,
+    img_path: &Path,
+    lut_path: &Path,
+    cancel: &CancelToken,
+) -> Result<(), anyhow::Error> {
+    if args.img_format != OutputFormat::Tiff || args.lut_format != LutFormat::Tiff {
+        return Err(anyhow::anyhow!("Single-channel outputs need TIFF outputs"));
+    }
+    for (unsupported, flag) in [
+        (args.raw.is_some() && !args.mask_mode, "--raw"),
+        (args.weight_map.is_some(), "--weight-map"),
+        (args.atlas.is_some(), "--atlas"),
+        (args.local_tiles.is_some(), "--local-tiles"),
+        (args.bit_exact_float, "--bit-exact-float"),
+        (args.tile_output.is_some(), "--tile-output"),
+        (args.max_lut_error.is_some(), "--max-lut-error"),
+        (args.error_map.is_some(), "--error-map"),
+        (args.sidecar, "--sidecar"),
+        (args.preview_output, "--preview-output"),
+        (args.debug_ties.is_some(), "--debug-ties"),
+        (args.plot_histograms.is_some(), "--plot-histograms"),
+        (args.force_alpha.is_some(), "--force-alpha"),
+        (args.confidence_alpha, "--confidence-alpha"),
+        (args.pack.is_some(), "--pack"),
+        (
+            args.resize_input.is_some() && !args.mask_mode,
+            "--resize-input",
+        ),
+        (args.emit_uniforms.is_some(), "--emit-uniforms"),
+        (args.emit_compute.is_some(), "--emit-compute"),
+        (args.emit_reconstruction.is_some(), "--emit-reconstruction"),
+        (!args.lut_variants.is_empty(), "--lut-variants"),
+        (args.lut_3d.is_some(), "--lut-3d"),
+        (args.forward_lut, "--forward-lut"),
+        (args.downsample_output.is_some(), "--downsample-output"),
+        (args.profile_guided_lut, "--profile-guided-lut"),
+        (args.lut_sampling != LutSampling::Uniform, "--lut-sampling"),
+        (args.channel_cache.is_some(), "--channel-cache"),
+        (args.lut_pack != LutPack::Rgb, "--lut-pack"),
+        (args.rgba_lut, "--rgba-lut"),
+        (args.colorspace != Colorspace::Source, "--colorspace"),
+        (args.compare.is_some(), "--compare"),
+        (args.diff_against.is_some(), "--diff-against"),
+        (
+            args.channel_stats || args.channel_stats_json.is_some(),
+            "--channel-stats",
+        ),
+        (args.channel_correlation, "--channel-correlation"),
+        (args.report_timing_json.is_some(), "--report-timing-json"),
+    ] {
+        if unsupported {
+            return Err(anyhow::anyhow!(
+                "{} isn't supported for single-channel outputs",
+                flag
+            ));
+        }
+    }
+    if !args.mask_mode && read::is_gray_int32_tiff(input_path) {
+        return match read::read_gray_int32(input_path)? {
+            GrayInt32::U32(heights) => bake_heights(
+                args, input_path, "heights", heights, img_path, lut_path, cancel,
+            ),
+            GrayInt32::I32(heights) => bake_heights(
+                args, input_path, "heights", heights, img_path, lut_path, cancel,
+            ),
+        };
+    }
+    let (heights, kind) = if args.mask_mode {
+        // Page by page rather than stacked, which would quantize float pages.
+        let pages = read::read_input(input_path, &args.read_options())?;
+        let pages = apply_mask_file(pages, args, &Diagnostics::new(args.strict))?;
+        let width = pages[0].width();
+        if let Some(page) = pages.iter().find(|page| page.width() != width) {
+            return Err(anyhow::anyhow!(
+                "All pages must share a width, found {} and {}",
+                width,
+                page.width()
+            ));
+        }
+        let mask = read::TiffImage {
+            width,
+            height: pages.iter().map(|page| page.height()).sum(),
+            data: pages.iter().flat_map(height::weighted_luminance).collect(),
+        };
+        (mask, "mask")
+    } else {
+        (read::read_gray_f32(input_path)?, "heights")
+    };
+    bake_heights(args, input_path, kind, heights, img_path, lut_path, cancel)
+}
+
+/// The part of [`run_height`] after reading: transforms `heights`, described
+/// as `kind` in progress output, and writes both outputs.
+fn bake_heights<T: HeightSample>(
+    args: &Args,
+    input_path: &Path,
+    kind: &str,
+    heights: read::TiffImage<T>,
+    img_path: &Path,
+    lut_path: &Path,
+    cancel: &CancelToken,
+) -> Result<(), anyhow::Error> {
+    if !args.quiet {
+        println!("Processing {} of {:?}...", kind, input_path);
+    }
+    let start = Instant::now();
+    let result =
+        args.transform_config()
+            .run_height(&heights.data, heights.width, heights.height, cancel)?;
+    if args.validate_lut_monotonic {
+        result.check_lut_monotonic()?;
+    }
+    if !args.quiet {
+        println!("Finished processing. Took {:?}", start.elapsed());
+        println!("Writing output to {:?} and {:?}", img_path, lut_path);
+    }
+    let img = OutputData::F32(&result.image);
+    args.img_encoder()
+        .encode(img_path, result.width, result.height, 1, img)?;
+    args.lut_encoder().encode(
+        lut_path,
+        result.lut_width,
+        1,
+        1,
+        T::output_data(&result.lut),
+    )
+}
+
+#[cfg(feature = "completions")]
+fn print_completions(shell: CompletionShell) -> Result<(), anyhow::Error> {
+    use std::io::Write;
+
+    // clap_complete panics on a write error, so the script is written in one
+    // go, and a reader that closed the pipe early, like `head`, isn't an error.
+    match std::io::stdout()
+        .lock()
+        .write_all(&completion_script(shell))
+    {
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(feature = "completions")]
+fn completion_script(shell: CompletionShell) -> Vec<u8> {
+    use clap_complete::Shell;
+
+    let shell = match shell {
+        CompletionShell::Bash => Shell::Bash,
+        CompletionShell::Zsh => Shell::Zsh,
+        CompletionShell::Fish => Shell::Fish,
+        CompletionShell::Powershell => Shell::PowerShell,
+        CompletionShell::Elvish => Shell::Elvish,
+    };
+    let mut command = Args::command();
+    let name = command.get_name().to_string();
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut command, name, &mut script);
+    script
+}
+
+#[cfg(not(feature = "completions"))]
+fn print_completions(_shell: CompletionShell) -> Result<(), anyhow::Error> {
+    Err(anyhow::anyhow!(
+        "completions needs a build with the completions feature: cargo build --release --features completions"
+    ))
+}
+
+#[cfg(feature = "gui")]
+fn run_preview_window(input_path: &Path, args: &Args) -> Result<(), anyhow::Error> {
+    let pages = read::read_input(input_path, &args.read_options())?;
+    gui::run(&pages[0], args.transform_config())
+}
+
+#[cfg(not(feature = "gui"))]
+fn run_preview_window(_input_path: &Path, _args: &Args) -> Result<(), anyhow::Error> {
+    Err(anyhow::anyhow!(
+        "--in-place-preview needs a build with the gui feature: cargo build --release --features gui"
+    ))
+}
+
+/// Reconstructs `gaussian_path` through `lut_path`, comparing against
+/// `--in-file` if given.
+fn run_invert(
+    gaussian_path: &Path,
+    lut_path: &Path,
+    out_dir: &Path,
+    args: &Args,
+) -> Result<(), anyhow::Error> {
+    let original_path = args.in_file.as_deref();
+    let mut gaussian = read::read_rgb_f32(gaussian_path)?;
+    if let Some(gradient) = &args.mean_gradient {
+        let (width, height) = (gaussian.width, gaussian.height);
+        gradient.shift(&mut gaussian.data, width, &[height], 3, -1.0);
+    }
+    let lut = read_lut(lut_path)?;
+    let config = TransformConfig::new()
+        .lut_for_linear_filtering(args.lut_for_linear_filtering)
+        .lut_texel_offset(args.lut_texel_offset);
+    let coordinates = inverse::lut_coordinates(&gaussian.data, config.lut_coordinate(lut.width));
+    let reconstructed = inverse::apply_inverse_lut(
+        &coordinates,
+        &lut.data,
+        lut.width,
+        gaussian.width,
+        gaussian.height,
+        LutFilter::Nearest,
+    )?;
+    let reconstructed = match args.colorspace {
+        Colorspace::Source => reconstructed,
+        // Hue and saturation come from the forward image, not the identity columns.
+        Colorspace::HsvValue => gaussian
+            .data
+            .chunks_exact(3)
+            .zip(reconstructed.chunks_exact(3))
+            .flat_map(|(stored, rgb)| {
+                hsv::hsv_to_rgb([stored[0], stored[1], rgb[2] as f32 / 255.0])
+                    .map(|c| (c * 255.0).round() as u8)
+            })
+            .collect(),
+    };
+
+    // The reconstruction carries the bake's parameters on to a re-bake of it.
+    let baked_with = match metadata::read_config(gaussian_path)? {
+        Some(config) => Some(config),
+        None => Sidecar::find(gaussian_path)?.map(|sidecar| sidecar.config),
+    };
+    let encoder = encode::Tiff {
+        byte_order: args.tiff_endian,
+        description: baked_with.as_ref().map(metadata::describe),
+        dpi: args.dpi,
+        ..Default::default()
+    };
+    let out_path = out_dir.join(output_file_name(
+        gaussian_path,
+        None,
+        RECONSTRUCTED_SUFFIX,
+        encoder.extension(),
+    ));
+    println!("Writing reconstruction to {:?}", out_path);
+    encoder.encode(
+        &out_path,
+        gaussian.width,
+        gaussian.height,
+        3,
+        OutputData::U8(&reconstructed),
+    )?;
+
+    if let Some(original_path) = original_path {
+        let original = image::open(original_path)?.to_rgb8();
+        if original.dimensions() != (gaussian.width, gaussian.height) {
+            return Err(anyhow::anyhow!(
+                "Original {:?} is {}x{}, expected {}x{}",
+                original_path,
+                original.width(),
+                original.height(),
+                gaussian.width,
+                gaussian.height
+            ));
+        }
+        let diff = compare::difference(original.as_raw(), &reconstructed);
+        println!(
+            "Compared against {:?}: max abs difference {}, mean abs difference {:.4}",
+            original_path, diff.max_abs, diff.mean_abs
+        );
+    }
+    Ok(())
+}
+
+/// Reads an 8-bit RGB LUT, or a float one from `--lut-precision f32` rounded
+/// back to source values.
+fn read_lut(lut_path: &Path) -> Result<read::TiffImage<u8>, anyhow::Error> {
+    read::read_rgb_u8(lut_path).or_else(|_| {
+        let lut = read::read_rgb_f32(lut_path)?;
+        let OutputSamples::U8(data) = OutputData::F32(&lut.data).convert(SampleType::U8)? else {
+            unreachable!("converted to 8 bits");
+        };
+        Ok(read::TiffImage {
+            width: lut.width,
+            height: lut.height,
+            data,
+        })
+    })
+}
+
+/// Writes `<input>-equalized.png`, `input` histogram-equalized by
+/// [`TransformConfig::equalize`].
+fn run_equalize(input_path: &Path, args: &Args, out_dir: &Path) -> Result<(), anyhow::Error> {
+    let pages = read_checked(input_path, args, &Diagnostics::new(args.strict))?;
+    let input = read::stack_pages(&pages)?;
+    let equalized = args.transform_config().equalize(&input)?;
+    let out_path = out_dir.join(output_file_name(input_path, None, EQUALIZED_SUFFIX, "png"));
+    if !args.quiet {
+        println!("Writing equalized image to {:?}", out_path);
+    }
+    encode::write_atomic(&out_path, &args.write_options(), |file| {
+        image::codecs::png::PngEncoder::new(std::io::BufWriter::new(file)).write_image(
+            &equalized,
+            input.width(),
+            input.height(),
+            image::ColorType::Rgb8,
+        )?;
+        Ok(())
+    })
+}
+
+/// Writes the forward image of `input_path` under the LUT at `lut_path`,
+/// mapped by [`TransformConfig::apply_lut`]. The LUT is left where it is.
+fn run_apply_lut(
+    input_path: &Path,
+    lut_path: &Path,
+    args: &Args,
+    img_path: &Path,
+    encoder: &dyn OutputEncoder,
+) -> Result<(), anyhow::Error> {
+    let lut = read_lut(lut_path)?;
+    let pages = read_checked(input_path, args, &Diagnostics::new(args.strict))?;
+    let input = read::stack_pages(&pages)?;
+    let image = args
+        .transform_config()
+        .apply_lut(&input, &lut.data, lut.width)?;
+    if !args.quiet {
+        println!(
+            "Writing output to {:?}, forward through {:?}",
+            img_path, lut_path
+        );
+    }
+    let page_heights: Vec<u32> = pages.iter().map(|page| page.height()).collect();
+    let alpha = args.force_alpha.map(ForwardAlpha::Constant);
+    write_forward(
+        encoder,
+        img_path,
+        input.width(),
+        &page_heights,
+        &image,
+        alpha,
+    )
+}
+
+/// Bakes `input_path` with [`TransformConfig::run`] and with
+/// [`TransformConfig::run_reference`], prints both times and how far apart
+/// the outputs are, and fails if the forward images differ by more than
+/// `--tolerance` or any LUT texel differs. Without `--deterministic`, equal
+/// subpixels can be ranked in any order, so each channel's forward values
+/// are compared sorted.
+fn run_benchmark_compare(input_path: &Path, args: &Args) -> Result<(), anyhow::Error> {
+    let pages = read_checked(input_path, args, &Diagnostics::new(args.strict))?;
+    let input = read::stack_pages(&pages)?;
+    let config = args.transform_config();
+    let start = Instant::now();
+    let optimized = config.run(&input)?;
+    let optimized_time = start.elapsed();
+    let start = Instant::now();
+    let reference = config.run_reference(&input)?;
+    let reference_time = start.elapsed();
+
+    let channels = config.channels.count();
+    let column = |image: &[f32], channel: usize| -> Vec<f32> {
+        let mut values: Vec<f32> = image.iter().skip(channel).step_by(3).copied().collect();
+        if !config.deterministic {
+            values.sort_by(f32::total_cmp);
+        }
+        values
+    };
+    let forward_difference = (0..channels)
+        .map(|channel| {
+            let (a, b) = (
+                column(&optimized.image, channel),
+                column(&reference.image, channel),
+            );
+            a.iter()
+                .zip(&b)
+                .map(|(a, b)| (a - b).abs() as f64)
+                .fold(0.0, f64::max)
+        })
+        .fold(0.0, f64::max);
+    let lut_texels = optimized.lut_width as usize * channels;
+    let lut_differences = optimized
+        .lut
+        .chunks_exact(3)
+        .zip(reference.lut.chunks_exact(3))
+        .map(|(a, b)| (0..channels).filter(|&c| a[c] != b[c]).count())
+        .sum::<usize>();
+    println!(
+        "Optimized: {:?}, reference: {:?}, {:.1}x faster",
+        optimized_time,
+        reference_time,
+        reference_time.as_secs_f64() / optimized_time.as_secs_f64()
+    );
+    println!(
+        "Forward image: largest difference {:e}{}",
+        forward_difference,
+        if config.deterministic {
+            ""
+        } else {
+            ", comparing each channel sorted"
+        }
+    );
+    println!("LUT: {} of {} texels differ", lut_differences, lut_texels);
+    if forward_difference > args.tolerance || lut_differences > 0 {
+        return Err(anyhow::anyhow!(
+            "The optimized bake doesn't match the reference within --tolerance {}",
+            args.tolerance
+        ));
+    }
+    Ok(())
+}
+
+/// Prints and/or writes the stats requested by --channel-stats, --channel-stats-json and --stats-only,
+/// with how many subpixels were left out of each channel when a bake counted them.
+fn report_channel_stats(
+    input_path: &Path,
+    histogram: &Histogram<u8>,
+    exclusions: Option<&[Exclusions]>,
+    correlation: Option<&ChannelCorrelation>,
+    args: &Args,
+) -> Result<(), anyhow::Error> {
+    if args.channel_stats || args.stats_only {
+        print_channel_stats(input_path, histogram, exclusions, args);
+    }
+    if let Some(correlation) = correlation {
+        print_channel_correlation(input_path, correlation);
+    }
+    if let Some(json_path) = &args.channel_stats_json {
+        let channels = channel_labels(histogram, args)
+            .into_iter()
+            .enumerate()
+            .map(|(channel, label)| {
+                let mut stats = serde_json::to_value(histogram.channel_stats(channel))?;
+                stats["channel"] = label.into();
+                if let Some(exclusions) = exclusions {
+                    stats["excluded"] = serde_json::to_value(exclusions[channel])?;
+                }
+                Ok(stats)
+            })
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        let mut report = serde_json::json!({
+            "input": input_path,
+            "channels": channels,
+        });
+        if let Some(correlation) = correlation {
+            report["correlation"] = serde_json::to_value(&correlation.correlation)?;
+            report["covariance"] = serde_json::to_value(&correlation.covariance)?;
+        }
+        std::fs::write(json_path, serde_json::to_string_pretty(&report)?)?;
+    }
+    Ok(())
+}
+
+fn print_channel_stats(
+    input_path: &Path,
+    histogram: &Histogram<u8>,
+    exclusions: Option<&[Exclusions]>,
+    args: &Args,
+) {
+    println!("Channel stats for {:?}:", input_path);
+    println!(
+        "{:>8} {:>6} {:>6} {:>8} {:>8} {:>8} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6}",
+        "channel", "min", "max", "mean", "median", "std", "p1", "p5", "p25", "p75", "p95", "p99"
+    );
+    let labels = channel_labels(histogram, args);
+    for (channel, label) in labels.iter().enumerate() {
+        let stats = histogram.channel_stats(channel);
+        println!(
+            "{:>8} {:>6} {:>6} {:>8.3} {:>8.1} {:>8.3} {:>6.1} {:>6.1} {:>6.1} {:>6.1} {:>6.1} {:>6.1}",
+            label,
+            stats.min,
+            stats.max,
+            stats.mean,
+            stats.median,
+            stats.std,
+            stats.p1,
+            stats.p5,
+            stats.p25,
+            stats.p75,
+            stats.p95,
+            stats.p99
+        );
+    }
+    for (label, excluded) in labels.iter().zip(exclusions.into_iter().flatten()) {
+        if excluded.total() > 0 {
+            println!(
+                "{:>8} left out {} subpixels: {} non-finite, {} below the alpha cutoff, {} without weight, {} trimmed",
+                label,
+                excluded.total(),
+                excluded.non_finite,
+                excluded.alpha_cutoff,
+                excluded.zero_weight,
+                excluded.trimmed
+            );
+        }
+    }
+}
+
+fn print_channel_correlation(input_path: &Path, correlation: &ChannelCorrelation) {
+    let labels = &["R", "G", "B"][..correlation.correlation.len()];
+    println!("Channel correlation for {:?}:", input_path);
+    println!(
+        "{:>8}{}",
+        "",
+        labels
+            .iter()
+            .map(|label| format!(" {:>8}", label))
+            .collect::<String>()
+    );
+    for (label, row) in labels.iter().zip(&correlation.correlation) {
+        println!(
+            "{:>8}{}",
+            label,
+            row.iter()
+                .map(|r| format!(" {:>8.3}", r))
+                .collect::<String>()
+        );
+    }
+}
+
+/// Names a histogram's channels: "R", "G", "B", or the combined channels with --shared-lut.
+fn channel_labels(histogram: &Histogram<u8>, args: &Args) -> Vec<&'static str> {
+    let labels = ["R", "G", "B"];
+    if args.colorspace == Colorspace::HsvValue {
+        vec!["V"]
+    } else if histogram.channel_count() == 1 && args.transform_config().shared_lut {
+        vec![match args.channels {
+            Channels::Rgb => "RGB",
+            Channels::Rg => "RG",
+        }]
+    } else {
+        labels[..histogram.channel_count()].to_vec()
+    }
+}
+
+/// Installs a subscriber printing this crate's warnings to stderr, and its
+/// debug events too with `verbose`, and recording spans as a Chrome trace at
+/// `profile`, written out when the guard drops.
+fn start_tracing(
+    profile: Option<&Path>,
+    verbose: bool,
+) -> Result<Option<tracing_chrome::FlushGuard>, anyhow::Error> {
+    use tracing_subscriber::filter::{LevelFilter, Targets};
+    use tracing_subscriber::prelude::*;
+
+    let (chrome_layer, guard) = match profile {
+        Some(path) => {
+            let (layer, guard) = chrome_trace(path)?;
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+    let level = if verbose {
+        LevelFilter::DEBUG
+    } else {
+        LevelFilter::WARN
+    };
+    let log_layer = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::IsTerminal::is_terminal(&std::io::stderr()))
+        .without_time()
+        .with_filter(Targets::new().with_target("precompute", level));
+    tracing_subscriber::registry()
+        .with(chrome_layer)
+        .with(log_layer)
+        .try_init()?;
+    Ok(guard)
+}
+
+/// The `--profile` layer writing a Chrome trace to `path`, flushed when the
+/// guard is dropped.
+fn chrome_trace<S>(
+    path: &Path,
+) -> Result<(tracing_chrome::ChromeLayer<S>, tracing_chrome::FlushGuard), anyhow::Error>
+where
+    S: tracing::Subscriber
+        + for<'span> tracing_subscriber::registry::LookupSpan<'span>
+        + Send
+        + Sync,
+{
+    Ok(tracing_chrome::ChromeLayerBuilder::new()
+        .writer(std::fs::File::create(path)?)
+        .include_args(true)
+        .build())
+}
+
+fn write_timing_report(
+    path: &Path,
+    result: &GaussianResult,
+    write_time: Duration,
+) -> Result<(), anyhow::Error> {
+    let report = timing_report(result, write_time);
+    std::fs::write(path, serde_json::to_string_pretty(&report)?)?;
+    Ok(())
+}
+
+/// The `--report-timing-json` object for `result`, written in `write_time`.
+fn timing_report(result: &GaussianResult, write_time: Duration) -> serde_json::Value {
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    serde_json::json!({
+        "width": result.width,
+        "height": result.height,
+        "threads": rayon::current_num_threads(),
+        "phases_ms": {
+            "extract": ms(result.timings.extract),
+            "sort": ms(result.timings.sort),
+            "forward": ms(result.timings.forward),
+            "inverse_lut": ms(result.timings.inverse_lut),
+            "write": ms(write_time),
+        },
+    })
+}
+
+fn compare_to_reference(
+    baked: &GaussianResult,
+    reference: &Path,
+    tolerance: f64,
+) -> Result<(), anyhow::Error> {
+    let reference_img = read::read_rgb_f32(reference)?;
+    if (reference_img.width, reference_img.height) != (baked.width, baked.height) {
+        return Err(anyhow::anyhow!(
+            "Reference {:?} is {}x{}, expected {}x{}",
+            reference,
+            reference_img.width,
+            reference_img.height,
+            baked.width,
+            baked.height
+        ));
+    }
+
+    let diff = compare::difference(&baked.image, &reference_img.data);
+    println!(
+        "Compared against {:?}: max abs difference {:e}, mean abs difference {:e}",
+        reference, diff.max_abs, diff.mean_abs
+    );
+    if diff.max_abs > tolerance {
+        return Err(anyhow::anyhow!(
+            "Difference {:e} exceeds tolerance {:e}",
+            diff.max_abs,
+            tolerance
+        ));
+    }
+    Ok(())
+}
+
+/// Reads the float forward image `--diff-against` names, which must be the
+/// size of `baked`'s.
+fn read_baseline(
+    baked: &GaussianResult,
+    baseline: &Path,
+) -> Result<read::TiffImage<f32>, anyhow::Error> {
+    let baseline_img = read::read_rgb_f32(baseline)
+        .map_err(|e| anyhow::anyhow!("Couldn't read baseline {:?}: {}", baseline, e))?;
+    if (baseline_img.width, baseline_img.height) != (baked.width, baked.height) {
+        return Err(anyhow::anyhow!(
+            "Baseline {:?} is {}x{}, expected {}x{}",
+            baseline,
+            baseline_img.width,
+            baseline_img.height,
+            baked.width,
+            baked.height
+        ));
+    }
+    Ok(baseline_img)
+}
+
+/// Writes [`compare::diff_heat_map`] of the forward image against `baseline`,
+/// read from `baseline_path`, to `path`, with `--std` in stored units as its
+/// full scale.
+fn write_diff(
+    baked: &GaussianResult,
+    args: &Args,
+    baseline_path: &Path,
+    baseline: &read::TiffImage<f32>,
+    path: &Path,
+) -> Result<(), anyhow::Error> {
+    let (scale, _) = args.transform_config().forward_encoding();
+    let full_scale = scale * args.std;
+    let heat = compare::diff_heat_map(&baked.image, &baseline.data, 3, full_scale);
+    if !args.quiet {
+        let diff = compare::difference(&baked.image, &baseline.data);
+        println!(
+            "Diff against {:?}: max abs difference {:.4}, mean {:.4}, yellow at {:.4}",
+            baseline_path, diff.max_abs, diff.mean_abs, full_scale
+        );
+    }
+    encode::write_atomic(path, &args.write_options(), |file| {
+        image::codecs::png::PngEncoder::new(std::io::BufWriter::new(file)).write_image(
+            &heat,
+            baked.width,
+            baked.height,
+            image::ColorType::Rgb8,
+        )?;
+        Ok(())
+    })
+}
+
+/// Writes [`compare::threshold_map`] of where `result`'s LUT reconstructs
+/// `source` off by more than `--tolerance` levels to `path`.
+fn write_error_map(
+    config: &TransformConfig,
+    source: &DynamicImage,
+    result: &GaussianResult,
+    args: &Args,
+    path: &Path,
+) -> Result<(), anyhow::Error> {
+    let errors = config.reconstruction_errors(source, result);
+    let map = compare::threshold_map(&errors, source.to_luma8().as_raw(), args.tolerance);
+    if !args.quiet {
+        let over = errors
+            .iter()
+            .filter(|&&e| e as f64 > args.tolerance)
+            .count();
+        println!(
+            "Writing error map to {:?}: {} of {} pixels off by more than {} levels, at most {}",
+            path,
+            over,
+            errors.len(),
+            args.tolerance,
+            errors.iter().max().unwrap_or(&0)
+        );
+    }
+    encode::write_atomic(path, &args.write_options(), |file| {
+        image::codecs::png::PngEncoder::new(std::io::BufWriter::new(file)).write_image(
+            &map,
+            source.width(),
+            source.height(),
+            image::ColorType::Rgb8,
+        )?;
+        Ok(())
+    })
+}
+
+/// Writes the forward image, as RGBA with every alpha `alpha` if given.
+fn write_img(
+    result: &GaussianResult,
+    path: &Path,
+    encoder: &dyn OutputEncoder,
+    args: &Args,
+) -> Result<(), anyhow::Error> {
+    let alpha = match args.confidence_alpha {
+        true => Some(ForwardAlpha::Pixels(
+            args.transform_config().forward_density(result)?,
+        )),
+        false => args.force_alpha.map(ForwardAlpha::Constant),
+    };
+    write_forward(
+        encoder,
+        path,
+        result.width,
+        &result.page_heights,
+        &result.image,
+        alpha,
+    )
+}
+
+/// Writes the forward image with its channels routed by `pack`, rereading
+/// the source from `input_path` for any channels taken from it.
+fn write_packed(
+    result: &GaussianResult,
+    pack: &PackSpec,
+    input_path: &Path,
+    path: &Path,
+    encoder: &dyn OutputEncoder,
+    args: &Args,
+) -> Result<(), anyhow::Error> {
+    let source = read::stack_pages(&read::read_input(input_path, &args.read_options())?)?;
+    let image = pack.pack(&args.transform_config(), result, &source)?;
+    encoder.encode_pages(
+        path,
+        result.width,
+        &result.page_heights,
+        pack.channels.len(),
+        OutputData::F32(&image),
+    )
+}
+
+/// What `--force-alpha` or `--confidence-alpha` append to each pixel of the
+/// forward image.
+enum ForwardAlpha {
+    Constant(f32),
+    /// One alpha per pixel, in row-major order.
+    Pixels(Vec<f32>),
+}
+
+/// Writes the interleaved RGB forward image `image`, with `alpha` appended to
+/// each pixel if given.
+fn write_forward(
+    encoder: &dyn OutputEncoder,
+    path: &Path,
+    width: u32,
+    page_heights: &[u32],
+    image: &[f32],
+    alpha: Option<ForwardAlpha>,
+) -> Result<(), anyhow::Error> {
+    let Some(alpha) = alpha else {
+        return encoder.encode_pages(path, width, page_heights, 3, OutputData::F32(image));
+    };
+    let rgba: Vec<f32> = image
+        .chunks_exact(3)
+        .enumerate()
+        .flat_map(|(i, rgb)| {
+            let alpha = match &alpha {
+                ForwardAlpha::Constant(alpha) => *alpha,
+                ForwardAlpha::Pixels(alphas) => alphas[i],
+            };
+            [rgb[0], rgb[1], rgb[2], alpha]
+        })
+        .collect();
+    encoder.encode_pages(path, width, page_heights, 4, OutputData::F32(&rgba))
+}
+
+/// Writes the forward image as an 8-bit RGB PNG for eyeballing, with
+/// `mean ± 3 std` stretched over the full range, the rest clipped, and the
+/// result encoded with `--display-curve`.
+fn write_preview(result: &GaussianResult, args: &Args, path: &Path) -> Result<(), anyhow::Error> {
+    let (scale, offset) = args.transform_config().forward_encoding();
+    let low = offset + scale * (args.mean - 3.0 * args.std);
+    let range = scale * 6.0 * args.std;
+    let encoded: Vec<f32> = result
+        .image
+        .iter()
+        .map(|&v| args.display_curve.encode(((v as f64 - low) / range) as f32))
+        .collect();
+    let OutputSamples::U8(data) = OutputData::F32(&encoded).convert(SampleType::U8)? else {
+        unreachable!("converted to 8 bits");
+    };
+    encode::write_atomic(path, &args.write_options(), |file| {
+        image::codecs::png::PngEncoder::new(std::io::BufWriter::new(file)).write_image(
+            &data,
+            result.width,
+            result.height,
+            image::ColorType::Rgb8,
+        )?;
+        Ok(())
+    })
+}
+
+/// Writes `<stem>-lut3d.cube` or `<stem>-lut3d.tif`, see [`Lut3d`].
+fn write_lut_3d(
+    result: &GaussianResult,
+    args: &Args,
+    size: u32,
+    out_dir: &Path,
+    stem: &str,
+) -> Result<(), anyhow::Error> {
+    let lut = Lut3d::new(result, &args.transform_config(), size)?;
+    match args.lut_3d_format {
+        Lut3dFormat::Cube => {
+            let path = out_dir.join(format!("{}-lut3d.cube", stem));
+            encode::write_atomic(&path, &args.write_options(), |file| {
+                let mut writer = std::io::BufWriter::new(file);
+                lut.write_cube(&mut writer, stem)?;
+                writer.flush()?;
+                Ok(())
+            })
+        }
+        Lut3dFormat::Tiff => {
+            let (width, height, image) = lut.tiled();
+            let encoder = encode::Tiff {
+                byte_order: args.tiff_endian,
+                dpi: args.dpi,
+                ..Default::default()
+            };
+            let path = out_dir.join(format!("{}-lut3d.tif", stem));
+            encoder.encode(&path, width, height, 3, OutputData::F32(&image))
+        }
+    }
+}
+
+/// Writes the `--forward-lut` of `result` to `path`.
+fn write_forward_lut(
+    result: &GaussianResult,
+    args: &Args,
+    path: &Path,
+) -> Result<(), anyhow::Error> {
+    let lut = args.transform_config().forward_lut(result)?;
+    let encoder = encode::Tiff {
+        byte_order: args.tiff_endian,
+        dpi: args.dpi,
+        ..Default::default()
+    };
+    encoder.encode(path, 256, 1, 3, OutputData::F32(&lut))
+}
+
+/// Writes `<stem>.meta.json` describing the outputs named `image` and `lut`
+/// baked from `input_path`.
+fn write_sidecar(
+    result: &GaussianResult,
+    args: &Args,
+    input_path: &Path,
+    out_dir: &Path,
+    stem: &str,
+    image: &str,
+    lut: &str,
+) -> Result<(), anyhow::Error> {
+    let mut sidecar = Sidecar::new(result, &args.transform_config(), image, lut);
+    let source = read::stack_pages(&read::read_input(input_path, &args.read_options())?)?;
+    sidecar.source_phash = Some(format!("{:016x}", phash::phash(&source)));
+    match args.lut_precision {
+        LutPrecision::U8 => {}
+        LutPrecision::U16 => sidecar.lut_depth = 16,
+        LutPrecision::F32 => sidecar.lut_depth = 32,
+    }
+    if args.lut_pack == LutPack::RgBa || args.rgba_lut {
+        sidecar.lut_channel_order = "RGBA".to_string();
+    }
+    if args.colorspace == Colorspace::HsvValue {
+        sidecar.channel_order = "HSV".to_string();
+    }
+    if args.force_alpha.is_some() || args.confidence_alpha {
+        sidecar.channel_order.push('A');
+    }
+    if args.lut_sampling == LutSampling::Stratified {
+        sidecar.lut_knots = Some(lut_knots_file_name(lut));
+    }
+    encode::write_atomic(
+        &out_dir.join(format!("{}.meta.json", stem)),
+        &args.write_options(),
+        |file| {
+            serde_json::to_writer_pretty(file, &sidecar)?;
+            Ok(())
+        },
+    )
+}
+
+/// Writes the [`Uniforms`] for `result` to `path`.
+fn write_uniforms(result: &GaussianResult, args: &Args, path: &Path) -> Result<(), anyhow::Error> {
+    let uniforms = Uniforms::new(result, &args.transform_config());
+    encode::write_atomic(path, &args.write_options(), |file| {
+        serde_json::to_writer_pretty(file, &uniforms)?;
+        Ok(())
+    })
+}
+
+/// Ranks `input_path` again and writes the ranks to `path`, see [`TransformConfig::ranks`].
+fn write_ranks(
+    input_path: &Path,
+    args: &Args,
+    path: &Path,
+    cancel: &CancelToken,
+) -> Result<(), anyhow::Error> {
+    let pages = read::read_input(input_path, &args.read_options())?;
+    let ranks = args
+        .transform_config()
+        .ranks(&read::stack_pages(&pages)?, cancel)?;
+    encode::write_atomic(path, &args.write_options(), |file| {
+        let mut writer = std::io::BufWriter::new(file);
+        for rank in &ranks {
+            writer.write_all(&rank.to_le_bytes())?;
+        }
+        writer.flush()?;
+        Ok(())
+    })
+}
+
+/// Writes [`TransformConfig::tie_overlay`] of the input as an RGB PNG.
+fn write_tie_overlay(
+    input_path: &Path,
+    args: &Args,
+    path: &Path,
+    min_size: u64,
+) -> Result<(), anyhow::Error> {
+    let pages = read::read_input(input_path, &args.read_options())?;
+    let input = read::stack_pages(&pages)?;
+    let overlay = args
+        .transform_config()
+        .tie_overlay(&input, min_size as usize)?;
+    encode::write_atomic(path, &args.write_options(), |file| {
+        image::codecs::png::PngEncoder::new(std::io::BufWriter::new(file)).write_image(
+            &overlay,
+            input.width(),
+            input.height(),
+            image::ColorType::Rgb8,
+        )?;
+        Ok(())
+    })
+}
+
+/// Writes [`plot::histogram_plot`] of each transformed channel as
+/// `<stem>-histogram-<channel>.png` in `dir`, creating it if needed.
+fn write_histogram_plots(
+    result: &GaussianResult,
+    args: &Args,
+    dir: &Path,
+    stem: &str,
+) -> Result<(), anyhow::Error> {
+    std::fs::create_dir_all(dir)?;
+    let config = args.transform_config();
+    for channel in 0..config.channels.count() {
+        let plot = plot::histogram_plot(result, &config, channel);
+        let path = dir.join(format!(
+            "{}-histogram-{}.png",
+            stem,
+            ["r", "g", "b"][channel]
+        ));
+        encode::write_atomic(&path, &args.write_options(), |file| {
+            image::codecs::png::PngEncoder::new(std::io::BufWriter::new(file)).write_image(
+                &plot,
+                plot.width(),
+                plot.height(),
+                image::ColorType::Rgb8,
+            )?;
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+/// Writes each tile as `<stem>-<column>-<row>` and the manifest as `<stem>.tiles.json`.
+fn write_tiles(
+    result: &GaussianResult,
+    out_dir: &Path,
+    stem: &str,
+    lut_file_name: &str,
+    tile_size: u32,
+    encoder: &dyn OutputEncoder,
+    args: &Args,
+) -> Result<(), anyhow::Error> {
+    if result.page_heights.len() > 1 {
+        return Err(anyhow::anyhow!(
+            "--tile-output doesn't support multi-page inputs"
+        ));
+    }
+    let manifest = TileManifest::new(
+        result.width,
+        result.height,
+        tile_size,
+        lut_file_name.to_string(),
+        |column, row| format!("{}-{}-{}.{}", stem, column, row, encoder.extension()),
+    );
+    manifest.tiles.par_iter().try_for_each(|tile| {
+        let data = tile.crop(&result.image, result.width, 3);
+        encoder.encode(
+            &out_dir.join(&tile.file),
+            tile.width,
+            tile.height,
+            3,
+            OutputData::F32(&data),
+        )
+    })?;
+    let manifest_path = out_dir.join(format!("{}.tiles.json", stem));
+    encode::write_atomic(&manifest_path, &args.write_options(), |file| {
+        serde_json::to_writer_pretty(file, &manifest)?;
+        Ok(())
+    })
+}
+
+/// Writes the manifest at `path` mapping each atlas tile to its LUT row.
+fn write_atlas_manifest(
+    result: &GaussianResult,
+    columns: u32,
+    rows: u32,
+    path: &Path,
+    image: &str,
+    lut: &str,
+    args: &Args,
+) -> Result<(), anyhow::Error> {
+    let manifest = AtlasManifest::new(
+        columns,
+        rows,
+        result.width,
+        result.height,
+        result.lut_width,
+        image.to_string(),
+        lut.to_string(),
+    );
+    encode::write_atomic(path, &args.write_options(), |file| {
+        serde_json::to_writer_pretty(file, &manifest)?;
+        Ok(())
+    })
+}
+
+fn write_lut(
+    result: &GaussianResult,
+    path: &Path,
+    encoder: &dyn OutputEncoder,
+    args: &Args,
+) -> Result<(), anyhow::Error> {
+    let lut = PackedLut::new(result, args);
+    encoder.encode(
+        path,
+        result.lut_width,
+        result.lut_height,
+        args.lut_texel_channels(),
+        lut.data(),
+    )?;
+    let Some(file_name) = path.file_name().and_then(OsStr::to_str) else {
+        return Ok(());
+    };
+    for &variant in &args.lut_variants {
+        let (variant_lut, variant_lut_f32) =
+            args.source_transfer.convert_lut(variant, &result.lut_f32);
+        let lut = PackedLut::with_values(result, &variant_lut, &variant_lut_f32, args);
+        let variant_path = path.with_file_name(lut_variant_file_name(file_name, variant));
+        encoder.encode(
+            &variant_path,
+            result.lut_width,
+            result.lut_height,
+            args.lut_texel_channels(),
+            lut.data(),
+        )?;
+    }
+    if let Some(knots) = args.transform_config().lut_knots(result) {
+        let encoder = encode::Tiff {
+            byte_order: args.tiff_endian,
+            dpi: args.dpi,
+            ..Default::default()
+        };
+        let knots_path = path.with_file_name(lut_knots_file_name(file_name));
+        encoder.encode(&knots_path, result.lut_width, 1, 3, OutputData::F32(&knots))?;
+    }
+    Ok(())
+}
+
+/// The name of the knot map `--lut-sampling stratified` writes beside the
+/// LUT `file_name`: `-knots` before the extension, always a float TIFF.
+fn lut_knots_file_name(file_name: &str) -> String {
+    let stem = file_name
+        .rsplit_once('.')
+        .map_or(file_name, |(stem, _)| stem);
+    format!("{}-knots.tif", stem)
+}
+
+/// The name `--lut-variants` gives the LUT `file_name` re-encoded with
+/// `variant`: `-linear` or `-srgb` before the extension.
+fn lut_variant_file_name(file_name: &str, variant: Transfer) -> String {
+    let (stem, extension) = file_name.rsplit_once('.').unwrap_or((file_name, "tif"));
+    let suffix = match variant {
+        Transfer::Linear => "linear",
+        Transfer::Srgb => "srgb",
+    };
+    format!("{}-{}.{}", stem, suffix, extension)
+}
+
+/// The names `--split-channels` gives the channels of the `file_name`
+/// output: `-r`, `-g` and `-b` before the extension, one per transformed channel.
+fn split_file_names(file_name: &str, channels: usize) -> Vec<String> {
+    let (stem, extension) = file_name.rsplit_once('.').unwrap_or((file_name, "tif"));
+    ["r", "g", "b"][..channels]
+        .iter()
+        .map(|channel| format!("{}-{}.{}", stem, channel, extension))
+        .collect()
+}
+
+/// Writes each transformed channel of the forward image and the LUT as a
+/// single-channel TIFF, named by [`split_file_names`].
+fn write_split_channels(
+    result: &GaussianResult,
+    out_dir: &Path,
+    img_file_name: &str,
+    lut_file_name: &str,
+    args: &Args,
+) -> Result<(), anyhow::Error> {
+    let channels = args.channels.count();
+    let img_names = split_file_names(img_file_name, channels);
+    let lut_names = split_file_names(lut_file_name, channels);
+    let mut names: Vec<_> = img_names.iter().chain(&lut_names).collect();
+    names.sort();
+    if let Some(name) = names.windows(2).find(|w| w[0] == w[1]).map(|w| w[0]) {
+        return Err(anyhow::anyhow!(
+            "--split-channels would write {} twice; give the forward image and LUT different names",
+            name
+        ));
+    }
+    let img_encoder = args.img_encoder();
+    let lut_encoder = args.lut_encoder();
+    let lut = PackedLut::new(result, args);
+    for (channel, (img_name, lut_name)) in img_names.iter().zip(&lut_names).enumerate() {
+        let image: Vec<f32> = result
+            .image
+            .iter()
+            .skip(channel)
+            .step_by(3)
+            .copied()
+            .collect();
+        img_encoder.encode_pages(
+            &out_dir.join(img_name),
+            result.width,
+            &result.page_heights,
+            1,
+            OutputData::F32(&image),
+        )?;
+        let lut_path = out_dir.join(lut_name);
+        let (lut_width, lut_height) = (result.lut_width, result.lut_height);
+        match &lut {
+            PackedLut::U8(lut) => {
+                let column: Vec<u8> = lut.iter().skip(channel).step_by(3).copied().collect();
+                lut_encoder.encode(&lut_path, lut_width, lut_height, 1, OutputData::U8(&column))?
+            }
+            PackedLut::U16(lut) => {
+                let column: Vec<u16> = lut.iter().skip(channel).step_by(3).copied().collect();
+                lut_encoder.encode(
+                    &lut_path,
+                    lut_width,
+                    lut_height,
+                    1,
+                    OutputData::U16(&column),
+                )?
+            }
+            PackedLut::F32(lut) => {
+                let column: Vec<f32> = lut.iter().skip(channel).step_by(3).copied().collect();
+                lut_encoder.encode(
+                    &lut_path,
+                    lut_width,
+                    lut_height,
+                    1,
+                    OutputData::F32(&column),
+                )?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// One channel of a LUT, as `--split-channels` writes it at either precision.
+enum SplitLut {
+    U8(read::TiffImage<u8>),
+    F32(read::TiffImage<f32>),
+}
+
+impl SplitLut {
+    fn read(path: &Path) -> Result<Self, anyhow::Error> {
+        read::read_gray_u8(path).map(SplitLut::U8).or_else(|_| {
+            read::read_gray_f32(path).map(SplitLut::F32).map_err(|_| {
+                anyhow::anyhow!(
+                    "{:?} isn't an 8-bit or 32-bit float single-channel LUT",
+                    path
+                )
+            })
+        })
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        match self {
+            SplitLut::U8(lut) => (lut.width, lut.height),
+            SplitLut::F32(lut) => (lut.width, lut.height),
+        }
+    }
+}
+
+/// The `describe` subcommand: writes to `out` the header of the TIFF or KTX2
+/// output at `path`, any `precompute.*` KTX2 metadata, and every field of the
+/// sidecar that names it, the bake's recorded parameters included. Without a
+/// sidecar, which is only written with `--sidecar`, the parameters a TIFF was
+/// baked with aren't recorded anywhere.
+fn describe(path: &Path, out: &mut dyn Write) -> Result<(), anyhow::Error> {
+    let extension = path
+        .extension()
+        .and_then(OsStr::to_str)
+        .map(str::to_lowercase);
+    match extension.as_deref() {
+        Some("tif" | "tiff") => {
+            // The `tiff` crate can't open planar files, but their sidecars
+            // still describe them.
+            let header = match read::read_tiff_header(path) {
+                Ok(header) => header,
+                Err(e) => {
+                    writeln!(
+                        out,
+                        "{:?}: TIFF, whose header couldn't be read: {}",
+                        path, e
+                    )?;
+                    return describe_sidecar(path, out);
+                }
+            };
+            let sample_format = match header.sample_format {
+                2 => "signed",
+                3 => "float",
+                _ => "unsigned",
+            };
+            let compression = match header.compression {
+                1 => "uncompressed".to_string(),
+                5 => "LZW".to_string(),
+                8 | 32946 => "Deflate".to_string(),
+                32773 => "Packbits".to_string(),
+                other => format!("compression {}", other),
+            };
+            writeln!(
+                out,
+                "{:?}: {}x{} TIFF, {} page(s), {:?} {}, {}, {}, {} {}",
+                path,
+                header.width,
+                header.height,
+                header.pages,
+                header.color_type,
+                sample_format,
+                compression,
+                if header.big_endian {
+                    "big-endian"
+                } else {
+                    "little-endian"
+                },
+                if header.planar_configuration == 2 {
+                    "planar"
+                } else {
+                    "interleaved"
+                },
+                if header.tiled { "tiles" } else { "strips" }
+            )?;
+            if let Some(config) = metadata::read_config(path)? {
+                writeln!(
+                    out,
+                    "  Embedded parameters: {}",
+                    serde_json::to_string(&config)?
+                )?;
+            }
+        }
+        Some("ktx2") => {
+            writeln!(out, "{:?}: KTX2", path)?;
+            for (key, value) in precompute::ktx2::read_key_values(path)? {
+                writeln!(out, "  {}: {}", key, value)?;
+            }
+        }
+        _ => {}
+    }
+    describe_sidecar(path, out)
+}
+
+/// Writes every field of the sidecar naming `path` to `out`, or that there
+/// is none.
+fn describe_sidecar(path: &Path, out: &mut dyn Write) -> Result<(), anyhow::Error> {
+    let Some(sidecar) = Sidecar::find(path)? else {
+        writeln!(out, "No .meta.json sidecar beside {:?} names it, so the parameters it was baked with weren't recorded; bake with --sidecar to keep them", path)?;
+        return Ok(());
+    };
+    writeln!(out, "Recorded by its sidecar:")?;
+    let serde_json::Value::Object(mut fields) = serde_json::to_value(&sidecar)? else {
+        unreachable!("a sidecar serializes to an object");
+    };
+    let config = fields.remove("config");
+    let show = |value: &serde_json::Value| match value {
+        serde_json::Value::String(s) => s.clone(),
+        value => value.to_string(),
+    };
+    for (key, value) in &fields {
+        writeln!(out, "  {}: {}", key, show(value))?;
+    }
+    if let Some(serde_json::Value::Object(config)) = config {
+        for (key, value) in &config {
+            writeln!(out, "  config.{}: {}", key, show(value))?;
+        }
+    }
+    Ok(())
+}
+
+/// Interleaves three equally long channels into RGB.
+fn interleave_rgb<T: Copy>(channels: [&[T]; 3]) -> Vec<T> {
+    (0..channels[0].len())
+        .flat_map(|i| channels.map(|channel| channel[i]))
+        .collect()
+}
+
+/// The `merge-luts` subcommand: writes the single-channel LUTs at `paths`,
+/// red, green and blue, to `output` as one RGB LUT, the inverse of
+/// `--split-channels`. They must have the same size and precision.
+fn merge_luts(paths: [&PathBuf; 3], output: &Path, args: &Args) -> Result<(), anyhow::Error> {
+    let luts = paths
+        .iter()
+        .map(|path| SplitLut::read(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (width, height) = luts[0].dimensions();
+    for (lut, path) in luts.iter().zip(paths).skip(1) {
+        if lut.dimensions() != (width, height) {
+            let (w, h) = lut.dimensions();
+            return Err(anyhow::anyhow!(
+                "{:?} is {}x{}, but {:?} is {}x{}",
+                path,
+                w,
+                h,
+                paths[0],
+                width,
+                height
+            ));
+        }
+    }
+    let encoder = args.lut_encoder();
+    match (&luts[0], &luts[1], &luts[2]) {
+        (SplitLut::U8(r), SplitLut::U8(g), SplitLut::U8(b)) => {
+            let lut = interleave_rgb([&r.data, &g.data, &b.data]);
+            encoder.encode(output, width, height, 3, OutputData::U8(&lut))?
+        }
+        (SplitLut::F32(r), SplitLut::F32(g), SplitLut::F32(b)) => {
+            let lut = interleave_rgb([&r.data, &g.data, &b.data]);
+            encoder.encode(output, width, height, 3, OutputData::F32(&lut))?
+        }
+        _ => {
+            return Err(anyhow::anyhow!(
+                "{:?}, {:?} and {:?} don't share one precision; bake them with the same --lut-precision",
+                paths[0],
+                paths[1],
+                paths[2]
+            ))
+        }
+    }
+    if !args.quiet {
+        println!("Merged {}x{} LUT written to {:?}", width, height, output);
+    }
+    Ok(())
+}
+
+/// 8-bit `levels` promoted to 16 bits by zero-padding: each level in the
+/// high byte and zeros below it, so no precision is made up.
+fn promote_to_16_bits(levels: &[u8]) -> Vec<u16> {
+    levels.iter().map(|&v| (v as u16) << 8).collect()
+}
+
+/// The interleaved RGB `lut` with `alpha` of each texel's `density` appended.
+fn with_alpha<T: Copy>(lut: &[T], density: &[f32], alpha: impl Fn(f32) -> T) -> Vec<T> {
+    lut.chunks_exact(3)
+        .zip(density)
+        .flat_map(|(rgb, &d)| [rgb[0], rgb[1], rgb[2], alpha(d)])
+        .collect()
+}
+
+/// The LUT laid out as `--lut-pack`, `--rgba-lut` and `--lut-precision` ask.
+enum PackedLut {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    F32(Vec<f32>),
+}
+
+impl PackedLut {
+    fn new(result: &GaussianResult, args: &Args) -> Self {
+        Self::with_values(result, &result.lut, &result.lut_f32, args)
+    }
+
+    /// Like [`PackedLut::new`], with `lut` and `lut_f32` in place of
+    /// `result`'s LUTs.
+    fn with_values(result: &GaussianResult, lut: &[u8], lut_f32: &[f32], args: &Args) -> Self {
+        let pack = args.lut_pack;
+        let config = args.transform_config();
+        let blue_transformed = config.channels.count() == 3 && config.lut_channel_enabled(2);
+        if args.rgba_lut {
+            let density = config.lut_density(result);
+            return match args.lut_precision {
+                LutPrecision::U8 => {
+                    PackedLut::U8(with_alpha(lut, &density, |d| (d * 255.0).round() as u8))
+                }
+                LutPrecision::U16 => {
+                    PackedLut::U16(with_alpha(&promote_to_16_bits(lut), &density, |d| {
+                        (d * 65535.0).round() as u16
+                    }))
+                }
+                LutPrecision::F32 => PackedLut::F32(with_alpha(lut_f32, &density, |d| d)),
+            };
+        }
+        match args.lut_precision {
+            LutPrecision::U8 => PackedLut::U8(pack.pack(lut, blue_transformed, u8::MAX)),
+            LutPrecision::U16 => {
+                PackedLut::U16(pack.pack(&promote_to_16_bits(lut), blue_transformed, u16::MAX))
+            }
+            LutPrecision::F32 => PackedLut::F32(pack.pack(lut_f32, blue_transformed, 1.0)),
+        }
+    }
+
+    fn data(&self) -> OutputData<'_> {
+        match self {
+            PackedLut::U8(lut) => OutputData::U8(lut),
+            PackedLut::U16(lut) => OutputData::U16(lut),
+            PackedLut::F32(lut) => OutputData::F32(lut),
+        }
+    }
+}
+
+/// Writes the forward image and LUT to one `.npz` as `image`, shaped
+/// `(height, width, 3)`, and `lut`, shaped `(lut_height, lut_width, channels)`.
+fn write_npz(result: &GaussianResult, path: &Path, args: &Args) -> Result<(), anyhow::Error> {
+    if result.page_heights.len() > 1 {
+        return Err(anyhow::anyhow!(
+            "npz outputs can't hold {} pages",
+            result.page_heights.len()
+        ));
+    }
+    let lut = PackedLut::new(result, args);
+    let image_shape = [result.height as usize, result.width as usize, 3];
+    let lut_shape = [
+        result.lut_height as usize,
+        result.lut_width as usize,
+        args.lut_texel_channels(),
+    ];
+    let arrays = [
+        npz::Array {
+            name: "image",
+            shape: &image_shape,
+            data: OutputData::F32(&result.image),
+        },
+        npz::Array {
+            name: "lut",
+            shape: &lut_shape,
+            data: lut.data(),
+        },
+    ];
+    encode::write_atomic(path, &args.write_options(), |file| {
+        let mut writer = std::io::BufWriter::new(file);
+        npz::write_npz(&mut writer, &arrays)?;
+        writer.flush()?;
+        Ok(())
+    })
+}
+
+fn bake(
+    input_path: &Path,
+    args: &Args,
+    diagnostics: &Diagnostics,
+    cancel: &CancelToken,
+) -> Result<GaussianResult, anyhow::Error> {
+    let _bake = tracing::info_span!("bake", input = ?input_path).entered();
+    let pages = read_checked(input_path, args, diagnostics)?;
+    let weights = check_layout(&pages, args)?;
+    if !args.quiet {
+        match pages.len() {
+            1 => println!("Processing {:?}...", input_path),
+            n => println!("Processing {} pages of {:?}...", n, input_path),
+        }
+    }
+    let start = Instant::now();
+
+    let mut config = args.transform_config();
+    if config.alpha_cutoff.is_none() && read::has_transparent_index(input_path) {
+        let cutoff = config.clone().alpha_cutoff(Some(TRANSPARENT_INDEX_CUTOFF));
+        // The modes that can't leave pixels out reject an alpha cutoff.
+        if args.local_tiles.is_some()
+            || args.colorspace != Colorspace::Source
+            || args.channel_cache.is_some()
+            || cutoff.validate().is_err()
+        {
+            diagnostics.warn(format!(
+                "{:?} has a transparent palette entry, but its pixels can't be left out of the histogram with these options",
+                input_path
+            ))?;
+        } else {
+            if !args.quiet {
+                println!(
+                    "Leaving the pixels of {:?}'s transparent palette entry out of the histogram",
+                    input_path
+                );
+            }
+            config = cutoff;
+        }
+    }
+    let run = || -> Result<GaussianResult, anyhow::Error> {
+        Ok(match (&weights, args.atlas, args.local_tiles) {
+            (Some(weights), _, _) => config.run_weighted(&pages[0], weights.as_raw(), cancel)?,
+            (None, Some((columns, rows)), _) => {
+                config.run_atlas(&pages[0], columns, rows, cancel)?
+            }
+            (None, None, Some((columns, rows))) => {
+                config.run_local_tiles(&pages[0], columns, rows, cancel)?
+            }
+            (None, None, None) if args.colorspace == Colorspace::HsvValue => {
+                config.run_hsv_value(&pages, cancel)?
+            }
+            (None, None, None) => match &args.channel_cache {
+                Some(dir) => {
+                    let (result, reused) =
+                        config.run_cached(&pages, &ChannelCache::new(dir)?, cancel)?;
+                    if !args.quiet {
+                        println!(
+                            "Reused {} of {} channels from the cache",
+                            reused,
+                            config.channels.count()
+                        );
+                    }
+                    result
+                }
+                None => config.run_pages(&pages, cancel)?,
+            },
+        })
+    };
+    let mut result = run()?;
+    if args.bit_exact_float {
+        let single_thread = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .map_err(|e| anyhow::anyhow!("Couldn't start a thread for --bit-exact-float: {}", e))?;
+        check_bit_exact(&result, &single_thread.install(run)?)?;
+    }
+
+    if args.max_lut_error.is_some() || args.error_map.is_some() {
+        let stacked;
+        let source = match pages.as_slice() {
+            [page] => page,
+            pages => {
+                stacked = read::stack_pages(pages)?;
+                &stacked
+            }
+        };
+        if let Some(max_error) = args.max_lut_error {
+            let rmse = config.fit_lut_size(source, &mut result, max_error);
+            if rmse > max_error {
+                diagnostics.warn(format!(
+                    "Even a LUT of {} texels has reconstruction RMSE {:.4}, above --max-lut-error {}",
+                    result.lut_width, rmse, max_error
+                ))?;
+            } else if !args.quiet {
+                println!(
+                    "Chose a LUT of {} texels, reconstruction RMSE {:.4}",
+                    result.lut_width, rmse
+                );
+            }
+        }
+        // After --max-lut-error, so the map shows the LUT that gets written.
+        if let Some(map_path) = &args.error_map {
+            write_error_map(&config, source, &result, args, map_path)?;
+        }
+    }
+    check_histogram(input_path, &result.histogram, args, diagnostics)?;
+    if args.validate_lut_monotonic {
+        result.check_lut_monotonic()?;
+    }
+    // Atlas tiles each size their columns from their own histogram.
+    if config.profile_guided_lut && args.atlas.is_none() && !args.quiet {
+        let widths: Vec<String> = (0..config.channels.count())
+            .map(|channel| {
+                let histogram_channel = if config.shared_lut { 0 } else { channel };
+                let width = config.effective_lut_width(
+                    &result.histogram,
+                    histogram_channel,
+                    channel,
+                    result.lut_width,
+                );
+                format!("{} {}", ["R", "G", "B"][channel], width)
+            })
+            .collect();
+        println!(
+            "Distinct texels per LUT column of {}: {}",
+            result.lut_width,
+            widths.join(", ")
+        );
+    }
+    if let Some(factor) = args.downsample_output {
+        result.downsample(factor, args.downsample_filter)?;
+    }
+
+    if !args.quiet {
+        println!("Finished processing. Took {:?}", start.elapsed());
+    }
+
+    Ok(result)
+}
+
+/// Warns about channels whose values are too uniform for the transform to be meaningful.
+/// Checks the flags a bake depends on, then reads `input_path`, warning
+/// about anything read only approximately.
+fn read_checked(
+    input_path: &Path,
+    args: &Args,
+    diagnostics: &Diagnostics,
+) -> Result<Vec<DynamicImage>, anyhow::Error> {
+    if let Some(max_error) = args.max_lut_error.filter(|e| !(e.is_finite() && *e >= 0.0)) {
+        return Err(anyhow::anyhow!(
+            "--max-lut-error must be at least 0, got {}",
+            max_error
+        ));
+    }
+    if !(args.transformed_tolerance.is_finite() && args.transformed_tolerance >= 0.0) {
+        return Err(anyhow::anyhow!(
+            "--transformed-tolerance must be at least 0, got {}",
+            args.transformed_tolerance
+        ));
+    }
+    let read_span = tracing::info_span!("read").entered();
+    // Only the transformed channels matter to a bake, unless alpha decides
+    // which pixels count.
+    let alpha_used = args.alpha_cutoff.is_some() || read::has_transparent_index(input_path);
+    let channel_count = (!alpha_used).then(|| args.channels.count());
+    let pages = read::read_input(
+        input_path,
+        &args.read_options().channel_count(channel_count),
+    )?;
+    let pages = apply_mask_file(pages, args, diagnostics)?;
+    drop(read_span);
+
+    if read::is_cmyk_tiff(input_path) {
+        diagnostics.warn(format!(
+            "{:?} is CMYK; converting to RGB without color management, so colors are approximate",
+            input_path
+        ))?;
+    }
+    if args.forward_lut && sample_bits(&pages[0]) > 8 {
+        return Err(anyhow::anyhow!(
+            "--forward-lut needs an 8-bit input, but {:?} has {}-bit samples",
+            input_path,
+            sample_bits(&pages[0])
+        ));
+    }
+    check_depth(input_path, &pages[0], args, diagnostics)?;
+    Ok(pages)
+}
+
+/// `pages` with their alpha replaced by `--mask-file`, if it's given.
+fn apply_mask_file(
+    pages: Vec<DynamicImage>,
+    args: &Args,
+    diagnostics: &Diagnostics,
+) -> Result<Vec<DynamicImage>, anyhow::Error> {
+    let Some(mask_path) = &args.mask_file else {
+        return Ok(pages);
+    };
+    let [page] = &pages[..] else {
+        return Err(anyhow::anyhow!(
+            "--mask-file doesn't support multi-page inputs"
+        ));
+    };
+    if args.alpha_cutoff.is_none() && !args.mask_mode {
+        diagnostics.warn(format!(
+            "--mask-file {:?} has no effect without --alpha-cutoff or --mask-mode",
+            mask_path
+        ))?;
+    }
+    let mask = read::read_weight_map(mask_path, &args.read_options())?;
+    if mask.dimensions() != (page.width(), page.height()) {
+        return Err(anyhow::anyhow!(
+            "Mask file {:?} is {}x{}, expected {}x{}",
+            mask_path,
+            mask.width(),
+            mask.height(),
+            page.width(),
+            page.height()
+        ));
+    }
+    let pages = vec![read::with_alpha(page, &mask)];
+    read::check_preprocessed(&pages, "--mask-file")?;
+    Ok(pages)
+}
+
+/// Bits per sample of `image`'s channels.
+fn sample_bits(image: &DynamicImage) -> u16 {
+    let color = image.color();
+    color.bits_per_pixel() / color.channel_count() as u16
+}
+
+/// Warns when the input's depth and the depth a bake works at disagree.
+/// Samples are always ranked at 8 bits, so deeper inputs are rounded down to
+/// 8 bits and the LUT can't give the lost bits back; an 8-bit input baked to a
+/// 16-bit LUT, whose levels are zero-padded, or to a float LUT that only holds
+/// its levels gains nothing from the extra bits.
+fn check_depth(
+    input_path: &Path,
+    input: &DynamicImage,
+    args: &Args,
+    diagnostics: &Diagnostics,
+) -> Result<(), anyhow::Error> {
+    let bits = sample_bits(input);
+    if bits > 8 {
+        return diagnostics.warn(format!(
+            "{:?} has {}-bit samples, but they're rounded to 8 bits to be ranked, so the LUT only reconstructs 256 levels per channel",
+            input_path, bits
+        ));
+    }
+    if args.lut_precision == LutPrecision::U16 {
+        return diagnostics.warn(format!(
+            "{:?} has 8-bit samples, so the --lut-precision u16 LUT holds them promoted to 16 bits with zero low bits, no more precise than an 8-bit LUT",
+            input_path
+        ));
+    }
+    if args.lut_precision == LutPrecision::F32
+        && args.lut_interp == LutInterp::Nearest
+        && !args.rgba_lut
+    {
+        return diagnostics.warn(format!(
+            "{:?} has 8-bit samples, so the --lut-precision f32 LUT only holds its 256 levels as v / 255; --lut-interp linear gives it values in between",
+            input_path
+        ));
+    }
+    Ok(())
+}
+
+/// Fails unless `single_thread`, the same bake on one thread, matches
+/// `result` bit for bit in the forward image and both LUTs.
+fn check_bit_exact(
+    result: &GaussianResult,
+    single_thread: &GaussianResult,
+) -> Result<(), anyhow::Error> {
+    let floats = [
+        ("forward image", &result.image, &single_thread.image),
+        ("float LUT", &result.lut_f32, &single_thread.lut_f32),
+    ];
+    for (name, a, b) in floats {
+        let bits = |values: &[f32], i: usize| values.get(i).map(|v| v.to_bits());
+        if let Some(i) = (0..a.len().max(b.len())).find(|&i| bits(a, i) != bits(b, i)) {
+            return Err(anyhow::anyhow!(
+                "--bit-exact-float: subpixel {} of the {} differs on one thread: {:?} against {:?}",
+                i,
+                name,
+                a.get(i),
+                b.get(i)
+            ));
+        }
+    }
+    if result.lut != single_thread.lut {
+        return Err(anyhow::anyhow!(
+            "--bit-exact-float: the 8-bit LUT differs on one thread"
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that `pages` suit `--weight-map`, `--atlas` and `--local-tiles`,
+/// returning the weight map if there is one.
+fn check_layout(pages: &[DynamicImage], args: &Args) -> Result<Option<WeightMap>, anyhow::Error> {
+    for (flag, grid) in [("--atlas", args.atlas), ("--local-tiles", args.local_tiles)] {
+        let Some((columns, rows)) = grid else {
+            continue;
+        };
+        let [page] = pages else {
+            return Err(anyhow::anyhow!(
+                "{} doesn't support multi-page inputs",
+                flag
+            ));
+        };
+        if page.width() % columns != 0 || page.height() % rows != 0 {
+            return Err(anyhow::anyhow!(
+                "A {}x{} input can't be split into {}x{} equal tiles",
+                page.width(),
+                page.height(),
+                columns,
+                rows
+            ));
+        }
+    }
+    let Some(weight_map_path) = &args.weight_map else {
+        return Ok(None);
+    };
+    let [page] = pages else {
+        return Err(anyhow::anyhow!(
+            "--weight-map doesn't support multi-page inputs"
+        ));
+    };
+    let weights = read::read_weight_map(weight_map_path, &args.read_options())?;
+    if weights.dimensions() != (page.width(), page.height()) {
+        return Err(anyhow::anyhow!(
+            "Weight map {:?} is {}x{}, expected {}x{}",
+            weight_map_path,
+            weights.width(),
+            weights.height(),
+            page.width(),
+            page.height()
+        ));
+    }
+    Ok(Some(weights))
+}
+
+/// Everything [`bake`] checks about `input_path` before and after the
+/// transform, without running it.
+fn validate_input(
+    input_path: &Path,
+    args: &Args,
+    diagnostics: &Diagnostics,
+) -> Result<(), anyhow::Error> {
+    let pages = read_checked(input_path, args, diagnostics)?;
+    check_layout(&pages, args)?;
+    let config = args.transform_config();
+    config.validate()?;
+    let histogram = config.histogram(&read::stack_pages(&pages)?)?;
+    check_histogram(input_path, &histogram, args, diagnostics)
+}
+
+/// Runs [`self_test::self_test`] and reports it with [`report_self_test`].
+fn run_self_test() -> Result<(), anyhow::Error> {
+    report_self_test(&self_test::self_test())
+}
+
+/// Prints `OK` or `FAIL` with the reason for each of `checks`, and fails if
+/// any did.
+fn report_self_test(checks: &[self_test::SelfTestCheck]) -> Result<(), anyhow::Error> {
+    let mut failed = 0;
+    for check in checks {
+        match &check.failure {
+            None => println!("OK {}", check.name),
+            Some(failure) => {
+                eprintln!("FAIL {}: {}", check.name, failure);
+                failed += 1;
+            }
+        }
+    }
+    println!(
+        "{} of {} checks passed",
+        checks.len() - failed,
+        checks.len()
+    );
+    match failed {
+        0 => Ok(()),
+        _ => Err(anyhow::anyhow!("{} self-test checks failed", failed)),
+    }
+}
+
+fn run_validate(args: &Args, inputs: &[PathBuf]) -> Result<(), anyhow::Error> {
+    let diagnostics = Diagnostics::new(args.strict);
+    let mut failed = 0;
+    for input_path in inputs {
+        match validate_input(input_path, args, &diagnostics) {
+            Ok(()) => println!("OK {:?}", input_path),
+            Err(e) => {
+                eprintln!("FAIL {:?}: {}", input_path, e);
+                failed += 1;
+            }
+        }
+    }
+    println!(
+        "{} of {} inputs passed",
+        inputs.len() - failed,
+        inputs.len()
+    );
+    if failed > 0 {
+        Err(anyhow::anyhow!("{} inputs failed validation", failed))
+    } else {
+        Ok(())
+    }
+}
+
+fn check_histogram(
+    input_path: &Path,
+    histogram: &Histogram<u8>,
+    args: &Args,
+    diagnostics: &Diagnostics,
+) -> Result<(), anyhow::Error> {
+    for (channel, label) in channel_labels(histogram, args).into_iter().enumerate() {
+        match histogram.distinct_values(channel) {
+            1 => diagnostics.warn(format!(
+                "{} of {:?} is constant, so its forward image only holds tie-breaking noise",
+                label, input_path
+            ))?,
+            n if n < MIN_DISTINCT_VALUES => diagnostics.warn(format!(
+                "{} of {:?} has only {} distinct values, so its forward image is mostly tie-breaking noise",
+                label, input_path, n
+            ))?,
+            _ => {}
+        }
+    }
+
+    // A forward image read back as an input quantizes to a near-perfect match
+    // of the target; a photo rarely gets close on every channel at once.
+    let config = args.transform_config();
+    let distance = (0..histogram.channel_count())
+        .map(|channel| {
+            histogram.cdf_distance(channel, config.channel_target(channel).as_ref(), 255.0)
+        })
+        .fold(0.0, f64::max);
+    if distance < args.transformed_tolerance {
+        diagnostics.warn(format!(
+            "{:?} already matches the target distribution (distance {:.4}), so it may be a forward image being transformed twice",
+            input_path, distance
+        ))?;
+    }
+    Ok(())
+}
+
+fn output_file_name(
+    input_path: &Path,
+    prefix: Option<&str>,
+    suffix: &str,
+    extension: &str,
+) -> String {
+    format!("{}.{}", output_stem(input_path, prefix, suffix), extension)
+}
+
+fn output_stem(input_path: &Path, prefix: Option<&str>, suffix: &str) -> String {
+    prefix
+        .map(str::to_string)
+        .unwrap_or(format!("{}-{}", input_stem(input_path), suffix))
+}
+
+fn input_stem(input_path: &Path) -> String {
+    input_path
+        .file_prefix()
+        .unwrap_or(OsStr::new("Texture"))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn path_directory(path: PathBuf) -> PathBuf {
+    if path.is_dir() {
+        path
+    } else {
+        path.parent()
+            .map(|x| x.to_path_buf())
+            .unwrap_or_else(|| PathBuf::from(""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use precompute::self_test::synthetic_input;
+
+    /// An empty directory under the system temp dir for the test `name`.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("precompute-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Runs the tool with the command-line arguments `args`.
+    fn run(args: &[&Path]) -> Result<(), anyhow::Error> {
+        let argv: Vec<OsString> = std::iter::once(Path::new("precompute"))
+            .chain(args.iter().copied())
+            .map(OsString::from)
+            .collect();
+        let mut args = Args::try_parse_from(&argv)?;
+        args.command_line = argv;
+        _main(args, &CancelToken::new())
+    }
+
+    #[test]
+    fn cli_defaults_match_config_defaults() {
+        let args = Args::try_parse_from(["precompute", "--in-file", "in.png"]).unwrap();
+        assert_eq!(args.transform_config(), TransformConfig::default());
+        assert_eq!(TransformConfig::new(), TransformConfig::default());
+    }
+
+    #[test]
+    fn cli_options_reach_the_config() {
+        let args = Args::try_parse_from([
+            "precompute",
+            "--in-file",
+            "in.png",
+            "--mean",
+            "0.25",
+            "--std",
+            "0.1",
+            "--lut-size",
+            "512",
+        ])
+        .unwrap();
+        let expected = TransformConfig::new()
+            .mean(0.25)
+            .std(0.1)
+            .lut_size(Some(512));
+        assert_eq!(args.transform_config(), expected);
+    }
+
+    #[test]
+    fn config_file_sets_defaults_and_the_command_line_wins() {
+        let table: toml::Table = toml::from_str(
+            "mean = 0.25\nstd = 0.1\ndistribution = [\"uniform\"]\ndeterministic = true\n",
+        )
+        .unwrap();
+        let mut argv: Vec<OsString> = vec!["precompute".into()];
+        argv.extend(table_args(table, Path::new("bake.toml"), &["config"]).unwrap());
+        argv.extend(["--in-file", "in.png", "--std", "0.2"].map(OsString::from));
+        let config = Args::try_parse_from(argv).unwrap().transform_config();
+        assert_eq!(config.mean, 0.25);
+        assert_eq!(config.std, 0.2);
+        assert_eq!(config.distribution, DistributionKind::Uniform);
+        assert!(config.deterministic);
+    }
+
+    #[test]
+    fn config_file_rejects_unknown_and_excluded_keys() {
+        for text in ["no_such_option = 1", "config = \"other.toml\""] {
+            let table: toml::Table = toml::from_str(text).unwrap();
+            assert!(table_args(table, Path::new("bake.toml"), &["config"]).is_err());
+        }
+    }
+
+    #[test]
+    fn timing_report_has_every_phase() {
+        let baked = TransformConfig::default().run(&synthetic_input()).unwrap();
+        let report = timing_report(&baked, Duration::from_millis(3));
+        assert_eq!(report["width"], 64);
+        assert_eq!(report["height"], 64);
+        assert!(report["threads"].as_u64().unwrap() >= 1);
+        let phases = report["phases_ms"].as_object().unwrap();
+        let mut keys: Vec<_> = phases.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["extract", "forward", "inverse_lut", "sort", "write"]);
+        assert!(phases.values().all(|ms| ms.as_f64().unwrap() >= 0.0));
+        assert_eq!(phases["write"], 3.0);
+    }
+
+    #[test]
+    fn invert_reconstructs_the_baked_input() {
+        let dir = scratch_dir("invert");
+        let input = dir.join("in.png");
+        let source = synthetic_input();
+        source.save(&input).unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        run(&[
+            flag("--quiet"),
+            flag("--in-file"),
+            &input,
+            flag("--out-dir"),
+            &dir,
+        ])
+        .unwrap();
+
+        let output = |suffix: &str| dir.join(output_file_name(&input, None, suffix, "tif"));
+        let (gaussian, lut) = (output(IMG_SUFFIX), output(LUT_SUFFIX));
+        run(&[
+            flag("--quiet"),
+            flag("--invert"),
+            &gaussian,
+            flag("--lut"),
+            &lut,
+            flag("--out-dir"),
+            &dir,
+        ])
+        .unwrap();
+        let reconstructed = image::open(dir.join(output_file_name(
+            &gaussian,
+            None,
+            RECONSTRUCTED_SUFFIX,
+            "tif",
+        )))
+        .unwrap()
+        .to_rgb8();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let diff = compare::difference(source.to_rgb8().as_raw(), reconstructed.as_raw());
+        assert!(diff.rmse < 2.5, "rmse {}", diff.rmse);
+    }
+
+    #[test]
+    fn preview_is_a_png_of_the_forward_image() {
+        let dir = scratch_dir("preview");
+        let args = Args::try_parse_from(["precompute", "--in-file", "in.png"]).unwrap();
+        let input = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(40, 24, |x, y| {
+            image::Rgb([(x * 6) as u8, (y * 10) as u8, ((x + y) * 4) as u8])
+        }));
+        let baked = args.transform_config().run(&input).unwrap();
+        let path = dir.join("in-gaussian-preview.png");
+        write_preview(&baked, &args, &path).unwrap();
+
+        let reader = image::io::Reader::open(&path)
+            .unwrap()
+            .with_guessed_format()
+            .unwrap();
+        assert_eq!(reader.format(), Some(image::ImageFormat::Png));
+        let preview = reader.decode().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!((preview.width(), preview.height()), (40, 24));
+        assert_eq!(preview.color(), image::ColorType::Rgb8);
+    }
+
+    #[test]
+    fn strict_fails_a_constant_channel_bake() {
+        let dir = scratch_dir("strict");
+        let input = dir.join("flat.png");
+        // Blue is a single level.
+        image::RgbImage::from_fn(32, 32, |x, y| {
+            image::Rgb([(x * 8) as u8, (y * 8) as u8, 90])
+        })
+        .save(&input)
+        .unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        let bake = |strict: bool| {
+            let mut args = vec![
+                flag("--quiet"),
+                flag("--in-file"),
+                &input,
+                flag("--out-dir"),
+                &dir,
+            ];
+            if strict {
+                args.push(flag("--strict"));
+            }
+            run(&args)
+        };
+        let strict = bake(true);
+        let lenient = bake(false);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let err = strict.unwrap_err().to_string();
+        assert!(
+            err.contains("is constant") && err.ends_with("(--strict)"),
+            "{}",
+            err
+        );
+        lenient.unwrap();
+    }
+
+    #[test]
+    fn separate_io_pools_write_the_same_outputs() {
+        let dir = scratch_dir("threads-io");
+        let inputs = dir.join("inputs");
+        std::fs::create_dir(&inputs).unwrap();
+        for name in ["a.png", "b.png", "c.png"] {
+            synthetic_input().save(inputs.join(name)).unwrap();
+        }
+        let flag = |name: &'static str| Path::new(name);
+        let outputs = |out_dir: &Path| {
+            let mut files: Vec<_> = std::fs::read_dir(out_dir)
+                .unwrap()
+                .map(|entry| {
+                    let path = entry.unwrap().path();
+                    (
+                        path.file_name().unwrap().to_owned(),
+                        std::fs::read(&path).unwrap(),
+                    )
+                })
+                .collect();
+            files.sort();
+            files
+        };
+        // Compute on a two-thread pool of its own, as --threads 2 would.
+        let compute = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let bake = |threads_io: &'static str| {
+            let out_dir = dir.join(format!("out-{}", threads_io));
+            let (single, batch) = (out_dir.join("single"), out_dir.join("batch"));
+            std::fs::create_dir_all(&single).unwrap();
+            std::fs::create_dir_all(&batch).unwrap();
+            let input = inputs.join("a.png");
+            let common = [
+                flag("--quiet"),
+                flag("--deterministic"),
+                flag("--threads-io"),
+            ];
+            compute.install(|| {
+                let mut args = common.to_vec();
+                args.extend([
+                    flag(threads_io),
+                    flag("--in-file"),
+                    &input,
+                    flag("--out-dir"),
+                    &single,
+                ]);
+                run(&args).unwrap();
+                let mut args = common.to_vec();
+                args.extend([
+                    flag(threads_io),
+                    flag("--in-dir"),
+                    &inputs,
+                    flag("--out-dir"),
+                    &batch,
+                ]);
+                run(&args).unwrap();
+            });
+            (outputs(&single), outputs(&batch))
+        };
+        let one = bake("1");
+        let four = bake("4");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(one.0.len(), 2);
+        assert_eq!(one.1.len(), 6);
+        assert!(one == four, "outputs differ between I/O pool sizes");
+        // The single bake writes what the batch writes for the same input.
+        assert!(one.0.iter().all(|file| one.1.contains(file)));
+    }
+
+    #[test]
+    fn rebaking_a_forward_image_is_flagged() {
+        let dir = scratch_dir("rebake");
+        let input = dir.join("ramp.png");
+        image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 4) as u8, (x * 2 + y * 2) as u8])
+        })
+        .save(&input)
+        .unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        let bake = |input: &Path| {
+            run(&[
+                flag("--quiet"),
+                flag("--strict"),
+                flag("--in-file"),
+                input,
+                flag("--out-dir"),
+                &dir,
+            ])
+        };
+        let photo = bake(&input);
+        // Saved as 8 bits, as a forward image exported for viewing would be.
+        let forward =
+            read::read_rgb_f32(&dir.join(output_file_name(&input, None, IMG_SUFFIX, "tif")))
+                .unwrap();
+        let levels = forward.data.iter().map(|&v| (v * 255.0).round() as u8);
+        let exported = dir.join("exported.png");
+        image::RgbImage::from_vec(64, 64, levels.collect())
+            .unwrap()
+            .save(&exported)
+            .unwrap();
+        let rebake = bake(&exported);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        photo.unwrap();
+        let err = rebake.unwrap_err().to_string();
+        assert!(err.contains("already matches the target"), "{}", err);
+    }
+
+    #[test]
+    fn validate_only_fails_on_any_bad_input_and_writes_nothing() {
+        let dir = scratch_dir("validate-only");
+        let good = dir.join("good.png");
+        image::RgbImage::from_fn(32, 32, |x, y| {
+            image::Rgb([(x * 8) as u8, (y * 8) as u8, (x * 4 + y * 4) as u8])
+        })
+        .save(&good)
+        .unwrap();
+        let inputs = dir.join("inputs");
+        std::fs::create_dir(&inputs).unwrap();
+        std::fs::copy(&good, inputs.join("good.png")).unwrap();
+        std::fs::write(inputs.join("broken.png"), b"not a png").unwrap();
+        let out_dir = dir.join("out");
+        let flag = |name: &'static str| Path::new(name);
+        let validate = |source: &'static str, input: &Path| {
+            run(&[
+                flag("--validate-only"),
+                flag(source),
+                input,
+                flag("--out-dir"),
+                &out_dir,
+            ])
+        };
+        let mixed = validate("--in-dir", &inputs);
+        let valid = validate("--in-file", &good);
+        let wrote_outputs = out_dir.exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let err = mixed.unwrap_err().to_string();
+        assert_eq!(err, "1 inputs failed validation");
+        valid.unwrap();
+        assert!(!wrote_outputs);
+    }
+
+    #[cfg(feature = "completions")]
+    #[test]
+    fn completion_scripts_cover_the_flags() {
+        for shell in [
+            CompletionShell::Bash,
+            CompletionShell::Zsh,
+            CompletionShell::Fish,
+            CompletionShell::Powershell,
+            CompletionShell::Elvish,
+        ] {
+            let script = String::from_utf8(completion_script(shell)).unwrap();
+            for flag in ["lut-size", "in-dir", "resume"] {
+                assert!(script.contains(flag), "{:?} has no {}", shell, flag);
+            }
+        }
+    }
+
+    #[test]
+    fn manifest_hashes_match_the_written_files() {
+        let dir = scratch_dir("manifest");
+        let inputs = dir.join("inputs");
+        std::fs::create_dir(&inputs).unwrap();
+        for name in ["a.png", "b.png"] {
+            synthetic_input().save(inputs.join(name)).unwrap();
+        }
+        let (out_dir, cache_dir) = (dir.join("out"), dir.join("cache"));
+        std::fs::create_dir(&out_dir).unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        for (algorithm, name) in [
+            (HashAlgorithm::Sha256, "sha256"),
+            (HashAlgorithm::Crc32, "crc32"),
+        ] {
+            let manifest = dir.join(format!("{}.json", name));
+            let argv = [
+                flag("precompute"),
+                flag("--quiet"),
+                flag("--in-dir"),
+                &inputs,
+                flag("--out-dir"),
+                &out_dir,
+                flag("--channel-cache"),
+                &cache_dir,
+                flag("--manifest"),
+                &manifest,
+                flag("--manifest-hash"),
+                flag(name),
+            ];
+            run_with_manifest(Args::try_parse_from(argv).unwrap(), &CancelToken::new()).unwrap();
+
+            let manifest: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(&manifest).unwrap()).unwrap();
+            assert_eq!(manifest["algorithm"], name);
+            let files = manifest["files"].as_object().unwrap();
+            // Both outputs of both inputs, and no channel cache entries.
+            let mut listed: Vec<PathBuf> = files.keys().map(PathBuf::from).collect();
+            listed.sort();
+            let mut written: Vec<PathBuf> = std::fs::read_dir(&out_dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .collect();
+            written.sort();
+            assert_eq!(listed, written);
+            assert_eq!(listed.len(), 4);
+            for (path, entry) in files {
+                let bytes = std::fs::read(path).unwrap();
+                assert_eq!(entry["size"], bytes.len());
+                assert_eq!(entry["hash"], digest::hash_bytes(&bytes, algorithm));
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn limit_processes_only_the_first_inputs_in_name_order() {
+        let dir = scratch_dir("limit");
+        let inputs = dir.join("inputs");
+        std::fs::create_dir(&inputs).unwrap();
+        // Written out of order, so the cut can't follow creation order.
+        for name in ["d.png", "b.png", "e.png", "a.png", "c.png"] {
+            synthetic_input().save(inputs.join(name)).unwrap();
+        }
+        let out_dir = dir.join("out");
+        std::fs::create_dir(&out_dir).unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        run(&[
+            flag("--quiet"),
+            flag("--in-dir"),
+            &inputs,
+            flag("--out-dir"),
+            &out_dir,
+            flag("--limit"),
+            flag("2"),
+        ])
+        .unwrap();
+        let mut written: Vec<_> = std::fs::read_dir(&out_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        written.sort();
+        let mut expected: Vec<_> = ["a.png", "b.png"]
+            .iter()
+            .flat_map(|name| {
+                let input = inputs.join(name);
+                [
+                    output_file_name(&input, None, IMG_SUFFIX, "tif"),
+                    output_file_name(&input, None, LUT_SUFFIX, "tif"),
+                ]
+            })
+            .map(std::ffi::OsString::from)
+            .collect();
+        expected.sort();
+        assert_eq!(written, expected);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn split_channels_match_the_combined_outputs() {
+        let dir = scratch_dir("split-channels");
+        let input = dir.join("in.png");
+        synthetic_input().save(&input).unwrap();
+        let (combined, split) = (dir.join("combined"), dir.join("split"));
+        let flag = |name: &'static str| Path::new(name);
+        for (out_dir, extra) in [(&combined, None), (&split, Some(flag("--split-channels")))] {
+            std::fs::create_dir(out_dir).unwrap();
+            let mut args = vec![
+                flag("--quiet"),
+                flag("--deterministic"),
+                flag("--in-file"),
+                &input,
+                flag("--out-dir"),
+                out_dir,
+            ];
+            args.extend(extra);
+            run(&args).unwrap();
+        }
+        let img_name = output_file_name(&input, None, IMG_SUFFIX, "tif");
+        let lut_name = output_file_name(&input, None, LUT_SUFFIX, "tif");
+        let image = read::read_rgb_f32(&combined.join(&img_name)).unwrap();
+        let lut = image::open(combined.join(&lut_name)).unwrap().to_rgb8();
+        let mut written: Vec<_> = std::fs::read_dir(&split)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        written.sort();
+        assert_eq!(
+            written,
+            [
+                "in-gaussian-b.tif",
+                "in-gaussian-g.tif",
+                "in-gaussian-r.tif",
+                "in-lut-b.tif",
+                "in-lut-g.tif",
+                "in-lut-r.tif"
+            ]
+        );
+        for (channel, name) in ["r", "g", "b"].into_iter().enumerate() {
+            let image_channel =
+                read::read_gray_f32(&split.join(format!("in-gaussian-{}.tif", name))).unwrap();
+            assert_eq!(
+                (image_channel.width, image_channel.height),
+                (image.width, image.height)
+            );
+            let expected: Vec<f32> = image
+                .data
+                .iter()
+                .skip(channel)
+                .step_by(3)
+                .copied()
+                .collect();
+            assert_eq!(image_channel.data, expected, "channel {}", name);
+            let lut_channel =
+                read::read_gray_u8(&split.join(format!("in-lut-{}.tif", name))).unwrap();
+            let expected: Vec<u8> = lut
+                .as_raw()
+                .iter()
+                .skip(channel)
+                .step_by(3)
+                .copied()
+                .collect();
+            assert_eq!(lut_channel.data, expected, "channel {}", name);
+        }
+
+        // The forward image and LUT can't be given the same name.
+        let err = run(&[
+            flag("--quiet"),
+            flag("--split-channels"),
+            flag("--in-file"),
+            &input,
+            flag("--out-dir"),
+            &split,
+            flag("--img-prefix"),
+            flag("same"),
+            flag("--lut-prefix"),
+            flag("same"),
+        ])
+        .unwrap_err();
+        assert!(err.to_string().contains("would write"), "{}", err);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn timed_out_input_fails_and_the_batch_carries_on() {
+        let dir = scratch_dir("timeout");
+        let inputs = dir.join("inputs");
+        std::fs::create_dir(&inputs).unwrap();
+        for name in ["a.png", "c.png"] {
+            synthetic_input().save(inputs.join(name)).unwrap();
+        }
+        // Far too large to bake within a second in a test build.
+        image::RgbImage::from_fn(4096, 4096, |x, y| {
+            image::Rgb([(x ^ y) as u8, (x * 3 + y) as u8, (x + y * 7) as u8])
+        })
+        .save(inputs.join("b.tif"))
+        .unwrap();
+        let (out_dir, report) = (dir.join("out"), dir.join("summary.json"));
+        std::fs::create_dir(&out_dir).unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        let err = run(&[
+            flag("--quiet"),
+            flag("--in-dir"),
+            &inputs,
+            flag("--out-dir"),
+            &out_dir,
+            flag("--timeout"),
+            flag("1"),
+            flag("--summary-json"),
+            &report,
+        ])
+        .unwrap_err();
+        assert_eq!(err.to_string(), "1 files failed");
+        let report: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+        let outcomes = report["inputs"].as_array().unwrap();
+        let statuses: Vec<_> = outcomes
+            .iter()
+            .map(|o| o["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(statuses, ["written", "failed", "written"]);
+        assert_eq!(outcomes[1]["error"], "Timed out after 1 s");
+        let mut written: Vec<_> = std::fs::read_dir(&out_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        written.sort();
+        assert_eq!(
+            written,
+            ["a-gaussian.tif", "a-lut.tif", "c-gaussian.tif", "c-lut.tif"]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn each_job_bakes_with_its_own_options() {
+        let dir = scratch_dir("jobs");
+        for name in ["a.png", "b.png"] {
+            synthetic_input().save(dir.join(name)).unwrap();
+        }
+        let jobs = dir.join("jobs.toml");
+        std::fs::write(
+            &jobs,
+            "[[job]]\ninput = \"a.png\"\nstd = 0.1\n\n[[job]]\ninput = \"b.png\"\nstd = 0.2\n",
+        )
+        .unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        run(&[
+            flag("--quiet"),
+            flag("--deterministic"),
+            flag("--jobs-from-file"),
+            &jobs,
+            flag("--out-dir"),
+            &dir,
+        ])
+        .unwrap();
+        for (name, std) in [("a.png", 0.1), ("b.png", 0.2)] {
+            let forward = read::read_rgb_f32(&dir.join(output_file_name(
+                &dir.join(name),
+                None,
+                IMG_SUFFIX,
+                "tif",
+            )))
+            .unwrap();
+            // The same input, so only the job's std tells them apart.
+            let expected = TransformConfig::default()
+                .deterministic(true)
+                .std(std)
+                .run(&synthetic_input())
+                .unwrap();
+            assert_eq!(forward.data, expected.image, "{}", name);
+        }
+
+        // A job that fails doesn't stop the others.
+        std::fs::remove_file(dir.join(output_file_name(
+            &dir.join("b.png"),
+            None,
+            IMG_SUFFIX,
+            "tif",
+        )))
+        .unwrap();
+        let json = dir.join("jobs.json");
+        std::fs::write(
+            &json,
+            r#"{"job": [{"input": "missing.png"}, {"input": "b.png", "std": 0.2}]}"#,
+        )
+        .unwrap();
+        let err = run(&[
+            flag("--quiet"),
+            flag("--jobs-from-file"),
+            &json,
+            flag("--out-dir"),
+            &dir,
+        ]);
+        assert!(err.is_err());
+        assert!(dir
+            .join(output_file_name(
+                &dir.join("b.png"),
+                None,
+                IMG_SUFFIX,
+                "tif"
+            ))
+            .exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn batch_into_its_own_input_dir_doesnt_rebake_its_outputs() {
+        let dir = scratch_dir("overlap");
+        for name in ["a.png", "b.png"] {
+            synthetic_input().save(dir.join(name)).unwrap();
+        }
+        let flag = |name: &'static str| Path::new(name);
+        let listing = || {
+            let mut names: Vec<_> = std::fs::read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            names.sort();
+            names
+        };
+        let batch = |extra: &[&Path]| {
+            let mut args = vec![
+                flag("--quiet"),
+                flag("--in-dir"),
+                &dir,
+                flag("--out-dir"),
+                &dir,
+            ];
+            args.extend(extra);
+            run(&args).unwrap();
+        };
+        batch(&[]);
+        let first = listing();
+        assert_eq!(
+            first,
+            [
+                "a-gaussian.tif",
+                "a-lut.tif",
+                "a.png",
+                "b-gaussian.tif",
+                "b-lut.tif",
+                "b.png"
+            ]
+        );
+        // Run again, the outputs of the first run aren't inputs of the second.
+        batch(&[]);
+        assert_eq!(listing(), first);
+
+        batch(&[flag("--allow-overlap")]);
+        assert!(listing().contains(&"a-lut-gaussian.tif".to_string()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resized_inputs_bake_at_the_requested_size() {
+        let dir = scratch_dir("resize");
+        let input = dir.join("in.png");
+        synthetic_input().save(&input).unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        let bake = |size: &'static str, filter: &'static str| {
+            run(&[
+                flag("--quiet"),
+                flag("--in-file"),
+                &input,
+                flag("--out-dir"),
+                &dir,
+                flag("--resize-input"),
+                flag(size),
+                flag("--resize-filter"),
+                flag(filter),
+            ])
+        };
+        bake("100,30", "nearest").unwrap();
+        let forward =
+            read::read_rgb_f32(&dir.join(output_file_name(&input, None, IMG_SUFFIX, "tif")))
+                .unwrap();
+        assert_eq!((forward.width, forward.height), (100, 30));
+        let lut = image::open(dir.join(output_file_name(&input, None, LUT_SUFFIX, "tif"))).unwrap();
+        assert_eq!(lut.width(), precompute::default_lut_size(100, 30));
+        // Nearest resampling only repeats source values, so the LUT holds
+        // nothing the input doesn't.
+        let source: std::collections::HashSet<u8> =
+            synthetic_input().to_rgb8().into_raw().into_iter().collect();
+        assert!(lut.to_rgb8().as_raw().iter().all(|v| source.contains(v)));
+
+        assert!(bake("0,30", "nearest").is_err());
+        assert!(bake("100,30", "bicubic").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn forced_alpha_is_constant_and_keeps_the_rgb_bake() {
+        let dir = scratch_dir("force-alpha");
+        let input = dir.join("in.png");
+        synthetic_input().save(&input).unwrap();
+        let (rgb_dir, rgba_dir) = (dir.join("rgb"), dir.join("rgba"));
+        let flag = |name: &'static str| Path::new(name);
+        for (out_dir, extra) in [
+            (&rgb_dir, vec![]),
+            (&rgba_dir, vec![flag("--force-alpha"), flag("0.5")]),
+        ] {
+            std::fs::create_dir(out_dir).unwrap();
+            let mut args = vec![
+                flag("--quiet"),
+                flag("--deterministic"),
+                flag("--in-file"),
+                &input,
+                flag("--out-dir"),
+                out_dir,
+            ];
+            args.extend(extra);
+            run(&args).unwrap();
+        }
+        let name = output_file_name(&input, None, IMG_SUFFIX, "tif");
+        let rgb = read::read_rgb_f32(&rgb_dir.join(&name)).unwrap();
+        let rgba = read::read_tiff_pages(&rgba_dir.join(&name))
+            .unwrap()
+            .remove(0);
+        let DynamicImage::ImageRgba32F(rgba) = rgba else {
+            panic!("{:?} isn't RGBA float", rgba.color());
+        };
+        assert_eq!(rgba.dimensions(), (rgb.width, rgb.height));
+        for (pixel, expected) in rgba.pixels().zip(rgb.data.chunks_exact(3)) {
+            assert_eq!(&pixel.0[..3], expected);
+            assert_eq!(pixel.0[3], 0.5);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bakes_are_bit_exact_across_thread_counts() {
+        let dir = scratch_dir("bit-exact-float");
+        let input = dir.join("in.png");
+        synthetic_input().save(&input).unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        let options: [&[&str]; 4] = [
+            &[],
+            &["--lut-interp", "linear"],
+            &["--trim-tails", "2", "--plotting-position", "blom"],
+            &["--shared-lut"],
+        ];
+        for (i, extra) in options.iter().enumerate() {
+            let bake = |threads: usize| {
+                let out_dir = dir.join(format!("{}-{}", i, threads));
+                std::fs::create_dir(&out_dir).unwrap();
+                let mut args = vec![
+                    flag("--quiet"),
+                    flag("--deterministic"),
+                    flag("--bit-exact-float"),
+                    flag("--in-file"),
+                    &input,
+                    flag("--out-dir"),
+                    &out_dir,
+                ];
+                args.extend(extra.iter().map(Path::new));
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .unwrap();
+                pool.install(|| run(&args)).unwrap();
+                [IMG_SUFFIX, LUT_SUFFIX].map(|suffix| {
+                    std::fs::read(out_dir.join(output_file_name(&input, None, suffix, "tif")))
+                        .unwrap()
+                })
+            };
+            let single = bake(1);
+            for threads in [2, 8] {
+                assert!(
+                    bake(threads) == single,
+                    "{:?} on {} threads",
+                    extra,
+                    threads
+                );
+            }
+        }
+
+        let result = TransformConfig::default()
+            .deterministic(true)
+            .run(&synthetic_input())
+            .unwrap();
+        assert!(check_bit_exact(&result, &result).is_ok());
+        let mut nudged = result.clone();
+        nudged.image[5] = f32::from_bits(nudged.image[5].to_bits() + 1);
+        let err = check_bit_exact(&result, &nudged).unwrap_err().to_string();
+        assert!(
+            err.starts_with("--bit-exact-float: subpixel 5 of the forward image"),
+            "{}",
+            err
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn diff_against_an_identical_bake_is_all_cold() {
+        let dir = scratch_dir("diff-against");
+        let input = dir.join("in.png");
+        synthetic_input().save(&input).unwrap();
+        let baseline = dir.join("baseline.tif");
+        let flag = |name: &'static str| Path::new(name);
+        let common = [
+            flag("--quiet"),
+            flag("--deterministic"),
+            flag("--in-file"),
+            &input,
+            flag("--out-dir"),
+            &dir,
+        ];
+        run(&common).unwrap();
+        let forward = dir.join(output_file_name(&input, None, IMG_SUFFIX, "tif"));
+        std::fs::copy(&forward, &baseline).unwrap();
+        let diff_path = dir.join(format!(
+            "{}-diff.png",
+            forward.file_stem().unwrap().to_str().unwrap()
+        ));
+        let diff = |extra: &[&Path]| {
+            let mut args = common.to_vec();
+            args.extend([flag("--diff-against"), &baseline]);
+            args.extend(extra);
+            run(&args).unwrap();
+            image::open(&diff_path).unwrap().to_rgb8()
+        };
+        let same = diff(&[]);
+        let source = synthetic_input();
+        assert_eq!(same.dimensions(), (source.width(), source.height()));
+        assert!(same.pixels().all(|p| p.0 == [0, 0, 4]));
+        let changed = diff(&[flag("--std"), flag("0.2")]);
+        assert!(changed.pixels().any(|p| p.0 != [0, 0, 4]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn merging_split_luts_reproduces_the_combined_lut() {
+        let dir = scratch_dir("merge-luts");
+        let input = dir.join("in.png");
+        synthetic_input().save(&input).unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        let lut_name = output_file_name(&input, None, LUT_SUFFIX, "tif");
+        for precision in ["u8", "f32"] {
+            let (combined, split) = (dir.join("combined"), dir.join("split"));
+            for (out_dir, extra) in [(&combined, None), (&split, Some(flag("--split-channels")))] {
+                std::fs::create_dir_all(out_dir).unwrap();
+                let mut args = vec![
+                    flag("--quiet"),
+                    flag("--deterministic"),
+                    flag("--lut-rows"),
+                    flag("4"),
+                    flag("--lut-precision"),
+                    Path::new(precision),
+                    flag("--in-file"),
+                    &input,
+                    flag("--out-dir"),
+                    out_dir,
+                ];
+                args.extend(extra);
+                run(&args).unwrap();
+            }
+            let channels: Vec<PathBuf> = split_file_names(&lut_name, 3)
+                .iter()
+                .map(|name| split.join(name))
+                .collect();
+            let merged = dir.join(format!("merged-{}.tif", precision));
+            let mut args = vec![
+                flag("--quiet"),
+                flag("--lut-precision"),
+                Path::new(precision),
+            ];
+            args.push(flag("merge-luts"));
+            args.extend(channels.iter().map(PathBuf::as_path));
+            args.push(&merged);
+            run(&args).unwrap();
+            assert_eq!(
+                std::fs::read(&merged).unwrap(),
+                std::fs::read(combined.join(&lut_name)).unwrap(),
+                "{}",
+                precision
+            );
+            std::fs::remove_dir_all(&combined).unwrap();
+            std::fs::remove_dir_all(&split).unwrap();
+        }
+
+        // A LUT of another size is rejected.
+        let other = dir.join("other");
+        std::fs::create_dir(&other).unwrap();
+        let flags = [
+            flag("--quiet"),
+            flag("--split-channels"),
+            flag("--lut-size"),
+        ];
+        let mut args = flags.to_vec();
+        args.extend([
+            flag("64"),
+            flag("--in-file"),
+            &input,
+            flag("--out-dir"),
+            &other,
+        ]);
+        run(&args).unwrap();
+        let mut args = flags[..2].to_vec();
+        args.extend([flag("--in-file"), &input, flag("--out-dir"), &dir]);
+        run(&args).unwrap();
+        let names = split_file_names(&lut_name, 3);
+        let (red, green) = (other.join(&names[0]), dir.join(&names[1]));
+        let blue = dir.join(&names[2]);
+        let merged = dir.join("mismatched.tif");
+        let err = run(&[flag("merge-luts"), &red, &green, &blue, &merged]).unwrap_err();
+        assert!(err.to_string().ends_with("is 64x1"), "{}", err);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn depth_promotion_and_demotion_warn_and_keep_values() {
+        let dir = scratch_dir("depth");
+        let (input, deep) = (dir.join("in.png"), dir.join("deep.png"));
+        let source = synthetic_input().to_rgb8();
+        DynamicImage::ImageRgb8(source.clone())
+            .save(&input)
+            .unwrap();
+        // The same levels at 16 bits, with low bits that round away.
+        let deep16 = image::ImageBuffer::from_fn(source.width(), source.height(), |x, y| {
+            image::Rgb(
+                source
+                    .get_pixel(x, y)
+                    .0
+                    .map(|v| (v as u16 * 257).saturating_add(((x * 7 + y * 13) % 128) as u16)),
+            )
+        });
+        DynamicImage::ImageRgb16(deep16).save(&deep).unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        let bake = |input: &Path, extra: &[&Path]| {
+            let out_dir = dir.join(format!(
+                "{}-{}",
+                input.file_stem().unwrap().to_str().unwrap(),
+                extra.len()
+            ));
+            std::fs::create_dir_all(&out_dir).unwrap();
+            let mut args = vec![
+                flag("--quiet"),
+                flag("--deterministic"),
+                flag("--in-file"),
+                input,
+                flag("--out-dir"),
+                &out_dir,
+            ];
+            args.extend(extra);
+            run(&args).map(|()| {
+                [IMG_SUFFIX, LUT_SUFFIX]
+                    .map(|suffix| out_dir.join(output_file_name(input, None, suffix, "tif")))
+            })
+        };
+
+        // 16 bits demoted to 8: a warning, and the 8-bit bake's outputs.
+        let err = bake(&deep, &[flag("--strict")]).unwrap_err().to_string();
+        assert!(
+            err.contains("has 16-bit samples, but they're rounded to 8 bits"),
+            "{}",
+            err
+        );
+        let [image, lut] = bake(&input, &[]).unwrap();
+        let [deep_image, deep_lut] = bake(&deep, &[]).unwrap();
+        assert_eq!(
+            std::fs::read(&image).unwrap(),
+            std::fs::read(deep_image).unwrap()
+        );
+        assert_eq!(
+            std::fs::read(&lut).unwrap(),
+            std::fs::read(deep_lut).unwrap()
+        );
+
+        // 8 bits promoted to 16: a warning, and the levels in the high byte.
+        let u16_lut = [flag("--lut-precision"), flag("u16")];
+        let mut strict = u16_lut.to_vec();
+        strict.push(flag("--strict"));
+        let err = bake(&input, &strict).unwrap_err().to_string();
+        assert!(
+            err.contains("promoted to 16 bits with zero low bits"),
+            "{}",
+            err
+        );
+        let [_, promoted] = bake(&input, &u16_lut).unwrap();
+        let promoted = image::open(promoted).unwrap();
+        let DynamicImage::ImageRgb16(promoted) = promoted else {
+            panic!("{:?} isn't 16-bit RGB", promoted.color());
+        };
+        let levels = image::open(&lut).unwrap().to_rgb8();
+        assert_eq!(promoted.dimensions(), levels.dimensions());
+        for (&wide, &level) in promoted.as_raw().iter().zip(levels.as_raw()) {
+            assert_eq!(wide, (level as u16) << 8);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mask_file_matches_the_embedded_alpha() {
+        let dir = scratch_dir("mask-file");
+        let rgb = synthetic_input().to_rgb8();
+        let (width, height) = rgb.dimensions();
+        let alpha = |x: u32, y: u32| ((x * 5 + y * 3) % 256) as u8;
+        let mask = image::GrayImage::from_fn(width, height, |x, y| image::Luma([alpha(x, y)]));
+        let rgba = image::RgbaImage::from_fn(width, height, |x, y| {
+            let [r, g, b] = rgb.get_pixel(x, y).0;
+            image::Rgba([r, g, b, alpha(x, y)])
+        });
+        let (color, mask_path, embedded) = (
+            dir.join("color.png"),
+            dir.join("mask.png"),
+            dir.join("embedded.png"),
+        );
+        rgb.save(&color).unwrap();
+        mask.save(&mask_path).unwrap();
+        rgba.save(&embedded).unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        let bake = |input: &Path, extra: &[&Path]| {
+            let out_dir = dir.join(input.file_stem().unwrap());
+            std::fs::create_dir_all(&out_dir).unwrap();
+            let mut args = vec![
+                flag("--quiet"),
+                flag("--deterministic"),
+                flag("--alpha-cutoff"),
+                flag("0.5"),
+                flag("--in-file"),
+                input,
+                flag("--out-dir"),
+                &out_dir,
+            ];
+            args.extend(extra);
+            run(&args).map(|()| {
+                [IMG_SUFFIX, LUT_SUFFIX].map(|suffix| {
+                    std::fs::read(out_dir.join(output_file_name(input, None, suffix, "tif")))
+                        .unwrap()
+                })
+            })
+        };
+        let external = bake(&color, &[flag("--mask-file"), &mask_path]).unwrap();
+        assert!(external == bake(&embedded, &[]).unwrap());
+        // Without the mask, more pixels count and the LUT changes.
+        assert!(external != bake(&color, &[]).unwrap());
+
+        let small = dir.join("small.png");
+        image::GrayImage::new(width / 2, height)
+            .save(&small)
+            .unwrap();
+        let err = bake(&color, &[flag("--mask-file"), &small]).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(&format!("is {}x{}, expected", width / 2, height)),
+            "{}",
+            err
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn describe_round_trips_the_baked_parameters() {
+        let dir = scratch_dir("describe");
+        let input = dir.join("in.png");
+        synthetic_input().save(&input).unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        run(&[
+            flag("--quiet"),
+            flag("--sidecar"),
+            flag("--mean"),
+            flag("0.4"),
+            flag("--std"),
+            flag("0.1"),
+            flag("--distribution"),
+            flag("uniform"),
+            flag("--lut-size"),
+            flag("128"),
+            flag("--in-file"),
+            &input,
+            flag("--out-dir"),
+            &dir,
+        ])
+        .unwrap();
+        for suffix in [IMG_SUFFIX, LUT_SUFFIX] {
+            let output = dir.join(output_file_name(&input, None, suffix, "tif"));
+            let mut described = Vec::new();
+            describe(&output, &mut described).unwrap();
+            let described = String::from_utf8(described).unwrap();
+            let recorded = Sidecar::find(&output).unwrap().unwrap().config;
+            assert_eq!(
+                (recorded.mean, recorded.std, recorded.lut_size),
+                (0.4, 0.1, Some(128))
+            );
+            assert_eq!(recorded.distribution, DistributionKind::Uniform);
+            for line in [
+                "  config.mean: 0.4",
+                "  config.std: 0.1",
+                "  config.distribution: uniform",
+                "  config.lut_size: 128",
+            ] {
+                assert!(
+                    described.lines().any(|l| l == line),
+                    "{} in\n{}",
+                    line,
+                    described
+                );
+            }
+            assert!(described.contains("TIFF, 1 page(s)"), "{}", described);
+        }
+        let mut described = Vec::new();
+        describe(&input, &mut described).unwrap();
+        assert!(String::from_utf8(described)
+            .unwrap()
+            .starts_with("No .meta.json sidecar"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn srgb_lut_variant_is_the_encoded_linear_variant() {
+        let dir = scratch_dir("lut-variants");
+        let input = dir.join("in.png");
+        synthetic_input().save(&input).unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        run(&[
+            flag("--quiet"),
+            flag("--deterministic"),
+            flag("--lut-precision"),
+            flag("f32"),
+            flag("--lut-variants"),
+            flag("linear,srgb"),
+            flag("--in-file"),
+            &input,
+            flag("--out-dir"),
+            &dir,
+        ])
+        .unwrap();
+        let lut_name = output_file_name(&input, None, LUT_SUFFIX, "tif");
+        let read_variant = |variant: Option<Transfer>| {
+            let name = match variant {
+                Some(variant) => lut_variant_file_name(&lut_name, variant),
+                None => lut_name.clone(),
+            };
+            read::read_rgb_f32(&dir.join(name)).unwrap().data
+        };
+        let (lut, linear) = (read_variant(None), read_variant(Some(Transfer::Linear)));
+        let srgb = read_variant(Some(Transfer::Srgb));
+        // The source is sRGB, so its own space's variant is the usual LUT.
+        assert_eq!(srgb, lut);
+        assert_eq!(linear.len(), srgb.len());
+        for (&linear, &srgb) in linear.iter().zip(&srgb) {
+            assert!((Transfer::Linear.convert(Transfer::Srgb, linear) - srgb).abs() < 1e-6);
+            assert!(linear <= srgb + 1e-7);
+        }
+        assert!(linear != srgb);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn combined_histogram_gives_every_channel_one_mapping() {
+        let dir = scratch_dir("combined-histogram");
+        let input = dir.join("in.png");
+        synthetic_input().save(&input).unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        let bake = |mode: &'static str, out_dir: &Path| {
+            std::fs::create_dir_all(out_dir).unwrap();
+            run(&[
+                flag("--quiet"),
+                flag("--deterministic"),
+                flag(mode),
+                flag("--in-file"),
+                &input,
+                flag("--out-dir"),
+                out_dir,
+            ])
+            .unwrap();
+            let read =
+                |suffix| std::fs::read(out_dir.join(output_file_name(&input, None, suffix, "tif")));
+            (read(IMG_SUFFIX).unwrap(), read(LUT_SUFFIX).unwrap())
+        };
+        let combined = bake("--combined-histogram", &dir.join("combined"));
+        assert_eq!(combined, bake("--shared-lut", &dir.join("shared")));
+
+        let lut_path = dir
+            .join("combined")
+            .join(output_file_name(&input, None, LUT_SUFFIX, "tif"));
+        let lut = image::open(lut_path).unwrap().into_rgb8();
+        assert!(lut
+            .pixels()
+            .all(|texel| texel[0] == texel[1] && texel[1] == texel[2]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rebakes_default_to_the_embedded_parameters() {
+        let dir = scratch_dir("embedded-params");
+        let input = dir.join("in.png");
+        synthetic_input().save(&input).unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        let options = [
+            "--deterministic",
+            "--mean",
+            "0.4",
+            "--std",
+            "0.2",
+            "--distribution",
+            "uniform",
+            "--plotting-position",
+            "blom",
+        ];
+        let mut bake: Vec<&Path> = options.iter().map(|&o| flag(o)).collect();
+        bake.extend([
+            flag("--quiet"),
+            flag("--in-file"),
+            &input,
+            flag("--out-dir"),
+            &dir,
+        ]);
+        run(&bake).unwrap();
+        let baked_with = Args::try_parse_from(
+            std::iter::once("precompute")
+                .chain(options)
+                .chain(["--in-file", "in.png"]),
+        )
+        .unwrap()
+        .transform_config();
+
+        let forward = dir.join(output_file_name(&input, None, IMG_SUFFIX, "tif"));
+        let rebake = |extra: &[&str]| {
+            let argv: Vec<OsString> = ["precompute", "--quiet", "--in-file"]
+                .into_iter()
+                .map(OsString::from)
+                .chain([forward.clone().into_os_string()])
+                .chain(extra.iter().map(OsString::from))
+                .collect();
+            Args::try_parse_from(command_line(argv).unwrap())
+                .unwrap()
+                .transform_config()
+        };
+        assert_eq!(rebake(&[]), baked_with);
+        assert_eq!(rebake(&["--std", "0.3"]), baked_with.clone().std(0.3));
+        assert_eq!(
+            rebake(&["--ignore-embedded-params"]),
+            TransformConfig::default()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn input_list_bakes_every_listed_path() {
+        let dir = scratch_dir("input-list");
+        let inputs = [
+            dir.join("a").join("first.png"),
+            dir.join("b").join("second.png"),
+        ];
+        for input in &inputs {
+            std::fs::create_dir_all(input.parent().unwrap()).unwrap();
+            synthetic_input().save(input).unwrap();
+        }
+        let list = dir.join("list.txt");
+        let lines: Vec<_> = inputs
+            .iter()
+            .map(|input| input.display().to_string())
+            .collect();
+        std::fs::write(&list, lines.join("\n\n")).unwrap();
+        let out_dir = dir.join("out");
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        run(&[
+            flag("--quiet"),
+            flag("--input-list"),
+            &list,
+            flag("--out-dir"),
+            &out_dir,
+        ])
+        .unwrap();
+        for input in &inputs {
+            for suffix in [IMG_SUFFIX, LUT_SUFFIX] {
+                assert!(out_dir
+                    .join(output_file_name(input, None, suffix, "tif"))
+                    .is_file());
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn events_socket_streams_each_file_in_sequence() {
+        let dir = scratch_dir("events");
+        let inputs = [dir.join("a.png"), dir.join("b.png"), dir.join("broken.png")];
+        synthetic_input().save(&inputs[0]).unwrap();
+        synthetic_input().save(&inputs[1]).unwrap();
+        std::fs::write(&inputs[2], b"not a png").unwrap();
+        let list = dir.join("list.txt");
+        let lines: Vec<_> = inputs
+            .iter()
+            .map(|input| input.display().to_string())
+            .collect();
+        std::fs::write(&list, lines.join("\n")).unwrap();
+        let out_dir = dir.join("out");
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let socket = dir.join("events.sock");
+        let listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+        let reader = std::thread::spawn(move || {
+            use std::io::Read;
+            let mut text = String::new();
+            listener
+                .accept()
+                .unwrap()
+                .0
+                .read_to_string(&mut text)
+                .unwrap();
+            text
+        });
+        let target = OsString::from(format!("socket:{}", socket.display()));
+        let flag = |name: &'static str| Path::new(name);
+        let result = run(&[
+            flag("--quiet"),
+            flag("--input-list"),
+            &list,
+            flag("--out-dir"),
+            &out_dir,
+            flag("--events"),
+            Path::new(&target),
+        ]);
+        let events: Vec<serde_json::Value> = reader
+            .join()
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_err());
+
+        let kind = |event: &serde_json::Value| event["event"].as_str().unwrap().to_string();
+        assert_eq!(kind(&events[0]), "batch_started");
+        assert_eq!(events[0]["files"], 3);
+        let last = events.last().unwrap();
+        assert_eq!(kind(last), "batch_finished");
+        assert_eq!((&last["written"], &last["failed"]), (&2.into(), &1.into()));
+        for input in &inputs {
+            let sequence: Vec<_> = events
+                .iter()
+                .filter(|event| event["input"] == input.display().to_string())
+                .map(|event| match event["phase"].as_str() {
+                    Some(phase) => format!("phase {}", phase),
+                    None => kind(event),
+                })
+                .collect();
+            let expected: &[&str] = if input.ends_with("broken.png") {
+                &["file_started", "phase bake", "error"]
+            } else {
+                &[
+                    "file_started",
+                    "phase bake",
+                    "phase write",
+                    "file_completed",
+                ]
+            };
+            assert_eq!(sequence, expected, "{:?}", input);
+        }
+        let done: Vec<_> = events
+            .iter()
+            .filter_map(|event| event["done"].as_u64())
+            .collect();
+        assert_eq!(done, [1, 2, 3]);
+    }
+
+    #[test]
+    fn self_test_fails_on_any_failed_check() {
+        run(&[Path::new("self-test")]).unwrap();
+        let checks = [
+            self_test::SelfTestCheck {
+                name: "bake",
+                failure: None,
+            },
+            self_test::SelfTestCheck {
+                name: "LUT matches the recorded one",
+                failure: Some("SHA-256 00, expected 1d".to_string()),
+            },
+        ];
+        let err = report_self_test(&checks).unwrap_err();
+        assert_eq!(err.to_string(), "1 self-test checks failed");
+        assert!(report_self_test(&checks[..1]).is_ok());
+    }
+
+    #[test]
+    fn sigma_from_range_parses_and_excludes_std() {
+        assert_eq!(
+            parse_sigma_from_range("0.1, 0.9,99"),
+            Ok(Gaussian::from_range(0.1, 0.9, 0.99))
+        );
+        for bad in [
+            "0.9,0.1,99",
+            "0.1,0.9,100",
+            "0.1,0.9,0",
+            "0.1,0.9",
+            "a,0.9,99",
+        ] {
+            assert!(parse_sigma_from_range(bad).is_err(), "{}", bad);
+        }
+        let parse = |extra: &[&str]| {
+            let fixed = [
+                "precompute",
+                "--in-file",
+                "in.png",
+                "--sigma-from-range",
+                "0.1,0.9,99",
+            ];
+            Args::try_parse_from(fixed.iter().chain(extra))
+        };
+        assert!(parse(&[]).is_ok());
+        assert!(parse(&["--std", "0.2"]).is_err());
+    }
+
+    /// An 8-bit indexed PNG of `indices`, with a gray palette whose entry
+    /// `i` is level `i` and whose first `alphas.len()` entries have those
+    /// alphas, written with stored deflate blocks.
+    fn indexed_png(width: u32, height: u32, indices: &[u8], alphas: &[u8]) -> Vec<u8> {
+        let crc = |bytes: &[u8]| {
+            let mut crc = !0u32;
+            for &b in bytes {
+                crc ^= b as u32;
+                for _ in 0..8 {
+                    crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+                }
+            }
+            !crc
+        };
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut chunk = |kind: &[u8], data: &[u8]| {
+            png.extend((data.len() as u32).to_be_bytes());
+            let body = [kind, data].concat();
+            png.extend(&body);
+            png.extend(crc(&body).to_be_bytes());
+        };
+        let ihdr = [
+            &width.to_be_bytes()[..],
+            &height.to_be_bytes(),
+            &[8, 3, 0, 0, 0],
+        ]
+        .concat();
+        chunk(b"IHDR", &ihdr);
+        chunk(
+            b"PLTE",
+            &(0..=255).flat_map(|i| [i; 3]).collect::<Vec<u8>>(),
+        );
+        chunk(b"tRNS", alphas);
+        // Each row behind a filter type of 0, in stored blocks of at most 65535 bytes.
+        let raw: Vec<u8> = indices
+            .chunks(width as usize)
+            .flat_map(|row| std::iter::once(0).chain(row.iter().copied()))
+            .collect();
+        let mut zlib = vec![0x78, 0x01];
+        let blocks: Vec<&[u8]> = raw.chunks(65535).collect();
+        for (i, block) in blocks.iter().enumerate() {
+            zlib.push((i + 1 == blocks.len()) as u8);
+            zlib.extend((block.len() as u16).to_le_bytes());
+            zlib.extend((!(block.len() as u16)).to_le_bytes());
+            zlib.extend(*block);
+        }
+        let (a, b) = raw.iter().fold((1u32, 0u32), |(a, b), &v| {
+            let a = (a + v as u32) % 65521;
+            (a, (b + a) % 65521)
+        });
+        zlib.extend(((b << 16) | a).to_be_bytes());
+        chunk(b"IDAT", &zlib);
+        chunk(b"IEND", &[]);
+        png
+    }
+
+    #[test]
+    fn transparent_palette_entry_is_left_out_of_the_histogram() {
+        let dir = scratch_dir("transparent-index");
+        // The left half is the transparent entry 0, the right half noise over
+        // the opaque rest.
+        let level = |x: u32, y: u32| 1 + ((x * 37 + y * 101) % 200) as u8;
+        let indices: Vec<u8> = (0..64 * 64)
+            .map(|i| match i % 64 {
+                x if x < 32 => 0,
+                x => level(x, i / 64),
+            })
+            .collect();
+        let indexed = dir.join("indexed.png");
+        std::fs::write(&indexed, indexed_png(64, 64, &indices, &[0, 255, 255])).unwrap();
+        let opaque = dir.join("opaque.png");
+        std::fs::write(&opaque, indexed_png(64, 64, &indices, &[255])).unwrap();
+        let visible = dir.join("visible.png");
+        image::GrayImage::from_fn(32, 64, |x, y| Luma([level(x + 32, y)]))
+            .save(&visible)
+            .unwrap();
+        assert!(read::has_transparent_index(&indexed));
+        assert!(!read::has_transparent_index(&opaque));
+        assert!(!read::has_transparent_index(&visible));
+
+        let flag = |name: &'static str| Path::new(name);
+        let bake = |input: &Path| {
+            run(&[
+                flag("--quiet"),
+                flag("--deterministic"),
+                flag("--in-file"),
+                input,
+                flag("--out-dir"),
+                &dir,
+            ])
+            .unwrap();
+            let output = |suffix| dir.join(output_file_name(input, None, suffix, "tif"));
+            let lut = image::open(output(LUT_SUFFIX))
+                .unwrap()
+                .into_rgb8()
+                .into_raw();
+            (read::read_rgb_f32(&output(IMG_SUFFIX)).unwrap().data, lut)
+        };
+        let (forward, lut) = bake(&indexed);
+        let (visible_forward, visible_lut) = bake(&visible);
+        assert_eq!(lut, visible_lut);
+        for y in 0..64 {
+            for x in 0..32 {
+                let (i, j) = ((y * 64 + x + 32) * 3, (y * 32 + x) * 3);
+                assert_eq!(forward[i..i + 3], visible_forward[j..j + 3]);
+            }
+        }
+        // Opaque, the entry takes half the histogram.
+        assert_ne!(bake(&opaque).1, visible_lut);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn plot_histograms_writes_a_png_per_transformed_channel() {
+        let dir = scratch_dir("plot-histograms");
+        let input = dir.join("texture.png");
+        synthetic_input().save(&input).unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        let plots = |channels: &'static str| {
+            let plot_dir = dir.join(channels);
+            run(&[
+                flag("--quiet"),
+                flag("--in-file"),
+                &input,
+                flag("--out-dir"),
+                &dir,
+                flag("--channels"),
+                flag(channels),
+                flag("--plot-histograms"),
+                &plot_dir,
+            ])
+            .unwrap();
+            let stem = output_stem(&input, None, IMG_SUFFIX);
+            ["r", "g", "b"].map(|channel| {
+                let path = plot_dir.join(format!("{}-histogram-{}.png", stem, channel));
+                path.is_file().then(|| {
+                    assert!(std::fs::metadata(&path).unwrap().len() > 0);
+                    let plot = image::open(&path).unwrap();
+                    assert!(plot.width() > 0 && plot.height() > 0);
+                })
+            })
+        };
+        assert_eq!(plots("rgb"), [Some(()); 3]);
+        assert_eq!(plots("rg"), [Some(()), Some(()), None]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn profile_trace_is_json_with_nested_spans() {
+        use tracing_subscriber::prelude::*;
+
+        let dir = scratch_dir("profile");
+        let input = dir.join("texture.png");
+        synthetic_input().save(&input).unwrap();
+        let trace = dir.join("trace.json");
+        let (layer, guard) = chrome_trace(&trace).unwrap();
+        let dispatch = tracing::Dispatch::new(tracing_subscriber::registry().with(layer));
+        // The per-channel spans are entered on the pool's threads, so each of
+        // them records to the trace too.
+        let pool = {
+            let dispatch = dispatch.clone();
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(2)
+                .spawn_handler(move |thread| {
+                    let dispatch = dispatch.clone();
+                    std::thread::spawn(move || {
+                        tracing::dispatcher::with_default(&dispatch, || thread.run())
+                    });
+                    Ok(())
+                })
+                .build()
+                .unwrap()
+        };
+        let flag = |name: &'static str| Path::new(name);
+        pool.install(|| {
+            tracing::dispatcher::with_default(&dispatch, || {
+                run(&[
+                    flag("--quiet"),
+                    flag("--in-file"),
+                    &input,
+                    flag("--out-dir"),
+                    &dir,
+                ])
+            })
+        })
+        .unwrap();
+        drop(pool);
+        drop(guard);
+        let events: Vec<serde_json::Value> =
+            serde_json::from_str(&std::fs::read_to_string(&trace).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Each span's begin and end timestamps, by name.
+        let spans = |name: &str| {
+            let times = |phase: &str| -> Vec<f64> {
+                events
+                    .iter()
+                    .filter(|event| event["name"] == name && event["ph"] == phase)
+                    .map(|event| event["ts"].as_f64().unwrap())
+                    .collect()
+            };
+            let (begins, ends) = (times("B"), times("E"));
+            assert_eq!(begins.len(), ends.len(), "{}", name);
+            begins.into_iter().zip(ends).collect::<Vec<_>>()
+        };
+        for name in [
+            "bake",
+            "read",
+            "transform",
+            "extract",
+            "sort",
+            "forward",
+            "inverse_lut",
+        ] {
+            assert_eq!(spans(name).len(), 1, "{}", name);
+        }
+        let transform = spans("transform")[0];
+        for name in ["rank_channel", "forward_channel", "lut_channel"] {
+            let channels = spans(name);
+            assert_eq!(channels.len(), 3, "{}", name);
+            for (begin, end) in channels {
+                assert!(transform.0 <= begin && end <= transform.1, "{}", name);
+            }
+        }
+        let bake = spans("bake")[0];
+        assert!(bake.0 <= transform.0 && transform.1 <= bake.1);
+    }
+
+    #[test]
+    fn resume_only_bakes_what_the_interrupted_run_didnt_write() {
+        let dir = scratch_dir("resume");
+        let inputs = dir.join("inputs");
+        std::fs::create_dir(&inputs).unwrap();
+        for name in ["a.png", "b.png"] {
+            synthetic_input().save(inputs.join(name)).unwrap();
+        }
+        // The first run stops short of c.png, as an interruption would.
+        std::fs::write(inputs.join("c.png"), b"not a png").unwrap();
+        let (out_dir, report) = (dir.join("out"), dir.join("summary.json"));
+        std::fs::create_dir(&out_dir).unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        let batch = |extra: &[&Path]| {
+            let mut args = vec![
+                flag("--quiet"),
+                flag("--resume"),
+                flag("--in-dir"),
+                &inputs,
+                flag("--out-dir"),
+                &out_dir,
+                flag("--summary-json"),
+                &report,
+            ];
+            args.extend(extra);
+            let result = run(&args);
+            let report: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+            let statuses: Vec<String> = (report["inputs"].as_array().unwrap().iter())
+                .map(|outcome| outcome["status"].as_str().unwrap().to_string())
+                .collect();
+            (result.map_err(|e| e.to_string()), statuses)
+        };
+        let manifest = out_dir.join(".precompute-resume");
+
+        let interrupted = batch(&[]);
+        assert_eq!(interrupted.0, Err("1 files failed".to_string()));
+        assert_eq!(interrupted.1, ["written", "written", "failed"]);
+        assert_eq!(
+            std::fs::read_to_string(&manifest).unwrap().lines().count(),
+            2
+        );
+
+        synthetic_input().save(inputs.join("c.png")).unwrap();
+        let a_image = out_dir.join(output_file_name(
+            &inputs.join("a.png"),
+            None,
+            IMG_SUFFIX,
+            "tif",
+        ));
+        std::fs::remove_file(&a_image).unwrap();
+        // a.png's forward image was cleaned up, so only b.png is still done.
+        let resumed = batch(&[]);
+        assert_eq!(resumed.0, Ok(()));
+        assert_eq!(resumed.1, ["written", "skipped", "written"]);
+        assert!(a_image.exists() && !manifest.exists());
+
+        // The completed run deleted the manifest, so the next starts over;
+        // a run with other settings after that doesn't count its records.
+        std::fs::write(inputs.join("d.png"), b"not a png").unwrap();
+        let fresh = batch(&[]);
+        let restd = batch(&[flag("--std"), flag("0.1")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(fresh.1, ["written", "written", "written", "failed"]);
+        assert_eq!(restd.1, ["written", "written", "written", "failed"]);
+    }
+
+    #[test]
+    fn batch_summary_counts_each_outcome() {
+        let dir = scratch_dir("summary");
+        let inputs = dir.join("inputs");
+        std::fs::create_dir(&inputs).unwrap();
+        for name in ["a.png", "b.png"] {
+            synthetic_input().save(inputs.join(name)).unwrap();
+        }
+        std::fs::write(inputs.join("broken.png"), b"not a png").unwrap();
+        let (out_dir, report) = (dir.join("out"), dir.join("summary.json"));
+        std::fs::create_dir(&out_dir).unwrap();
+        let flag = |name: &'static str| Path::new(name);
+        let batch = |skip_if_newer: bool| {
+            let mut args = vec![
+                flag("--quiet"),
+                flag("--in-dir"),
+                &inputs,
+                flag("--out-dir"),
+                &out_dir,
+                flag("--summary-json"),
+                &report,
+            ];
+            if skip_if_newer {
+                args.push(flag("--skip-if-newer"));
+            }
+            let result = run(&args);
+            let report: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+            (result, report)
+        };
+        let first = batch(false);
+        let second = batch(true);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let counts = |report: &serde_json::Value| {
+            ["files", "written", "skipped", "failed"].map(|key| report[key].as_u64().unwrap())
+        };
+        for (result, _) in [&first, &second] {
+            assert_eq!(result.as_ref().unwrap_err().to_string(), "1 files failed");
+        }
+        assert_eq!(counts(&first.1), [3, 2, 0, 1]);
+        assert_eq!(counts(&second.1), [3, 0, 2, 1]);
+        let megapixels = first.1["megapixels"].as_f64().unwrap();
+        assert!((megapixels - 2.0 * 64.0 * 64.0 / 1e6).abs() < 1e-9);
+        let statuses: Vec<(&str, &str)> = (first.1["inputs"].as_array().unwrap().iter())
+            .map(|outcome| {
+                let input = Path::new(outcome["input"].as_str().unwrap());
+                let name = input.file_name().unwrap().to_str().unwrap();
+                (name, outcome["status"].as_str().unwrap())
+            })
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("a.png", "written"),
+                ("b.png", "written"),
+                ("broken.png", "failed")
+            ]
+        );
+    }
+
+    #[test]
+    fn name_template_renders_its_placeholders() {
+        let dir = scratch_dir("name-template");
+        let input = dir.join("rock.png");
+        image::RgbImage::new(24, 16).save(&input).unwrap();
+        let args = Args::try_parse_from([
+            "precompute",
+            "--in-file",
+            "rock.png",
+            "--std",
+            "0.125",
+            "--distribution",
+            "uniform",
+        ])
+        .unwrap();
+        let template =
+            NameTemplate::parse("{input}_{width}x{height}_{distribution}-{std}.{suffix}").unwrap();
+        let name = template.render(&input, IMG_SUFFIX, &args);
+        let lut_name = template.render(&input, LUT_SUFFIX, &args);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(name.unwrap(), "rock_24x16_uniform-0.125.gaussian");
+        assert_eq!(lut_name.unwrap(), "rock_24x16_uniform-0.125.lut");
+
+        let dated = NameTemplate::parse("{date}-{suffix}").unwrap();
+        assert_eq!(dated.0[0], NamePart::Literal(String::new()));
+        assert!(matches!(&dated.0[1], NamePart::Literal(date) if date.len() == 10));
+    }
+
+    #[test]
+    fn each_pair_naming_preset_names_the_pair() {
+        let pair = |convention: &str| {
+            let args = Args::try_parse_from([
+                "precompute",
+                "--in-file",
+                "rock.png",
+                "--pair-naming",
+                convention,
+            ])
+            .unwrap();
+            let name = |output| {
+                args.output_file_name(Path::new("rock.png"), output, "tif")
+                    .unwrap()
+            };
+            (name(Output::Img), name(Output::Lut))
+        };
+        for (convention, img, lut) in [
+            ("dotted", "rock.gauss.tif", "rock.gauss.lut.tif"),
+            ("unreal", "T_rock_Gauss.tif", "T_rock_GaussLUT.tif"),
+            ("snake", "rock_gauss.tif", "rock_gauss_lut.tif"),
+            (
+                "{input}-fwd,{input}-{suffix}",
+                "rock-fwd.tif",
+                "rock-lut.tif",
+            ),
+        ] {
+            assert_eq!(
+                pair(convention),
+                (img.to_string(), lut.to_string()),
+                "{}",
+                convention
+            );
+        }
+        for convention in ["godot", "{input}", "{input},{input}", "{input},out/{input}"] {
+            assert!(PairNaming::parse(convention).is_err(), "{}", convention);
+        }
+    }
+
+    #[test]
+    fn name_template_rejects_bad_templates() {
+        for template in [
+            "{input}",
+            "{input}-{suffix",
+            "{suffix}}",
+            "{nope}-{suffix}",
+            "out/{suffix}",
+        ] {
+            assert!(NameTemplate::parse(template).is_err(), "{}", template);
+        }
+    }
 }
@@ -0,0 +1,28 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use precompute::digest::{self, HashAlgorithm};
+use precompute::encode::{self, WriteOptions};
+
+/// Hashes each of the `files` written and writes them to `path` as
+/// `{"algorithm", "files": {path: {"size", "hash"}}}`.
+pub fn write_manifest(
+    path: &Path,
+    algorithm: HashAlgorithm,
+    files: &[PathBuf],
+    write_options: &WriteOptions,
+) -> Result<(), anyhow::Error> {
+    let files = files
+        .iter()
+        .map(|written| {
+            let (hash, size) = digest::hash_file(written, algorithm)?;
+            let entry = serde_json::json!({ "size": size, "hash": hash });
+            Ok((written.to_string_lossy().into_owned(), entry))
+        })
+        .collect::<Result<BTreeMap<_, _>, anyhow::Error>>()?;
+    let manifest = serde_json::json!({ "algorithm": algorithm, "files": files });
+    encode::write_atomic(path, write_options, |file| {
+        serde_json::to_writer_pretty(&mut *file, &manifest)?;
+        Ok(())
+    })
+}
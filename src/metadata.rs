@@ -0,0 +1,46 @@
+use std::path::Path;
+
+use tiff::decoder::Decoder;
+use tiff::tags::Tag;
+
+use crate::config::TransformConfig;
+
+/// Start of an ImageDescription written by [`describe`], telling it apart
+/// from other writers' descriptions.
+const PREFIX: &str = "precompute ";
+
+/// TIFF ImageDescription text recording the parameters of a bake, so that a
+/// bake of an image made from its output, such as the `--invert`
+/// reconstruction of its forward image, can default to them. The text is
+/// `precompute ` followed by `config` as JSON, with the same fields as a
+/// sidecar's `config`. A tabulated target and a mean gradient are left out,
+/// since the command line takes them in forms their JSON doesn't give back.
+pub fn describe(config: &TransformConfig) -> String {
+    let config = TransformConfig {
+        target_cdf: None,
+        mean_gradient: None,
+        ..config.clone()
+    };
+    let json = serde_json::to_string(&config).expect("a config serializes to JSON");
+    format!("{}{}", PREFIX, json)
+}
+
+/// The parameters [`describe`] recorded in the TIFF at `path`, or `None` if
+/// it isn't a TIFF or its first page's ImageDescription wasn't written by
+/// [`describe`].
+pub fn read_config(path: &Path) -> Result<Option<TransformConfig>, anyhow::Error> {
+    if image::ImageFormat::from_path(path).ok() != Some(image::ImageFormat::Tiff) {
+        return Ok(None);
+    }
+    let mut decoder = Decoder::new(std::io::BufReader::new(std::fs::File::open(path)?))?;
+    let Some(description) = decoder.find_tag(Tag::ImageDescription)? else {
+        return Ok(None);
+    };
+    let description = description.into_string()?;
+    let Some(json) = description.strip_prefix(PREFIX) else {
+        return Ok(None);
+    };
+    let config = serde_json::from_str(json.trim_end_matches('\0'))
+        .map_err(|e| anyhow::anyhow!("{:?}: parameters in its ImageDescription: {}", path, e))?;
+    Ok(Some(config))
+}
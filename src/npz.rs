@@ -0,0 +1,289 @@
+use std::io::Write;
+
+use crate::digest::Crc32;
+use crate::encode::OutputData;
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY\x01\x00";
+/// The npy header, magic included, is padded to a multiple of this so the
+/// data that follows is aligned.
+const NPY_ALIGN: usize = 64;
+/// DOS date of 1980-01-01, the earliest a zip entry can carry.
+const ZIP_DATE: u16 = 0x21;
+
+/// One named array of an `.npz` archive, in C order.
+#[derive(Clone, Copy, Debug)]
+pub struct Array<'a> {
+    /// Name numpy loads the array under; `.npy` is appended in the archive.
+    pub name: &'a str,
+    pub shape: &'a [usize],
+    pub data: OutputData<'a>,
+}
+
+/// Writes `arrays` as an uncompressed `.npz`, the zip of `.npy` files
+/// `numpy.savez` writes: 8-bit data as `uint8`, float data as little-endian
+/// `float32` and 32-bit integer data as `uint32` or `int32`. Archives are
+/// plain zip, so each array and the whole file must stay under 4 GiB.
+pub fn write_npz<W: Write>(writer: &mut W, arrays: &[Array]) -> Result<(), anyhow::Error> {
+    let mut central = Vec::new();
+    let mut offset = 0u64;
+    for array in arrays {
+        let count: usize = array.shape.iter().product();
+        if count != array.data.len() {
+            return Err(anyhow::anyhow!(
+                "Array {} has shape {:?} but {} values",
+                array.name,
+                array.shape,
+                array.data.len()
+            ));
+        }
+        let header = npy_header(array.shape, array.data);
+        let mut crc = Crc32::new();
+        crc.write(&header);
+        for_each_chunk(array.data, |bytes| {
+            crc.write(bytes);
+            Ok(())
+        })?;
+        let size = header.len() as u64 + data_bytes(array.data);
+        let name = format!("{}.npy", array.name);
+        let (size32, offset32) = match (u32::try_from(size), u32::try_from(offset)) {
+            (Ok(size), Ok(offset)) => (size, offset),
+            _ => return Err(anyhow::anyhow!("npz outputs can't exceed 4 GiB")),
+        };
+
+        let entry = ZipEntry {
+            name: &name,
+            crc: crc.finish(),
+            size: size32,
+        };
+        let local = entry.local_header();
+        writer.write_all(&local)?;
+        writer.write_all(&header)?;
+        for_each_chunk(array.data, |bytes| writer.write_all(bytes))?;
+        central.extend(entry.central_header(offset32));
+        offset += local.len() as u64 + size;
+    }
+
+    let offset =
+        u32::try_from(offset).map_err(|_| anyhow::anyhow!("npz outputs can't exceed 4 GiB"))?;
+    writer.write_all(&central)?;
+    let mut end = Vec::with_capacity(22);
+    end.extend(0x0605_4b50u32.to_le_bytes());
+    // This disk and the disk the central directory starts on.
+    end.extend([0u8; 4]);
+    for _ in 0..2 {
+        end.extend((arrays.len() as u16).to_le_bytes());
+    }
+    end.extend((central.len() as u32).to_le_bytes());
+    end.extend(offset.to_le_bytes());
+    // No comment.
+    end.extend([0u8; 2]);
+    writer.write_all(&end)?;
+    Ok(())
+}
+
+/// A format 1.0 `.npy` header for `data` shaped `shape`.
+fn npy_header(shape: &[usize], data: OutputData) -> Vec<u8> {
+    let descr = match data {
+        OutputData::U8(_) => "|u1",
+        OutputData::U16(_) => "<u2",
+        OutputData::F32(_) => "<f4",
+        OutputData::U32(_) => "<u4",
+        OutputData::I32(_) => "<i4",
+    };
+    let shape = match shape {
+        [n] => format!("({},)", n),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut dict = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+    let unpadded = NPY_MAGIC.len() + 2 + dict.len() + 1;
+    dict.extend(std::iter::repeat_n(
+        ' ',
+        (NPY_ALIGN - unpadded % NPY_ALIGN) % NPY_ALIGN,
+    ));
+    dict.push('\n');
+
+    let mut header = NPY_MAGIC.to_vec();
+    header.extend((dict.len() as u16).to_le_bytes());
+    header.extend(dict.as_bytes());
+    header
+}
+
+fn data_bytes(data: OutputData) -> u64 {
+    match data {
+        OutputData::U8(data) => data.len() as u64,
+        OutputData::U16(data) => data.len() as u64 * 2,
+        OutputData::F32(data) => data.len() as u64 * 4,
+        OutputData::U32(data) => data.len() as u64 * 4,
+        OutputData::I32(data) => data.len() as u64 * 4,
+    }
+}
+
+/// Hands `data`'s little-endian bytes to `f` a bounded chunk at a time, so
+/// float data never has to be copied whole.
+fn for_each_chunk(
+    data: OutputData,
+    mut f: impl FnMut(&[u8]) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    match data {
+        OutputData::U8(data) => f(data),
+        OutputData::U16(data) => le_chunks(data, u16::to_le_bytes, f),
+        OutputData::F32(data) => le_chunks(data, f32::to_le_bytes, f),
+        OutputData::U32(data) => le_chunks(data, u32::to_le_bytes, f),
+        OutputData::I32(data) => le_chunks(data, i32::to_le_bytes, f),
+    }
+}
+
+fn le_chunks<T: Copy, const N: usize>(
+    data: &[T],
+    to_le_bytes: fn(T) -> [u8; N],
+    mut f: impl FnMut(&[u8]) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut bytes = Vec::with_capacity(1 << 16);
+    for chunk in data.chunks(1 << 14) {
+        bytes.clear();
+        bytes.extend(chunk.iter().flat_map(|&v| to_le_bytes(v)));
+        f(&bytes)?;
+    }
+    Ok(())
+}
+
+/// A stored, uncompressed zip entry.
+struct ZipEntry<'a> {
+    name: &'a str,
+    crc: u32,
+    size: u32,
+}
+
+impl ZipEntry<'_> {
+    /// The fields the local and central headers share, from "version needed"
+    /// through "extra field length".
+    fn common(&self) -> Vec<u8> {
+        let mut fields = Vec::with_capacity(26);
+        // Version needed (2.0), flags, method (stored) and modification time.
+        for v in [20u16, 0, 0, 0, ZIP_DATE] {
+            fields.extend(v.to_le_bytes());
+        }
+        fields.extend(self.crc.to_le_bytes());
+        // Compressed and uncompressed sizes are the same.
+        fields.extend(self.size.to_le_bytes());
+        fields.extend(self.size.to_le_bytes());
+        fields.extend((self.name.len() as u16).to_le_bytes());
+        fields.extend([0u8; 2]);
+        fields
+    }
+
+    fn local_header(&self) -> Vec<u8> {
+        let mut header = 0x0403_4b50u32.to_le_bytes().to_vec();
+        header.extend(self.common());
+        header.extend(self.name.as_bytes());
+        header
+    }
+
+    fn central_header(&self, offset: u32) -> Vec<u8> {
+        let mut header = 0x0201_4b50u32.to_le_bytes().to_vec();
+        // Version made by.
+        header.extend(20u16.to_le_bytes());
+        header.extend(self.common());
+        // Comment length, disk number, internal and external attributes.
+        header.extend([0u8; 10]);
+        header.extend(offset.to_le_bytes());
+        header.extend(self.name.as_bytes());
+        header
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(bytes: &[u8], at: usize) -> usize {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]]) as usize
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn npz_entries_hold_npy_arrays_with_their_shapes_and_dtypes() {
+        let image: Vec<f32> = (0..2 * 3 * 3).map(|i| i as f32 / 4.0).collect();
+        let lut: Vec<u8> = (0..8 * 3).map(|i| i as u8).collect();
+        let mut bytes = Vec::new();
+        let arrays = [
+            Array {
+                name: "image",
+                shape: &[2, 3, 3],
+                data: OutputData::F32(&image),
+            },
+            Array {
+                name: "lut",
+                shape: &[1, 8, 3],
+                data: OutputData::U8(&lut),
+            },
+        ];
+        write_npz(&mut bytes, &arrays).unwrap();
+
+        let mut at = 0;
+        let mut entries = Vec::new();
+        while u32_at(&bytes, at) == 0x0403_4b50 {
+            let (crc, size) = (u32_at(&bytes, at + 14), u32_at(&bytes, at + 18) as usize);
+            let name_len = u16_at(&bytes, at + 26);
+            let name = std::str::from_utf8(&bytes[at + 30..at + 30 + name_len]).unwrap();
+            let npy = &bytes[at + 30 + name_len..at + 30 + name_len + size];
+            let mut check = Crc32::new();
+            check.write(npy);
+            assert_eq!(check.finish(), crc, "{}", name);
+
+            assert_eq!(&npy[..8], NPY_MAGIC);
+            let header_len = u16_at(npy, 8);
+            assert_eq!((10 + header_len) % NPY_ALIGN, 0);
+            let header = std::str::from_utf8(&npy[10..10 + header_len]).unwrap();
+            assert!(header.ends_with('\n'));
+            entries.push((
+                name.to_string(),
+                header.trim_end().to_string(),
+                npy[10 + header_len..].to_vec(),
+            ));
+            at += 30 + name_len + size;
+        }
+        // Then the central directory, with an end record counting both entries.
+        let end = bytes.len() - 22;
+        assert_eq!(u32_at(&bytes, end), 0x0605_4b50);
+        assert_eq!(u16_at(&bytes, end + 10), 2);
+        assert_eq!(u32_at(&bytes, end + 16) as usize, at);
+
+        let image_bytes: Vec<u8> = image.iter().flat_map(|v| v.to_le_bytes()).collect();
+        assert_eq!(
+            entries,
+            [
+                (
+                    "image.npy".to_string(),
+                    "{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3, 3), }".to_string(),
+                    image_bytes
+                ),
+                (
+                    "lut.npy".to_string(),
+                    "{'descr': '|u1', 'fortran_order': False, 'shape': (1, 8, 3), }".to_string(),
+                    lut
+                ),
+            ]
+        );
+        let short = Array {
+            name: "short",
+            shape: &[4],
+            data: OutputData::U8(&[0; 3]),
+        };
+        let err = write_npz(&mut Vec::new(), &[short]).unwrap_err();
+        assert_eq!(err.to_string(), "Array short has shape [4] but 3 values");
+    }
+}
@@ -0,0 +1,212 @@
+use std::fmt;
+use std::str::FromStr;
+
+use image::DynamicImage;
+
+use crate::config::{GaussianResult, TransformConfig};
+
+const CHANNEL_NAMES: [char; 4] = ['r', 'g', 'b', 'a'];
+
+/// Where a packed output channel's values come from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PackSource {
+    /// A channel of the forward image, by index.
+    Forward(usize),
+    /// A channel of the source image, by index, unchanged, in `[0, 1]`.
+    Source(usize),
+    Constant(f32),
+}
+
+/// Which values go to each channel of a packed forward image, such as a
+/// material texture with roughness, metallic and occlusion kept as they are
+/// and a Gaussianized detail mask in alpha. Parsed from comma-separated
+/// `output=source` pairs, e.g. `r=source.r,g=source.g,b=source.b,a=forward.r`:
+/// each output is `r`, `g`, `b` or `a`; each source is `forward.r`,
+/// `forward.g` or `forward.b`, `source.r` to `source.a`, or a number. Outputs
+/// left out of R, G and B keep their forward channel, and the packed image
+/// has an alpha channel only if `a` is given.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PackSpec {
+    /// One source per output channel, three or four.
+    pub channels: Vec<PackSource>,
+}
+
+impl PackSpec {
+    /// Checks that every forward channel moved to another output is one
+    /// `config` transforms, since the others only hold zeros. Left in place,
+    /// they're written as zero like in a forward image that isn't packed.
+    pub fn validate(&self, config: &TransformConfig) -> Result<(), anyhow::Error> {
+        for (output, source) in self.channels.iter().enumerate() {
+            if let PackSource::Forward(channel) = *source {
+                if channel != output && channel >= config.channels.count() {
+                    return Err(anyhow::anyhow!(
+                        "forward.{} isn't transformed, so it can't be packed",
+                        CHANNEL_NAMES[channel]
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Packs `result` and the `source` it was baked from into an interleaved
+    /// image of `channels.len()` channels per pixel, after
+    /// [`PackSpec::validate`].
+    pub fn pack(
+        &self,
+        config: &TransformConfig,
+        result: &GaussianResult,
+        source: &DynamicImage,
+    ) -> Result<Vec<f32>, anyhow::Error> {
+        if (source.width(), source.height()) != (result.width, result.height) {
+            return Err(anyhow::anyhow!(
+                "Can't pack a {}x{} source with a {}x{} forward image",
+                source.width(),
+                source.height(),
+                result.width,
+                result.height
+            ));
+        }
+        self.validate(config)?;
+        let source = source.to_rgba32f();
+        Ok(result
+            .image
+            .chunks_exact(3)
+            .zip(source.pixels())
+            .flat_map(|(forward, pixel)| {
+                self.channels.iter().map(move |source| match *source {
+                    PackSource::Forward(channel) => forward[channel],
+                    PackSource::Source(channel) => pixel[channel],
+                    PackSource::Constant(value) => value,
+                })
+            })
+            .collect())
+    }
+}
+
+impl FromStr for PackSpec {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut channels: [Option<PackSource>; 4] = [None; 4];
+        for pair in value.split(',') {
+            let Some((output, source)) = pair.split_once('=') else {
+                return Err(format!("expected output=source, got {:?}", pair));
+            };
+            let output = match output.trim() {
+                "r" => 0,
+                "g" => 1,
+                "b" => 2,
+                "a" => 3,
+                output => {
+                    return Err(format!(
+                        "expected an output of r, g, b or a, got {:?}",
+                        output
+                    ))
+                }
+            };
+            if channels[output].is_some() {
+                return Err(format!("{} is packed twice", CHANNEL_NAMES[output]));
+            }
+            let source = source.trim();
+            let channel = |name: &str, count: usize| {
+                CHANNEL_NAMES[..count]
+                    .iter()
+                    .position(|&c| name.len() == 1 && name.starts_with(c))
+            };
+            channels[output] = Some(
+                match source.split_once('.') {
+                    Some(("forward", name)) => channel(name, 3).map(PackSource::Forward),
+                    Some(("source", name)) => channel(name, 4).map(PackSource::Source),
+                    _ => source.parse().ok().filter(|v: &f32| v.is_finite()).map(PackSource::Constant),
+                }
+                .ok_or_else(|| {
+                    format!(
+                        "expected forward.r, forward.g, forward.b, source.r to source.a or a number, got {:?}",
+                        source
+                    )
+                })?,
+            );
+        }
+        let count = if channels[3].is_some() { 4 } else { 3 };
+        Ok(Self {
+            channels: (0..count)
+                .map(|output| channels[output].unwrap_or(PackSource::Forward(output)))
+                .collect(),
+        })
+    }
+}
+
+impl fmt::Display for PackSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (output, source) in self.channels.iter().enumerate() {
+            if output > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}=", CHANNEL_NAMES[output])?;
+            match source {
+                PackSource::Forward(channel) => write!(f, "forward.{}", CHANNEL_NAMES[*channel])?,
+                PackSource::Source(channel) => write!(f, "source.{}", CHANNEL_NAMES[*channel])?,
+                PackSource::Constant(value) => write!(f, "{}", value)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::self_test::synthetic_input;
+    use crate::Channels;
+
+    #[test]
+    fn transformed_channel_packs_into_alpha() {
+        let spec: PackSpec = "r=source.r, g=source.g,b=0.5,a=forward.r".parse().unwrap();
+        assert_eq!(
+            spec.channels,
+            [
+                PackSource::Source(0),
+                PackSource::Source(1),
+                PackSource::Constant(0.5),
+                PackSource::Forward(0),
+            ]
+        );
+        assert_eq!(spec.to_string().parse::<PackSpec>(), Ok(spec.clone()));
+
+        let input = synthetic_input();
+        let config = TransformConfig::default().deterministic(true);
+        let result = config.run(&input).unwrap();
+        let packed = spec.pack(&config, &result, &input).unwrap();
+        assert_eq!(packed.len(), result.image.len() / 3 * 4);
+        let source = input.to_rgb8();
+        for ((texel, forward), pixel) in packed
+            .chunks_exact(4)
+            .zip(result.image.chunks_exact(3))
+            .zip(source.pixels())
+        {
+            assert_eq!(texel[0], pixel[0] as f32 / 255.0);
+            assert_eq!(texel[1], pixel[1] as f32 / 255.0);
+            assert_eq!((texel[2], texel[3]), (0.5, forward[0]));
+        }
+
+        // Outputs left out keep their forward channel, without an alpha.
+        let spec: PackSpec = "g=forward.r".parse().unwrap();
+        let packed = spec.pack(&config, &result, &input).unwrap();
+        for (texel, forward) in packed.chunks_exact(3).zip(result.image.chunks_exact(3)) {
+            assert_eq!(texel, [forward[0], forward[0], forward[2]]);
+        }
+
+        let rg = config.channels(Channels::Rg);
+        let spec: PackSpec = "a=forward.b".parse().unwrap();
+        assert!(spec.validate(&rg).is_err());
+        assert!("b=forward.b"
+            .parse::<PackSpec>()
+            .unwrap()
+            .validate(&rg)
+            .is_ok());
+        for bad in ["r=forward.a", "x=source.r", "r=source.r,r=0", "r", "a=inf"] {
+            assert!(bad.parse::<PackSpec>().is_err(), "{}", bad);
+        }
+    }
+}
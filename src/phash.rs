@@ -0,0 +1,100 @@
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+/// Side of the luma thumbnail the DCT is taken over.
+const SIZE: usize = 32;
+/// Side of the block of lowest frequencies kept, one bit each.
+const KEPT: usize = 8;
+
+/// DCT-based perceptual hash of `image`, for finding the same texture again
+/// after it was resized, recompressed or slightly retouched, where a
+/// checksum of its file changes with every byte. `image` is reduced to a
+/// 32x32 luma thumbnail, whose 8x8 lowest DCT-II frequencies, DC included,
+/// give one bit each, set if the coefficient is above their median, first
+/// coefficient in the highest bit and row by row. Alpha is ignored. Compare
+/// two hashes with [`distance`].
+pub fn phash(image: &DynamicImage) -> u64 {
+    let thumbnail = image::imageops::resize(
+        &image.to_luma32f(),
+        SIZE as u32,
+        SIZE as u32,
+        FilterType::Triangle,
+    );
+    // cos((2x + 1) u pi / 2N) for each kept frequency u, SIZE per row.
+    let basis: Vec<f64> = (0..KEPT * SIZE)
+        .map(|i| {
+            let (u, x) = (i / SIZE, i % SIZE);
+            ((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / (2 * SIZE) as f64).cos()
+        })
+        .collect();
+    let dct = |values: &mut dyn Iterator<Item = f64>, u: usize| -> f64 {
+        values
+            .zip(&basis[u * SIZE..(u + 1) * SIZE])
+            .map(|(v, b)| v * b)
+            .sum()
+    };
+    // The kept frequencies of each row, KEPT per row, then of each column of those.
+    let rows: Vec<f64> = thumbnail
+        .as_raw()
+        .chunks_exact(SIZE)
+        .flat_map(|row| (0..KEPT).map(|u| dct(&mut row.iter().map(|&v| v as f64), u)))
+        .collect();
+    let coefficients: Vec<f64> = (0..KEPT * KEPT)
+        .map(|i| {
+            let (v, u) = (i / KEPT, i % KEPT);
+            dct(&mut rows.iter().skip(u).step_by(KEPT).copied(), v)
+        })
+        .collect();
+    let mut sorted = coefficients.clone();
+    sorted.sort_by(f64::total_cmp);
+    let median = (sorted[KEPT * KEPT / 2 - 1] + sorted[KEPT * KEPT / 2]) / 2.0;
+    coefficients
+        .iter()
+        .fold(0, |hash, &c| (hash << 1) | (c > median) as u64)
+}
+
+/// Number of bits two [`phash`]es differ in, out of 64. Resized copies of an
+/// image usually differ in a few, unrelated images in about half.
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::self_test::synthetic_input;
+
+    /// An `f` drawn at `width x height`, `f` taking coordinates in `[0, 1)`.
+    fn draw(width: u32, height: u32, f: impl Fn(f64, f64) -> f64) -> DynamicImage {
+        DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, |x, y| {
+            let v = f(x as f64 / width as f64, y as f64 / height as f64);
+            let level = (v.clamp(0.0, 1.0) * 255.0) as u8;
+            image::Rgb([level, level / 2, 255 - level])
+        }))
+    }
+
+    #[test]
+    fn resized_copies_hash_alike_and_unrelated_images_apart() {
+        let scene = |x: f64, y: f64| {
+            let disc = ((x - 0.3).powi(2) + (y - 0.6).powi(2) < 0.04) as u8 as f64;
+            0.6 * x * y + 0.4 * disc + 0.1 * (9.0 * x).sin()
+        };
+        let original = phash(&draw(256, 256, scene));
+        for (width, height) in [(512, 512), (97, 61), (64, 200)] {
+            let resized = phash(&draw(width, height, scene));
+            assert!(distance(original, resized) <= 6, "{}x{}", width, height);
+        }
+        let resampled = draw(256, 256, scene).resize_exact(40, 40, FilterType::CatmullRom);
+        assert!(distance(original, phash(&resampled)) <= 6);
+
+        let unrelated = [
+            draw(256, 256, |x, y| 1.0 - (x - 0.7).abs() - (y - 0.2).abs()),
+            draw(256, 256, |x, _| ((x * 20.0) as u32 % 2) as f64),
+            synthetic_input(),
+        ];
+        for other in &unrelated {
+            assert!(distance(original, phash(other)) >= 16);
+        }
+        assert_eq!(distance(original, original), 0);
+    }
+}
@@ -0,0 +1,120 @@
+use image::{Rgb, RgbImage};
+
+use crate::config::{GaussianResult, TransformConfig};
+
+/// Width of a bin in a [`histogram_plot`], in pixels.
+const BIN_WIDTH: u32 = 2;
+/// Bins in each panel: one per 8-bit level in the input panel, and as many
+/// spans of the target in the output panel.
+const BINS: usize = 256;
+const PANEL_HEIGHT: u32 = 192;
+/// Space around and between the panels.
+const MARGIN: u32 = 8;
+/// Share of the target, at each end, the output panel leaves out.
+const TAIL: f64 = 0.001;
+
+const BACKGROUND: Rgb<u8> = Rgb([255, 255, 255]);
+const FRAME: Rgb<u8> = Rgb([200, 200, 200]);
+const INPUT: Rgb<u8> = Rgb([96, 96, 96]);
+const OUTPUT: Rgb<u8> = Rgb([70, 110, 200]);
+const TARGET: Rgb<u8> = Rgb([220, 40, 40]);
+
+/// A bar chart of `channel`'s histograms in `result`, for reviewing a bake
+/// by eye. The top panel has the input's 256 levels in gray, each bar its
+/// share of the channel's histogram, with weights if it has them. The bottom
+/// panel bins the channel's stored forward values in blue over the middle
+/// `99.8%` of `channel`'s target, with the target's own share of each bin
+/// drawn over them in red, so a bake that follows its target has its bars
+/// reach the line. Each panel is scaled to its tallest bar or point; there
+/// are no axes or labels.
+pub fn histogram_plot(
+    result: &GaussianResult,
+    config: &TransformConfig,
+    channel: usize,
+) -> RgbImage {
+    let width = BINS as u32 * BIN_WIDTH + 2 * MARGIN;
+    let height = 2 * PANEL_HEIGHT + 3 * MARGIN;
+    let mut plot = RgbImage::from_pixel(width, height, BACKGROUND);
+
+    let histogram_channel = if config.shared_lut { 0 } else { channel };
+    let input: Vec<f64> = (0..=255u8)
+        .map(|v| result.histogram.share(histogram_channel, v))
+        .collect();
+    draw_bars(&mut plot, MARGIN, &input, INPUT, None);
+
+    let target = config.channel_target(channel);
+    let (low, high) = (target.inv_cdf(TAIL), target.inv_cdf(1.0 - TAIL));
+    let edge = |bin: usize| low + (high - low) * bin as f64 / BINS as f64;
+    let mut output = vec![0.0; BINS];
+    let forward = result
+        .image
+        .chunks_exact(3)
+        .map(|pixel| pixel[channel] as f64);
+    let n = result.image.len() / 3;
+    for v in forward.filter(|v| (low..high).contains(v)) {
+        let bin = (((v - low) / (high - low)) * BINS as f64) as usize;
+        output[bin.min(BINS - 1)] += 1.0 / n as f64;
+    }
+    let expected: Vec<f64> = (0..BINS)
+        .map(|bin| target.cdf(edge(bin + 1)) - target.cdf(edge(bin)))
+        .collect();
+    draw_bars(
+        &mut plot,
+        2 * MARGIN + PANEL_HEIGHT,
+        &output,
+        OUTPUT,
+        Some(&expected),
+    );
+    plot
+}
+
+/// Draws `bars` in `color` as a panel whose top edge is at `top`, and `line`
+/// over them through the middle of each bin, both scaled to the largest of
+/// either.
+fn draw_bars(plot: &mut RgbImage, top: u32, bars: &[f64], color: Rgb<u8>, line: Option<&[f64]>) {
+    let peak = bars
+        .iter()
+        .chain(line.unwrap_or_default())
+        .copied()
+        .fold(0.0, f64::max);
+    // Rows from the panel's bottom edge a share of the peak reaches.
+    let rows = |value: f64| match peak > 0.0 {
+        true => ((value / peak) * (PANEL_HEIGHT - 1) as f64).round() as u32,
+        false => 0,
+    };
+    let bottom = top + PANEL_HEIGHT - 1;
+    for x in MARGIN - 1..=MARGIN + BINS as u32 * BIN_WIDTH {
+        plot.put_pixel(x, top - 1, FRAME);
+        plot.put_pixel(x, bottom + 1, FRAME);
+    }
+    for y in top - 1..=bottom + 1 {
+        plot.put_pixel(MARGIN - 1, y, FRAME);
+        plot.put_pixel(MARGIN + BINS as u32 * BIN_WIDTH, y, FRAME);
+    }
+    for (bin, &value) in bars.iter().enumerate() {
+        let height = rows(value);
+        for x in MARGIN + bin as u32 * BIN_WIDTH..MARGIN + (bin as u32 + 1) * BIN_WIDTH {
+            for y in bottom + 1 - height..=bottom {
+                plot.put_pixel(x, y, color);
+            }
+        }
+    }
+    let Some(line) = line else {
+        return;
+    };
+    // Each pixel column joins the line's height at its center to the next
+    // column's, interpolating between bin centers.
+    let columns = BINS as u32 * BIN_WIDTH;
+    let at = |x: u32| {
+        let position = ((x as f64 + 0.5) / BIN_WIDTH as f64 - 0.5).clamp(0.0, (BINS - 1) as f64);
+        let bin = (position.floor() as usize).min(BINS - 2);
+        let t = position - bin as f64;
+        rows(line[bin] * (1.0 - t) + line[bin + 1] * t)
+    };
+    for x in 0..columns {
+        let (a, b) = (at(x), at((x + 1).min(columns - 1)));
+        for height in a.min(b)..=a.max(b) {
+            plot.put_pixel(MARGIN + x, bottom - height.min(PANEL_HEIGHT - 1), TARGET);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use rayon::prelude::*;
+
+/// Edge-preserving bilateral filter over one `width x height` channel. Each
+/// sample becomes the average of its neighbours within `3 * sigma_spatial`
+/// pixels, weighted by a Gaussian of their distance and another of their
+/// difference from it in 8-bit levels, so values across an edge barely count.
+pub(crate) fn bilateral(
+    values: &[u8],
+    width: u32,
+    height: u32,
+    sigma_spatial: f64,
+    sigma_range: f64,
+) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let radius = (3.0 * sigma_spatial).ceil() as usize;
+    let gaussian = |d: f64, sigma: f64| (-d * d / (2.0 * sigma * sigma)).exp();
+    let spatial: Vec<f64> = (0..=radius)
+        .flat_map(|dy| (0..=radius).map(move |dx| (dx * dx + dy * dy) as f64))
+        .map(|d2| gaussian(d2.sqrt(), sigma_spatial))
+        .collect();
+    let range: Vec<f64> = (0..256).map(|d| gaussian(d as f64, sigma_range)).collect();
+
+    let mut out = vec![0; values.len()];
+    out.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+        let (top, bottom) = (y.saturating_sub(radius), (y + radius).min(height - 1));
+        for (x, out) in row.iter_mut().enumerate() {
+            let (left, right) = (x.saturating_sub(radius), (x + radius).min(width - 1));
+            let center = values[y * width + x];
+            let (mut sum, mut total) = (0.0, 0.0);
+            for ny in top..=bottom {
+                let spatial_row = &spatial[ny.abs_diff(y) * (radius + 1)..];
+                for nx in left..=right {
+                    let value = values[ny * width + nx];
+                    let weight =
+                        spatial_row[nx.abs_diff(x)] * range[value.abs_diff(center) as usize];
+                    sum += weight * value as f64;
+                    total += weight;
+                }
+            }
+            *out = (sum / total).round() as u8;
+        }
+    });
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    #[test]
+    fn step_edge_survives_bilateral_prefiltering() {
+        // Noisy 60 | 180 halves, split between columns 15 and 16.
+        let rng = Rng::new(7);
+        let (width, height) = (32, 16);
+        let values: Vec<u8> = (0..width * height)
+            .map(|i| {
+                let level = if i % width < 16 { 60 } else { 180 };
+                level + rng.below(i as u64, 9) as u8 - 4
+            })
+            .collect();
+        let filtered = bilateral(&values, width, height, 2.0, 16.0);
+
+        let spread = |data: &[u8], column: u32| {
+            let column: Vec<f64> = (0..height)
+                .map(|y| data[(y * width + column) as usize] as f64)
+                .collect();
+            let mean = column.iter().sum::<f64>() / column.len() as f64;
+            let variance =
+                column.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / column.len() as f64;
+            (mean, variance)
+        };
+        for (column, level) in [(15, 60.0), (16, 180.0)] {
+            let (mean, variance) = spread(&filtered, column);
+            assert!(
+                (mean - level).abs() < 2.0,
+                "column {} mean {}",
+                column,
+                mean
+            );
+            assert!(variance < spread(&values, column).1, "column {}", column);
+        }
+    }
+}
@@ -0,0 +1,1393 @@
+use std::borrow::Borrow;
+use std::io::{Cursor, Read, Seek};
+use std::path::Path;
+
+use image::{DynamicImage, GenericImage, ImageBuffer, Luma};
+use memmap2::Mmap;
+use tiff::decoder::{ChunkType, Decoder, DecodingResult};
+use tiff::ColorType;
+
+pub struct TiffImage<T> {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<T>,
+}
+
+/// Reads a 32-bit float RGB TIFF such as the forward Gaussian output.
+pub fn read_rgb_f32(path: &Path) -> Result<TiffImage<f32>, anyhow::Error> {
+    let mut decoder = Decoder::new(std::fs::File::open(path)?)?;
+    reject_planar(&mut decoder, path)?;
+    let (width, height) = decoder.dimensions()?;
+    let color_type = decoder.colortype()?;
+    match (color_type, decoder.read_image()?) {
+        (ColorType::RGB(32), DecodingResult::F32(data)) => Ok(TiffImage {
+            width,
+            height,
+            data,
+        }),
+        _ => Err(anyhow::anyhow!(
+            "{:?} is {:?}, expected 32-bit float RGB",
+            path,
+            color_type
+        )),
+    }
+}
+
+/// Reads a single-channel 32-bit float TIFF such as a heightmap, the first
+/// page only.
+pub fn read_gray_f32(path: &Path) -> Result<TiffImage<f32>, anyhow::Error> {
+    let mut decoder = Decoder::new(std::fs::File::open(path)?)?;
+    let (width, height) = decoder.dimensions()?;
+    let color_type = decoder.colortype()?;
+    match (color_type, decoder.read_image()?) {
+        (ColorType::Gray(32), DecodingResult::F32(data)) => Ok(TiffImage {
+            width,
+            height,
+            data,
+        }),
+        _ => Err(anyhow::anyhow!(
+            "{:?} is {:?}, expected 32-bit float gray",
+            path,
+            color_type
+        )),
+    }
+}
+
+/// Reads a single-channel 8-bit TIFF such as one channel of a LUT written
+/// by `--split-channels`, the first page only.
+pub fn read_gray_u8(path: &Path) -> Result<TiffImage<u8>, anyhow::Error> {
+    let mut decoder = Decoder::new(std::fs::File::open(path)?)?;
+    let (width, height) = decoder.dimensions()?;
+    let color_type = decoder.colortype()?;
+    match (color_type, decoder.read_image()?) {
+        (ColorType::Gray(8), DecodingResult::U8(data)) => Ok(TiffImage {
+            width,
+            height,
+            data,
+        }),
+        _ => Err(anyhow::anyhow!(
+            "{:?} is {:?}, expected 8-bit gray",
+            path,
+            color_type
+        )),
+    }
+}
+
+/// A single-channel 32-bit integer TIFF read by [`read_gray_int32`].
+pub enum GrayInt32 {
+    U32(TiffImage<u32>),
+    I32(TiffImage<i32>),
+}
+
+/// Reads a single-channel 32-bit unsigned or signed integer TIFF, such as
+/// an integer heightmap, the first page only.
+pub fn read_gray_int32(path: &Path) -> Result<GrayInt32, anyhow::Error> {
+    let mut decoder = Decoder::new(std::fs::File::open(path)?)?;
+    let (width, height) = decoder.dimensions()?;
+    let color_type = decoder.colortype()?;
+    match (color_type, decoder.read_image()?) {
+        (ColorType::Gray(32), DecodingResult::U32(data)) => Ok(GrayInt32::U32(TiffImage {
+            width,
+            height,
+            data,
+        })),
+        (ColorType::Gray(32), DecodingResult::I32(data)) => Ok(GrayInt32::I32(TiffImage {
+            width,
+            height,
+            data,
+        })),
+        _ => Err(anyhow::anyhow!(
+            "{:?} is {:?}, expected 32-bit integer gray",
+            path,
+            color_type
+        )),
+    }
+}
+
+/// Reads an 8-bit RGB TIFF such as the inverse LUT.
+pub fn read_rgb_u8(path: &Path) -> Result<TiffImage<u8>, anyhow::Error> {
+    let mut decoder = Decoder::new(std::fs::File::open(path)?)?;
+    reject_planar(&mut decoder, path)?;
+    let (width, height) = decoder.dimensions()?;
+    let color_type = decoder.colortype()?;
+    match (color_type, decoder.read_image()?) {
+        (ColorType::RGB(8), DecodingResult::U8(data)) => Ok(TiffImage {
+            width,
+            height,
+            data,
+        }),
+        _ => Err(anyhow::anyhow!(
+            "{:?} is {:?}, expected 8-bit RGB",
+            path,
+            color_type
+        )),
+    }
+}
+
+/// `tiff` decodes only the first plane of a planar file, so refuse those.
+fn reject_planar<R: Read + Seek>(
+    decoder: &mut Decoder<R>,
+    path: &Path,
+) -> Result<(), anyhow::Error> {
+    if decoder.find_tag_unsigned::<u16>(tiff::tags::Tag::PlanarConfiguration)? == Some(2) {
+        return Err(anyhow::anyhow!(
+            "{:?} is planar, only interleaved TIFFs can be read",
+            path
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ReadOptions {
+    /// Rotate and flip inputs as their EXIF orientation tag says. Default `true`.
+    pub apply_orientation: bool,
+    /// Decode from a memory map of the file instead of buffered reads, so the
+    /// encoded bytes live in the page cache rather than the heap. Falls back to
+    /// buffered reads if the file can't be mapped. Default `false`.
+    pub memory_map: bool,
+    /// Read inputs as headerless pixels of this layout instead of decoding a
+    /// container. Weight maps are still decoded. Default `None`.
+    pub raw: Option<RawLayout>,
+    /// Keep only the first this many color channels of single-page,
+    /// interleaved, striped RGB and RGBA TIFFs, dropping the rest from each
+    /// strip as it's decoded, so the full-width pixels never have to be held
+    /// at once. The result is RGB with the dropped channels zero. Other inputs
+    /// are decoded in full. Default `None`, every channel.
+    pub channel_count: Option<usize>,
+    /// Resample every page to this width and height, after orientation, so
+    /// inputs of any size bake at one. Default `None`, as decoded.
+    pub resize: Option<(u32, u32)>,
+    /// Filter `resize` resamples with. Default Lanczos3.
+    pub resize_filter: ResizeFilter,
+    /// Decode single-page, interleaved TIFFs of more than one strip or tile
+    /// a chunk per task on the rayon pool, each worker with its own decoder,
+    /// instead of in one pass. The pixels are the same. Default `true`.
+    pub parallel_decode: bool,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            apply_orientation: true,
+            memory_map: false,
+            raw: None,
+            channel_count: None,
+            resize: None,
+            resize_filter: ResizeFilter::default(),
+            parallel_decode: true,
+        }
+    }
+}
+
+/// Filter [`ReadOptions::resize`] resamples inputs with.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResizeFilter {
+    /// The nearest source pixel, so only source values appear.
+    Nearest,
+    /// Linear interpolation.
+    Triangle,
+    /// Catmull-Rom cubic interpolation.
+    CatmullRom,
+    /// A Gaussian, softer than the others.
+    Gaussian,
+    /// Lanczos with a window of three, the sharpest.
+    #[default]
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    fn filter_type(self) -> image::imageops::FilterType {
+        use image::imageops::FilterType;
+        match self {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+impl ReadOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn apply_orientation(mut self, apply_orientation: bool) -> Self {
+        self.apply_orientation = apply_orientation;
+        self
+    }
+
+    pub fn memory_map(mut self, memory_map: bool) -> Self {
+        self.memory_map = memory_map;
+        self
+    }
+
+    pub fn raw(mut self, raw: Option<RawLayout>) -> Self {
+        self.raw = raw;
+        self
+    }
+
+    pub fn channel_count(mut self, channel_count: Option<usize>) -> Self {
+        self.channel_count = channel_count;
+        self
+    }
+
+    pub fn resize(mut self, resize: Option<(u32, u32)>) -> Self {
+        self.resize = resize;
+        self
+    }
+
+    pub fn resize_filter(mut self, resize_filter: ResizeFilter) -> Self {
+        self.resize_filter = resize_filter;
+        self
+    }
+
+    pub fn parallel_decode(mut self, parallel_decode: bool) -> Self {
+        self.parallel_decode = parallel_decode;
+        self
+    }
+}
+
+/// Dimensions and sample format of a headerless pixel dump, such as a GPU
+/// readback, read by [`decode_raw`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawLayout {
+    pub width: u32,
+    pub height: u32,
+    pub format: RawFormat,
+}
+
+/// Interleaved sample formats [`decode_raw`] reads. Multi-byte samples are
+/// little-endian.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RawFormat {
+    Gray8,
+    Rgb8,
+    Rgba8,
+    Rgb16,
+    Rgba16,
+    Rgb32f,
+    Rgba32f,
+}
+
+impl RawFormat {
+    pub fn channels(self) -> u64 {
+        match self {
+            RawFormat::Gray8 => 1,
+            RawFormat::Rgb8 | RawFormat::Rgb16 | RawFormat::Rgb32f => 3,
+            RawFormat::Rgba8 | RawFormat::Rgba16 | RawFormat::Rgba32f => 4,
+        }
+    }
+
+    pub fn bytes_per_sample(self) -> u64 {
+        match self {
+            RawFormat::Gray8 | RawFormat::Rgb8 | RawFormat::Rgba8 => 1,
+            RawFormat::Rgb16 | RawFormat::Rgba16 => 2,
+            RawFormat::Rgb32f | RawFormat::Rgba32f => 4,
+        }
+    }
+}
+
+/// Reinterprets `bytes` as a `layout.width x layout.height` image of
+/// interleaved `layout.format` pixels, rows top to bottom. `bytes` must hold
+/// exactly that many pixels, and the image is checked with [`validate_image`].
+pub fn decode_raw(bytes: &[u8], layout: &RawLayout) -> Result<DynamicImage, anyhow::Error> {
+    let RawLayout {
+        width,
+        height,
+        format,
+    } = *layout;
+    let expected = width as u64 * height as u64 * format.channels() * format.bytes_per_sample();
+    if bytes.len() as u64 != expected {
+        return Err(anyhow::anyhow!(
+            "A raw {}x{} {:?} image is {} bytes, got {}",
+            width,
+            height,
+            format,
+            expected,
+            bytes.len()
+        ));
+    }
+    let u16s = || -> Vec<u16> {
+        bytes
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect()
+    };
+    let f32s = || -> Vec<f32> {
+        bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    };
+    let image = match format {
+        RawFormat::Gray8 => {
+            ImageBuffer::from_raw(width, height, bytes.to_vec()).map(DynamicImage::ImageLuma8)
+        }
+        RawFormat::Rgb8 => {
+            ImageBuffer::from_raw(width, height, bytes.to_vec()).map(DynamicImage::ImageRgb8)
+        }
+        RawFormat::Rgba8 => {
+            ImageBuffer::from_raw(width, height, bytes.to_vec()).map(DynamicImage::ImageRgba8)
+        }
+        RawFormat::Rgb16 => {
+            ImageBuffer::from_raw(width, height, u16s()).map(DynamicImage::ImageRgb16)
+        }
+        RawFormat::Rgba16 => {
+            ImageBuffer::from_raw(width, height, u16s()).map(DynamicImage::ImageRgba16)
+        }
+        RawFormat::Rgb32f => {
+            ImageBuffer::from_raw(width, height, f32s()).map(DynamicImage::ImageRgb32F)
+        }
+        RawFormat::Rgba32f => {
+            ImageBuffer::from_raw(width, height, f32s()).map(DynamicImage::ImageRgba32F)
+        }
+    }
+    .ok_or_else(|| anyhow::anyhow!("A raw {}x{} image is too large", width, height))?;
+    validate_image(&image)?;
+    Ok(image)
+}
+
+/// Decodes an input texture. Multi-page TIFFs yield one image per page; every
+/// other input goes through `image::open` and yields a single image. CMYK
+/// TIFFs are converted to RGB, see [`is_cmyk_tiff`]. With `options.raw` the
+/// file is read by [`decode_raw`] instead, without orientation.
+pub fn read_input(path: &Path, options: &ReadOptions) -> Result<Vec<DynamicImage>, anyhow::Error> {
+    let is_tiff = matches!(
+        image::ImageFormat::from_path(path),
+        Ok(image::ImageFormat::Tiff)
+    );
+    let mapped = if options.memory_map {
+        map_file(path)
+    } else {
+        None
+    };
+    if let Some(raw) = &options.raw {
+        let page = match &mapped {
+            Some(mapped) => decode_raw(mapped, raw),
+            None => decode_raw(&std::fs::read(path)?, raw),
+        };
+        return resize_pages(
+            vec![page.map_err(|e| anyhow::anyhow!("{:?}: {}", path, e))?],
+            options,
+        );
+    }
+    let parallel = is_tiff && options.parallel_decode;
+    let mut pages = match &mapped {
+        Some(mapped) => match parallel {
+            true => decode_tiff_parallel(|| Ok(Cursor::new(&mapped[..])))?,
+            false => None,
+        }
+        .map(|page| validate_image(&page).map(|_| vec![page]))
+        .unwrap_or_else(|| decode_bytes(mapped, image::ImageFormat::from_path(path).ok()))?,
+        None => {
+            let partial = match options.channel_count {
+                Some(count) if is_tiff => decode_tiff_channels(
+                    std::io::BufReader::new(std::fs::File::open(path)?),
+                    count,
+                )?,
+                _ => None,
+            };
+            let partial = match partial {
+                None if parallel => decode_tiff_parallel(|| {
+                    Ok(std::io::BufReader::new(std::fs::File::open(path)?))
+                })?,
+                partial => partial,
+            };
+            let pages = if let Some(page) = partial {
+                vec![page]
+            } else if is_tiff && needs_page_decoder(std::fs::File::open(path)?)? {
+                read_tiff_pages(path)?
+            } else {
+                vec![image::open(path)?]
+            };
+            for (i, page) in pages.iter().enumerate() {
+                validate_image(page)
+                    .map_err(|e| anyhow::anyhow!("{:?} page {}: {}", path, i, e))?;
+            }
+            pages
+        }
+    };
+
+    if options.apply_orientation {
+        let orientation = exif_orientation(path);
+        for page in &mut pages {
+            *page = apply_orientation(std::mem::take(page), orientation);
+        }
+        check_preprocessed(&pages, "orientation")?;
+    }
+    resize_pages(pages, options)
+}
+
+/// Checks `pages` still hold something to transform after the preprocessing
+/// `step`, so a step that left one empty, or too large, fails naming the step
+/// instead of somewhere downstream.
+pub fn check_preprocessed(pages: &[DynamicImage], step: &str) -> Result<(), anyhow::Error> {
+    if pages.is_empty() {
+        return Err(anyhow::anyhow!("No image is left after {}", step));
+    }
+    for (i, page) in pages.iter().enumerate() {
+        validate_image(page).map_err(|e| match pages.len() {
+            1 => anyhow::anyhow!("After {}: {}", step, e),
+            _ => anyhow::anyhow!("After {}, page {}: {}", step, i, e),
+        })?;
+    }
+    Ok(())
+}
+
+/// `pages` resampled as [`ReadOptions::resize`] asks, if it does.
+fn resize_pages(
+    mut pages: Vec<DynamicImage>,
+    options: &ReadOptions,
+) -> Result<Vec<DynamicImage>, anyhow::Error> {
+    let Some((width, height)) = options.resize else {
+        return Ok(pages);
+    };
+    if width == 0 || height == 0 || width as u64 * height as u64 > MAX_PIXELS {
+        return Err(anyhow::anyhow!(
+            "Can't resize inputs to {}x{}, it must be non-empty and at most {} pixels",
+            width,
+            height,
+            MAX_PIXELS
+        ));
+    }
+    for page in &mut pages {
+        *page = page.resize_exact(width, height, options.resize_filter.filter_type());
+    }
+    check_preprocessed(&pages, "resizing")?;
+    Ok(pages)
+}
+
+/// Decodes an encoded input held in memory, like [`read_input`] without the
+/// orientation step, guessing the format from the bytes if `format` is
+/// `None`. Every page is checked with [`validate_image`], so anything this
+/// returns can be transformed.
+pub fn decode_bytes(
+    bytes: &[u8],
+    format: Option<image::ImageFormat>,
+) -> Result<Vec<DynamicImage>, anyhow::Error> {
+    let format = match format {
+        Some(format) => format,
+        None => image::guess_format(bytes)?,
+    };
+    let pages = if format == image::ImageFormat::Tiff && needs_page_decoder(Cursor::new(bytes))? {
+        decode_tiff_pages(Cursor::new(bytes), "input")?
+    } else {
+        vec![image::load_from_memory_with_format(bytes, format)?]
+    };
+    for (i, page) in pages.iter().enumerate() {
+        validate_image(page).map_err(|e| anyhow::anyhow!("Page {}: {}", i, e))?;
+    }
+    Ok(pages)
+}
+
+/// Largest `width * height` accepted, so every subpixel's rank fits a `u32`
+/// and a forward image page fits a TIFF. Lengths and indices are `usize`
+/// throughout, so this is a limit of the formats, not the arithmetic.
+const MAX_PIXELS: u64 = u32::MAX as u64 / 4;
+
+/// Checks a decoded image is something the transform supports: non-empty,
+/// within [`MAX_PIXELS`], and one of the 8-bit, 16-bit or 32-bit float gray,
+/// gray-alpha, RGB or RGBA color types.
+pub fn validate_image(image: &DynamicImage) -> Result<(), anyhow::Error> {
+    use image::ColorType as C;
+
+    let (width, height) = (image.width(), image.height());
+    if width == 0 || height == 0 {
+        return Err(anyhow::anyhow!("Image is empty ({}x{})", width, height));
+    }
+    if width as u64 * height as u64 > MAX_PIXELS {
+        return Err(anyhow::anyhow!(
+            "Image is {}x{}, more than the supported {} pixels",
+            width,
+            height,
+            MAX_PIXELS
+        ));
+    }
+    match image.color() {
+        C::L8
+        | C::La8
+        | C::Rgb8
+        | C::Rgba8
+        | C::L16
+        | C::La16
+        | C::Rgb16
+        | C::Rgba16
+        | C::Rgb32F
+        | C::Rgba32F => Ok(()),
+        color => Err(anyhow::anyhow!("Unsupported color type {:?}", color)),
+    }
+}
+
+/// Pixels of a 32-bit float image with a NaN or infinite sample, by row-major
+/// index, with their samples; RGB pixels get an alpha of `1.0`. Empty for
+/// integer images, which can't hold either.
+pub fn non_finite_pixels(image: &DynamicImage) -> Vec<(usize, [f32; 4])> {
+    let (samples, stride) = match image {
+        DynamicImage::ImageRgb32F(buffer) => (buffer.as_raw(), 3),
+        DynamicImage::ImageRgba32F(buffer) => (buffer.as_raw(), 4),
+        _ => return Vec::new(),
+    };
+    samples
+        .chunks_exact(stride)
+        .enumerate()
+        .filter(|(_, px)| px.iter().any(|v| !v.is_finite()))
+        .map(|(i, px)| {
+            let mut samples = [1.0; 4];
+            samples[..stride].copy_from_slice(px);
+            (i, samples)
+        })
+        .collect()
+}
+
+/// Replaces every NaN or infinite sample of a 32-bit float image with zero.
+pub fn zero_non_finite(image: &mut DynamicImage) {
+    let samples: &mut [f32] = match image {
+        DynamicImage::ImageRgb32F(buffer) => buffer,
+        DynamicImage::ImageRgba32F(buffer) => buffer,
+        _ => return,
+    };
+    samples
+        .iter_mut()
+        .filter(|v| !v.is_finite())
+        .for_each(|v| *v = 0.0);
+}
+
+/// Reads a grayscale weight map, taking the luma of color images and the
+/// first page of multi-page ones. `options.raw` is ignored.
+pub fn read_weight_map(
+    path: &Path,
+    options: &ReadOptions,
+) -> Result<ImageBuffer<Luma<u16>, Vec<u16>>, anyhow::Error> {
+    let options = options.clone().raw(None);
+    let page = read_input(path, &options)?.swap_remove(0);
+    Ok(page.to_luma16())
+}
+
+/// `image` with its alpha replaced by `mask`, a grayscale image of the same
+/// size such as one from [`read_weight_map`], for opacity kept in a texture of
+/// its own. 8-bit inputs become RGBA8, with the mask rounded to 8 bits, 16-bit
+/// inputs RGBA16 and float inputs RGBA32F.
+pub fn with_alpha(image: &DynamicImage, mask: &ImageBuffer<Luma<u16>, Vec<u16>>) -> DynamicImage {
+    let alpha = mask.as_raw();
+    match image {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+            let mut rgba = image.to_rgba32f();
+            for (px, &a) in rgba.pixels_mut().zip(alpha) {
+                px[3] = a as f32 / 65535.0;
+            }
+            DynamicImage::ImageRgba32F(rgba)
+        }
+        DynamicImage::ImageLuma16(_)
+        | DynamicImage::ImageLumaA16(_)
+        | DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_) => {
+            let mut rgba = image.to_rgba16();
+            for (px, &a) in rgba.pixels_mut().zip(alpha) {
+                px[3] = a;
+            }
+            DynamicImage::ImageRgba16(rgba)
+        }
+        _ => {
+            let mut rgba = image.to_rgba8();
+            for (px, &a) in rgba.pixels_mut().zip(alpha) {
+                px[3] = ((a as u32 + 128) / 257) as u8;
+            }
+            DynamicImage::ImageRgba8(rgba)
+        }
+    }
+}
+
+/// Width and height of an input as [`read_input`] would orient and resize
+/// it, read from the header without decoding. Multi-page TIFFs report their
+/// first page.
+pub fn input_dimensions(path: &Path, options: &ReadOptions) -> Result<(u32, u32), anyhow::Error> {
+    if let Some(size) = options.resize {
+        return Ok(size);
+    }
+    if let Some(raw) = &options.raw {
+        return Ok((raw.width, raw.height));
+    }
+    let (width, height) = match image::ImageFormat::from_path(path) {
+        // `image` refuses CMYK TIFFs outright, so ask `tiff` directly.
+        Ok(image::ImageFormat::Tiff) => Decoder::new(std::fs::File::open(path)?)?.dimensions()?,
+        _ => image::image_dimensions(path)?,
+    };
+    if options.apply_orientation && (5..=8).contains(&exif_orientation(path)) {
+        Ok((height, width))
+    } else {
+        Ok((width, height))
+    }
+}
+
+/// The EXIF orientation tag (1-8), or 1 when the file has none.
+fn exif_orientation(path: &Path) -> u32 {
+    let read = || -> Option<u32> {
+        let mut reader = std::io::BufReader::new(std::fs::File::open(path).ok()?);
+        let exif = exif::Reader::new().read_from_container(&mut reader).ok()?;
+        exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+            .value
+            .get_uint(0)
+    };
+    read().unwrap_or(1)
+}
+
+fn apply_orientation(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+/// Maps `path` read-only, or `None` if it can't be mapped.
+fn map_file(path: &Path) -> Option<Mmap> {
+    let file = std::fs::File::open(path).ok()?;
+    // SAFETY: The map is only read while decoding. Truncating or rewriting the
+    // file from another process meanwhile is undefined behavior, the same
+    // caveat as any mmap-based reader.
+    unsafe { Mmap::map(&file) }.ok()
+}
+
+/// Whether `path` is a CMYK TIFF. Those are read with a naive conversion to
+/// RGB that ignores any embedded ICC profile, so colors are approximate.
+pub fn is_cmyk_tiff(path: &Path) -> bool {
+    let read = || -> Option<bool> {
+        let mut decoder = Decoder::new(std::fs::File::open(path).ok()?).ok()?;
+        Some(matches!(decoder.colortype().ok()?, ColorType::CMYK(_)))
+    };
+    read().unwrap_or(false)
+}
+
+/// Whether `path` is an indexed PNG or GIF with a fully transparent palette
+/// entry: a PNG of color type 3 whose tRNS chunk gives an entry alpha 0, or
+/// a GIF whose first frame sets a transparent index. Both decode to RGBA
+/// with those pixels at alpha 0, so an alpha cutoff leaves them out of the
+/// histogram. Only the headers are read; anything else, or a file that
+/// can't be read, gives `false`.
+pub fn has_transparent_index(path: &Path) -> bool {
+    let Ok(mut file) = std::fs::File::open(path) else {
+        return false;
+    };
+    // The palette and whatever else comes ahead of the pixels usually fit.
+    let mut header = Vec::new();
+    if file
+        .by_ref()
+        .take(1 << 16)
+        .read_to_end(&mut header)
+        .is_err()
+    {
+        return false;
+    }
+    match &header[..] {
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', chunks @ ..] => {
+            png_transparent_index(chunks)
+        }
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', rest @ ..] => gif_transparent_index(rest),
+        _ => false,
+    }
+}
+
+fn png_transparent_index(mut chunks: &[u8]) -> bool {
+    let mut indexed = false;
+    while let [l0, l1, l2, l3, t0, t1, t2, t3, rest @ ..] = chunks {
+        let len = u32::from_be_bytes([*l0, *l1, *l2, *l3]) as usize;
+        let Some(data) = rest.get(..len) else {
+            return false;
+        };
+        match &[*t0, *t1, *t2, *t3] {
+            b"IHDR" => indexed = data.get(9) == Some(&3),
+            b"tRNS" => return indexed && data.contains(&0),
+            b"IDAT" => return false,
+            _ => {}
+        }
+        // Data and CRC.
+        chunks = rest.get(len + 4..).unwrap_or_default();
+    }
+    false
+}
+
+fn gif_transparent_index(rest: &[u8]) -> bool {
+    // The logical screen descriptor, then the global color table if its flag is set.
+    let Some(&packed) = rest.get(4) else {
+        return false;
+    };
+    let table_len = if packed & 0x80 != 0 {
+        3 << ((packed & 7) + 1)
+    } else {
+        0
+    };
+    let mut blocks = rest.get(7 + table_len..).unwrap_or_default();
+    // Extensions up to the first image descriptor.
+    while let [0x21, label, rest @ ..] = blocks {
+        if *label == 0xf9 {
+            return matches!(rest, [4, flags, ..] if flags & 1 != 0);
+        }
+        let mut sub_blocks = rest;
+        while let [len, rest @ ..] = sub_blocks {
+            if *len == 0 {
+                break;
+            }
+            sub_blocks = rest.get(*len as usize..).unwrap_or_default();
+        }
+        blocks = sub_blocks.get(1..).unwrap_or_default();
+    }
+    false
+}
+
+/// What a TIFF's first page header says about its samples, for describing a
+/// file without decoding it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TiffHeader {
+    pub width: u32,
+    pub height: u32,
+    pub pages: usize,
+    pub color_type: ColorType,
+    /// `SampleFormat`: 1 unsigned, 2 signed, 3 float.
+    pub sample_format: u16,
+    /// `Compression`: 1 none, 32773 Packbits.
+    pub compression: u16,
+    /// `PlanarConfiguration`: 1 interleaved, 2 planar.
+    pub planar_configuration: u16,
+    pub big_endian: bool,
+    pub tiled: bool,
+}
+
+/// Reads the [`TiffHeader`] of the TIFF at `path`, counting its pages.
+pub fn read_tiff_header(path: &Path) -> Result<TiffHeader, anyhow::Error> {
+    use tiff::tags::Tag;
+    let mut file = std::fs::File::open(path)?;
+    let mut byte_order = [0; 2];
+    file.read_exact(&mut byte_order)?;
+    file.rewind()?;
+    let mut decoder = Decoder::new(std::io::BufReader::new(file))?;
+    let (width, height) = decoder.dimensions()?;
+    let mut header = TiffHeader {
+        width,
+        height,
+        pages: 1,
+        color_type: decoder.colortype()?,
+        sample_format: decoder
+            .find_tag_unsigned_vec(Tag::SampleFormat)?
+            .and_then(|formats| formats.first().copied())
+            .unwrap_or(1),
+        compression: decoder.find_tag_unsigned(Tag::Compression)?.unwrap_or(1),
+        planar_configuration: decoder
+            .find_tag_unsigned(Tag::PlanarConfiguration)?
+            .unwrap_or(1),
+        big_endian: &byte_order == b"MM",
+        tiled: decoder.find_tag(Tag::TileWidth)?.is_some(),
+    };
+    while decoder.more_images() {
+        decoder.next_image()?;
+        header.pages += 1;
+    }
+    Ok(header)
+}
+
+/// Whether `path` is a single-channel 32-bit float TIFF, which is transformed
+/// as a heightmap with [`TransformConfig::run_height`](crate::TransformConfig::run_height).
+pub fn is_gray_f32_tiff(path: &Path) -> bool {
+    let read = || -> Option<bool> {
+        let mut decoder = Decoder::new(std::fs::File::open(path).ok()?).ok()?;
+        let is_float = decoder
+            .find_tag_unsigned::<u16>(tiff::tags::Tag::SampleFormat)
+            .ok()?
+            == Some(3);
+        Some(decoder.colortype().ok()? == ColorType::Gray(32) && is_float)
+    };
+    read().unwrap_or(false)
+}
+
+/// Whether `path` is a single-channel 32-bit unsigned or signed integer TIFF,
+/// which is transformed as a heightmap like [`is_gray_f32_tiff`]'s.
+pub fn is_gray_int32_tiff(path: &Path) -> bool {
+    let read = || -> Option<bool> {
+        let mut decoder = Decoder::new(std::fs::File::open(path).ok()?).ok()?;
+        let sample_format = decoder
+            .find_tag_unsigned::<u16>(tiff::tags::Tag::SampleFormat)
+            .ok()?;
+        let is_integer = matches!(sample_format, None | Some(1) | Some(2));
+        Some(decoder.colortype().ok()? == ColorType::Gray(32) && is_integer)
+    };
+    read().unwrap_or(false)
+}
+
+/// Whether a TIFF has to go through [`decode_tiff_pages`] rather than
+/// `image`: it has several pages, or is CMYK or 32-bit float, which `image`
+/// can't decode.
+fn needs_page_decoder<R: Read + Seek>(reader: R) -> Result<bool, anyhow::Error> {
+    let mut decoder = Decoder::new(reader)?;
+    let unsupported = matches!(
+        decoder.colortype()?,
+        ColorType::CMYK(_) | ColorType::RGB(32) | ColorType::RGBA(32)
+    );
+    Ok(unsupported || decoder.more_images())
+}
+
+pub fn read_tiff_pages(path: &Path) -> Result<Vec<DynamicImage>, anyhow::Error> {
+    decode_tiff_pages(std::fs::File::open(path)?, &format!("{:?}", path))
+}
+
+/// Decodes every page of a TIFF; `source` names it in errors.
+fn decode_tiff_pages<R: Read + Seek>(
+    reader: R,
+    source: &str,
+) -> Result<Vec<DynamicImage>, anyhow::Error> {
+    let mut decoder = Decoder::new(reader)?;
+    let mut pages = Vec::new();
+    loop {
+        let (width, height) = decoder.dimensions()?;
+        let color_type = decoder.colortype()?;
+        let page =
+            tiff_page_image(width, height, color_type, decoder.read_image()?).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Page {} of {} has unsupported color type {:?}",
+                    pages.len(),
+                    source,
+                    color_type
+                )
+            })?;
+        pages.push(page);
+
+        if !decoder.more_images() {
+            return Ok(pages);
+        }
+        decoder.next_image()?;
+    }
+}
+
+/// Decodes the first `count` channels of a single-page, interleaved, striped
+/// 8-bit, 16-bit or float RGB or RGBA TIFF strip by strip into an RGB image,
+/// leaving the rest zero. `None` for any other TIFF, which has to be decoded
+/// in full; `tiff` can't skip samples within a strip.
+fn decode_tiff_channels<R: Read + Seek>(
+    reader: R,
+    count: usize,
+) -> Result<Option<DynamicImage>, anyhow::Error> {
+    let mut decoder = Decoder::new(reader)?;
+    let color_type = decoder.colortype()?;
+    let samples = match color_type {
+        ColorType::RGB(_) => 3,
+        ColorType::RGBA(_) => 4,
+        _ => return Ok(None),
+    };
+    let planar = decoder.find_tag_unsigned::<u16>(tiff::tags::Tag::PlanarConfiguration)? == Some(2);
+    if planar || decoder.more_images() || decoder.get_chunk_type() != ChunkType::Strip {
+        return Ok(None);
+    }
+    let (width, height) = decoder.dimensions()?;
+    let count = count.min(3);
+    Ok(match color_type {
+        ColorType::RGB(8) | ColorType::RGBA(8) => {
+            let data = gather_channels(&mut decoder, width, samples, count, |strip| match strip {
+                DecodingResult::U8(data) => Some(data),
+                _ => None,
+            })?;
+            data.and_then(|data| ImageBuffer::from_raw(width, height, data))
+                .map(DynamicImage::ImageRgb8)
+        }
+        ColorType::RGB(16) | ColorType::RGBA(16) => {
+            let data = gather_channels(&mut decoder, width, samples, count, |strip| match strip {
+                DecodingResult::U16(data) => Some(data),
+                _ => None,
+            })?;
+            data.and_then(|data| ImageBuffer::from_raw(width, height, data))
+                .map(DynamicImage::ImageRgb16)
+        }
+        ColorType::RGB(32) | ColorType::RGBA(32) => {
+            let data = gather_channels(&mut decoder, width, samples, count, |strip| match strip {
+                DecodingResult::F32(data) => Some(data),
+                _ => None,
+            })?;
+            data.and_then(|data| ImageBuffer::from_raw(width, height, data))
+                .map(DynamicImage::ImageRgb32F)
+        }
+        _ => None,
+    })
+}
+
+/// Decodes a single-page, interleaved, striped or tiled TIFF of more than
+/// one chunk, each strip or tile a task on the rayon pool with a decoder of
+/// its worker's own from `open`, then copies the chunks into place. `None`
+/// for any other TIFF, and for photometric interpretations or sample types
+/// [`tiff_page_image`] doesn't take as they are, which `image` decodes.
+fn decode_tiff_parallel<R, F>(open: F) -> Result<Option<DynamicImage>, anyhow::Error>
+where
+    R: Read + Seek,
+    F: Fn() -> Result<R, anyhow::Error> + Sync,
+{
+    use rayon::prelude::*;
+    use tiff::tags::Tag;
+
+    let mut decoder = Decoder::new(open()?)?;
+    let color_type = decoder.colortype()?;
+    let samples = match color_type {
+        ColorType::Gray(8 | 16) => 1,
+        ColorType::GrayA(8 | 16) => 2,
+        ColorType::RGB(8 | 16 | 32) => 3,
+        ColorType::RGBA(8 | 16 | 32) | ColorType::CMYK(8 | 16) => 4,
+        _ => return Ok(None),
+    };
+    // BlackIsZero, RGB and CMYK; `image` inverts WhiteIsZero.
+    let photometric = decoder.find_tag_unsigned::<u16>(Tag::PhotometricInterpretation)?;
+    let planar = decoder.find_tag_unsigned::<u16>(Tag::PlanarConfiguration)? == Some(2);
+    if planar || decoder.more_images() || !matches!(photometric, Some(1 | 2 | 5)) {
+        return Ok(None);
+    }
+    let (width, height) = decoder.dimensions()?;
+    let chunks = match decoder.get_chunk_type() {
+        ChunkType::Strip => decoder.strip_count()?,
+        ChunkType::Tile => decoder.tile_count()?,
+    };
+    if chunks < 2 {
+        return Ok(None);
+    }
+    let layout = ChunkLayout {
+        width,
+        height,
+        chunk_size: decoder.chunk_dimensions(),
+        data_sizes: (0..chunks)
+            .map(|i| decoder.chunk_data_dimensions(i))
+            .collect(),
+        samples,
+    };
+    let decoded = (0..chunks)
+        .into_par_iter()
+        .map_init(
+            || open().and_then(|reader| Ok(Decoder::new(reader)?)),
+            |decoder, chunk| match decoder {
+                Ok(decoder) => Ok(decoder.read_chunk(chunk)?),
+                Err(e) => Err(anyhow::anyhow!("{}", e)),
+            },
+        )
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
+    let data = match decoded.first() {
+        Some(DecodingResult::U8(_)) => layout
+            .assemble(decoded, |chunk| match chunk {
+                DecodingResult::U8(data) => Some(data),
+                _ => None,
+            })
+            .map(DecodingResult::U8),
+        Some(DecodingResult::U16(_)) => layout
+            .assemble(decoded, |chunk| match chunk {
+                DecodingResult::U16(data) => Some(data),
+                _ => None,
+            })
+            .map(DecodingResult::U16),
+        Some(DecodingResult::F32(_)) => layout
+            .assemble(decoded, |chunk| match chunk {
+                DecodingResult::F32(data) => Some(data),
+                _ => None,
+            })
+            .map(DecodingResult::F32),
+        _ => None,
+    };
+    Ok(data.and_then(|data| tiff_page_image(width, height, color_type, data)))
+}
+
+/// Where the strips or tiles of a TIFF page go, for [`decode_tiff_parallel`].
+struct ChunkLayout {
+    width: u32,
+    height: u32,
+    /// Width and height of a full chunk; a strip is the page's width.
+    chunk_size: (u32, u32),
+    /// Width and height of each chunk's data, less the padding of those on
+    /// the right and bottom edges.
+    data_sizes: Vec<(u32, u32)>,
+    samples: usize,
+}
+
+impl ChunkLayout {
+    /// Copies the decoded `chunks`, in chunk order, into one interleaved
+    /// page. `None` if `unpack` doesn't accept a chunk's sample type.
+    fn assemble<T: Copy + Default>(
+        &self,
+        chunks: Vec<DecodingResult>,
+        unpack: impl Fn(DecodingResult) -> Option<Vec<T>>,
+    ) -> Option<Vec<T>> {
+        let (chunk_width, chunk_height) = self.chunk_size;
+        let across = self.width.div_ceil(chunk_width);
+        let mut page =
+            vec![T::default(); self.width as usize * self.height as usize * self.samples];
+        for (i, (chunk, &(data_width, data_height))) in
+            chunks.into_iter().zip(&self.data_sizes).enumerate()
+        {
+            let data = unpack(chunk)?;
+            let (x, y) = (
+                (i as u32 % across) * chunk_width,
+                (i as u32 / across) * chunk_height,
+            );
+            let row_len = data_width as usize * self.samples;
+            for (row, src) in data
+                .chunks_exact(row_len)
+                .take(data_height as usize)
+                .enumerate()
+            {
+                let start = ((y as usize + row) * self.width as usize + x as usize) * self.samples;
+                page[start..start + row_len].copy_from_slice(src);
+            }
+        }
+        Some(page)
+    }
+}
+
+/// Reads every strip of `decoder`'s image, of `samples` channels per pixel,
+/// and copies their first `count` channels into an RGB buffer. `None` if
+/// `unpack` doesn't accept a strip's sample type.
+fn gather_channels<R: Read + Seek, T: Copy + Default>(
+    decoder: &mut Decoder<R>,
+    width: u32,
+    samples: usize,
+    count: usize,
+    unpack: impl Fn(DecodingResult) -> Option<Vec<T>>,
+) -> Result<Option<Vec<T>>, anyhow::Error> {
+    let (_, height) = decoder.dimensions()?;
+    let mut rgb = vec![T::default(); width as usize * height as usize * 3];
+    let mut pixels = rgb.chunks_exact_mut(3);
+    for strip in 0..decoder.strip_count()? {
+        let Some(data) = unpack(decoder.read_chunk(strip)?) else {
+            return Ok(None);
+        };
+        // Strip first, so the pixel after the strip isn't pulled and dropped.
+        for (src, px) in data.chunks_exact(samples).zip(pixels.by_ref()) {
+            px[..count].copy_from_slice(&src[..count]);
+        }
+    }
+    Ok(Some(rgb))
+}
+
+fn tiff_page_image(
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+    data: DecodingResult,
+) -> Option<DynamicImage> {
+    match (color_type, data) {
+        (ColorType::Gray(8), DecodingResult::U8(d)) => {
+            ImageBuffer::from_raw(width, height, d).map(DynamicImage::ImageLuma8)
+        }
+        (ColorType::GrayA(8), DecodingResult::U8(d)) => {
+            ImageBuffer::from_raw(width, height, d).map(DynamicImage::ImageLumaA8)
+        }
+        (ColorType::RGB(8), DecodingResult::U8(d)) => {
+            ImageBuffer::from_raw(width, height, d).map(DynamicImage::ImageRgb8)
+        }
+        (ColorType::RGBA(8), DecodingResult::U8(d)) => {
+            ImageBuffer::from_raw(width, height, d).map(DynamicImage::ImageRgba8)
+        }
+        (ColorType::Gray(16), DecodingResult::U16(d)) => {
+            ImageBuffer::from_raw(width, height, d).map(DynamicImage::ImageLuma16)
+        }
+        (ColorType::GrayA(16), DecodingResult::U16(d)) => {
+            ImageBuffer::from_raw(width, height, d).map(DynamicImage::ImageLumaA16)
+        }
+        (ColorType::RGB(16), DecodingResult::U16(d)) => {
+            ImageBuffer::from_raw(width, height, d).map(DynamicImage::ImageRgb16)
+        }
+        (ColorType::RGBA(16), DecodingResult::U16(d)) => {
+            ImageBuffer::from_raw(width, height, d).map(DynamicImage::ImageRgba16)
+        }
+        (ColorType::CMYK(8), DecodingResult::U8(d)) => cmyk_to_rgb(&d, u8::MAX.into())
+            .and_then(|rgb| ImageBuffer::from_raw(width, height, rgb))
+            .map(DynamicImage::ImageRgb8),
+        (ColorType::CMYK(16), DecodingResult::U16(d)) => cmyk_to_rgb(&d, u16::MAX.into())
+            .and_then(|rgb| ImageBuffer::from_raw(width, height, rgb))
+            .map(DynamicImage::ImageRgb16),
+        (ColorType::RGB(32), DecodingResult::F32(d)) => {
+            ImageBuffer::from_raw(width, height, d).map(DynamicImage::ImageRgb32F)
+        }
+        (ColorType::RGBA(32), DecodingResult::F32(d)) => {
+            ImageBuffer::from_raw(width, height, d).map(DynamicImage::ImageRgba32F)
+        }
+        _ => None,
+    }
+}
+
+/// Naive CMYK to RGB, `rgb = (1 - cmy) * (1 - k)` with samples scaled by `max`.
+fn cmyk_to_rgb<T>(cmyk: &[T], max: u64) -> Option<Vec<T>>
+where
+    T: Copy + Into<u64> + TryFrom<u64>,
+{
+    cmyk.chunks_exact(4)
+        .flat_map(|px| {
+            let k = max - px[3].into();
+            px[..3]
+                .iter()
+                .map(move |&c| T::try_from(((max - c.into()) * k + max / 2) / max).ok())
+        })
+        .collect()
+}
+
+/// Stacks equally wide pages top to bottom so they share one histogram.
+pub fn stack_pages<P: Borrow<DynamicImage>>(pages: &[P]) -> Result<DynamicImage, anyhow::Error> {
+    let pages: Vec<&DynamicImage> = pages.iter().map(Borrow::borrow).collect();
+    let width = pages[0].width();
+    if let Some(page) = pages.iter().find(|page| page.width() != width) {
+        return Err(anyhow::anyhow!(
+            "All pages must share a width, found {} and {}",
+            width,
+            page.width()
+        ));
+    }
+
+    let height: u64 = pages.iter().map(|page| page.height() as u64).sum();
+    let height = u32::try_from(height)
+        .map_err(|_| anyhow::anyhow!("Pages stack to {} rows, more than an image holds", height))?;
+    let mut stacked = DynamicImage::new_rgba8(width, height);
+    let mut y = 0;
+    for page in pages {
+        stacked.copy_from(&page.to_rgba8(), 0, y)?;
+        y += page.height();
+    }
+    Ok(stacked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::CancelToken;
+    use crate::encode::{encode_to_vec, OutputData, Tiff};
+    use crate::self_test::synthetic_input;
+    use crate::TransformConfig;
+
+    #[test]
+    fn parallel_decode_matches_the_serial_decode() {
+        let (width, height) = (300, 210);
+        let samples = |channels: usize| width as usize * height as usize * channels;
+        let level = |i: usize| ((i * 37) % 251) as u8;
+        for tile_size in [None, Some(16)] {
+            let tiff = Tiff {
+                tile_size,
+                ..Default::default()
+            };
+            // Only three-channel TIFFs are written in tiles.
+            let channel_counts: &[usize] = if tile_size.is_some() {
+                &[3]
+            } else {
+                &[1, 3, 4]
+            };
+            for &channels in channel_counts {
+                let u8s: Vec<u8> = (0..samples(channels)).map(level).collect();
+                let u16s: Vec<u16> = (0..samples(channels))
+                    .map(|i| (i * 7919 % 65536) as u16)
+                    .collect();
+                let f32s: Vec<f32> = (0..samples(channels))
+                    .map(|i| level(i) as f32 / 250.0)
+                    .collect();
+                let data = [
+                    ("u8", OutputData::U8(&u8s)),
+                    ("u16", OutputData::U16(&u16s)),
+                    ("f32", OutputData::F32(&f32s)),
+                ];
+                for (name, data) in data {
+                    let bytes = encode_to_vec(&tiff, width, &[height], channels, data).unwrap();
+                    let parallel = decode_tiff_parallel(|| Ok(Cursor::new(&bytes[..]))).unwrap();
+                    let what = format!("{:?} tiles, {} channels of {}", tile_size, channels, name);
+                    // One-channel float TIFFs are heightmaps, read on their own.
+                    if (channels, name) == (1, "f32") {
+                        assert!(parallel.is_none(), "{}", what);
+                        continue;
+                    }
+                    let serial = decode_bytes(&bytes, Some(image::ImageFormat::Tiff)).unwrap();
+                    assert_eq!(parallel.as_ref(), Some(&serial[0]), "{}", what);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn degenerate_preprocessing_fails_naming_the_step() {
+        let page = synthetic_input();
+        let resized = |size| resize_pages(vec![page.clone()], &ReadOptions::default().resize(size));
+        for size in [(0, 5), (5, 0)] {
+            let err = resized(Some(size)).unwrap_err().to_string();
+            assert!(err.starts_with("Can't resize inputs to"), "{}", err);
+        }
+        let tiny = &resized(Some((1, 1))).unwrap()[0];
+        assert_eq!((tiny.width(), tiny.height()), (1, 1));
+
+        let empty = DynamicImage::new_rgb8(0, 3);
+        let err = check_preprocessed(std::slice::from_ref(&empty), "cropping").unwrap_err();
+        assert_eq!(err.to_string(), "After cropping: Image is empty (0x3)");
+        let err = check_preprocessed(&[page.clone(), empty], "cropping").unwrap_err();
+        assert!(err.to_string().starts_with("After cropping, page 1:"));
+        let err = check_preprocessed(&[], "cropping").unwrap_err();
+        assert_eq!(err.to_string(), "No image is left after cropping");
+        check_preprocessed(&[page], "cropping").unwrap();
+    }
+
+    #[test]
+    fn two_page_tiff_is_transformed_with_a_shared_histogram() {
+        let first = synthetic_input().into_rgb8().into_raw();
+        let second: Vec<u8> = first.iter().map(|&v| 255 - v).collect();
+        let data = [first.clone(), second.clone()].concat();
+        let bytes =
+            encode_to_vec(&Tiff::default(), 64, &[64, 64], 3, OutputData::U8(&data)).unwrap();
+
+        let pages = decode_bytes(&bytes, None).unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].to_rgb8().into_raw(), first);
+        assert_eq!(pages[1].to_rgb8().into_raw(), second);
+
+        let result = TransformConfig::default()
+            .deterministic(true)
+            .run_pages(&pages, &CancelToken::new())
+            .unwrap();
+        assert_eq!(result.page_heights, [64, 64]);
+        assert_eq!(result.image.len(), data.len());
+        assert_eq!(result.histogram.sample_count(0), 2 * 64 * 64);
+        // One histogram: the brightest subpixel of either page sits at the top.
+        let (brightest, _) = data
+            .iter()
+            .enumerate()
+            .step_by(3)
+            .max_by_key(|&(i, v)| (*v, i))
+            .unwrap();
+        let top = result
+            .image
+            .iter()
+            .step_by(3)
+            .cloned()
+            .fold(f32::MIN, f32::max);
+        assert_eq!(result.image[brightest], top);
+    }
+
+    #[test]
+    fn exif_orientation_rotates_the_input() {
+        use tiff::encoder::{colortype, TiffEncoder};
+        use tiff::tags::Tag;
+
+        // 3x2, every subpixel distinct.
+        let data: Vec<u8> = (0..18).collect();
+        let path =
+            std::env::temp_dir().join(format!("precompute-orientation-{}.tif", std::process::id()));
+        let write = || -> Result<(), anyhow::Error> {
+            let mut file = std::fs::File::create(&path)?;
+            let mut encoder = TiffEncoder::new(&mut file)?;
+            let mut image = encoder.new_image::<colortype::RGB8>(3, 2)?;
+            // 6: the stored rows must be turned 90 degrees clockwise.
+            image.encoder().write_tag(Tag::Orientation, 6u16)?;
+            image.write_data(&data)?;
+            Ok(())
+        };
+        let written = write();
+        let rotated = written.and_then(|_| read_input(&path, &ReadOptions::new()));
+        let ignored = read_input(&path, &ReadOptions::new().apply_orientation(false));
+        std::fs::remove_file(&path).unwrap();
+
+        let stored = DynamicImage::ImageRgb8(image::RgbImage::from_raw(3, 2, data).unwrap());
+        let [rotated] = &rotated.unwrap()[..] else {
+            panic!("expected one page");
+        };
+        assert_eq!((rotated.width(), rotated.height()), (2, 3));
+        assert_eq!(rotated.to_rgb8(), stored.rotate90().to_rgb8());
+        // Top left of the rotated image is the stored bottom left.
+        assert_eq!(rotated.to_rgb8().get_pixel(0, 0).0, [9, 10, 11]);
+        assert_eq!(ignored.unwrap()[0].to_rgb8(), stored.to_rgb8());
+    }
+
+    #[test]
+    fn cmyk_tiff_is_converted_and_transformed() {
+        use tiff::encoder::{colortype, TiffEncoder};
+
+        let path = std::env::temp_dir().join(format!("precompute-cmyk-{}.tif", std::process::id()));
+        // White, black, cyan and a mid gray from K alone, repeated down 4 rows.
+        let pixel_row = [0, 0, 0, 0, 0, 0, 0, 255, 255, 0, 0, 0, 0, 0, 0, 128];
+        let data = pixel_row.repeat(4);
+        let write = || -> Result<(), anyhow::Error> {
+            let mut file = std::fs::File::create(&path)?;
+            TiffEncoder::new(&mut file)?.write_image::<colortype::CMYK8>(4, 4, &data)?;
+            Ok(())
+        };
+        let pages = write().and_then(|_| read_input(&path, &ReadOptions::new()));
+        let is_cmyk = is_cmyk_tiff(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(is_cmyk);
+        let pages = pages.unwrap();
+        let rgb = pages[0].to_rgb8();
+        let pixels: Vec<[u8; 3]> = rgb.pixels().take(4).map(|p| p.0).collect();
+        assert_eq!(pixels, [[255; 3], [0; 3], [0, 255, 255], [127; 3]]);
+        let result =
+            crate::TransformConfig::default().run_pages(&pages, &crate::cancel::CancelToken::new());
+        assert_eq!(result.unwrap().image.len(), 4 * 4 * 3);
+    }
+
+    #[test]
+    fn raw_dump_reads_and_transforms_like_the_source() {
+        let source = synthetic_input();
+        let config = TransformConfig::default().deterministic(true);
+        let expected = config.run(&source).unwrap();
+        let rgb = source.to_rgb8().into_raw();
+        let rgba32f: Vec<u8> = rgb
+            .chunks_exact(3)
+            .flat_map(|px| [px[0], px[1], px[2], 255])
+            .flat_map(|v| (v as f32 / 255.0).to_le_bytes())
+            .collect();
+        let path = std::env::temp_dir().join(format!("precompute-raw-{}.bin", std::process::id()));
+        for (format, bytes) in [(RawFormat::Rgb8, &rgb), (RawFormat::Rgba32f, &rgba32f)] {
+            std::fs::write(&path, bytes).unwrap();
+            let layout = RawLayout {
+                width: 64,
+                height: 64,
+                format,
+            };
+            let pages = read_input(&path, &ReadOptions::new().raw(Some(layout))).unwrap();
+            assert_eq!(pages[0].to_rgb8().into_raw(), rgb, "{:?}", format);
+            let result = config.run(&pages[0]).unwrap();
+            assert_eq!(result.lut, expected.lut, "{:?}", format);
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        let short = RawLayout {
+            width: 64,
+            height: 65,
+            format: RawFormat::Rgb8,
+        };
+        let err = decode_raw(&rgb, &short).unwrap_err().to_string();
+        assert!(err.contains("12480 bytes, got 12288"), "{}", err);
+    }
+
+    #[test]
+    fn partial_channel_decode_matches_the_full_decode() {
+        let rgba = synthetic_input().to_rgba8();
+        let path = std::env::temp_dir().join(format!("precompute-channels-{}", std::process::id()));
+        let paths = [path.with_extension("8.tif"), path.with_extension("f32.tif")];
+        DynamicImage::ImageRgba8(rgba.clone())
+            .save(&paths[0])
+            .unwrap();
+        let floats: Vec<f32> = rgba.as_raw().iter().map(|&v| v as f32 / 255.0).collect();
+        let bytes = encode_to_vec(&Tiff::default(), 64, &[64], 4, OutputData::F32(&floats));
+        std::fs::write(&paths[1], bytes.unwrap()).unwrap();
+
+        for path in &paths {
+            let full = read_input(path, &ReadOptions::default()).unwrap().remove(0);
+            let full = full.to_rgb32f().into_raw();
+            for count in 1..=3 {
+                let options = ReadOptions::default().channel_count(Some(count));
+                let partial = read_input(path, &options).unwrap().remove(0);
+                // Decoded straight to RGB, so really by strips rather than in full.
+                assert_eq!(partial.color().channel_count(), 3);
+                let partial = partial.to_rgb32f().into_raw();
+                for (i, (a, b)) in partial.iter().zip(&full).enumerate() {
+                    let expected = if i % 3 < count { *b } else { 0.0 };
+                    assert_eq!(*a, expected, "{:?}, {} channels", path, count);
+                }
+            }
+        }
+        for path in &paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use image::DynamicImage;
+
+use crate::config::{
+    LutEndpoints, LutInterp, LutSampling, NanPolicy, PlottingPosition, Prefilter, TransformConfig,
+};
+use crate::read::{non_finite_pixels, validate_image};
+
+/// Output of [`TransformConfig::run_reference`], laid out like a
+/// [`GaussianResult`](crate::GaussianResult)'s: RGB interleaved, with
+/// channels that aren't transformed left at zero.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReferenceBake {
+    pub lut_width: u32,
+    pub image: Vec<f32>,
+    pub lut: Vec<u8>,
+}
+
+impl TransformConfig {
+    /// The transform written as plainly as possible, as an oracle for the
+    /// optimized paths: each channel's subpixels are sorted on one thread
+    /// with a stable comparison sort, so ties stay in image order as
+    /// `deterministic` leaves them, the `k`th of `n` is mapped through the
+    /// target's scalar quantile function at `(k + 0.5) / n`, and each LUT
+    /// texel holds the sorted sample nearest its quantile. Nothing is shared
+    /// with [`TransformConfig::run`] but the LUT's size and texel positions.
+    /// Only the core transform is covered; options that change it are
+    /// rejected.
+    pub fn run_reference(&self, input: &DynamicImage) -> Result<ReferenceBake, anyhow::Error> {
+        self.validate()?;
+        validate_image(input)?;
+        for (unsupported, name) in [
+            (self.prefilter != Prefilter::None, "prefilter"),
+            (self.alpha_cutoff.is_some(), "alpha_cutoff"),
+            (self.nan_policy != NanPolicy::Error, "nan_policy"),
+            (self.histogram_samples.is_some(), "histogram_samples"),
+            (self.trim_tails != 0.0, "trim_tails"),
+            (self.clip_limit.is_some(), "clip_limit"),
+            (self.shared_lut, "shared_lut"),
+            (self.mean_gradient.is_some(), "mean_gradient"),
+            (self.target_cdf.is_some(), "target_cdf"),
+            (self.median_target != 0.5, "median_target"),
+            (self.post_contrast != 1.0, "post_contrast"),
+            (self.signed_encoding, "signed_encoding"),
+            (self.lut_channels.is_some(), "lut_channels"),
+            (self.lut_rows != 1, "lut_rows"),
+            (self.lut_interp != LutInterp::Nearest, "lut_interp"),
+            (self.lut_endpoints != LutEndpoints::Clamp, "lut_endpoints"),
+            (
+                self.plotting_position != PlottingPosition::Midpoint,
+                "plotting_position",
+            ),
+            (self.preserve_extremes, "preserve_extremes"),
+            (self.profile_guided_lut, "profile_guided_lut"),
+            (self.lut_sampling != LutSampling::Uniform, "lut_sampling"),
+        ] {
+            if unsupported {
+                return Err(anyhow::anyhow!(
+                    "{} isn't supported by the reference transform",
+                    name
+                ));
+            }
+        }
+        if !non_finite_pixels(input).is_empty() {
+            return Err(anyhow::anyhow!(
+                "The reference transform needs finite samples"
+            ));
+        }
+        let pixels = input.to_rgba8();
+        let (width, height) = pixels.dimensions();
+        let n = width as usize * height as usize;
+        let lut_width = self.lut_width(width, height);
+        let mut image = vec![0.0; n * 3];
+        let mut lut = vec![0; lut_width as usize * 3];
+        for channel in 0..self.channels.count() {
+            let target = self
+                .channel_distribution(channel)
+                .with_params(self.mean, self.std);
+            let mut order: Vec<(u8, usize)> =
+                pixels.pixels().map(|px| px[channel]).zip(0..n).collect();
+            order.sort_by_key(|&(value, _)| value);
+            for (k, &(_, i)) in order.iter().enumerate() {
+                image[i * 3 + channel] = target.inv_cdf((k as f64 + 0.5) / n as f64) as f32;
+            }
+            for i in 0..lut_width {
+                let u = target.cdf(self.lut_texel_value(i, lut_width, 0.5));
+                let k = ((u * n as f64).floor().max(0.0) as usize).min(n - 1);
+                lut[i as usize * 3 + channel] = order[k].0;
+            }
+        }
+        Ok(ReferenceBake {
+            lut_width,
+            image,
+            lut,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channels::Channels;
+    use crate::distribution::{DistributionKind, QuantilePrecision};
+    use crate::self_test::synthetic_input;
+
+    /// The largest forward difference and the number of LUT texels that
+    /// differ between `config`'s bake of `input` and its reference bake.
+    fn distance(config: &TransformConfig, input: &DynamicImage) -> (f32, usize) {
+        let optimized = config.run(input).unwrap();
+        let reference = config.run_reference(input).unwrap();
+        assert_eq!(optimized.lut_width, reference.lut_width);
+        let forward = optimized
+            .image
+            .iter()
+            .zip(&reference.image)
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        let texels = optimized
+            .lut
+            .iter()
+            .zip(&reference.lut)
+            .filter(|(a, b)| a != b)
+            .count();
+        (forward, texels)
+    }
+
+    #[test]
+    fn optimized_bakes_match_the_reference() {
+        let small = DynamicImage::ImageRgb8(image::RgbImage::from_fn(7, 5, |x, y| {
+            image::Rgb([(x * 30) as u8, (y * 50 + x) as u8, ((x * y) % 4) as u8])
+        }));
+        let base = TransformConfig::default().deterministic(true);
+        for input in [synthetic_input(), small] {
+            for config in [
+                base.clone(),
+                base.clone().channels(Channels::Rg),
+                base.clone().lut_for_linear_filtering(true),
+                base.clone()
+                    .distribution(DistributionKind::Uniform)
+                    .std(0.2),
+            ] {
+                assert_eq!(distance(&config, &input), (0.0, 0), "{:?}", config);
+            }
+            // The faster quantile functions are close, not exact.
+            for config in [
+                base.clone().fast_quantiles(true),
+                base.clone().quantile_precision(QuantilePrecision::Low),
+            ] {
+                let (forward, texels) = distance(&config, &input);
+                assert!(forward < 1e-6 && texels == 0, "{:?} {}", config, forward);
+            }
+        }
+
+        let err = base
+            .shared_lut(true)
+            .run_reference(&synthetic_input())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "shared_lut isn't supported by the reference transform"
+        );
+    }
+}
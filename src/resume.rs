@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// File in the output directory where `--resume` records finished inputs.
+const RESUME_MANIFEST: &str = ".precompute-resume";
+
+/// Inputs of a `--resume` batch that have been written, one line each as
+/// `<seconds>.<nanoseconds> <settings> <path>`: the modification time of the
+/// newest of the input's sources when it was baked, a digest of the settings
+/// it was baked with, then the input's path.
+pub struct ResumeManifest {
+    path: PathBuf,
+    /// Digest of this run's transform config and output options.
+    settings: String,
+    done: HashMap<PathBuf, String>,
+    file: Mutex<std::fs::File>,
+}
+
+impl ResumeManifest {
+    /// Reads the finished inputs recorded in `out_dir`, if any, and opens the
+    /// manifest to record more baked with `settings`, which must not contain
+    /// a space.
+    pub fn open(out_dir: &Path, settings: String) -> Result<Self, anyhow::Error> {
+        let path = out_dir.join(RESUME_MANIFEST);
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(anyhow::anyhow!("Couldn't read {:?}: {}", path, e)),
+        };
+        // A line cut short by an interruption has no newline; skip it, and
+        // end it so the next record starts on a line of its own.
+        let complete = contents
+            .rsplit_once('\n')
+            .map_or("", |(complete, _)| complete);
+        let done = complete
+            .lines()
+            .filter_map(|line| {
+                let (mtime, rest) = line.split_once(' ')?;
+                let (settings, input) = rest.split_once(' ')?;
+                Some((PathBuf::from(input), format!("{} {}", mtime, settings)))
+            })
+            .collect();
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            writeln!(file)?;
+        }
+        Ok(Self {
+            path,
+            settings,
+            done,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Whether `input` was written with the same settings, none of its
+    /// `sources` has changed since, and its `outputs` are all still there and
+    /// newer than the sources.
+    pub fn is_done(&self, input: &Path, sources: &[&Path], outputs: &[PathBuf]) -> bool {
+        let stamp = self.done.get(input);
+        stamp.is_some()
+            && stamp.cloned() == self.stamp(sources)
+            && outputs_up_to_date(sources, outputs)
+    }
+
+    /// Records `input` as written, flushed to disk before returning.
+    pub fn record(&self, input: &Path, sources: &[&Path]) -> Result<(), anyhow::Error> {
+        let stamp = self
+            .stamp(sources)
+            .ok_or_else(|| anyhow::anyhow!("Couldn't read the modification time of {:?}", input))?;
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{} {}", stamp, input.display())?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    fn stamp(&self, sources: &[&Path]) -> Option<String> {
+        sources_stamp(sources).map(|mtime| format!("{} {}", mtime, self.settings))
+    }
+
+    /// Removes the manifest once the whole batch has been written.
+    pub fn finish(self) -> Result<(), anyhow::Error> {
+        drop(self.file);
+        Ok(std::fs::remove_file(&self.path)?)
+    }
+}
+
+/// Modification time of the newest of `sources`, as `<seconds>.<nanoseconds>`
+/// since the Unix epoch.
+fn sources_stamp(sources: &[&Path]) -> Option<String> {
+    let newest = sources
+        .iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .max()?;
+    let since_epoch = newest.duration_since(std::time::UNIX_EPOCH).ok()?;
+    Some(format!(
+        "{}.{:09}",
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos()
+    ))
+}
+
+/// True when every output exists and none is older than any source.
+pub fn outputs_up_to_date(sources: &[&Path], outputs: &[PathBuf]) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let newest_source = sources
+        .iter()
+        .map(|path| modified(path))
+        .collect::<Option<Vec<_>>>()
+        .and_then(|times| times.into_iter().max());
+    let oldest_output = outputs
+        .iter()
+        .map(|path| modified(path))
+        .collect::<Option<Vec<_>>>()
+        .and_then(|times| times.into_iter().min());
+    matches!((newest_source, oldest_output), (Some(source), Some(output)) if output >= source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn outputs_up_to_date_compares_mtimes() {
+        let dir = std::env::temp_dir().join(format!("precompute-skip-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (input, image, lut) = (
+            dir.join("in.png"),
+            dir.join("in-gaussian.tif"),
+            dir.join("in-lut.tif"),
+        );
+        let start = SystemTime::now() - Duration::from_secs(3600);
+        let touch = |path: &Path, minutes: u64| {
+            let file = std::fs::File::create(path).unwrap();
+            file.set_modified(start + Duration::from_secs(60 * minutes))
+                .unwrap();
+        };
+        let outputs = [image.clone(), lut.clone()];
+
+        touch(&input, 0);
+        assert!(!outputs_up_to_date(&[&input], &outputs), "no outputs yet");
+        touch(&image, 1);
+        assert!(
+            !outputs_up_to_date(&[&input], &outputs),
+            "one output missing"
+        );
+        touch(&lut, 1);
+        assert!(outputs_up_to_date(&[&input], &outputs));
+        touch(&input, 2);
+        assert!(
+            !outputs_up_to_date(&[&input], &outputs),
+            "input edited since"
+        );
+        touch(&image, 3);
+        assert!(
+            !outputs_up_to_date(&[&input], &outputs),
+            "the LUT is still older"
+        );
+        touch(&lut, 3);
+        assert!(outputs_up_to_date(&[&input], &outputs));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,33 @@
+/// The random numbers every stochastic part of a bake draws from, built from
+/// [`TransformConfig::seed`](crate::TransformConfig::seed). Draws are
+/// counter-based: the `j`th depends only on the seed and `j`, so they can be
+/// taken in parallel and in any order, and come out the same on every machine
+/// and thread count.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Rng {
+    seed: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    /// The `j`th draw.
+    pub(crate) fn u64(self, j: u64) -> u64 {
+        splitmix64(self.seed ^ splitmix64(j))
+    }
+
+    /// The `j`th draw, reduced to `0..n`.
+    pub(crate) fn below(self, j: u64, n: u64) -> u64 {
+        self.u64(j) % n
+    }
+}
+
+/// SplitMix64's output function, a fast, well-mixed hash of `x`.
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
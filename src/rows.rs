@@ -0,0 +1,130 @@
+use crate::config::{MeanGradient, PhaseTimings, TransformConfig};
+use crate::distribution::Distribution;
+use crate::histogram::Histogram;
+use crate::transform::{build_lut, forward_values, Ranked, Ranks};
+
+/// The forward image of [`TransformConfig::run_rows`], yielded one row of
+/// `width * 3` interleaved RGB samples at a time, top to bottom. Rows are the
+/// same values [`TransformConfig::run_with_cancel`] would put in
+/// `GaussianResult::image`.
+///
+/// Every subpixel's rank, 8 bytes each, is held until the last row is
+/// yielded, since any row can sit anywhere in the sort. The batch transform
+/// holds the same ranks alongside the per-channel forward values and the
+/// interleaved image, another 8 bytes per subpixel, which is what this saves.
+pub struct ForwardRows {
+    pub width: u32,
+    pub height: u32,
+    pub lut_width: u32,
+    pub lut_height: u32,
+    /// The LUT, built before the first row, as in `GaussianResult`.
+    pub lut: Vec<u8>,
+    pub lut_f32: Vec<f32>,
+    pub histogram: Histogram<u8>,
+    /// Extract, sort and inverse LUT times. The forward phase happens row by
+    /// row and isn't timed.
+    pub timings: PhaseTimings,
+    ranks: Ranks,
+    /// Each channel's target distribution.
+    targets: Vec<Box<dyn Distribution>>,
+    shared_lut: bool,
+    mean_gradient: Option<MeanGradient>,
+    next_row: u32,
+}
+
+impl ForwardRows {
+    pub(crate) fn new(ranked: Ranked, config: &TransformConfig, width: u32, height: u32) -> Self {
+        let lut_width = config.lut_width(width, height);
+        let mut timings = ranked.timings;
+        let start = std::time::Instant::now();
+        let (lut, lut_f32) = build_lut(&ranked.histogram, config, lut_width);
+        timings.inverse_lut = start.elapsed();
+        Self {
+            width,
+            height,
+            lut_width,
+            lut_height: config.lut_rows,
+            lut,
+            lut_f32,
+            histogram: ranked.histogram,
+            timings,
+            ranks: ranked.ranks,
+            targets: (0..config.channels.count())
+                .map(|channel| config.channel_target(channel))
+                .collect(),
+            shared_lut: config.shared_lut,
+            mean_gradient: config.mean_gradient,
+            next_row: 0,
+        }
+    }
+}
+
+impl Iterator for ForwardRows {
+    type Item = Vec<f32>;
+
+    fn next(&mut self) -> Option<Vec<f32>> {
+        if self.next_row == self.height {
+            return None;
+        }
+        let y = self.next_row;
+        self.next_row += 1;
+        let width = self.width as usize;
+        let mut row = vec![0.0; width * 3];
+        let channels = self.ranks.channel_count();
+        let mut values = vec![0.0; width];
+        for channel in 0..channels {
+            let histogram_channel = if self.shared_lut { 0 } else { channel };
+            forward_values(
+                &self.ranks,
+                &self.histogram,
+                self.targets[channel].as_ref(),
+                channel,
+                histogram_channel,
+                y as usize * width,
+                &mut values,
+            );
+            for (px, &value) in row.chunks_exact_mut(3).zip(&values) {
+                px[channel] = value;
+            }
+        }
+        if let Some(gradient) = &self.mean_gradient {
+            for (x, px) in row.chunks_exact_mut(3).enumerate() {
+                let offset = gradient.offset(x as u32, y, self.width, self.height) as f32;
+                px[..channels].iter_mut().for_each(|v| *v += offset);
+            }
+        }
+        Some(row)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.height - self.next_row) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for ForwardRows {}
+
+#[cfg(test)]
+mod tests {
+    use crate::cancel::CancelToken;
+    use crate::config::{GradientAxis, MeanGradient, TransformConfig};
+    use crate::self_test::synthetic_input;
+
+    #[test]
+    fn collected_rows_match_the_batch_image() {
+        let input = synthetic_input();
+        let plain = TransformConfig::default().deterministic(true);
+        let graded = plain.clone().mean_gradient(Some(MeanGradient {
+            start: 0.4,
+            end: 0.6,
+            axis: GradientAxis::X,
+        }));
+        for config in [plain, graded] {
+            let batch = config.run(&input).unwrap();
+            let rows = config.run_rows(&input, &CancelToken::new()).unwrap();
+            assert_eq!((rows.len(), rows.lut.clone()), (64, batch.lut.clone()));
+            let image: Vec<f32> = rows.flatten().collect();
+            assert_eq!(image, batch.image);
+        }
+    }
+}
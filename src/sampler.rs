@@ -0,0 +1,325 @@
+use std::fmt::Write;
+
+use rayon::prelude::*;
+
+use crate::compute::literal;
+use crate::config::{GaussianResult, GradientAxis, LutRowMode, MeanGradient, TransformConfig};
+use crate::inverse::LutFilter;
+
+/// How a shader has to sample a bake's LUT to reconstruct its source, every
+/// choice resolved from the config it was baked with: where a stored forward
+/// value lands in the LUT, whether to filter between texels, the knot map of
+/// a stratified LUT and the mean gradient to take back out. Interpolation,
+/// endpoints and rounding need nothing at sampling time, since they're
+/// already in the texels, and clamping the texel index gives the LUT's
+/// endpoints past either end. [`LutSampler::shader`] writes it as WGSL and
+/// [`LutSampler::reconstruct`] runs the same steps on the CPU.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LutSampler {
+    pub lut_width: u32,
+    /// `(scale, offset)` from a stored forward value to the LUT coordinate,
+    /// see [`TransformConfig::lut_coordinate`].
+    pub coordinate: (f64, f64),
+    /// Linear with `lut_for_linear_filtering`, which builds the LUT for a
+    /// filtering sampler, nearest otherwise.
+    pub filter: LutFilter,
+    /// The knot map the coordinate goes through, see
+    /// [`TransformConfig::lut_knots`].
+    pub knots: Option<Vec<f32>>,
+    pub mean_gradient: Option<MeanGradient>,
+    /// Channels the mean gradient shifts.
+    pub channels: usize,
+    /// Height of each page of the forward image, which the mean gradient
+    /// restarts on.
+    pub page_height: u32,
+}
+
+impl LutSampler {
+    /// The sampler for `result`, baked with `config`. Only the first LUT row
+    /// is read, so jittered rows, whose texels sit off their centers, and the
+    /// per-tile rows of atlases and local tiles aren't supported, and neither
+    /// are pages of different heights with a mean gradient.
+    pub fn new(result: &GaussianResult, config: &TransformConfig) -> Result<Self, anyhow::Error> {
+        if config.lut_row_mode == LutRowMode::Jitter && result.lut_height > 1 {
+            return Err(anyhow::anyhow!(
+                "The reconstruction shader doesn't support lut_row_mode jitter"
+            ));
+        }
+        if result.lut_height != config.lut_rows {
+            return Err(anyhow::anyhow!(
+                "The reconstruction shader reads one LUT, not one row per tile"
+            ));
+        }
+        let page_height = result.page_heights[0];
+        if config.mean_gradient.is_some() && result.page_heights.iter().any(|&h| h != page_height) {
+            return Err(anyhow::anyhow!(
+                "The reconstruction shader needs pages of one height with a mean gradient"
+            ));
+        }
+        Ok(Self {
+            lut_width: result.lut_width,
+            coordinate: config.lut_coordinate(result.lut_width),
+            filter: match config.lut_for_linear_filtering {
+                true => LutFilter::Linear,
+                false => LutFilter::Nearest,
+            },
+            knots: config.lut_knots(result),
+            mean_gradient: config.mean_gradient,
+            channels: config.channels.count(),
+            page_height,
+        })
+    }
+
+    /// A WGSL compute shader reconstructing the source from the forward
+    /// image, with this sampler's choices built in as constants. The forward
+    /// image is a float texture at binding 0 and the LUT one at binding 1,
+    /// `rgba8unorm` for an 8-bit LUT or `rgba32float` for a float one, read
+    /// with `textureLoad` so no sampler state can change the result; a
+    /// stratified LUT's knot map is an `rgba32float` texture at binding 2.
+    /// Each 16x16 workgroup writes a 16x16 block of the `rgba8unorm` output
+    /// at binding 3, alpha 1. `reconstruct` can also be called from a
+    /// fragment shader with the pixel's integer position.
+    pub fn shader(&self) -> Result<String, anyhow::Error> {
+        let mut shader = String::new();
+        writeln!(
+            shader,
+            "// Generated by precompute: reconstructs through a {}-texel LUT, {} filtering{}.",
+            self.lut_width,
+            match self.filter {
+                LutFilter::Nearest => "nearest",
+                LutFilter::Linear => "linear",
+            },
+            match self.knots {
+                Some(_) => ", stratified texels",
+                None => "",
+            }
+        )?;
+        writeln!(shader, "const LUT_WIDTH: i32 = {};", self.lut_width)?;
+        writeln!(
+            shader,
+            "const COORDINATE_SCALE: f32 = {};",
+            literal(self.coordinate.0)
+        )?;
+        writeln!(
+            shader,
+            "const COORDINATE_OFFSET: f32 = {};",
+            literal(self.coordinate.1)
+        )?;
+        shader.push_str("\n@group(0) @binding(0) var forward: texture_2d<f32>;\n");
+        shader.push_str("@group(0) @binding(1) var lut: texture_2d<f32>;\n");
+        if self.knots.is_some() {
+            shader.push_str("@group(0) @binding(2) var knots: texture_2d<f32>;\n");
+        }
+        shader
+            .push_str("@group(0) @binding(3) var output: texture_storage_2d<rgba8unorm, write>;\n");
+
+        if let Some(gradient) = &self.mean_gradient {
+            let (position, extent) = match gradient.axis {
+                GradientAxis::X => ("f32(pixel.x)", "f32(textureDimensions(forward).x)"),
+                GradientAxis::Y => ("f32(pixel.y % PAGE_HEIGHT)", "f32(PAGE_HEIGHT)"),
+            };
+            writeln!(shader, "\nconst PAGE_HEIGHT: u32 = {}u;", self.page_height)?;
+            shader.push_str(
+                "\n// How far the gradient moved the mean at `pixel` from its midpoint.\n",
+            );
+            shader.push_str("fn gradient_offset(pixel: vec2<u32>) -> f32 {\n");
+            writeln!(shader, "    let t = ({} + 0.5) / {};", position, extent)?;
+            writeln!(
+                shader,
+                "    return {} * (t - 0.5);",
+                literal(gradient.end - gradient.start)
+            )?;
+            shader.push_str("}\n");
+        }
+        if self.knots.is_some() {
+            shader.push_str(KNOTS);
+        }
+        match self.filter {
+            LutFilter::Nearest => shader.push_str(NEAREST),
+            LutFilter::Linear => shader.push_str(LINEAR),
+        }
+
+        shader.push_str("\nfn reconstruct(pixel: vec2<u32>) -> vec3<f32> {\n");
+        let binding = |mutated: bool| if mutated { "var" } else { "let" };
+        writeln!(
+            shader,
+            "    {} g = textureLoad(forward, vec2<i32>(pixel), 0).rgb;",
+            binding(self.mean_gradient.is_some())
+        )?;
+        if self.mean_gradient.is_some() {
+            let mask: Vec<&str> = (0..3)
+                .map(|c| if c < self.channels { "1.0" } else { "0.0" })
+                .collect();
+            writeln!(
+                shader,
+                "    g -= gradient_offset(pixel) * vec3<f32>({});",
+                mask.join(", ")
+            )?;
+        }
+        writeln!(
+            shader,
+            "    {} u = COORDINATE_OFFSET + COORDINATE_SCALE * g;",
+            binding(self.knots.is_some())
+        )?;
+        if self.knots.is_some() {
+            shader.push_str("    u = vec3<f32>(warp(u.r, 0), warp(u.g, 1), warp(u.b, 2));\n");
+        }
+        shader.push_str("    return vec3<f32>(sample_lut(u.r, 0), sample_lut(u.g, 1), sample_lut(u.b, 2));\n}\n");
+        shader.push_str(MAIN);
+        Ok(shader)
+    }
+
+    /// What [`shader`](Self::shader) writes for an interleaved RGB forward
+    /// image `width` pixels wide, as 8-bit RGB, reading an interleaved RGB
+    /// LUT of `[0, 1]` values, `lut / 255` for an 8-bit one. Evaluated in
+    /// 32-bit floats, step by step as the shader does, so it's the CPU
+    /// reference to check a port of the shader against.
+    pub fn reconstruct(&self, forward: &[f32], width: u32, lut: &[f32]) -> Vec<u8> {
+        let lut_width = self.lut_width as i32;
+        let texel = |x: i32, channel: usize| lut[x.clamp(0, lut_width - 1) as usize * 3 + channel];
+        let (scale, offset) = (self.coordinate.0 as f32, self.coordinate.1 as f32);
+        forward
+            .par_iter()
+            .enumerate()
+            .map(|(i, &g)| {
+                let channel = i % 3;
+                let pixel = (i / 3) as u32;
+                let (x, y) = (pixel % width, pixel / width);
+                let mut g = g;
+                if let (Some(gradient), true) = (&self.mean_gradient, channel < self.channels) {
+                    let (position, extent) = match gradient.axis {
+                        GradientAxis::X => (x as f32, width as f32),
+                        GradientAxis::Y => ((y % self.page_height) as f32, self.page_height as f32),
+                    };
+                    let t = (position + 0.5) / extent;
+                    g -= (gradient.end - gradient.start) as f32 * (t - 0.5);
+                }
+                let mut u = offset + scale * g;
+                if let Some(knots) = &self.knots {
+                    let last = (lut_width - 1) as f32;
+                    let x = (u * last).clamp(0.0, last);
+                    let i = x.floor() as i32;
+                    let (a, b) = (
+                        knots[i as usize * 3 + channel],
+                        knots[(i + 1).min(lut_width - 1) as usize * 3 + channel],
+                    );
+                    u = a + (b - a) * (x - i as f32);
+                }
+                let value = match self.filter {
+                    LutFilter::Nearest => texel((u * lut_width as f32).floor() as i32, channel),
+                    LutFilter::Linear => {
+                        let x = (u * lut_width as f32 - 0.5).clamp(0.0, (lut_width - 1) as f32);
+                        let i = x.floor() as i32;
+                        let (a, b) = (texel(i, channel), texel(i + 1, channel));
+                        a + (b - a) * (x - i as f32)
+                    }
+                };
+                (value.clamp(0.0, 1.0) * 255.0).round() as u8
+            })
+            .collect()
+    }
+}
+
+const KNOTS: &str = "
+// The LUT coordinate the knot map gives `u` in `channel`, linear between
+// its texels, which hold the coordinate at j / (LUT_WIDTH - 1).
+fn warp(u: f32, channel: i32) -> f32 {
+    let last = f32(LUT_WIDTH - 1);
+    let x = clamp(u * last, 0.0, last);
+    let i = i32(floor(x));
+    let a = textureLoad(knots, vec2<i32>(i, 0), 0)[channel];
+    let b = textureLoad(knots, vec2<i32>(min(i + 1, LUT_WIDTH - 1), 0), 0)[channel];
+    return mix(a, b, x - f32(i));
+}
+";
+
+const NEAREST: &str = "
+// The texel containing `u`, clamped to the LUT's ends.
+fn sample_lut(u: f32, channel: i32) -> f32 {
+    let i = clamp(i32(floor(u * f32(LUT_WIDTH))), 0, LUT_WIDTH - 1);
+    return textureLoad(lut, vec2<i32>(i, 0), 0)[channel];
+}
+";
+
+const LINEAR: &str = "
+// Linear between the two texel centers around `u`, clamped to the LUT's ends.
+fn sample_lut(u: f32, channel: i32) -> f32 {
+    let x = clamp(u * f32(LUT_WIDTH) - 0.5, 0.0, f32(LUT_WIDTH - 1));
+    let i = i32(floor(x));
+    let a = textureLoad(lut, vec2<i32>(i, 0), 0)[channel];
+    let b = textureLoad(lut, vec2<i32>(min(i + 1, LUT_WIDTH - 1), 0), 0)[channel];
+    return mix(a, b, x - f32(i));
+}
+";
+
+const MAIN: &str = "
+@compute @workgroup_size(16, 16)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (any(id.xy >= textureDimensions(forward))) {
+        return;
+    }
+    let source = clamp(reconstruct(id.xy), vec3<f32>(0.0), vec3<f32>(1.0));
+    textureStore(output, id.xy, vec4<f32>(round(source * 255.0) / 255.0, 1.0));
+}
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::tests::validate_wgsl;
+    use crate::config::LutSampling;
+
+    #[test]
+    fn every_sampling_mode_validates() {
+        let input = crate::self_test::synthetic_input();
+        let gradient = |axis| {
+            Some(MeanGradient {
+                start: 0.4,
+                end: 0.6,
+                axis,
+            })
+        };
+        let config = TransformConfig::default().deterministic(true);
+        for (mode, config) in [
+            ("nearest", config.clone()),
+            ("linear", config.clone().lut_for_linear_filtering(true)),
+            (
+                "stratified",
+                config.clone().lut_sampling(LutSampling::Stratified),
+            ),
+            (
+                "mean gradient x",
+                config.clone().mean_gradient(gradient(GradientAxis::X)),
+            ),
+            (
+                "mean gradient y",
+                config.clone().mean_gradient(gradient(GradientAxis::Y)),
+            ),
+            (
+                "stratified with a mean gradient",
+                config
+                    .lut_sampling(LutSampling::Stratified)
+                    .mean_gradient(gradient(GradientAxis::Y)),
+            ),
+        ] {
+            let result = config.run(&input).unwrap();
+            let sampler = LutSampler::new(&result, &config).unwrap();
+            let shader = sampler.shader().unwrap();
+            if let Err(e) = validate_wgsl(&shader) {
+                panic!("{}:\n{}", mode, e);
+            }
+            assert_eq!(
+                shader.contains("var knots"),
+                mode.starts_with("stratified"),
+                "{}",
+                mode
+            );
+            assert_eq!(
+                shader.contains("gradient_offset"),
+                mode.contains("gradient"),
+                "{}",
+                mode
+            );
+        }
+    }
+}
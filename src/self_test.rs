@@ -0,0 +1,369 @@
+use image::{DynamicImage, RgbImage};
+
+use crate::compare::difference;
+use crate::digest::Sha256;
+use crate::encode::{encode_to_vec, OutputData, Tiff};
+use crate::inverse::{lut_coordinates, sample_lut, warp_coordinates};
+use crate::read::decode_bytes;
+use crate::rng::Rng;
+use crate::sampler::LutSampler;
+use crate::stats::channel_stats;
+use crate::{
+    GaussianResult, GradientAxis, LutEndpoints, LutInterp, LutSampling, LutTexelOffset,
+    MeanGradient, Rounding, TransformConfig,
+};
+
+/// Side of [`synthetic_input`].
+const SIZE: u32 = 64;
+
+/// SHA-256 of the 8-bit LUT a default, deterministic bake of
+/// [`synthetic_input`] gives. Its texels are source levels picked by rank,
+/// so they don't depend on float rounding and must match exactly.
+const EXPECTED_LUT_SHA256: &str =
+    "1d50dea0985f9a44d764a24da60879e9a29ed8ef02d21c25a83bf8bdd3d0e04a";
+
+/// Forward values of a few pixels of the same bake, `(x, y, rgb)`.
+const EXPECTED_FORWARD: [(u32, u32, [f32; 3]); 4] = [
+    (0, 0, [0.30718383, 0.2508942, 0.59985846]),
+    (17, 5, [0.3546203, 0.3591913, 0.5782025]),
+    (40, 33, [0.470109, 0.69401616, 0.57322675]),
+    (63, 63, [0.71269083, 0.7548627, 0.6884994]),
+];
+
+/// How far [`EXPECTED_FORWARD`] may be off, for float libraries that round
+/// the quantile function differently.
+const FORWARD_TOLERANCE: f64 = 1e-5;
+
+/// Largest reconstruction RMSE allowed, in 8-bit levels. The bake gives
+/// 1.99: 256 texels for 4096 pixels, where the texels straddling the jumps
+/// between green's patches miss by up to 57 levels.
+const MAX_RECONSTRUCTION_RMSE: f64 = 2.5;
+
+/// LUT width the stratified and uniform bakes are compared at, small enough
+/// for texel placement to matter: their RMSEs are 1.16 and 3.85 levels.
+const SAMPLING_LUT_SIZE: u32 = 64;
+
+/// LUT options the reconstruction shader is checked with, each on top of the
+/// default, deterministic bake. Its CPU simulation gives 1.52 to 2.24 levels
+/// RMSE with them, 0.40 stratified, the same as the library's own sampling.
+fn sampler_configs(config: &TransformConfig) -> [(&'static str, TransformConfig); 11] {
+    let gradient = MeanGradient {
+        start: 0.4,
+        end: 0.6,
+        axis: GradientAxis::Y,
+    };
+    [
+        ("defaults", config.clone()),
+        (
+            "lut_interp linear",
+            config.clone().lut_interp(LutInterp::Linear),
+        ),
+        (
+            "lut_for_linear_filtering",
+            config.clone().lut_for_linear_filtering(true),
+        ),
+        (
+            "lut_texel_offset half",
+            config.clone().lut_texel_offset(LutTexelOffset::Half),
+        ),
+        (
+            "lut_endpoints extend",
+            config
+                .clone()
+                .lut_interp(LutInterp::Linear)
+                .lut_endpoints(LutEndpoints::Extend),
+        ),
+        (
+            "rounding floor",
+            config
+                .clone()
+                .lut_interp(LutInterp::Linear)
+                .rounding(Rounding::Floor),
+        ),
+        (
+            "lut_sampling stratified",
+            config.clone().lut_sampling(LutSampling::Stratified),
+        ),
+        ("lut_rows 4", config.clone().lut_rows(4)),
+        (
+            "mean_gradient y",
+            config.clone().mean_gradient(Some(gradient)),
+        ),
+        (
+            "mean_gradient x",
+            config.clone().mean_gradient(Some(MeanGradient {
+                axis: GradientAxis::X,
+                ..gradient
+            })),
+        ),
+        (
+            "signed_encoding",
+            config.clone().mean(0.0).std(0.15).signed_encoding(true),
+        ),
+    ]
+}
+
+/// Outcome of one check of [`self_test`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelfTestCheck {
+    pub name: &'static str,
+    /// Why it failed, `None` if it passed.
+    pub failure: Option<String>,
+}
+
+/// A 64x64 RGB texture generated from integers alone, the same on every
+/// machine: a noisy horizontal gradient in red, four flat patches of a few
+/// levels in green, which tie, and uniform noise in blue.
+pub fn synthetic_input() -> DynamicImage {
+    let rng = Rng::new(0x5e1f_7e57);
+    DynamicImage::ImageRgb8(RgbImage::from_fn(SIZE, SIZE, |x, y| {
+        let i = (y * SIZE + x) as u64 * 3;
+        let noise = |c: u64, range: u64| rng.below(i + c, range) as u8;
+        image::Rgb([
+            (x * 2) as u8 + noise(0, 64),
+            ((x / 32 + y / 32) * 60) as u8 + noise(1, 4),
+            noise(2, 256),
+        ])
+    }))
+}
+
+/// Runs the whole pipeline on [`synthetic_input`] and checks every stage
+/// against what it must give, for a quick sanity check of a build without
+/// any files: the input's round trip through a TIFF in memory, a default,
+/// deterministic bake against values recorded from a known-good build, the
+/// forward image's statistics, the LUT's monotonicity, the reconstruction,
+/// whether a [`LutSampling::Stratified`] LUT reconstructs better than a
+/// uniform one as wide, whether the [`LutSampler`] shader, simulated on
+/// the CPU with 8-bit and float LUTs, reconstructs as closely with each of
+/// a set of LUT options, the
+/// [`run_reference`](TransformConfig::run_reference) oracle and the forward
+/// image's round trip through a float TIFF. Later checks that depend on the
+/// bake fail with it.
+pub fn self_test() -> Vec<SelfTestCheck> {
+    let mut checks = Vec::new();
+    let mut check = |name, result: Result<(), String>| {
+        checks.push(SelfTestCheck {
+            name,
+            failure: result.err(),
+        });
+    };
+    let input = synthetic_input();
+    let source = input.to_rgb8().into_raw();
+
+    check(
+        "input round trip",
+        encode_to_vec(&Tiff::default(), SIZE, &[SIZE], 3, OutputData::U8(&source))
+            .and_then(|bytes| decode_bytes(&bytes, Some(image::ImageFormat::Tiff)))
+            .map_err(|e| e.to_string())
+            .and_then(|pages| match pages[0].to_rgb8().into_raw() == source {
+                true => Ok(()),
+                false => Err("the decoded TIFF differs from the texture encoded".to_string()),
+            }),
+    );
+
+    let config = TransformConfig::default().deterministic(true);
+    let result = match config.run(&input) {
+        Ok(result) => {
+            check("bake", Ok(()));
+            result
+        }
+        Err(e) => {
+            check("bake", Err(e.to_string()));
+            return checks;
+        }
+    };
+
+    let mut sha256 = Sha256::new();
+    sha256.write(&result.lut);
+    let digest: String = sha256
+        .finish()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    check(
+        "LUT matches the recorded one",
+        match digest == EXPECTED_LUT_SHA256 {
+            true => Ok(()),
+            false => Err(format!(
+                "SHA-256 {}, expected {}",
+                digest, EXPECTED_LUT_SHA256
+            )),
+        },
+    );
+
+    check(
+        "forward values match the recorded ones",
+        EXPECTED_FORWARD.iter().try_for_each(|&(x, y, expected)| {
+            let i = (y * SIZE + x) as usize * 3;
+            let actual = &result.image[i..i + 3];
+            match actual
+                .iter()
+                .zip(expected)
+                .all(|(a, e)| (a - e).abs() as f64 <= FORWARD_TOLERANCE)
+            {
+                true => Ok(()),
+                false => Err(format!(
+                    "({}, {}) is {:?}, expected {:?}",
+                    x, y, actual, expected
+                )),
+            }
+        }),
+    );
+
+    check(
+        "forward image follows the target",
+        (0..3).try_for_each(|channel| {
+            let stats = channel_stats(&result.image, 3, channel, true);
+            let (mean, std) = (stats.mean(), stats.variance().unwrap().sqrt());
+            match (mean - config.mean).abs() <= 0.005 && (std - config.std).abs() <= 0.005 {
+                true => Ok(()),
+                false => Err(format!(
+                    "channel {} has mean {:.4} and std {:.4}, expected {} and {:.4}",
+                    channel, mean, std, config.mean, config.std
+                )),
+            }
+        }),
+    );
+
+    check(
+        "LUT never decreases",
+        result.check_lut_monotonic().map_err(|e| e.to_string()),
+    );
+
+    let rmse = reconstruction_rmse(&config, &source, &result);
+    check(
+        "reconstruction",
+        match rmse <= MAX_RECONSTRUCTION_RMSE {
+            true => Ok(()),
+            false => Err(format!(
+                "RMSE {:.4} levels, above {}",
+                rmse, MAX_RECONSTRUCTION_RMSE
+            )),
+        },
+    );
+
+    check(
+        "stratified LUT sampling beats uniform",
+        [LutSampling::Uniform, LutSampling::Stratified]
+            .map(|sampling| {
+                let config = config
+                    .clone()
+                    .lut_size(Some(SAMPLING_LUT_SIZE))
+                    .lut_sampling(sampling);
+                config
+                    .run(&input)
+                    .map(|result| reconstruction_rmse(&config, &source, &result))
+            })
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())
+            .and_then(|rmse| match rmse[1] < rmse[0] {
+                true => Ok(()),
+                false => Err(format!(
+                    "RMSE {:.4} levels stratified, {:.4} uniform",
+                    rmse[1], rmse[0]
+                )),
+            }),
+    );
+
+    check(
+        "reconstruction shader follows the LUT options",
+        sampler_configs(&config)
+            .iter()
+            .try_for_each(|(name, config)| {
+                let result = config.run(&input).map_err(|e| format!("{}: {}", name, e))?;
+                let sampler =
+                    LutSampler::new(&result, config).map_err(|e| format!("{}: {}", name, e))?;
+                let row = result.lut_width as usize * 3;
+                let lut: Vec<f32> = result.lut[..row]
+                    .iter()
+                    .map(|&v| v as f32 / 255.0)
+                    .collect();
+                for (depth, lut) in [("8-bit", &lut[..]), ("float", &result.lut_f32[..row])] {
+                    let rmse = difference(
+                        &source,
+                        &sampler.reconstruct(&result.image, result.width, lut),
+                    )
+                    .rmse;
+                    if rmse > MAX_RECONSTRUCTION_RMSE {
+                        return Err(format!(
+                            "{} with a {} LUT: RMSE {:.4} levels, above {}",
+                            name, depth, rmse, MAX_RECONSTRUCTION_RMSE
+                        ));
+                    }
+                }
+                Ok(())
+            }),
+    );
+
+    check(
+        "matches the reference",
+        config
+            .run_reference(&input)
+            .map_err(|e| e.to_string())
+            .and_then(|reference| {
+                let error = difference(&reference.image, &result.image);
+                match (error.max_abs <= 1e-6, reference.lut == result.lut) {
+                    (true, true) => Ok(()),
+                    (false, _) => Err(format!(
+                        "forward values differ by up to {:e}",
+                        error.max_abs
+                    )),
+                    (_, false) => Err("the LUTs differ".to_string()),
+                }
+            }),
+    );
+
+    check(
+        "forward image round trip",
+        encode_to_vec(
+            &Tiff::default(),
+            SIZE,
+            &[SIZE],
+            3,
+            OutputData::F32(&result.image),
+        )
+        .and_then(|bytes| decode_bytes(&bytes, Some(image::ImageFormat::Tiff)))
+        .map_err(|e| e.to_string())
+        .and_then(
+            |pages| match pages[0].to_rgb32f().into_raw() == result.image {
+                true => Ok(()),
+                false => Err("the decoded TIFF differs from the forward image encoded".to_string()),
+            },
+        ),
+    );
+    checks
+}
+
+/// RMSE in 8-bit levels of `result`'s nearest-texel reconstruction of
+/// `source`, sampling its LUT as a shader built for `config` would.
+fn reconstruction_rmse(config: &TransformConfig, source: &[u8], result: &GaussianResult) -> f64 {
+    let mut coordinates = lut_coordinates(&result.image, config.lut_coordinate(result.lut_width));
+    if let Some(knots) = config.lut_knots(result) {
+        coordinates = warp_coordinates(&coordinates, &knots, result.lut_width);
+    }
+    difference(
+        source,
+        &sample_lut(&coordinates, &result.lut, result.lut_width),
+    )
+    .rmse
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_check_passes() {
+        let checks = self_test();
+        let failures: Vec<_> = checks
+            .iter()
+            .filter(|check| check.failure.is_some())
+            .collect();
+        assert!(failures.is_empty(), "{:?}", failures);
+        let mut names: Vec<_> = checks.iter().map(|check| check.name).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), checks.len());
+    }
+}
@@ -0,0 +1,167 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{GaussianResult, TransformConfig};
+
+/// Everything a consumer needs to sample a bake's outputs, written as JSON
+/// next to them for importers that don't read the outputs' own metadata.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sidecar {
+    /// Version of the tool that wrote the outputs.
+    pub version: String,
+    pub image: String,
+    pub lut: String,
+    pub width: u32,
+    pub height: u32,
+    pub page_heights: Vec<u32>,
+    pub lut_width: u32,
+    /// One LUT row per atlas tile, otherwise 1.
+    pub lut_height: u32,
+    /// Bits per LUT sample.
+    pub lut_depth: u32,
+    /// Order of the interleaved samples in the forward image. Channels the
+    /// config doesn't transform are zero.
+    pub channel_order: String,
+    /// Order of the interleaved samples in the LUT: `RGB`, or `RGBA` when
+    /// packed with [`LutPack::RgBa`](crate::encode::LutPack::RgBa).
+    pub lut_channel_order: String,
+    /// Colorspace of the LUT's values. They are the source's encoded values,
+    /// unconverted.
+    pub colorspace: String,
+    /// What the forward image stores: values of the target distribution.
+    pub store: String,
+    /// Scale and offset forward values are stored with: a target value `v`
+    /// is stored as `forward_offset + forward_scale * v`, see
+    /// [`TransformConfig::forward_encoding`].
+    pub forward_scale: f64,
+    pub forward_offset: f64,
+    /// Range of forward image values the LUT spans, with texel `i` centered
+    /// at `lut_domain[0] + (i + 0.5) / lut_width * (lut_domain[1] - lut_domain[0])`.
+    pub lut_domain: [f64; 2],
+    /// Scale and offset from a stored forward value `g` to the texture
+    /// coordinate to sample the LUT at, `lut_coordinate_offset +
+    /// lut_coordinate_scale * g`, see [`TransformConfig::lut_coordinate`].
+    pub lut_coordinate_scale: f64,
+    pub lut_coordinate_offset: f64,
+    /// The knot map a [`LutSampling::Stratified`](crate::LutSampling::Stratified)
+    /// LUT is sampled through, see [`TransformConfig::lut_knots`]. Missing
+    /// with uniform sampling.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lut_knots: Option<String>,
+    /// The resolved transform parameters.
+    pub config: TransformConfig,
+    /// [`phash`](crate::phash::phash) of the source image, as 16 hex digits,
+    /// to find other bakes of the same texture by. Missing in sidecars written
+    /// before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_phash: Option<String>,
+}
+
+impl Sidecar {
+    pub fn new(result: &GaussianResult, config: &TransformConfig, image: &str, lut: &str) -> Self {
+        let (forward_scale, forward_offset) = config.forward_encoding();
+        let (lut_coordinate_scale, lut_coordinate_offset) = config.lut_coordinate(result.lut_width);
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            image: image.to_string(),
+            lut: lut.to_string(),
+            width: result.width,
+            height: result.height,
+            page_heights: result.page_heights.clone(),
+            lut_width: result.lut_width,
+            lut_height: result.lut_height,
+            lut_depth: 8,
+            channel_order: "RGB".to_string(),
+            lut_channel_order: "RGB".to_string(),
+            colorspace: "source".to_string(),
+            store: "gaussian".to_string(),
+            forward_scale,
+            forward_offset,
+            lut_domain: [0.0, 1.0],
+            lut_coordinate_scale,
+            lut_coordinate_offset,
+            lut_knots: None,
+            config: config.clone(),
+            source_phash: None,
+        }
+    }
+    /// The sidecar describing the output at `path`: `path` itself if it's a
+    /// `.meta.json` file, otherwise the `.meta.json` file beside it whose
+    /// `image` or `lut` names it, if there is one.
+    pub fn find(path: &Path) -> Result<Option<Self>, anyhow::Error> {
+        let is_sidecar = |path: &Path| path.to_string_lossy().ends_with(".meta.json");
+        let read = |path: &Path| -> Result<Self, anyhow::Error> {
+            let file = std::io::BufReader::new(std::fs::File::open(path)?);
+            serde_json::from_reader(file).map_err(|e| anyhow::anyhow!("{:?}: {}", path, e))
+        };
+        if is_sidecar(path) {
+            return read(path).map(Some);
+        }
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            return Ok(None);
+        };
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut candidates: Vec<_> = std::fs::read_dir(dir)?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|candidate| is_sidecar(candidate))
+            .collect();
+        candidates.sort();
+        for candidate in candidates {
+            // Unreadable sidecars of other bakes don't describe this one.
+            let Ok(sidecar) = read(&candidate) else {
+                continue;
+            };
+            if sidecar.image == name || sidecar.lut == name {
+                return Ok(Some(sidecar));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::self_test::synthetic_input;
+    use crate::{DistributionKind, Rounding};
+
+    #[test]
+    fn sidecar_round_trips_every_parameter() {
+        let config = TransformConfig::default()
+            .mean(0.4)
+            .std(0.12)
+            .distribution(DistributionKind::Uniform)
+            .lut_size(Some(200))
+            .pad_lut(true)
+            .rounding(Rounding::Floor)
+            .signed_encoding(true)
+            .deterministic(true)
+            .seed(7);
+        let result = config.run(&synthetic_input()).unwrap();
+        let mut sidecar = Sidecar::new(&result, &config, "in-gaussian.tif", "in-lut.tif");
+        sidecar.source_phash = Some("0123456789abcdef".to_string());
+        assert_eq!((sidecar.lut_width, sidecar.width), (256, 64));
+        assert_eq!((sidecar.forward_scale, sidecar.forward_offset), (0.5, 0.5));
+
+        let dir = std::env::temp_dir().join(format!("precompute-sidecar-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("in.meta.json"),
+            serde_json::to_string_pretty(&sidecar).unwrap(),
+        )
+        .unwrap();
+        let by_image = Sidecar::find(&dir.join("in-gaussian.tif"));
+        let by_lut = Sidecar::find(&dir.join("in-lut.tif"));
+        let unrelated = Sidecar::find(&dir.join("other.tif"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(by_image.unwrap().as_ref(), Some(&sidecar));
+        assert_eq!(by_lut.unwrap().as_ref(), Some(&sidecar));
+        assert_eq!(unrelated.unwrap(), None);
+        assert_eq!(sidecar.config, config);
+    }
+}
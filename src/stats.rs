@@ -0,0 +1,166 @@
+use rayon::prelude::*;
+
+/// Pixels reduced per leaf of [`channel_stats`]' tree. Fixed, like the shape
+/// of the tree, so the result doesn't depend on how rayon splits the work.
+const CHUNK_LEN: usize = 1 << 16;
+
+/// Running summary of one channel's samples, see [`channel_stats`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampleStats {
+    pub count: usize,
+    /// Infinite, `min` positive and `max` negative, if there are no samples.
+    pub min: f64,
+    pub max: f64,
+    pub sum: f64,
+    /// Sum of squared differences from the mean, if asked for.
+    pub m2: Option<f64>,
+}
+
+impl SampleStats {
+    fn empty(variance: bool) -> Self {
+        Self {
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            sum: 0.0,
+            m2: variance.then_some(0.0),
+        }
+    }
+
+    /// NaN without samples.
+    pub fn mean(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+
+    /// Population variance, if `m2` was asked for.
+    pub fn variance(&self) -> Option<f64> {
+        self.m2.map(|m2| m2 / self.count as f64)
+    }
+
+    /// Stats of both halves' samples together, with the pairwise update of
+    /// Chan et al. for `m2`.
+    fn merge(self, other: Self) -> Self {
+        let count = self.count + other.count;
+        let m2 = match (self.m2, other.m2) {
+            (Some(a), Some(b)) if self.count == 0 || other.count == 0 => Some(a + b),
+            (Some(a), Some(b)) => {
+                let delta = other.mean() - self.mean();
+                Some(
+                    a + b + delta * delta * (self.count as f64 * other.count as f64 / count as f64),
+                )
+            }
+            _ => None,
+        };
+        Self {
+            count,
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+            sum: self.sum + other.sum,
+            m2,
+        }
+    }
+}
+
+/// Min, max, sum and, if `variance` is set, the sum of squared differences
+/// from the mean of `channel` in `data`, which interleaves `channels`
+/// samples per pixel. Leaves of a fixed number of pixels are summarized in
+/// order, `m2` with Welford's update, and merged pairwise down a tree whose
+/// shape only depends on `data`'s length, so the result is bit-identical
+/// whatever the number of threads, unlike a parallel float sum.
+pub fn channel_stats<T>(data: &[T], channels: usize, channel: usize, variance: bool) -> SampleStats
+where
+    T: Copy + Into<f64> + Sync,
+{
+    assert!(channel < channels);
+    let leaves: Vec<SampleStats> = data
+        .par_chunks(CHUNK_LEN * channels)
+        .map(|chunk| {
+            let mut stats = SampleStats::empty(variance);
+            let mut mean = 0.0;
+            for pixel in chunk.chunks_exact(channels) {
+                let x = pixel[channel].into();
+                stats.count += 1;
+                stats.min = stats.min.min(x);
+                stats.max = stats.max.max(x);
+                stats.sum += x;
+                if let Some(m2) = &mut stats.m2 {
+                    let delta = x - mean;
+                    mean += delta / stats.count as f64;
+                    *m2 += delta * (x - mean);
+                }
+            }
+            stats
+        })
+        .collect();
+    reduce(&leaves, variance)
+}
+
+/// Merges `leaves` in order, splitting them in halves at fixed points.
+fn reduce(leaves: &[SampleStats], variance: bool) -> SampleStats {
+    match leaves {
+        [] => SampleStats::empty(variance),
+        [leaf] => *leaf,
+        _ => {
+            let (left, right) = leaves.split_at(leaves.len() / 2);
+            let (left, right) = rayon::join(|| reduce(left, variance), || reduce(right, variance));
+            left.merge(right)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    #[test]
+    fn stats_are_identical_on_any_number_of_threads() {
+        // Several leaves' worth of pixels, the last leaf partial.
+        let rng = Rng::new(17);
+        let data: Vec<f32> = (0..(CHUNK_LEN * 5 + 123) as u64 * 3)
+            .map(|i| (rng.u64(i) >> 11) as f32 / (1u64 << 53) as f32 * 1e3 - 400.0)
+            .collect();
+        let on_threads = |threads: usize, channel: usize| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| channel_stats(&data, 3, channel, true))
+        };
+        for channel in 0..3 {
+            let serial = on_threads(1, channel);
+            for threads in [2, 8] {
+                let parallel = on_threads(threads, channel);
+                assert_eq!(parallel.sum.to_bits(), serial.sum.to_bits());
+                assert_eq!(parallel.m2.unwrap().to_bits(), serial.m2.unwrap().to_bits());
+                assert_eq!(parallel, serial);
+            }
+
+            // And they agree with the plain two-pass formulas.
+            let values: Vec<f64> = data
+                .iter()
+                .skip(channel)
+                .step_by(3)
+                .map(|&v| v as f64)
+                .collect();
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            let variance =
+                values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+            assert_eq!(serial.count, values.len());
+            assert_eq!(
+                serial.min,
+                values.iter().copied().fold(f64::INFINITY, f64::min)
+            );
+            assert_eq!(
+                serial.max,
+                values.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+            );
+            assert!((serial.mean() - mean).abs() < 1e-9);
+            assert!((serial.variance().unwrap() - variance).abs() < 1e-6 * variance);
+        }
+
+        let empty = channel_stats::<f32>(&[], 3, 0, false);
+        assert_eq!((empty.count, empty.m2, empty.variance()), (0, None, None));
+        assert!(empty.mean().is_nan() && empty.min > empty.max);
+    }
+}
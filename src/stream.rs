@@ -0,0 +1,181 @@
+use image::DynamicImage;
+
+use crate::config::{NanPolicy, PlottingPosition, TransformConfig};
+use crate::histogram::Histogram;
+use crate::read::validate_image;
+use crate::transform::{build_lut, extract_channels};
+
+/// Builds a LUT from frames that arrive one at a time, such as a capture
+/// feed, without keeping them. Each frame only adds to a count of every 8-bit
+/// level per channel, so memory stays the same however many frames are
+/// pushed. Samples are ranked at 8 bits anyway, so the counts lose nothing a
+/// bake of all the frames stacked would rank: a nearest LUT at the midpoint
+/// plotting position comes out identical to that bake's, and a linear one
+/// interpolates between levels rather than between individual samples.
+#[derive(Clone, Debug)]
+pub struct HistogramAccumulator {
+    config: TransformConfig,
+    /// Subpixels seen at each level, per histogram channel.
+    counts: Vec<[u64; 256]>,
+    /// Size of the first frame, which the LUT width defaults from.
+    size: Option<(u32, u32)>,
+}
+
+/// The LUT [`HistogramAccumulator::finalize`] builds, laid out as
+/// [`GaussianResult`](crate::GaussianResult)'s.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccumulatedLut {
+    pub lut_width: u32,
+    pub lut_height: u32,
+    pub lut: Vec<u8>,
+    pub lut_f32: Vec<f32>,
+}
+
+impl HistogramAccumulator {
+    /// An empty accumulator for `config`. Options that need every pixel at
+    /// once are rejected: `histogram_samples`, `alpha_cutoff`, `trim_tails`,
+    /// `mean_gradient`, `clip_limit`, [`NanPolicy::Skip`], and any
+    /// plotting position but the midpoint, which weighted histograms use.
+    pub fn new(config: TransformConfig) -> Result<Self, anyhow::Error> {
+        config.validate()?;
+        for (unsupported, name) in [
+            (config.histogram_samples.is_some(), "histogram_samples"),
+            (config.alpha_cutoff.is_some(), "alpha_cutoff"),
+            (config.trim_tails != 0.0, "trim_tails"),
+            (config.mean_gradient.is_some(), "mean_gradient"),
+            (config.nan_policy == NanPolicy::Skip, "nan_policy skip"),
+            (
+                config.plotting_position != PlottingPosition::Midpoint,
+                "plotting_position",
+            ),
+            (config.clip_limit.is_some(), "clip_limit"),
+        ] {
+            if unsupported {
+                return Err(anyhow::anyhow!(
+                    "{} isn't supported when accumulating frames",
+                    name
+                ));
+            }
+        }
+        let channels = if config.shared_lut {
+            1
+        } else {
+            config.channels.count()
+        };
+        Ok(Self {
+            config,
+            counts: vec![[0; 256]; channels],
+            size: None,
+        })
+    }
+
+    /// Adds `frame`'s subpixels to the counts, through `prefilter` and
+    /// `nan_policy` as a bake would.
+    pub fn push(&mut self, frame: &DynamicImage) -> Result<(), anyhow::Error> {
+        validate_image(frame)?;
+        let (frame, _) = self.config.apply_nan_policy(frame)?;
+        let last = self.counts.len() - 1;
+        for (channel, values) in extract_channels(&frame, &self.config)
+            .into_iter()
+            .enumerate()
+        {
+            let counts = &mut self.counts[channel.min(last)];
+            values.iter().for_each(|&v| counts[v as usize] += 1);
+        }
+        self.size.get_or_insert((frame.width(), frame.height()));
+        Ok(())
+    }
+
+    /// Subpixels counted so far in each histogram channel.
+    pub fn sample_count(&self) -> u64 {
+        self.counts[0].iter().sum()
+    }
+
+    /// The counts as a weighted histogram of the levels that occur, each
+    /// weighted by how often it does. Fails before any frame was pushed.
+    pub fn histogram(&self) -> Result<Histogram<u8>, anyhow::Error> {
+        if self.size.is_none() {
+            return Err(anyhow::anyhow!("No frames were pushed"));
+        }
+        let (values, weights): (Vec<Vec<u8>>, Vec<Vec<u64>>) = self
+            .counts
+            .iter()
+            .map(|counts| {
+                (0..=255u8)
+                    .zip(counts.iter().copied())
+                    .filter(|&(_, count)| count > 0)
+                    .unzip()
+            })
+            .unzip();
+        Ok(Histogram::from_sorted_weighted(values, weights))
+    }
+
+    /// The LUT of everything pushed so far, as wide as `lut_size` or the
+    /// default for the first frame's size. Frames can still be pushed after,
+    /// for an updated LUT on the next call.
+    pub fn finalize(&self) -> Result<AccumulatedLut, anyhow::Error> {
+        let Some((width, height)) = self.size else {
+            return Err(anyhow::anyhow!("No frames were pushed"));
+        };
+        let lut_width = self.config.lut_width(width, height);
+        let (lut, lut_f32) = build_lut(&self.histogram()?, &self.config, lut_width);
+        Ok(AccumulatedLut {
+            lut_width,
+            lut_height: self.config.lut_rows,
+            lut,
+            lut_f32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::CancelToken;
+    use crate::config::LutInterp;
+    use crate::rng::Rng;
+
+    #[test]
+    fn streamed_lut_matches_the_stacked_bake() {
+        let rng = Rng::new(6);
+        // Frames of different brightness, so each one shifts the LUT.
+        let frames: Vec<DynamicImage> = (0..4u64)
+            .map(|frame| {
+                DynamicImage::ImageRgb8(image::RgbImage::from_fn(32, 32, |x, y| {
+                    let i = (frame * 1024 + (y * 32 + x) as u64) * 3;
+                    image::Rgb([0, 1, 2].map(|c| (rng.below(i + c, 128) + frame * 32) as u8))
+                }))
+            })
+            .collect();
+        for interp in [LutInterp::Nearest, LutInterp::Linear] {
+            let config = TransformConfig::default()
+                .deterministic(true)
+                .lut_size(Some(256))
+                .lut_interp(interp);
+            let mut accumulator = HistogramAccumulator::new(config.clone()).unwrap();
+            assert!(accumulator.finalize().is_err());
+            for frame in &frames {
+                accumulator.push(frame).unwrap();
+            }
+            assert_eq!(accumulator.sample_count(), 4 * 32 * 32);
+            let streamed = accumulator.finalize().unwrap();
+            let stacked = config.run_pages(&frames, &CancelToken::new()).unwrap();
+            assert_eq!((streamed.lut_width, streamed.lut_height), (256, 1));
+            match interp {
+                LutInterp::Nearest => assert_eq!(streamed.lut, stacked.lut),
+                _ => {
+                    let max_error = (streamed.lut_f32.iter().zip(&stacked.lut_f32))
+                        .map(|(a, b)| (a - b).abs())
+                        .fold(0.0, f32::max);
+                    // Interpolating between levels moves a texel by at most one.
+                    assert!(max_error <= 1.0 / 255.0 + 1e-6, "{}", max_error * 255.0);
+                    assert!(
+                        (streamed.lut.iter().zip(&stacked.lut)).all(|(&a, &b)| a.abs_diff(b) <= 1)
+                    );
+                }
+            }
+        }
+        let skip = TransformConfig::default().nan_policy(NanPolicy::Skip);
+        assert!(HistogramAccumulator::new(skip).is_err());
+    }
+}
@@ -0,0 +1,352 @@
+use std::io::Write;
+
+use crate::encode::{ByteOrder, Layout, OutputData, Tiff};
+
+const TYPE_ASCII: u16 = 2;
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+const TYPE_RATIONAL: u16 = 5;
+
+/// Writes uncompressed RGB pages as `tiff` asks, in either byte order and
+/// layout, one strip per page and plane, or `tile_size` square tiles per
+/// plane when set. The `tiff` crate only writes interleaved strips in the
+/// host's order; this covers the rest. `data` is always interleaved.
+pub(crate) fn write_rgb<W: Write + ?Sized>(
+    writer: &mut W,
+    tiff: &Tiff,
+    width: u32,
+    page_heights: &[u32],
+    data: OutputData,
+) -> Result<(), anyhow::Error> {
+    let (byte_order, layout, tile_size) = (tiff.byte_order, tiff.layout, tiff.tile_size);
+    let (bits, sample_format, mut samples) = match data {
+        OutputData::U8(data) => (8, 1, data.to_vec()),
+        OutputData::U16(data) => {
+            let samples = match byte_order {
+                ByteOrder::LittleEndian => data.iter().flat_map(|v| v.to_le_bytes()).collect(),
+                ByteOrder::BigEndian => data.iter().flat_map(|v| v.to_be_bytes()).collect(),
+            };
+            (16, 1, samples)
+        }
+        OutputData::F32(data) => {
+            let samples = match byte_order {
+                ByteOrder::LittleEndian => data.iter().flat_map(|v| v.to_le_bytes()).collect(),
+                ByteOrder::BigEndian => data.iter().flat_map(|v| v.to_be_bytes()).collect(),
+            };
+            (32, 3, samples)
+        }
+        OutputData::U32(_) | OutputData::I32(_) => {
+            return Err(anyhow::anyhow!(
+                "32-bit integer TIFFs are only written with one channel"
+            ))
+        }
+    };
+    let sample_size = (bits / 8) as usize;
+    if layout == Layout::Planar {
+        samples = planarize(&samples, width as usize, page_heights, sample_size);
+    }
+    let mut out = Out {
+        buf: Vec::with_capacity(samples.len() + 256 * page_heights.len()),
+        byte_order,
+    };
+    out.buf.extend_from_slice(match byte_order {
+        ByteOrder::LittleEndian => b"II",
+        ByteOrder::BigEndian => b"MM",
+    });
+    out.u16(42);
+    // Offset of the first IFD, patched once it's written.
+    let mut next_ifd_at = out.buf.len();
+    out.u32(0);
+
+    // Planes per page, and the PlanarConfiguration tag's value.
+    let (planes, planar_configuration): (usize, u32) = match layout {
+        Layout::Interleaved => (1, 1),
+        Layout::Planar => (3, 2),
+    };
+    let pixel_len = 3 / planes * sample_size;
+    let mut offset = 0;
+    for (i, &height) in page_heights.iter().enumerate() {
+        let page_len = width as usize * height as usize * 3 * sample_size;
+        let page = &samples[offset..offset + page_len];
+        offset += page_len;
+        // Strips or tiles, plane by plane, and each tile row by row.
+        let mut chunk_offsets = Vec::new();
+        let mut chunk_len = page_len / planes;
+        for plane in page.chunks_exact(page_len / planes) {
+            let Some(tile_size) = tile_size else {
+                chunk_offsets.push(out.offset()?);
+                out.buf.extend_from_slice(plane);
+                continue;
+            };
+            let row_len = width as usize * pixel_len;
+            let tile_row_len = tile_size as usize * pixel_len;
+            chunk_len = tile_size as usize * tile_row_len;
+            for tile_y in (0..height).step_by(tile_size as usize) {
+                for tile_x in (0..width).step_by(tile_size as usize) {
+                    chunk_offsets.push(out.offset()?);
+                    let tile_start = out.buf.len();
+                    // Tiles past the right and bottom edges are zero-padded.
+                    let x_range = tile_x as usize * pixel_len
+                        ..(tile_x + tile_size).min(width) as usize * pixel_len;
+                    for y in tile_y..(tile_y + tile_size).min(height) {
+                        let row = &plane[y as usize * row_len..][..row_len];
+                        let row_start = out.buf.len();
+                        out.buf.extend_from_slice(&row[x_range.clone()]);
+                        out.buf.resize(row_start + tile_row_len, 0);
+                    }
+                    out.buf.resize(tile_start + chunk_len, 0);
+                }
+            }
+        }
+        out.buf.resize(out.buf.len().next_multiple_of(4), 0);
+
+        // Fields with several values don't fit an IFD entry and are stored
+        // ahead of it. A single strip's offset and length are stored inline.
+        let bits_offset = out.offset()?;
+        (0..3).for_each(|_| out.u16(bits));
+        let sample_format_offset = out.offset()?;
+        (0..3).for_each(|_| out.u16(sample_format));
+        out.buf.resize(out.buf.len().next_multiple_of(4), 0);
+        // NUL-terminated, and always stored ahead, even if it would fit inline.
+        let description = match tiff.description.as_deref().filter(|_| i == 0) {
+            Some(text) => {
+                let at = out.offset()?;
+                out.buf.extend_from_slice(text.as_bytes());
+                out.buf.push(0);
+                out.buf.resize(out.buf.len().next_multiple_of(4), 0);
+                Some((text.len() as u32 + 1, at))
+            }
+            None => None,
+        };
+        // Both resolutions point at the same numerator and denominator.
+        let resolution = match tiff.dpi {
+            Some(dpi) => {
+                let at = out.offset()?;
+                out.u32(dpi);
+                out.u32(1);
+                Some(at)
+            }
+            None => None,
+        };
+        let chunks = chunk_offsets.len() as u32;
+        let (chunk_offsets_value, chunk_byte_counts_value) = if chunks == 1 {
+            (chunk_offsets[0], chunk_len as u32)
+        } else {
+            let offsets_at = out.offset()?;
+            chunk_offsets.iter().for_each(|&offset| out.u32(offset));
+            let counts_at = out.offset()?;
+            (0..chunks).for_each(|_| out.u32(chunk_len as u32));
+            (offsets_at, counts_at)
+        };
+
+        let ifd_offset = out.offset()?;
+        out.patch_u32(next_ifd_at, ifd_offset);
+        let mut entries: Vec<(u16, u16, u32, u32)> = vec![
+            (256, TYPE_LONG, 1, width),        // ImageWidth
+            (257, TYPE_LONG, 1, height),       // ImageLength
+            (258, TYPE_SHORT, 3, bits_offset), // BitsPerSample
+            (259, TYPE_SHORT, 1, 1),           // Compression: none
+            (262, TYPE_SHORT, 1, 2),           // PhotometricInterpretation: RGB
+        ];
+        if let Some((count, at)) = description {
+            entries.push((270, TYPE_ASCII, count, at)); // ImageDescription
+        }
+        match tile_size {
+            None => entries.extend([
+                (273, TYPE_LONG, chunks, chunk_offsets_value), // StripOffsets
+                (277, TYPE_SHORT, 1, 3),                       // SamplesPerPixel
+                (278, TYPE_LONG, 1, height),                   // RowsPerStrip
+                (279, TYPE_LONG, chunks, chunk_byte_counts_value), // StripByteCounts
+                (284, TYPE_SHORT, 1, planar_configuration),    // PlanarConfiguration
+            ]),
+            Some(tile_size) => entries.extend([
+                (277, TYPE_SHORT, 1, 3),                           // SamplesPerPixel
+                (284, TYPE_SHORT, 1, planar_configuration),        // PlanarConfiguration
+                (322, TYPE_LONG, 1, tile_size),                    // TileWidth
+                (323, TYPE_LONG, 1, tile_size),                    // TileLength
+                (324, TYPE_LONG, chunks, chunk_offsets_value),     // TileOffsets
+                (325, TYPE_LONG, chunks, chunk_byte_counts_value), // TileByteCounts
+            ]),
+        }
+        if let Some(at) = resolution {
+            entries.extend([
+                (282, TYPE_RATIONAL, 1, at), // XResolution
+                (283, TYPE_RATIONAL, 1, at), // YResolution
+                (296, TYPE_SHORT, 1, 2),     // ResolutionUnit: inch
+            ]);
+        }
+        entries.push((339, TYPE_SHORT, 3, sample_format_offset)); // SampleFormat
+                                                                  // Readers expect the entries in ascending tag order.
+        entries.sort_by_key(|&(tag, ..)| tag);
+        out.u16(entries.len() as u16);
+        for (tag, field_type, count, value) in entries {
+            out.u16(tag);
+            out.u16(field_type);
+            out.u32(count);
+            if field_type == TYPE_SHORT && count == 1 {
+                // Short values sit in the first two bytes of the value field.
+                out.u16(value as u16);
+                out.u16(0);
+            } else {
+                out.u32(value);
+            }
+        }
+        next_ifd_at = out.buf.len();
+        out.u32(0);
+    }
+    out.offset()?;
+
+    writer.write_all(&out.buf)?;
+    Ok(())
+}
+
+/// Reorders each page's interleaved samples, `sample_size` bytes each, into
+/// its R, G and B planes.
+fn planarize(samples: &[u8], width: usize, page_heights: &[u32], sample_size: usize) -> Vec<u8> {
+    let mut planar = Vec::with_capacity(samples.len());
+    let mut offset = 0;
+    for &height in page_heights {
+        let page_len = width * height as usize * 3 * sample_size;
+        let page = &samples[offset..offset + page_len];
+        for channel in 0..3 {
+            for px in page.chunks_exact(3 * sample_size) {
+                planar.extend_from_slice(&px[channel * sample_size..(channel + 1) * sample_size]);
+            }
+        }
+        offset += page_len;
+    }
+    planar
+}
+
+struct Out {
+    buf: Vec<u8>,
+    byte_order: ByteOrder,
+}
+
+impl Out {
+    /// Current length as a TIFF offset, which is limited to 32 bits.
+    fn offset(&self) -> Result<u32, anyhow::Error> {
+        u32::try_from(self.buf.len())
+            .map_err(|_| anyhow::anyhow!("Output is larger than the 4 GiB a TIFF can hold"))
+    }
+
+    fn u16(&mut self, value: u16) {
+        let bytes = match self.byte_order {
+            ByteOrder::LittleEndian => value.to_le_bytes(),
+            ByteOrder::BigEndian => value.to_be_bytes(),
+        };
+        self.buf.extend_from_slice(&bytes);
+    }
+
+    fn u32(&mut self, value: u32) {
+        let bytes = self.u32_bytes(value);
+        self.buf.extend_from_slice(&bytes);
+    }
+
+    fn patch_u32(&mut self, at: usize, value: u32) {
+        let bytes = self.u32_bytes(value);
+        self.buf[at..at + 4].copy_from_slice(&bytes);
+    }
+
+    fn u32_bytes(&self, value: u32) -> [u8; 4] {
+        match self.byte_order {
+            ByteOrder::LittleEndian => value.to_le_bytes(),
+            ByteOrder::BigEndian => value.to_be_bytes(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::encode_image_to_vec;
+    use crate::read::decode_bytes;
+    use crate::self_test::synthetic_input;
+    use crate::TransformConfig;
+
+    /// The samples of the first page of an uncompressed little-endian float
+    /// TIFF with one strip per plane, interleaved again. `tiff` can't decode
+    /// planar files, so this walks the IFD itself.
+    fn read_planar_f32(bytes: &[u8]) -> Vec<f32> {
+        let u16_at = |at: usize| u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap());
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        assert_eq!(&bytes[..4], b"II\x2a\x00");
+        let ifd = u32_at(4) as usize;
+        let entry = |tag: u16| -> Vec<u32> {
+            let at = (0..u16_at(ifd) as usize)
+                .map(|i| ifd + 2 + 12 * i)
+                .find(|&at| u16_at(at) == tag)
+                .unwrap();
+            let (kind, count) = (u16_at(at + 2), u32_at(at + 4) as usize);
+            let size = if kind == TYPE_SHORT { 2 } else { 4 };
+            let values = if size * count <= 4 {
+                at + 8
+            } else {
+                u32_at(at + 8) as usize
+            };
+            (0..count)
+                .map(|i| match kind {
+                    TYPE_SHORT => u16_at(values + 2 * i) as u32,
+                    _ => u32_at(values + 4 * i),
+                })
+                .collect()
+        };
+        assert_eq!(entry(284), [2]); // PlanarConfiguration
+        let planes: Vec<Vec<f32>> = entry(273) // StripOffsets
+            .iter()
+            .zip(entry(279)) // StripByteCounts
+            .map(|(&offset, count)| {
+                bytes[offset as usize..(offset + count) as usize]
+                    .chunks_exact(4)
+                    .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(planes.len(), 3);
+        (0..planes[0].len() * 3)
+            .map(|i| planes[i % 3][i / 3])
+            .collect()
+    }
+
+    #[test]
+    fn planar_forward_image_has_the_interleaved_pixels() {
+        let result = TransformConfig::default().run(&synthetic_input()).unwrap();
+        let interleaved = encode_image_to_vec(&result, &Tiff::default()).unwrap();
+        let planar = Tiff {
+            layout: Layout::Planar,
+            ..Default::default()
+        };
+        let planar = encode_image_to_vec(&result, &planar).unwrap();
+
+        let pages = decode_bytes(&interleaved, Some(image::ImageFormat::Tiff)).unwrap();
+        let interleaved = pages[0].to_rgb32f().into_raw();
+        assert_eq!(interleaved, result.image);
+        assert_eq!(read_planar_f32(&planar), interleaved);
+    }
+
+    #[test]
+    fn tiled_forward_image_decodes_to_the_same_pixels() {
+        let result = TransformConfig::default().run(&synthetic_input()).unwrap();
+        // 48 doesn't divide 64, so the last tiles of each row and column are padded.
+        for tile_size in [16, 48] {
+            let tiled = Tiff {
+                tile_size: Some(tile_size),
+                ..Default::default()
+            };
+            let bytes = encode_image_to_vec(&result, &tiled).unwrap();
+            let pages = decode_bytes(&bytes, Some(image::ImageFormat::Tiff)).unwrap();
+            assert_eq!((pages[0].width(), pages[0].height()), (64, 64));
+            assert_eq!(
+                pages[0].to_rgb32f().into_raw(),
+                result.image,
+                "tile {}",
+                tile_size
+            );
+        }
+        let unaligned = Tiff {
+            tile_size: Some(24),
+            ..Default::default()
+        };
+        assert!(encode_image_to_vec(&result, &unaligned).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+
+/// Index of the tiles a forward image was split into, written alongside them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TileManifest {
+    pub tile_size: u32,
+    pub columns: u32,
+    pub rows: u32,
+    /// Size of the full image. Tiles in the last column and row are cropped to it.
+    pub width: u32,
+    pub height: u32,
+    /// File name of the LUT shared by every tile.
+    pub lut: String,
+    pub tiles: Vec<Tile>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tile {
+    pub column: u32,
+    pub row: u32,
+    /// Top-left pixel of the tile in the full image.
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub file: String,
+}
+
+impl TileManifest {
+    /// Lays out a `width x height` image as tiles of `tile_size`, named by `file_name(column, row)`.
+    pub fn new(
+        width: u32,
+        height: u32,
+        tile_size: u32,
+        lut: String,
+        file_name: impl Fn(u32, u32) -> String,
+    ) -> Self {
+        let columns = width.div_ceil(tile_size);
+        let rows = height.div_ceil(tile_size);
+        let tiles = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                let x = column * tile_size;
+                let y = row * tile_size;
+                Tile {
+                    column,
+                    row,
+                    x,
+                    y,
+                    width: tile_size.min(width - x),
+                    height: tile_size.min(height - y),
+                    file: file_name(column, row),
+                }
+            })
+            .collect();
+        Self {
+            tile_size,
+            columns,
+            rows,
+            width,
+            height,
+            lut,
+            tiles,
+        }
+    }
+}
+
+impl Tile {
+    /// Copies this tile's pixels out of an interleaved image `image_width` pixels wide.
+    pub fn crop<T: Copy>(&self, data: &[T], image_width: u32, channels: usize) -> Vec<T> {
+        let row_len = self.width as usize * channels;
+        let mut out = Vec::with_capacity(row_len * self.height as usize);
+        for y in self.y..self.y + self.height {
+            let start = (y as usize * image_width as usize + self.x as usize) * channels;
+            out.extend_from_slice(&data[start..start + row_len]);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_reassemble_into_the_full_image() {
+        let (width, height, channels) = (10, 7, 3);
+        let image: Vec<u32> = (0..width * height * channels as u32).collect();
+        let manifest = TileManifest::new(width, height, 4, "lut.tif".to_string(), |column, row| {
+            format!("tile-{}-{}.tif", column, row)
+        });
+        assert_eq!((manifest.columns, manifest.rows), (3, 2));
+        assert_eq!(manifest.tiles.len(), 6);
+        let last = manifest.tiles.last().unwrap();
+        assert_eq!(
+            (last.width, last.height, last.file.as_str()),
+            (2, 3, "tile-2-1.tif")
+        );
+
+        let manifest: TileManifest =
+            serde_json::from_str(&serde_json::to_string(&manifest).unwrap()).unwrap();
+        let mut reassembled = vec![u32::MAX; image.len()];
+        for tile in &manifest.tiles {
+            let pixels = tile.crop(&image, width, channels);
+            assert_eq!(pixels.len(), (tile.width * tile.height) as usize * channels);
+            for (dy, row) in pixels
+                .chunks_exact(tile.width as usize * channels)
+                .enumerate()
+            {
+                let start = ((tile.y as usize + dy) * width as usize + tile.x as usize) * channels;
+                reassembled[start..start + row.len()].copy_from_slice(row);
+            }
+        }
+        assert_eq!(reassembled, image);
+    }
+}
@@ -0,0 +1,134 @@
+/// How a LUT's values encode light: linearly, or with the sRGB transfer
+/// function most 8-bit color textures are stored with.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transfer {
+    Linear,
+    Srgb,
+}
+
+impl Transfer {
+    /// The linear value `v`, in `[0, 1]`, encodes.
+    pub fn to_linear(self, v: f32) -> f32 {
+        match self {
+            Transfer::Linear => v,
+            Transfer::Srgb if v <= 0.04045 => v / 12.92,
+            Transfer::Srgb => ((v + 0.055) / 1.055).powf(2.4),
+        }
+    }
+
+    /// The encoding of the linear value `v`, in `[0, 1]`.
+    pub fn from_linear(self, v: f32) -> f32 {
+        match self {
+            Transfer::Linear => v,
+            Transfer::Srgb if v <= 0.0031308 => v * 12.92,
+            Transfer::Srgb => 1.055 * v.powf(1.0 / 2.4) - 0.055,
+        }
+    }
+
+    /// `v`, encoded with `self`, re-encoded with `to`. Values are clamped to
+    /// `[0, 1]` first, and returned unchanged when the two are the same.
+    pub fn convert(self, to: Transfer, v: f32) -> f32 {
+        match self == to {
+            true => v,
+            false => to.from_linear(self.to_linear(v.clamp(0.0, 1.0))),
+        }
+    }
+
+    /// A float LUT of values encoded with `self` re-encoded with `to`, as
+    /// floats and rounded to 8 bits.
+    pub fn convert_lut(self, to: Transfer, lut_f32: &[f32]) -> (Vec<u8>, Vec<f32>) {
+        let converted: Vec<f32> = lut_f32.iter().map(|&v| self.convert(to, v)).collect();
+        let lut = converted
+            .iter()
+            .map(|&v| (v * 255.0).round() as u8)
+            .collect();
+        (lut, converted)
+    }
+}
+
+/// Curve `--preview-output` encodes the stretched forward image with for
+/// display, taking the stretched values to be linear.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DisplayCurve {
+    /// Written as stretched, the default.
+    #[default]
+    Linear,
+    /// The sRGB transfer function, as displays expect 8-bit images to be encoded.
+    Srgb,
+    /// A pure power curve, `v^(1 / gamma)`.
+    Gamma(f32),
+}
+
+impl DisplayCurve {
+    /// The display encoding of `v`, clamped to `[0, 1]` first.
+    pub fn encode(self, v: f32) -> f32 {
+        let v = v.clamp(0.0, 1.0);
+        match self {
+            DisplayCurve::Linear => v,
+            DisplayCurve::Srgb => Transfer::Srgb.from_linear(v),
+            DisplayCurve::Gamma(gamma) => v.powf(1.0 / gamma),
+        }
+    }
+}
+
+impl std::str::FromStr for DisplayCurve {
+    type Err = String;
+
+    /// `linear`, `srgb`, or `gamma:X` for a positive `X`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "linear" => return Ok(DisplayCurve::Linear),
+            "srgb" => return Ok(DisplayCurve::Srgb),
+            _ => {}
+        }
+        let gamma = value
+            .strip_prefix("gamma:")
+            .and_then(|gamma| gamma.parse::<f32>().ok())
+            .filter(|gamma| gamma.is_finite() && *gamma > 0.0);
+        match gamma {
+            Some(gamma) => Ok(DisplayCurve::Gamma(gamma)),
+            None => Err(format!(
+                "expected linear, srgb or gamma:X with X positive, got {:?}",
+                value
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for DisplayCurve {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisplayCurve::Linear => write!(f, "linear"),
+            DisplayCurve::Srgb => write!(f, "srgb"),
+            DisplayCurve::Gamma(gamma) => write!(f, "gamma:{}", gamma),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_curves_encode_mid_gray() {
+        // A forward value at the mean is stretched to 0.5 in the preview.
+        let level = |curve: DisplayCurve| (curve.encode(0.5) * 255.0).round() as u8;
+        assert!((DisplayCurve::Srgb.encode(0.5) - 0.735_357).abs() < 1e-5);
+        assert_eq!(level(DisplayCurve::Srgb), 188);
+        assert_eq!(level(DisplayCurve::Linear), 128);
+        assert_eq!(level(DisplayCurve::Gamma(2.2)), 186);
+        for curve in [
+            DisplayCurve::Linear,
+            DisplayCurve::Srgb,
+            DisplayCurve::Gamma(2.2),
+        ] {
+            let clamped = |v: f32| (curve.encode(v) * 255.0).round() as u8;
+            assert_eq!((clamped(-1.0), clamped(2.0)), (0, 255));
+            assert_eq!(curve.to_string().parse::<DisplayCurve>(), Ok(curve));
+        }
+        for bad in ["gamma:0", "gamma:-1", "gamma:", "rec709"] {
+            assert!(bad.parse::<DisplayCurve>().is_err(), "{}", bad);
+        }
+    }
+}
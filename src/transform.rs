@@ -0,0 +1,1035 @@
+use std::time::Instant;
+
+use image::{DynamicImage, GenericImageView};
+use rayon::prelude::*;
+use tracing::info_span;
+
+use crate::cancel::{CancelToken, Cancelled};
+use crate::config::{
+    Exclusions, GaussianResult, LutEndpoints, LutInterp, LutRowMode, LutSampling, PhaseTimings,
+    Prefilter, TransformConfig,
+};
+use crate::distribution::Distribution;
+use crate::histogram::Histogram;
+use crate::prefilter::bilateral;
+use crate::rng::Rng;
+
+struct ChannelPixel {
+    subpx_idx: usize,
+    sort_idx: usize,
+}
+
+/// The sorted half of a transform: every subpixel's rank, and the histogram
+/// the ranks index into.
+pub(crate) struct Ranked {
+    pub(crate) ranks: Ranks,
+    pub(crate) histogram: Histogram<u8>,
+    pub(crate) timings: PhaseTimings,
+}
+
+/// Per transformed channel, where each subpixel falls in its histogram channel.
+pub(crate) enum Ranks {
+    /// Each subpixel's index into the sorted samples.
+    Sorted(Vec<Vec<usize>>),
+    /// Each subpixel's quantile, interpolated from a sampled histogram.
+    Sampled(Vec<Vec<f64>>),
+}
+
+impl Ranks {
+    pub(crate) fn channel_count(&self) -> usize {
+        match self {
+            Ranks::Sorted(sort_indices) => sort_indices.len(),
+            Ranks::Sampled(quantiles) => quantiles.len(),
+        }
+    }
+
+    /// Quantile of subpixel `i` of `channel`, which ranks into
+    /// `histogram_channel` of `histogram`.
+    fn quantile(
+        &self,
+        histogram: &Histogram<u8>,
+        channel: usize,
+        histogram_channel: usize,
+        i: usize,
+    ) -> f64 {
+        match self {
+            Ranks::Sorted(sort_indices) => {
+                histogram.sample_quantile(histogram_channel, sort_indices[channel][i])
+            }
+            Ranks::Sampled(quantiles) => quantiles[channel][i],
+        }
+    }
+}
+
+/// Subpixels the forward pass hands [`forward_values`] at a time.
+pub(crate) const FORWARD_CHUNK: usize = 4096;
+
+/// Forward image values of subpixels `start..start + out.len()` of `channel`,
+/// evaluated together so the target can batch its quantile function.
+pub(crate) fn forward_values(
+    ranks: &Ranks,
+    histogram: &Histogram<u8>,
+    target: &dyn Distribution,
+    channel: usize,
+    histogram_channel: usize,
+    start: usize,
+    out: &mut [f32],
+) {
+    let u: Vec<f64> = (start..start + out.len())
+        .map(|i| ranks.quantile(histogram, channel, histogram_channel, i))
+        .collect();
+    let mut values = vec![0.0; u.len()];
+    target.inv_cdf_slice(&u, &mut values);
+    for (out, v) in out.iter_mut().zip(values) {
+        *out = v as f32;
+    }
+}
+
+/// `weights`, if given, holds one weight per pixel in row-major order.
+pub(crate) fn transform_histogram(
+    input: &DynamicImage,
+    weights: Option<&[u16]>,
+    config: &TransformConfig,
+    cancel: &CancelToken,
+) -> Result<GaussianResult, Cancelled> {
+    let _transform =
+        info_span!("transform", width = input.width(), height = input.height()).entered();
+    let lut_width = config.lut_width(input.width(), input.height());
+    let ranked = rank(input, weights, config, cancel)?;
+    let mut timings = ranked.timings;
+
+    let start = Instant::now();
+    let forward = info_span!("forward");
+    let forward_entered = forward.enter();
+    let len = input.width() as usize * input.height() as usize;
+    let t: Vec<Vec<f32>> = (0..ranked.ranks.channel_count())
+        .into_par_iter()
+        .map(|channel| {
+            let _channel = info_span!(parent: &forward, "forward_channel", channel).entered();
+            let histogram_channel = if config.shared_lut { 0 } else { channel };
+            let target = config.channel_target(channel);
+            let mut values = vec![0.0; len];
+            values
+                .par_chunks_mut(FORWARD_CHUNK)
+                .enumerate()
+                .try_for_each(|(chunk, out)| {
+                    cancel.check()?;
+                    forward_values(
+                        &ranked.ranks,
+                        &ranked.histogram,
+                        target.as_ref(),
+                        channel,
+                        histogram_channel,
+                        chunk * FORWARD_CHUNK,
+                        out,
+                    );
+                    Ok(())
+                })?;
+            Ok(values)
+        })
+        .collect::<Result<_, Cancelled>>()?;
+    let image = interleave(&t, 3);
+    drop(forward_entered);
+    timings.forward = start.elapsed();
+    cancel.check()?;
+
+    let start = Instant::now();
+    let inverse_lut = info_span!("inverse_lut").entered();
+    let (lut, lut_f32) = build_lut(&ranked.histogram, config, lut_width);
+    drop(inverse_lut);
+    timings.inverse_lut = start.elapsed();
+    cancel.check()?;
+
+    let subpixels = if config.shared_lut {
+        config.channels.count()
+    } else {
+        1
+    };
+    Ok(GaussianResult {
+        width: input.width(),
+        height: input.height(),
+        page_heights: vec![input.height()],
+        lut_width,
+        lut_height: config.lut_rows,
+        image,
+        lut,
+        lut_f32,
+        exclusions: trimmed_exclusions(&ranked.histogram, config, len * subpixels),
+        histogram: ranked.histogram,
+        timings,
+    })
+}
+
+/// For each channel of `histogram`, built from `subpixels` subpixels, those
+/// `trim_tails` left out of it, with nothing else left out yet.
+pub(crate) fn trimmed_exclusions(
+    histogram: &Histogram<u8>,
+    config: &TransformConfig,
+    subpixels: usize,
+) -> Vec<Exclusions> {
+    (0..histogram.channel_count())
+        .map(|channel| Exclusions {
+            trimmed: match config.trim_tails > 0.0 {
+                true => subpixels - histogram.sample_count(channel),
+                false => 0,
+            },
+            ..Exclusions::default()
+        })
+        .collect()
+}
+
+/// Extracts and sorts `input`'s channels, the phases a transform has to
+/// finish before any forward value is known.
+pub(crate) fn rank(
+    input: &DynamicImage,
+    weights: Option<&[u16]>,
+    config: &TransformConfig,
+    cancel: &CancelToken,
+) -> Result<Ranked, Cancelled> {
+    let mut timings = PhaseTimings::default();
+
+    let start = Instant::now();
+    let extract = info_span!("extract").entered();
+    let inputs = extract_channels(input, config);
+    drop(extract);
+    timings.extract = start.elapsed();
+    cancel.check()?;
+
+    let start = Instant::now();
+    // Spans entered inside rayon closures name their parent explicitly, since
+    // they may run on a worker thread that isn't inside the phase's span.
+    let sort = info_span!("sort");
+    let sort_entered = sort.enter();
+    let len = inputs[0].len();
+    if let Some(samples) = config.histogram_samples {
+        let rng = Rng::new(config.seed);
+        let (quantiles, sorted): (Vec<_>, Vec<_>) = if config.shared_lut {
+            let (quantiles, sorted) = rank_sampled(&inputs.concat(), samples, rng);
+            (
+                quantiles.chunks(len).map(<[_]>::to_vec).collect(),
+                vec![sorted],
+            )
+        } else {
+            inputs
+                .par_iter()
+                .enumerate()
+                .map(|(channel, values)| {
+                    let _rank = info_span!(parent: &sort, "rank_sampled", channel).entered();
+                    rank_sampled(values, samples, rng)
+                })
+                .unzip()
+        };
+        drop(sort_entered);
+        timings.sort = start.elapsed();
+        cancel.check()?;
+        return Ok(Ranked {
+            ranks: Ranks::Sampled(quantiles),
+            histogram: Histogram::from_sorted(sorted),
+            timings,
+        });
+    }
+    let (ranked, sorted): (Vec<_>, Vec<_>) = if config.shared_lut {
+        let (sort_idx, sorted) = rank_channel(inputs.concat(), config.deterministic, cancel)?;
+        (vec![sort_idx], vec![sorted])
+    } else {
+        inputs
+            .into_par_iter()
+            .enumerate()
+            .map(|(channel, values)| {
+                let _rank = info_span!(parent: &sort, "rank_channel", channel).entered();
+                rank_channel(values, config.deterministic, cancel)
+            })
+            .collect::<Result<Vec<_>, Cancelled>>()?
+            .into_iter()
+            .unzip()
+    };
+    let (ranked, sorted) = if config.trim_tails > 0.0 {
+        trim_tails(ranked, sorted, config.trim_tails)
+    } else {
+        (ranked, sorted)
+    };
+    let histogram = match (weights, config.clip_limit) {
+        (None, None) => {
+            Histogram::from_sorted(sorted).with_plotting_position(config.plotting_position)
+        }
+        (weights, clip_limit) => {
+            let sorted_weights = ranked
+                .par_iter()
+                .zip(&sorted)
+                .map(|(sort_idx, sorted)| {
+                    let mut sorted_weights = match weights {
+                        Some(weights) => {
+                            let mut sorted_weights = vec![0; sort_idx.len()];
+                            for (i, &sort_idx) in sort_idx.iter().enumerate() {
+                                sorted_weights[sort_idx] = weights[i % len] as u64;
+                            }
+                            sorted_weights
+                        }
+                        None => vec![1; sorted.len()],
+                    };
+                    if let Some(clip_limit) = clip_limit {
+                        clip_weights(sorted, &mut sorted_weights, clip_limit);
+                    }
+                    sorted_weights
+                })
+                .collect();
+            Histogram::from_sorted_weighted(sorted, sorted_weights)
+        }
+    }
+    .with_preserve_extremes(config.preserve_extremes);
+    let sort_indices: Vec<Vec<usize>> = if config.shared_lut {
+        ranked[0].chunks(len).map(<[_]>::to_vec).collect()
+    } else {
+        ranked
+    };
+    drop(sort_entered);
+    timings.sort = start.elapsed();
+    cancel.check()?;
+
+    Ok(Ranked {
+        ranks: Ranks::Sorted(sort_indices),
+        histogram,
+        timings,
+    })
+}
+
+/// Fixed-point scale of the weights [`clip_weights`] gives, so the lowered
+/// weights of a clipped level keep their precision.
+const CLIP_WEIGHT_SCALE: f64 = 65536.0;
+
+/// Rescales the `weights` of the `sorted` samples of a channel so that no
+/// level weighs more than `clip_limit` times the mean weight per level of
+/// all 256, spreading what was clipped evenly over all levels, as
+/// [`TransformConfig::clip_limit`] describes. Within a level the weights
+/// keep their proportions. The sums are taken in order, so the result
+/// doesn't depend on the thread count.
+fn clip_weights(sorted: &[u8], weights: &mut [u64], clip_limit: f64) {
+    let mut counts = [0.0; 256];
+    for (&v, &weight) in sorted.iter().zip(weights.iter()) {
+        counts[v as usize] += weight as f64;
+    }
+    let limit = clip_limit * counts.iter().sum::<f64>() / 256.0;
+    let excess: f64 = counts.iter().map(|&c| (c - limit).max(0.0)).sum();
+    let scale = counts.map(|c| match c > 0.0 {
+        true => (c.min(limit) + excess / 256.0) / c * CLIP_WEIGHT_SCALE,
+        false => 0.0,
+    });
+    weights
+        .par_iter_mut()
+        .zip(sorted)
+        .for_each(|(weight, &v)| *weight = (*weight as f64 * scale[v as usize]).round() as u64);
+}
+
+/// Samples `histogram` into an interleaved RGB LUT `lut_width` texels wide and
+/// `config.lut_rows` high, returned rounded and as `[0, 1]` floats.
+pub(crate) fn build_lut(
+    histogram: &Histogram<u8>,
+    config: &TransformConfig,
+    lut_width: u32,
+) -> (Vec<u8>, Vec<f32>) {
+    let rows = config.lut_rows;
+    if config.lut_row_mode == LutRowMode::Replicate || rows == 1 {
+        let (lut, lut_f32) = build_lut_row(histogram, config, lut_width, 0.5);
+        return (lut.repeat(rows as usize), lut_f32.repeat(rows as usize));
+    }
+    let (lut, lut_f32): (Vec<_>, Vec<_>) = (0..rows)
+        .map(|row| {
+            build_lut_row(
+                histogram,
+                config,
+                lut_width,
+                (row as f64 + 0.5) / rows as f64,
+            )
+        })
+        .unzip();
+    (lut.concat(), lut_f32.concat())
+}
+
+/// One row of [`build_lut`], sampling texel `i` at [`TransformConfig::lut_texel_value`].
+fn build_lut_row(
+    histogram: &Histogram<u8>,
+    config: &TransformConfig,
+    lut_width: u32,
+    offset: f64,
+) -> (Vec<u8>, Vec<f32>) {
+    let parent = tracing::Span::current();
+    let t_inv: Vec<Vec<f64>> = (0..config.channels.count())
+        .into_par_iter()
+        .map(|channel| {
+            let _channel = info_span!(parent: &parent, "lut_channel", channel).entered();
+            let histogram_channel = if config.shared_lut { 0 } else { channel };
+            let column = LutColumn {
+                histogram,
+                histogram_channel,
+                channel,
+            };
+            column.sample(
+                config,
+                config.channel_target(channel).as_ref(),
+                lut_width,
+                offset,
+            )
+        })
+        .collect();
+    let lut_f32: Vec<Vec<f32>> = t_inv
+        .iter()
+        .map(|t| t.iter().map(|&v| (v / 255.0) as f32).collect())
+        .collect();
+    let lut_u8: Vec<Vec<u8>> = t_inv
+        .iter()
+        .map(|t| t.iter().map(|&v| config.rounding.apply(v) as u8).collect())
+        .collect();
+    (interleave(&lut_u8, 3), interleave(&lut_f32, 3))
+}
+
+/// The LUT column of `channel`, built from `histogram_channel` of `histogram`.
+struct LutColumn<'a> {
+    histogram: &'a Histogram<u8>,
+    histogram_channel: usize,
+    channel: usize,
+}
+
+impl LutColumn<'_> {
+    /// One row of the column in source levels, sampling texel `i` at
+    /// [`TransformConfig::lut_texel_value`], or where the column's
+    /// [`KnotWarp`] maps it with [`LutSampling::Stratified`]. With
+    /// `profile_guided_lut`, the row is sampled at its
+    /// [`TransformConfig::effective_lut_width`] and each texel repeated to
+    /// fill `lut_width`.
+    fn sample(
+        &self,
+        config: &TransformConfig,
+        target: &dyn Distribution,
+        lut_width: u32,
+        offset: f64,
+    ) -> Vec<f64> {
+        let width = config.effective_lut_width(
+            self.histogram,
+            self.histogram_channel,
+            self.channel,
+            lut_width,
+        );
+        let repeat = (lut_width / width) as usize;
+        let warp = (config.lut_sampling == LutSampling::Stratified)
+            .then(|| KnotWarp::new(self, config, target, width));
+        let row: Vec<f64> = (0..width)
+            .into_par_iter()
+            .map(|i| {
+                let g = config.lut_texel_value(i, width, offset);
+                let g = warp.as_ref().map_or(g, |warp| warp.forward_value(g));
+                if !config.lut_channel_enabled(self.channel) {
+                    return g * 255.0;
+                }
+                let u = target.cdf(g);
+                if config.lut_endpoints == LutEndpoints::Extend {
+                    let extended = self
+                        .histogram
+                        .extrapolated_value_at_quantile(self.histogram_channel, u);
+                    if let Some(value) = extended {
+                        return value;
+                    }
+                }
+                match config.lut_interp {
+                    LutInterp::Nearest => {
+                        self.histogram.value_at_quantile(self.histogram_channel, u) as f64
+                    }
+                    LutInterp::Linear => self
+                        .histogram
+                        .interpolated_value_at_quantile(self.histogram_channel, u),
+                }
+            })
+            .collect();
+        if repeat == 1 {
+            return row;
+        }
+        row.into_iter()
+            .flat_map(|v| std::iter::repeat_n(v, repeat))
+            .collect()
+    }
+
+    /// Every row of the column, as [`build_lut`] lays them out, in source levels.
+    fn sample_rows(
+        &self,
+        config: &TransformConfig,
+        target: &dyn Distribution,
+        lut_width: u32,
+    ) -> Vec<f64> {
+        let rows = config.lut_rows;
+        if config.lut_row_mode == LutRowMode::Replicate || rows == 1 {
+            return self
+                .sample(config, target, lut_width, 0.5)
+                .repeat(rows as usize);
+        }
+        (0..rows)
+            .flat_map(|row| {
+                self.sample(config, target, lut_width, (row as f64 + 0.5) / rows as f64)
+            })
+            .collect()
+    }
+}
+
+/// Cells per LUT texel [`KnotWarp`] measures the forward range in.
+const KNOT_CELLS_PER_TEXEL: usize = 8;
+
+/// Share of the texels [`KnotWarp`] spreads evenly, so the tails and flat
+/// stretches keep a few and the warp keeps rising everywhere.
+const UNIFORM_KNOT_SHARE: f64 = 0.1;
+
+/// How [`LutSampling::Stratified`] spreads a LUT column's texels: an
+/// increasing map from stored forward values `g` to LUT coordinates, both in
+/// `[0, 1]`, that is linear between its values at `j / (W - 1)` for a
+/// `W`-wide LUT. Those `W` values are the column's knot map, which a shader
+/// samples with linear filtering at `(g * (W - 1) + 0.5) / W`, and texel `i`
+/// is built for the `g` mapped to `(i + 0.5) / W`.
+pub(crate) struct KnotWarp {
+    /// The map at `j / (values.len() - 1)`, from 0 to 1.
+    values: Vec<f64>,
+}
+
+impl KnotWarp {
+    /// The warp evenly spacing texels, `g` itself.
+    fn identity(width: u32) -> Self {
+        let last = (width.max(2) - 1) as f64;
+        Self {
+            values: (0..width.max(2)).map(|j| j as f64 / last).collect(),
+        }
+    }
+
+    /// The warp for `column`'s texels. At a nearest-texel lookup, a stretch
+    /// of forward values holding a share `p` of the pixels, across which the
+    /// source rises by `r` levels, costs about `p * (r / n)^2 / 12` in squared
+    /// error when it gets `n` texels, so the total is smallest when `n` goes
+    /// as the cube root of `p * r^2`. The forward range is cut into
+    /// [`KNOT_CELLS_PER_TEXEL`] cells per texel, `p` taken from the target's
+    /// CDF, `r` from the interpolated source values, and
+    /// [`UNIFORM_KNOT_SHARE`] of the texels spread evenly on top. A column
+    /// that never rises, or an identity column, keeps evenly spaced texels.
+    fn new(
+        column: &LutColumn,
+        config: &TransformConfig,
+        target: &dyn Distribution,
+        width: u32,
+    ) -> Self {
+        if width < 2 || !config.lut_channel_enabled(column.channel) {
+            return Self::identity(width);
+        }
+        let cells = width as usize * KNOT_CELLS_PER_TEXEL;
+        let edges: Vec<(f64, f64)> = (0..=cells)
+            .into_par_iter()
+            .map(|k| {
+                let u = target.cdf(k as f64 / cells as f64);
+                let value = column
+                    .histogram
+                    .interpolated_value_at_quantile(column.histogram_channel, u);
+                (u, value)
+            })
+            .collect();
+        let density: Vec<f64> = edges
+            .windows(2)
+            .map(|pair| ((pair[1].0 - pair[0].0) * (pair[1].1 - pair[0].1).powi(2)).cbrt())
+            .collect();
+        let total: f64 = density.iter().sum();
+        if total <= 0.0 {
+            return Self::identity(width);
+        }
+        let mut cumulative = Vec::with_capacity(cells + 1);
+        cumulative.push(0.0);
+        for d in &density {
+            let share = (1.0 - UNIFORM_KNOT_SHARE) * d / total + UNIFORM_KNOT_SHARE / cells as f64;
+            cumulative.push(cumulative.last().unwrap() + share);
+        }
+        let last = (width - 1) as f64;
+        let values = (0..width)
+            .map(|j| {
+                let x = j as f64 / last * cells as f64;
+                let k = (x.floor() as usize).min(cells - 1);
+                let value = cumulative[k] + (cumulative[k + 1] - cumulative[k]) * (x - k as f64);
+                (value / cumulative[cells]).min(1.0)
+            })
+            .collect();
+        Self { values }
+    }
+
+    /// The stored forward value the warp maps to the LUT coordinate `u`.
+    fn forward_value(&self, u: f64) -> f64 {
+        let u = u.clamp(0.0, 1.0);
+        let last = self.values.len() - 1;
+        let j = self.values.partition_point(|&v| v <= u).clamp(1, last) - 1;
+        let (a, b) = (self.values[j], self.values[j + 1]);
+        (j as f64 + ((u - a) / (b - a)).clamp(0.0, 1.0)) / last as f64
+    }
+}
+
+/// The knot map of a `lut_width` wide LUT built from `histogram`, one
+/// [`KnotWarp`] per column, interleaved RGB as `[0, 1]` floats; evenly
+/// spaced values for channels that aren't transformed.
+pub(crate) fn lut_knots(
+    histogram: &Histogram<u8>,
+    config: &TransformConfig,
+    lut_width: u32,
+) -> Vec<f32> {
+    let columns: Vec<Vec<f32>> = (0..3)
+        .map(|channel| {
+            let warp = match channel < config.channels.count() {
+                true => {
+                    let column = LutColumn {
+                        histogram,
+                        histogram_channel: if config.shared_lut { 0 } else { channel },
+                        channel,
+                    };
+                    KnotWarp::new(
+                        &column,
+                        config,
+                        config.channel_target(channel).as_ref(),
+                        lut_width,
+                    )
+                }
+                false => KnotWarp::identity(lut_width),
+            };
+            warp.values[..lut_width as usize]
+                .iter()
+                .map(|&v| v as f32)
+                .collect()
+        })
+        .collect();
+    interleave(&columns, 3)
+}
+
+/// One channel's share of a transform, as [`transform_channel`] bakes it.
+pub(crate) struct ChannelBake {
+    /// The channel's forward values, one per subpixel.
+    pub(crate) forward: Vec<f32>,
+    /// The channel's LUT column, every row, rounded and as `[0, 1]` floats.
+    pub(crate) lut: Vec<u8>,
+    pub(crate) lut_f32: Vec<f32>,
+    /// The channel's sorted source values.
+    pub(crate) sorted: Vec<u8>,
+}
+
+/// Transforms `channel` on its own from its subpixels `values`, already
+/// prefiltered. Gives the same results as that channel of
+/// [`transform_histogram`] without weights, `shared_lut` or
+/// `histogram_samples`.
+pub(crate) fn transform_channel(
+    values: Vec<u8>,
+    channel: usize,
+    config: &TransformConfig,
+    lut_width: u32,
+    cancel: &CancelToken,
+) -> Result<ChannelBake, Cancelled> {
+    let _channel = info_span!("transform_channel", channel).entered();
+    let (sort_idx, sorted) = rank_channel(values, config.deterministic, cancel)?;
+    let (mut ranked, mut sorted) = (vec![sort_idx], vec![sorted]);
+    if config.trim_tails > 0.0 {
+        (ranked, sorted) = trim_tails(ranked, sorted, config.trim_tails);
+    }
+    let histogram = Histogram::from_sorted(sorted)
+        .with_plotting_position(config.plotting_position)
+        .with_preserve_extremes(config.preserve_extremes);
+    let target = config.channel_target(channel);
+    let mut forward = vec![0.0; ranked[0].len()];
+    let ranks = Ranks::Sorted(ranked);
+    forward
+        .par_chunks_mut(FORWARD_CHUNK)
+        .enumerate()
+        .try_for_each(|(chunk, out)| {
+            cancel.check()?;
+            forward_values(
+                &ranks,
+                &histogram,
+                target.as_ref(),
+                0,
+                0,
+                chunk * FORWARD_CHUNK,
+                out,
+            );
+            Ok(())
+        })?;
+    let column = LutColumn {
+        histogram: &histogram,
+        histogram_channel: 0,
+        channel,
+    };
+    let t_inv = column.sample_rows(config, target.as_ref(), lut_width);
+    Ok(ChannelBake {
+        forward,
+        lut: t_inv
+            .iter()
+            .map(|&v| config.rounding.apply(v) as u8)
+            .collect(),
+        lut_f32: t_inv.iter().map(|&v| (v / 255.0) as f32).collect(),
+        sorted: histogram.sorted_values(0).to_vec(),
+    })
+}
+
+/// The identity LUT column of a channel without a LUT of its own, every row
+/// as [`build_lut`] lays them out, in source levels.
+pub(crate) fn identity_column(config: &TransformConfig, lut_width: u32) -> Vec<f64> {
+    let rows = config.lut_rows;
+    let offset = |row: u32| match config.lut_row_mode {
+        LutRowMode::Replicate => 0.5,
+        _ if rows == 1 => 0.5,
+        _ => (row as f64 + 0.5) / rows as f64,
+    };
+    (0..rows)
+        .flat_map(|row| {
+            (0..lut_width).map(move |i| config.lut_texel_value(i, lut_width, offset(row)) * 255.0)
+        })
+        .collect()
+}
+
+/// Builds the histogram [`transform_histogram`] would, without the transform.
+pub(crate) fn build_histogram(
+    input: &DynamicImage,
+    config: &TransformConfig,
+) -> Result<Histogram<u8>, anyhow::Error> {
+    let mut inputs = extract_channels(input, config);
+    if config.shared_lut {
+        inputs = vec![inputs.concat()];
+    }
+    if let Some(samples) = config.histogram_samples {
+        for values in &mut inputs {
+            *values = sample(values, samples, Rng::new(config.seed));
+        }
+    }
+    Ok(Histogram::from_channels(inputs)?
+        .with_plotting_position(config.plotting_position)
+        .with_preserve_extremes(config.preserve_extremes))
+}
+
+/// Copies each transformed channel's subpixels into its own buffer, through
+/// `config.prefilter`.
+pub(crate) fn extract_channels(input: &DynamicImage, config: &TransformConfig) -> Vec<Vec<u8>> {
+    let inputs = extract_raw_channels(input, config);
+    if config.prefilter == Prefilter::None {
+        return inputs;
+    }
+    let parent = tracing::Span::current();
+    inputs
+        .par_iter()
+        .enumerate()
+        .map(|(channel, values)| {
+            let _filter = info_span!(parent: &parent, "prefilter", channel).entered();
+            prefilter_channel(values, input.width(), input.height(), config)
+        })
+        .collect()
+}
+
+/// Copies each transformed channel's subpixels into its own buffer, unfiltered.
+pub(crate) fn extract_raw_channels(input: &DynamicImage, config: &TransformConfig) -> Vec<Vec<u8>> {
+    let mut inputs =
+        vec![vec![0; input.width() as usize * input.height() as usize]; config.channels.count()];
+    input.pixels().enumerate().for_each(|(i, (_, _, px))| {
+        for (c, channel) in inputs.iter_mut().enumerate() {
+            channel[i] = px[c];
+        }
+    });
+    inputs
+}
+
+/// `values`, one channel of a `width x height` image, through `config.prefilter`.
+pub(crate) fn prefilter_channel(
+    values: &[u8],
+    width: u32,
+    height: u32,
+    config: &TransformConfig,
+) -> Vec<u8> {
+    match config.prefilter {
+        Prefilter::None => values.to_vec(),
+        Prefilter::Bilateral => bilateral(
+            values,
+            width,
+            height,
+            config.prefilter_sigma_spatial,
+            config.prefilter_sigma_range,
+        ),
+    }
+}
+
+/// Drops the lowest and highest `percent` of each channel's sorted samples,
+/// and ranks the subpixels that held them as the nearest sample left.
+fn trim_tails(
+    ranked: Vec<Vec<usize>>,
+    sorted: Vec<Vec<u8>>,
+    percent: f64,
+) -> (Vec<Vec<usize>>, Vec<Vec<u8>>) {
+    ranked
+        .into_par_iter()
+        .zip(sorted)
+        .map(|(sort_idx, sorted)| {
+            let n = sorted.len();
+            // Below half of `n`, so at least one sample is kept.
+            let trim = (n as f64 * percent / 100.0) as usize;
+            let last = n - trim - 1;
+            let sort_idx = sort_idx
+                .iter()
+                .map(|&k| k.clamp(trim, last) - trim)
+                .collect();
+            (sort_idx, sorted[trim..=last].to_vec())
+        })
+        .unzip()
+}
+
+/// Returns each subpixel's position in the sorted order, alongside the sorted values.
+/// A `stable` sort ranks equal values in image order.
+fn rank_channel<T: Copy + Ord + Send + Sync>(
+    inp: Vec<T>,
+    stable: bool,
+    cancel: &CancelToken,
+) -> Result<(Vec<usize>, Vec<T>), Cancelled> {
+    let mut input_sorted: Vec<_> = inp.into_iter().enumerate().collect();
+    if stable {
+        input_sorted.par_sort_by_key(|(_, val)| *val);
+    } else {
+        input_sorted.par_sort_unstable_by_key(|(_, val)| *val);
+    }
+    cancel.check()?;
+
+    let mut input_orig_order: Vec<_> = input_sorted
+        .par_iter()
+        .enumerate()
+        .map(|(sort_idx, (subpx_idx, _))| ChannelPixel {
+            subpx_idx: *subpx_idx,
+            sort_idx,
+        })
+        .collect();
+    input_orig_order.par_sort_unstable_by_key(|pixel| pixel.subpx_idx);
+    cancel.check()?;
+
+    Ok((
+        input_orig_order
+            .into_par_iter()
+            .map(|px| px.sort_idx)
+            .collect(),
+        input_sorted.into_par_iter().map(|(_, val)| val).collect(),
+    ))
+}
+
+/// `samples` of `values` drawn at random with replacement, or all of them in
+/// order if there aren't more than `samples`. The same `rng` and length draw
+/// the same positions, so every channel is sampled at the same pixels.
+fn sample(values: &[u8], samples: usize, rng: Rng) -> Vec<u8> {
+    if samples >= values.len() {
+        return values.to_vec();
+    }
+    (0..samples as u64)
+        .into_par_iter()
+        .map(|j| values[rng.below(j, values.len() as u64) as usize])
+        .collect()
+}
+
+/// Quantile of each subpixel in a [`sample`] of `values`, alongside the sorted
+/// sample. The subpixels holding a value share the span of the sample's CDF
+/// that value covers, evenly and in image order, which is where an exact,
+/// stable sort would rank them when the sample is all of `values`.
+fn rank_sampled(values: &[u8], samples: usize, rng: Rng) -> (Vec<f64>, Vec<u8>) {
+    let mut sorted = sample(values, samples, rng);
+    sorted.par_sort_unstable();
+    let n = sorted.len() as f64;
+    // Share of the sample below each value, and its span of the CDF.
+    let mut below = [0.0; 256];
+    let mut span = [0.0; 256];
+    for v in 0..256 {
+        let lo = sorted.partition_point(|&x| (x as usize) < v);
+        let hi = sorted.partition_point(|&x| (x as usize) <= v);
+        below[v] = lo as f64 / n;
+        span[v] = (hi - lo) as f64 / n;
+    }
+
+    // Each subpixel's index among the subpixels of its value, counted chunk
+    // by chunk so the chunks can be filled in parallel.
+    const CHUNK: usize = 1 << 16;
+    let chunk_counts: Vec<[usize; 256]> = values
+        .par_chunks(CHUNK)
+        .map(|chunk| {
+            let mut counts = [0; 256];
+            chunk.iter().for_each(|&v| counts[v as usize] += 1);
+            counts
+        })
+        .collect();
+    let mut totals = [0; 256];
+    let chunk_starts: Vec<[usize; 256]> = chunk_counts
+        .iter()
+        .map(|counts| {
+            let start = totals;
+            totals
+                .iter_mut()
+                .zip(counts)
+                .for_each(|(total, count)| *total += count);
+            start
+        })
+        .collect();
+
+    let mut quantiles = vec![0.0; values.len()];
+    quantiles
+        .par_chunks_mut(CHUNK)
+        .zip(values.par_chunks(CHUNK))
+        .zip(chunk_starts)
+        .for_each(|((quantiles, chunk), mut seen)| {
+            for (u, &v) in quantiles.iter_mut().zip(chunk) {
+                let v = v as usize;
+                let within = (seen[v] as f64 + 0.5) / totals[v] as f64;
+                seen[v] += 1;
+                // Values the sample missed collapse onto the CDF between its
+                // neighbours; clamped so the target's tails stay finite.
+                *u = (below[v] + span[v] * within).clamp(0.5 / n, 1.0 - 0.5 / n);
+            }
+        });
+    (quantiles, sorted)
+}
+
+/// Interleaves channels into `stride` samples per pixel, leaving trailing samples at their default.
+pub(crate) fn interleave<T: Copy + Default + Send + Sync>(
+    channels: &[Vec<T>],
+    stride: usize,
+) -> Vec<T> {
+    let mut out = vec![T::default(); channels[0].len() * stride];
+    out.par_chunks_mut(stride)
+        .enumerate()
+        .for_each(|(i, subpx)| {
+            for (c, channel) in channels.iter().enumerate() {
+                subpx[c] = channel[i];
+            }
+        });
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::identity_column;
+    use crate::channels::Channel;
+    use crate::compare::difference;
+    use crate::encode::{encode_image_to_vec, encode_lut_to_vec, Tiff};
+    use crate::self_test::synthetic_input;
+    use crate::TransformConfig;
+    use image::DynamicImage;
+
+    #[test]
+    fn shared_lut_ranks_all_channels_together() {
+        let input = synthetic_input();
+        let config = TransformConfig::default().deterministic(true);
+        let independent = config.clone().run(&input).unwrap();
+        let shared = config.shared_lut(true).run(&input).unwrap();
+
+        assert_eq!(independent.histogram.channel_count(), 3);
+        assert_eq!(shared.histogram.channel_count(), 1);
+        assert_eq!(shared.histogram.sample_count(0), 3 * 64 * 64);
+
+        // One LUT for all three channels, where each channel had its own.
+        let column = |lut: &[u8], channel: usize| -> Vec<u8> {
+            lut.iter().skip(channel).step_by(3).cloned().collect()
+        };
+        assert_eq!(column(&shared.lut, 0), column(&shared.lut, 1));
+        assert_eq!(column(&shared.lut, 0), column(&shared.lut, 2));
+        assert_ne!(column(&independent.lut, 0), column(&independent.lut, 2));
+
+        // Ordering the forward image orders the input across channels too.
+        let source = input.to_rgb8().into_raw();
+        let mut pairs: Vec<(f32, u8)> = shared.image.iter().cloned().zip(source).collect();
+        pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+        assert!(pairs.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+    }
+
+    #[test]
+    fn deterministic_bakes_match_across_thread_counts() {
+        let input = synthetic_input();
+        let config = TransformConfig::default().deterministic(true);
+        let bake = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let result = pool.install(|| config.run(&input)).unwrap();
+            let encoder = Tiff::default();
+            (
+                encode_image_to_vec(&result, &encoder).unwrap(),
+                encode_lut_to_vec(&result, &encoder).unwrap(),
+            )
+        };
+        let single = bake(1);
+        for threads in [2, 7] {
+            assert!(bake(threads) == single, "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn unselected_lut_channels_are_identity() {
+        let input = synthetic_input();
+        let config = TransformConfig::default().deterministic(true);
+        let all = config.run(&input).unwrap();
+        let red_only = config.clone().lut_channels(Some(vec![Channel::R]));
+        let red = red_only.run(&input).unwrap();
+
+        assert_eq!(red.image, all.image);
+        let column = |lut: &[u8], channel: usize| -> Vec<u8> {
+            lut.iter().skip(channel).step_by(3).cloned().collect()
+        };
+        assert_eq!(column(&red.lut, 0), column(&all.lut, 0));
+        let identity: Vec<u8> = identity_column(&red_only, red.lut_width)
+            .iter()
+            .map(|&v| config.rounding.apply(v) as u8)
+            .collect();
+        // One texel per level at the default 256 wide LUT.
+        assert_eq!(identity, (0..=255).collect::<Vec<u8>>());
+        for channel in 1..3 {
+            assert_eq!(column(&red.lut, channel), identity, "channel {}", channel);
+            assert_ne!(column(&all.lut, channel), identity, "channel {}", channel);
+        }
+    }
+
+    #[test]
+    fn full_histogram_sample_matches_the_exact_transform() {
+        let input = synthetic_input();
+        let exact = TransformConfig::default().deterministic(true);
+        let expected = exact.run(&input).unwrap();
+        for samples in [64 * 64, 100_000] {
+            let sampled = exact.clone().histogram_samples(Some(samples));
+            let result = sampled.run(&input).unwrap();
+            assert_eq!(result.image, expected.image, "{} samples", samples);
+            assert_eq!(result.lut, expected.lut, "{} samples", samples);
+        }
+
+        let sparse = exact.histogram_samples(Some(512)).run(&input).unwrap();
+        let diff = difference(&sparse.image, &expected.image);
+        assert!(diff.max_abs > 0.0 && diff.rmse < 0.02, "rmse {}", diff.rmse);
+    }
+
+    #[test]
+    fn trimmed_outliers_dont_compress_the_bulk() {
+        // Values in [64, 192), with 20 dead and 20 hot pixels, exactly the
+        // 0.5% trimmed from each end of the 4096.
+        let rng = crate::rng::Rng::new(5);
+        let bulk = |i: u32| 64 + rng.below(i as u64, 128) as u8;
+        let clean = image::RgbImage::from_fn(64, 64, |x, y| image::Rgb([bulk(y * 64 + x); 3]));
+        let mut spiked = clean.clone();
+        for i in 0..20 {
+            spiked.put_pixel(i, 0, image::Rgb([0; 3]));
+            spiked.put_pixel(i, 63, image::Rgb([255; 3]));
+        }
+        let is_spike = |i: usize| i < 20 || (63 * 64..63 * 64 + 20).contains(&i);
+        let config = TransformConfig::default().deterministic(true);
+        let spread = |image: &[f32]| {
+            let bulk = image.iter().step_by(3).enumerate();
+            let bulk: Vec<f32> = bulk
+                .filter(|(i, _)| !is_spike(*i))
+                .map(|(_, v)| *v)
+                .collect();
+            let max = bulk.iter().cloned().fold(f32::MIN, f32::max);
+            let min = bulk.iter().cloned().fold(f32::MAX, f32::min);
+            (min, max)
+        };
+
+        let expected = spread(&config.run(&DynamicImage::ImageRgb8(clean)).unwrap().image);
+        let spiked = DynamicImage::ImageRgb8(spiked);
+        let untrimmed = spread(&config.run(&spiked).unwrap().image);
+        let trimmed = config.clone().trim_tails(0.5).run(&spiked).unwrap();
+        let (min, max) = spread(&trimmed.image);
+
+        assert!(untrimmed.1 - untrimmed.0 < 0.95 * (expected.1 - expected.0));
+        assert!((min - expected.0).abs() < 0.01 && (max - expected.1).abs() < 0.01);
+        // The outliers land on the ends of the bulk, and the LUT only holds it.
+        assert_eq!(trimmed.image[0], min);
+        assert_eq!(trimmed.image[3 * 63 * 64], max);
+        assert!(trimmed.lut.iter().all(|v| (64..192).contains(v)));
+    }
+}
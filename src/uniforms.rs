@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::{GaussianResult, GradientAxis, TransformConfig};
+
+/// The constants a sampling shader needs to reconstruct a bake, flattened to
+/// plain numbers so an engine can copy them straight into a uniform buffer.
+/// Channels the config doesn't transform have a mean and std of zero.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Uniforms {
+    pub lut_width: u32,
+    pub lut_height: u32,
+    /// Range of forward image values the LUT spans, see
+    /// [`Sidecar::lut_domain`](crate::sidecar::Sidecar::lut_domain).
+    pub lut_domain: [f64; 2],
+    /// `[scale, offset]` forward values are stored with, see
+    /// [`TransformConfig::forward_encoding`]. The LUT is indexed by the stored
+    /// value; `(stored - offset) / scale` recovers the signed target value.
+    pub forward_encoding: [f64; 2],
+    /// `[scale, offset]` from a stored value `g` to the LUT's texture
+    /// coordinate, `offset + scale * g`, see [`TransformConfig::lut_coordinate`].
+    pub lut_coordinate: [f64; 2],
+    pub channel_count: u32,
+    /// Per channel, the mean the LUT was built for.
+    pub channel_mean: [f64; 3],
+    /// Per channel, the standard deviation of the target distribution.
+    pub channel_std: [f64; 3],
+    /// Mean at the start and end of the gradient, both the LUT's mean
+    /// without one. Subtract `mix(start, end, t) - (start + end) / 2` from a
+    /// sample before looking it up.
+    pub mean_gradient: [f64; 2],
+    /// Axis `t` runs along: `0` for x, `1` for y.
+    pub mean_gradient_axis: u32,
+}
+
+impl Uniforms {
+    pub fn new(result: &GaussianResult, config: &TransformConfig) -> Self {
+        let channel_count = config.channels.count();
+        let per_channel = |value: f64| {
+            let mut values = [0.0; 3];
+            values[..channel_count].fill(value);
+            values
+        };
+        let (scale, offset) = config.forward_encoding();
+        let (lut_scale, lut_offset) = config.lut_coordinate(result.lut_width);
+        let (mean_gradient, axis) = match &config.mean_gradient {
+            Some(gradient) => ([gradient.start, gradient.end], gradient.axis),
+            None => ([config.mean; 2], GradientAxis::default()),
+        };
+        Self {
+            lut_width: result.lut_width,
+            lut_height: result.lut_height,
+            lut_domain: [0.0, 1.0],
+            forward_encoding: [scale, offset],
+            lut_coordinate: [lut_scale, lut_offset],
+            channel_count: channel_count as u32,
+            channel_mean: per_channel(config.lut_mean()),
+            channel_std: per_channel(config.std),
+            mean_gradient,
+            mean_gradient_axis: match axis {
+                GradientAxis::X => 0,
+                GradientAxis::Y => 1,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compare::difference;
+    use crate::config::MeanGradient;
+    use crate::inverse::{lut_coordinates, sample_lut};
+    use crate::self_test::synthetic_input;
+
+    #[test]
+    fn uniforms_have_every_key_and_reconstruct_the_bake() {
+        let input = synthetic_input();
+        let config = TransformConfig::default().deterministic(true);
+        let result = config.run(&input).unwrap();
+        let uniforms = Uniforms::new(&result, &config);
+
+        let json = serde_json::to_value(&uniforms).unwrap();
+        let keys: Vec<&str> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        for key in [
+            "lut_width",
+            "lut_height",
+            "lut_domain",
+            "forward_encoding",
+            "lut_coordinate",
+            "channel_count",
+            "channel_mean",
+            "channel_std",
+            "mean_gradient",
+            "mean_gradient_axis",
+        ] {
+            assert!(keys.contains(&key), "missing {}", key);
+        }
+        assert_eq!(keys.len(), 10);
+
+        // The forward image has the advertised mean and std per channel.
+        assert_eq!(uniforms.channel_count, 3);
+        for channel in 0..3 {
+            let values: Vec<f64> = result
+                .image
+                .iter()
+                .skip(channel)
+                .step_by(3)
+                .map(|&v| v as f64)
+                .collect();
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            let std = (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>()
+                / values.len() as f64)
+                .sqrt();
+            assert!((mean - uniforms.channel_mean[channel]).abs() < 0.01);
+            assert!((std - uniforms.channel_std[channel]).abs() < 0.01);
+        }
+
+        // Sampling the LUT at the advertised coordinate gives the source back.
+        let [scale, offset] = uniforms.lut_coordinate;
+        let coordinates = lut_coordinates(&result.image, (scale, offset));
+        let reconstructed = sample_lut(&coordinates, &result.lut, uniforms.lut_width);
+        let diff = difference(input.to_rgb8().as_raw(), &reconstructed);
+        assert!(diff.rmse < 2.5, "rmse {}", diff.rmse);
+
+        let gradient = MeanGradient {
+            start: 0.4,
+            end: 0.6,
+            axis: GradientAxis::X,
+        };
+        let config = config.mean_gradient(Some(gradient));
+        let uniforms = Uniforms::new(&config.run(&input).unwrap(), &config);
+        assert_eq!(
+            (uniforms.mean_gradient, uniforms.mean_gradient_axis),
+            ([0.4, 0.6], 0)
+        );
+    }
+}